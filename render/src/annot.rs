@@ -0,0 +1,239 @@
+//! Appearance of annotations without an /AP stream, derived from their properties.
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary, Name};
+use pdf::content::{Op, Color, Rgb, Cmyk, parse_ops};
+use pathfinder_geometry::{vector::Vector2F, rect::RectF};
+use pathfinder_content::outline::Contour;
+use crate::{Fill, colorspace::{gray2rgb, cmyk2rgb, CmykConversion}};
+
+/// magic number for approximating a quarter circle with a cubic bézier
+const KAPPA: f32 = 0.552_284_8;
+
+/// /S of a border style dictionary
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorderKind {
+    Solid,
+    Dashed,
+    /// raised, lighter at the top left
+    Beveled,
+    /// pressed in, darker at the top left
+    Inset,
+    /// only a line along the bottom
+    Underline,
+}
+
+pub struct BorderStyle {
+    pub kind: BorderKind,
+    pub width: f32,
+    pub dash: Option<Vec<f32>>,
+    /// /C
+    pub stroke: Option<Fill>,
+    /// /IC
+    pub fill: Option<Fill>,
+}
+
+/// The border of the annotation from /BS, or the older /Border array.
+pub fn border_style(annot: &Annot, resolve: &impl Resolve, cmyk: CmykConversion) -> BorderStyle {
+    let mut kind = BorderKind::Solid;
+    let mut width = 1.0;
+    let mut dash = None;
+    let bs = annot.other.get("BS").and_then(|p| p.clone().resolve(resolve).ok()).and_then(|p| p.into_dictionary().ok());
+    match bs {
+        Some(bs) => {
+            if let Some(w) = number(&bs, "W", resolve) {
+                width = w;
+            }
+            if let Some(Primitive::Name(ref s)) = bs.get("S") {
+                kind = match s.as_str() {
+                    "D" => BorderKind::Dashed,
+                    "B" => BorderKind::Beveled,
+                    "I" => BorderKind::Inset,
+                    "U" => BorderKind::Underline,
+                    _ => BorderKind::Solid,
+                };
+                if kind == BorderKind::Dashed {
+                    dash = Some(numbers(bs.get("D"), resolve).unwrap_or_else(|| vec![3.0]));
+                }
+            }
+        }
+        None => {
+            // [h_radius v_radius width dash?]
+            if let Some(border) = annot.border.as_ref().and_then(|b| b.clone().resolve(resolve).ok()).and_then(|b| b.into_array().ok()) {
+                if let Some(w) = border.get(2).and_then(|w| w.as_number().ok()) {
+                    width = w;
+                }
+                dash = numbers(border.get(3), resolve);
+                if dash.is_some() {
+                    kind = BorderKind::Dashed;
+                }
+            }
+        }
+    }
+    BorderStyle {
+        kind,
+        width,
+        dash,
+        stroke: color(annot.color.as_ref(), resolve, cmyk),
+        fill: color(annot.other.get("IC"), resolve, cmyk),
+    }
+}
+
+/// An annotation color array. An empty array is transparent.
+pub fn color(p: Option<&Primitive>, resolve: &impl Resolve, cmyk: CmykConversion) -> Option<Fill> {
+    match numbers(p, resolve)?[..] {
+        [g] => Some(gray2rgb(g)),
+        [r, g, b] => Some(Fill::Solid(r, g, b)),
+        [c, m, y, k] => Some(cmyk2rgb((c, m, y, k), cmyk)),
+        _ => None,
+    }
+}
+
+/// The ellipse inscribed in `r`
+pub fn ellipse(r: RectF) -> Contour {
+    let c = r.center();
+    let (rx, ry) = (r.width() * 0.5, r.height() * 0.5);
+    let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+    let p = |x: f32, y: f32| c + Vector2F::new(x, y);
+
+    let mut contour = Contour::new();
+    contour.push_endpoint(p(rx, 0.0));
+    contour.push_cubic(p(rx, ky), p(kx, ry), p(0.0, ry));
+    contour.push_cubic(p(-kx, ry), p(-rx, ky), p(-rx, 0.0));
+    contour.push_cubic(p(-rx, -ky), p(-kx, -ry), p(0.0, -ry));
+    contour.push_cubic(p(kx, -ry), p(rx, -ky), p(rx, 0.0));
+    contour.close();
+    contour
+}
+
+/// Font and color for the text of FreeText annotations and form fields, from /DA
+pub struct DefaultAppearance {
+    pub font: Option<Name>,
+    pub font_size: f32,
+    /// a font size of 0, the text is scaled to fit
+    pub auto_size: bool,
+    pub color: Fill,
+}
+impl DefaultAppearance {
+    pub fn parse(annot: &Annot, resolve: &impl Resolve, cmyk: CmykConversion) -> DefaultAppearance {
+        match annot.other.get("DA").and_then(|p| p.clone().resolve(resolve).ok()) {
+            Some(Primitive::String(s)) => DefaultAppearance::from_data(s.as_bytes(), resolve, cmyk),
+            _ => DefaultAppearance::from_data(b"", resolve, cmyk)
+        }
+    }
+    pub fn from_data(data: &[u8], resolve: &impl Resolve, cmyk: CmykConversion) -> DefaultAppearance {
+        let mut da = DefaultAppearance {
+            font: None,
+            font_size: 12.0,
+            auto_size: false,
+            color: Fill::black(),
+        };
+        let ops = match parse_ops(data, resolve) {
+            Ok(ops) => ops,
+            Err(e) => {
+                warn!("invalid /DA: {:?}", e);
+                return da;
+            }
+        };
+        for op in ops {
+            match op {
+                Op::TextFont { name, size } => {
+                    da.font = Some(name);
+                    if size > 0.0 {
+                        da.font_size = size;
+                    } else {
+                        da.auto_size = true;
+                    }
+                }
+                Op::FillColor { color: Color::Gray(g) } => da.color = gray2rgb(g),
+                Op::FillColor { color: Color::Rgb(Rgb { red, green, blue }) } => da.color = Fill::Solid(red, green, blue),
+                Op::FillColor { color: Color::Cmyk(Cmyk { cyan, magenta, yellow, key }) } => da.color = cmyk2rgb((cyan, magenta, yellow, key), cmyk),
+                _ => {}
+            }
+        }
+        da
+    }
+}
+
+/// /Contents split into lines and encoded for a simple font
+pub fn text_lines(annot: &Annot) -> Vec<Vec<u8>> {
+    let text = match annot.contents {
+        Some(ref s) => s.to_string_lossy(),
+        None => return vec![]
+    };
+    text.split(|c| c == '\r' || c == '\n').map(encode_simple).collect()
+}
+
+/// Encode `text` for a simple font, dropping characters outside of Latin-1
+pub fn encode_simple(text: &str) -> Vec<u8> {
+    text.chars().filter_map(|c| u8::try_from(c as u32).ok()).collect()
+}
+
+/// Break `text` into lines at its line breaks and between words, so that no line is wider than `max_width`.
+/// A single word that doesn't fit gets a line of its own.
+pub fn wrap_text(text: &str, max_width: f32, width: impl Fn(&str) -> f32) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.split("\r\n").flat_map(|p| p.split(|c| c == '\r' || c == '\n')) {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            if line.is_empty() {
+                line.push_str(word);
+                continue;
+            }
+            let candidate = format!("{} {}", line, word);
+            if width(&candidate) > max_width {
+                lines.push(std::mem::replace(&mut line, word.into()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Background (/BG) and border (/BC) color of a widget, from its /MK
+pub fn widget_colors(annot: &Annot, resolve: &impl Resolve, cmyk: CmykConversion) -> (Option<Fill>, Option<Fill>) {
+    let mk = match annot.other.get("MK").and_then(|p| p.clone().resolve(resolve).ok()).and_then(|p| p.into_dictionary().ok()) {
+        Some(mk) => mk,
+        None => return (None, None)
+    };
+    (color(mk.get("BG"), resolve, cmyk), color(mk.get("BC"), resolve, cmyk))
+}
+
+/// A check mark in the square centered in `r`, to be stroked with a width of a tenth of its size
+pub fn check_mark(r: RectF) -> Contour {
+    let size = r.width().min(r.height()) * 0.8;
+    let origin = r.center() - Vector2F::splat(size * 0.5);
+    let p = |x: f32, y: f32| origin + Vector2F::new(x, y) * size;
+
+    let mut contour = Contour::new();
+    contour.push_endpoint(p(0.1, 0.5));
+    contour.push_endpoint(p(0.4, 0.15));
+    contour.push_endpoint(p(0.9, 0.85));
+    contour
+}
+
+/// The top left and bottom right edges of a beveled or inset border of `width` inside `r`
+pub fn bevel(r: RectF, width: f32) -> (Contour, Contour) {
+    let (x0, y0, x1, y1) = (r.min_x() + width, r.min_y() + width, r.max_x() - width, r.max_y() - width);
+    let w = width;
+    let polygon = |points: &[(f32, f32)]| {
+        let mut contour = Contour::new();
+        for &(x, y) in points {
+            contour.push_endpoint(Vector2F::new(x, y));
+        }
+        contour.close();
+        contour
+    };
+    let top_left = polygon(&[(x0, y0), (x0, y1), (x1, y1), (x1 - w, y1 - w), (x0 + w, y1 - w), (x0 + w, y0 + w)]);
+    let bottom_right = polygon(&[(x1, y1), (x1, y0), (x0, y0), (x0 + w, y0 + w), (x1 - w, y0 + w), (x1 - w, y1 - w)]);
+    (top_left, bottom_right)
+}
+
+fn number(dict: &Dictionary, key: &str, resolve: &impl Resolve) -> Option<f32> {
+    dict.get(key)?.clone().resolve(resolve).ok()?.as_number().ok()
+}
+
+fn numbers(p: Option<&Primitive>, resolve: &impl Resolve) -> Option<Vec<f32>> {
+    p?.clone().resolve(resolve).ok()?.into_array().ok()?.iter().map(|n| n.as_number().ok()).collect()
+}
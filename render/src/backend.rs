@@ -4,14 +4,16 @@ use pathfinder_geometry::{
 };
 use pathfinder_content::{
     fill::FillRule,
-    stroke::{StrokeStyle},
+    stroke::{StrokeStyle, OutlineStrokeToFill},
     outline::Outline,
+    dash::OutlineDash,
 };
 
-use pdf::{object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef}, content::Op};
+use pdf::{object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, RenderingIntent}, content::{Op, FormXObject}};
+use pdf::function::Function;
 use pdf::error::PdfError;
 use font::Glyph;
-use super::{FontEntry, TextSpan, Fill};
+use super::{FontEntry, TextSpan, TextGlyph, Fill, Shading, ShadingQuality, IccTransform, CmykConversion, RenderConfig, HAIRLINE_WIDTH};
 use pdf::font::Font as PdfFont;
 use std::sync::Arc;
 
@@ -21,20 +23,118 @@ pub enum BlendMode {
     Darken
 }
 
+/// The separable and non-separable blend modes of /BM
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub enum Blend {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+impl Blend {
+    pub fn from_name(name: &str) -> Option<Blend> {
+        Some(match name {
+            "Normal" | "Compatible" => Blend::Normal,
+            "Multiply" => Blend::Multiply,
+            "Screen" => Blend::Screen,
+            "Overlay" => Blend::Overlay,
+            "Darken" => Blend::Darken,
+            "Lighten" => Blend::Lighten,
+            "ColorDodge" => Blend::ColorDodge,
+            "ColorBurn" => Blend::ColorBurn,
+            "HardLight" => Blend::HardLight,
+            "SoftLight" => Blend::SoftLight,
+            "Difference" => Blend::Difference,
+            "Exclusion" => Blend::Exclusion,
+            "Hue" => Blend::Hue,
+            "Saturation" => Blend::Saturation,
+            "Color" => Blend::Color,
+            "Luminosity" => Blend::Luminosity,
+            _ => return None
+        })
+    }
+}
+
+/// Default of `Backend::recursion_limit`
+pub const DEFAULT_RECURSION_LIMIT: usize = 32;
+
 pub trait Backend {
     type ClipPathId: Copy;
 
     fn create_clip_path(&mut self, path: Outline, fill_rule: FillRule, parent: Option<Self::ClipPathId>) -> Self::ClipPathId;
     fn draw(&mut self, outline: &Outline, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<Self::ClipPathId>);
     fn set_view_box(&mut self, r: RectF);
-    fn draw_image(&mut self, xref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>, resolve: &impl Resolve);
-    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<Self::ClipPathId>, resolve: &impl Resolve);
-    fn draw_glyph(&mut self, glyph: &Glyph, mode: &DrawMode, transform: Transform2F, clip: Option<Self::ClipPathId>) {
+    /// `alpha` is the constant alpha (/ca) the image is painted with.
+    ///
+    /// Images that can't be decoded return an error, which the renderer skips unless invalid ops are disallowed.
+    fn draw_image(&mut self, xref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: BlendMode, alpha: f32, clip: Option<Self::ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError>;
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: BlendMode, alpha: f32, clip: Option<Self::ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError>;
+    /// An inline image with /IM true, like `draw_image_mask`.
+    fn draw_inline_image_mask(&mut self, im: &Arc<ImageXObject>, fill: &FillMode, resources: &Resources, transform: Transform2F, clip: Option<Self::ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        self.draw_inline_image(im, resources, transform, fill.mode, fill.alpha, clip, resolve)
+    }
+    /// An image with /ImageMask true: paint `fill` where the stencil is set.
+    fn draw_image_mask(&mut self, xref: Ref<XObject>, im: &ImageXObject, fill: &FillMode, resources: &Resources, transform: Transform2F, clip: Option<Self::ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        self.draw_image(xref, im, resources, transform, fill.mode, fill.alpha, clip, resolve)
+    }
+    /// `code` is the character code of the glyph in the font
+    fn draw_glyph(&mut self, glyph: &Glyph, code: u16, mode: &DrawMode, transform: Transform2F, clip: Option<Self::ClipPathId>) {
         self.draw(&glyph.path, mode, FillRule::Winding, transform, clip);
     }
+    /// Called for every glyph of shown text, also for invisible text (Tr 3) and glyphs without an outline.
+    fn text_glyph(&mut self, glyph: &TextGlyph) {}
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError>;
     fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>);
 
+    /// Used to convert colors in ICCBased color spaces. Without it the alternate color space is used.
+    fn icc_transform(&self) -> Option<Arc<dyn IccTransform>> { None }
+
+    /// How DeviceCMYK colors (and images) are converted to RGB.
+    fn cmyk_conversion(&self) -> CmykConversion { CmykConversion::default() }
+
+    /// How deeply forms, patterns and Type3 glyphs may be nested. Deeper content is skipped with a warning.
+    fn recursion_limit(&self) -> usize { DEFAULT_RECURSION_LIMIT }
+
+    /// How finely function-based and mesh shadings are sampled.
+    fn shading_quality(&self) -> ShadingQuality { ShadingQuality::default() }
+
+    /// The blend mode of everything drawn from now on.
+    fn set_blend_mode(&mut self, blend: Blend) {}
+
+    /// The rendering intent of the images drawn from now on, for converting ICC based images with `icc_transform`.
+    fn set_rendering_intent(&mut self, intent: RenderingIntent) {}
+
+    /// `sh`: paint the shading over the current clip region.
+    fn draw_shading(&mut self, shading: &Shading, transform: Transform2F, mode: BlendMode, alpha: f32, clip: Option<Self::ClipPathId>) {}
+
+    /// An ExtGState turned on overprinting (/OP, /op) with overprint mode /OPM.
+    /// Rendering only approximates it; this lets tools warn about pages that depend on it.
+    fn overprint(&mut self, fill: bool, stroke: bool, mode: i32) {}
+
+    /// A form XObject with a transparency /Group starts. Returning `true` means the backend draws the
+    /// following calls, until `end_group`, offscreen and composites them with the group's alpha and blend mode.
+    /// Otherwise the group is drawn inline, with its alpha applied to each object.
+    fn begin_group(&mut self, group: &TransparencyGroup<Self::ClipPathId>) -> bool { false }
+    fn end_group(&mut self) {}
+
+    /// A soft mask was set in the graphics state. The following draw calls, until `end_smask`, paint the mask group.
+    fn begin_smask(&mut self, mask: &SoftMask) {}
+    /// The mask group is complete. The returned clip path is applied to everything drawn while the mask is active,
+    /// so it has to be nested in `parent`, the clip path in effect when the mask was set.
+    fn end_smask(&mut self, parent: Option<Self::ClipPathId>) -> Option<Self::ClipPathId> { None }
+
     /// The following functions are for debugging PDF files and not relevant for rendering them.
     fn bug_text_no_font(&mut self, data: &[u8]) {}
     fn bug_text_invisible(&mut self, text: &str) {}
@@ -42,6 +142,35 @@ pub trait Backend {
     fn bug_op(&mut self, op_nr: usize) {}
     fn inspect_op(&mut self, op: &Op) {}
 }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SoftMaskKind {
+    Luminosity,
+    Alpha,
+}
+
+/// A transparency group (a form XObject with /Group /S /Transparency)
+#[derive(Debug, Clone)]
+pub struct TransparencyGroup<C> {
+    /// /I: composited against a transparent backdrop instead of the page
+    pub isolated: bool,
+    /// /K: objects replace each other instead of compositing. Only recorded.
+    pub knockout: bool,
+    /// the constant alpha the group is painted with
+    pub alpha: f32,
+    pub blend: Blend,
+    pub clip: Option<C>,
+}
+
+pub struct SoftMask<'a> {
+    pub kind: SoftMaskKind,
+    pub group: &'a FormXObject,
+    /// /BC, the backdrop color (as RGB) for luminosity masks
+    pub backdrop: Option<(f32, f32, f32)>,
+    /// /TR, maps mask values before they are applied
+    pub transfer: Option<&'a Function>,
+    pub transform: Transform2F,
+}
+
 #[derive(Clone, Debug)]
 
 pub struct FillMode {
@@ -54,8 +183,58 @@ pub enum DrawMode {
     Stroke { stroke: FillMode, stroke_mode: Stroke },
     FillStroke { fill: FillMode, stroke: FillMode, stroke_mode: Stroke },
 }
+impl DrawMode {
+    /// Without the fill unless `fill`, without the stroke unless `stroke`. `None` if nothing is left to paint.
+    pub(crate) fn painted(self, fill: bool, stroke: bool) -> Option<DrawMode> {
+        match self {
+            DrawMode::Fill { .. } if !fill => None,
+            DrawMode::Stroke { .. } if !stroke => None,
+            DrawMode::FillStroke { fill: f, stroke: s, stroke_mode } => match (fill, stroke) {
+                (true, true) => Some(DrawMode::FillStroke { fill: f, stroke: s, stroke_mode }),
+                (true, false) => Some(DrawMode::Fill { fill: f }),
+                (false, true) => Some(DrawMode::Stroke { stroke: s, stroke_mode }),
+                (false, false) => None,
+            },
+            mode => Some(mode)
+        }
+    }
+}
 #[derive(Clone, Debug)]
 pub struct Stroke {
     pub dash_pattern: Option<(Vec<f32>, f32)>,
     pub style: StrokeStyle,
 }
+impl Stroke {
+    /// With hairlines widened to one device pixel and strokes thinner than `RenderConfig::min_stroke_width`
+    /// widened to that, for content drawn with `transform`.
+    pub(crate) fn widened(mut self, transform: Transform2F, config: Option<&RenderConfig>) -> Stroke {
+        let min_width = config.map(|c| c.min_stroke_width).unwrap_or(0.0);
+        // device units per user space unit
+        let t = transform;
+        let scale = (t.m11() * t.m22() - t.m12() * t.m21()).abs().sqrt();
+        if scale == 0.0 {
+            return self;
+        }
+        let width = &mut self.style.line_width;
+        if *width == 0.0 {
+            *width = HAIRLINE_WIDTH.max(min_width) / scale;
+        } else if *width * scale < min_width {
+            *width = min_width / scale;
+        }
+        self
+    }
+    /// The area covered by stroking `outline`, to be filled with the nonzero winding rule
+    pub fn outline(&self, outline: &Outline) -> Outline {
+        let dashed;
+        let outline = match self.dash_pattern {
+            Some((ref pat, phase)) => {
+                dashed = OutlineDash::new(outline, pat, phase).into_outline();
+                &dashed
+            }
+            None => outline
+        };
+        let mut stroke = OutlineStrokeToFill::new(outline, self.style);
+        stroke.offset();
+        stroke.into_outline()
+    }
+}
@@ -0,0 +1,292 @@
+//! Metrics of the standard 14 fonts, for PDFs that use them without embedding them or giving /Widths.
+//!
+//! The substitutes from `STANDARD_FONTS` have different widths, so text set in the real fonts
+//! would run too long or too short. The widths here are those of the Adobe AFM files: for the
+//! characters of WinAnsiEncoding in the text fonts, and by code of the built-in encoding in
+//! Symbol and ZapfDingbats. Other characters use the substitute's advance.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Base14 {
+    Helvetica,
+    HelveticaBold,
+    TimesRoman,
+    TimesBold,
+    TimesItalic,
+    TimesBoldItalic,
+    Courier,
+    Symbol,
+    ZapfDingbats,
+}
+impl Base14 {
+    /// The standard font named `name`, including the common aliases (Arial, TimesNewRoman, CourierNew)
+    /// and the `Name,Bold` style suffixes. Subset prefixes are ignored.
+    pub fn from_name(name: &str) -> Option<Base14> {
+        let name = name.split('+').nth(1).unwrap_or(name);
+        let (family, style) = match name.find(|c| c == '-' || c == ',') {
+            Some(i) => (&name[.. i], &name[i + 1 ..]),
+            None => (name, "")
+        };
+        let bold = style.contains("Bold");
+        let italic = style.contains("Italic") || style.contains("Oblique");
+        Some(match family {
+            "Helvetica" | "Arial" | "ArialMT" => match bold {
+                true => Base14::HelveticaBold,
+                false => Base14::Helvetica,
+            },
+            "Times" | "TimesNewRoman" | "TimesNewRomanPS" | "TimesNewRomanPSMT" => match (bold, italic) {
+                (false, false) => Base14::TimesRoman,
+                (true, false) => Base14::TimesBold,
+                (false, true) => Base14::TimesItalic,
+                (true, true) => Base14::TimesBoldItalic,
+            },
+            "Courier" | "CourierNew" | "CourierNewPSMT" => Base14::Courier,
+            "Symbol" => Base14::Symbol,
+            "ZapfDingbats" => Base14::ZapfDingbats,
+            _ => return None
+        })
+    }
+    /// Symbol and ZapfDingbats have their own built-in encodings
+    pub fn is_symbolic(self) -> bool {
+        matches!(self, Base14::Symbol | Base14::ZapfDingbats)
+    }
+    /// Advance of `c` in 1/1000 em, for the text fonts
+    pub fn width(self, c: char) -> Option<f32> {
+        let table = match self {
+            Base14::Helvetica => &HELVETICA,
+            Base14::HelveticaBold => &HELVETICA_BOLD,
+            Base14::TimesRoman => &TIMES_ROMAN,
+            Base14::TimesBold => &TIMES_BOLD,
+            Base14::TimesItalic => &TIMES_ITALIC,
+            Base14::TimesBoldItalic => &TIMES_BOLD_ITALIC,
+            Base14::Courier => return Some(600.),
+            Base14::Symbol | Base14::ZapfDingbats => return None,
+        };
+        lookup(table, winansi_code(c)?)
+    }
+    /// Advance of `code` in the built-in encoding of Symbol and ZapfDingbats, in 1/1000 em
+    pub fn code_width(self, code: u8) -> Option<f32> {
+        match self {
+            Base14::Symbol => lookup(&SYMBOL, code),
+            Base14::ZapfDingbats => lookup(&ZAPF_DINGBATS, code),
+            _ => None
+        }
+    }
+    /// The character of `code` in the built-in encoding of ZapfDingbats.
+    ///
+    /// Symbol is covered by `AdobeSymbol` of the font crate.
+    pub fn builtin_unicode(self, code: u8) -> Option<char> {
+        if self != Base14::ZapfDingbats {
+            return None;
+        }
+        let u = match code {
+            0x20 => 0x20,
+            0x25 => 0x260E,
+            0x2A => 0x261B,
+            0x2B => 0x261E,
+            0x48 => 0x2605,
+            0x6C => 0x25CF,
+            0x6E => 0x25A0,
+            0x73 => 0x25B2,
+            0x74 => 0x25BC,
+            0x75 => 0x25C6,
+            0x77 => 0x25D7,
+            0x21 ..= 0x7E => 0x2700 + (code as u32 - 0x20),
+            0x80 ..= 0x8D => 0x2768 + (code as u32 - 0x80),
+            0xA1 ..= 0xA7 => 0x2761 + (code as u32 - 0xA1),
+            0xA8 => 0x2663,
+            0xA9 => 0x2666,
+            0xAA => 0x2665,
+            0xAB => 0x2660,
+            0xAC ..= 0xB5 => 0x2460 + (code as u32 - 0xAC),
+            0xD5 => 0x2192,
+            0xD6 => 0x2194,
+            0xD7 => 0x2195,
+            0xF0 => return None,
+            0xB6 ..= 0xFE => 0x2776 + (code as u32 - 0xB6),
+            _ => return None
+        };
+        std::char::from_u32(u)
+    }
+}
+
+fn lookup(table: &[u16; 224], code: u8) -> Option<f32> {
+    match table.get((code as usize).checked_sub(0x20)?) {
+        Some(&w) if w != 0 => Some(w as f32),
+        _ => None
+    }
+}
+
+/// code of `c` in WinAnsiEncoding
+fn winansi_code(c: char) -> Option<u8> {
+    match c as u32 {
+        0x20 ..= 0x7E | 0xA0 ..= 0xFF => return Some(c as u8),
+        _ => {}
+    }
+    // 0x80 ..= 0x9F
+    let i = CP1252.iter().position(|&u| u == c)?;
+    Some(0x80 + i as u8)
+}
+
+static CP1252: [char; 32] = [
+    '\u{20AC}', '\0', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\0', '\u{017D}', '\0',
+    '\0', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\0', '\u{017E}', '\u{0178}',
+];
+
+// by WinAnsi code, 0x20 ..= 0xFF. 0 for unused codes
+static HELVETICA: [u16; 224] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, 0,
+    556, 0, 222, 556, 333, 1000, 556, 556, 333, 1000, 667, 333, 1000, 0, 611, 0,
+    0, 222, 222, 333, 333, 350, 556, 1000, 333, 1000, 500, 333, 944, 0, 500, 667,
+    278, 333, 556, 556, 556, 556, 260, 556, 333, 737, 370, 556, 584, 333, 737, 333,
+    400, 584, 333, 333, 333, 556, 537, 278, 333, 333, 365, 556, 834, 834, 834, 611,
+    667, 667, 667, 667, 667, 667, 1000, 722, 667, 667, 667, 667, 278, 278, 278, 278,
+    722, 722, 778, 778, 778, 778, 778, 584, 778, 722, 722, 722, 722, 667, 667, 611,
+    556, 556, 556, 556, 556, 556, 889, 500, 556, 556, 556, 556, 278, 278, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 584, 611, 556, 556, 556, 556, 500, 556, 500,
+];
+
+static HELVETICA_BOLD: [u16; 224] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584, 0,
+    556, 0, 278, 556, 500, 1000, 556, 556, 333, 1000, 667, 333, 1000, 0, 611, 0,
+    0, 278, 278, 500, 500, 350, 556, 1000, 333, 1000, 556, 333, 944, 0, 500, 667,
+    278, 333, 556, 556, 556, 556, 280, 556, 333, 737, 370, 556, 584, 333, 737, 333,
+    400, 584, 333, 333, 333, 611, 556, 278, 333, 333, 365, 556, 834, 834, 834, 611,
+    722, 722, 722, 722, 722, 722, 1000, 722, 667, 667, 667, 667, 278, 278, 278, 278,
+    722, 722, 778, 778, 778, 778, 778, 584, 778, 722, 722, 722, 722, 667, 667, 611,
+    556, 556, 556, 556, 556, 556, 889, 556, 556, 556, 556, 556, 278, 278, 278, 278,
+    611, 611, 611, 611, 611, 611, 611, 584, 611, 611, 611, 611, 611, 556, 611, 556,
+];
+
+static TIMES_ROMAN: [u16; 224] = [
+    250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444,
+    921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722,
+    556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500,
+    333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500,
+    500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541, 0,
+    500, 0, 333, 500, 444, 1000, 500, 500, 333, 1000, 556, 333, 889, 0, 611, 0,
+    0, 333, 333, 444, 444, 350, 500, 1000, 333, 980, 389, 333, 722, 0, 444, 722,
+    250, 333, 500, 500, 500, 500, 200, 500, 333, 760, 276, 500, 564, 333, 760, 333,
+    400, 564, 300, 300, 333, 500, 453, 250, 333, 300, 310, 500, 750, 750, 750, 444,
+    722, 722, 722, 722, 722, 722, 889, 667, 611, 611, 611, 611, 333, 333, 333, 333,
+    722, 722, 722, 722, 722, 722, 722, 564, 722, 722, 722, 722, 722, 722, 556, 500,
+    444, 444, 444, 444, 444, 444, 667, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+    500, 500, 500, 500, 500, 500, 500, 564, 500, 500, 500, 500, 500, 500, 500, 500,
+];
+
+static TIMES_BOLD: [u16; 224] = [
+    250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+    930, 722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778,
+    611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667, 333, 278, 333, 581, 500,
+    333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500,
+    556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520, 0,
+    500, 0, 333, 500, 500, 1000, 500, 500, 333, 1000, 556, 333, 1000, 0, 667, 0,
+    0, 333, 333, 500, 500, 350, 500, 1000, 333, 1000, 389, 333, 722, 0, 444, 722,
+    250, 333, 500, 500, 500, 500, 220, 500, 333, 747, 300, 500, 570, 333, 747, 333,
+    400, 570, 300, 300, 333, 556, 540, 250, 333, 300, 330, 500, 750, 750, 750, 500,
+    722, 722, 722, 722, 722, 722, 1000, 722, 667, 667, 667, 667, 389, 389, 389, 389,
+    722, 722, 778, 778, 778, 778, 778, 570, 778, 722, 722, 722, 722, 722, 611, 556,
+    500, 500, 500, 500, 500, 500, 722, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+    500, 556, 500, 500, 500, 500, 500, 570, 500, 556, 556, 556, 556, 500, 556, 500,
+];
+
+static TIMES_ITALIC: [u16; 224] = [
+    250, 333, 420, 500, 500, 833, 778, 214, 333, 333, 500, 675, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 675, 675, 675, 500,
+    920, 611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833, 667, 722,
+    611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556, 389, 278, 389, 422, 500,
+    333, 500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722, 500, 500,
+    500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389, 400, 275, 400, 541, 0,
+    500, 0, 333, 500, 556, 889, 500, 500, 333, 1000, 500, 333, 944, 0, 556, 0,
+    0, 333, 333, 556, 556, 350, 500, 889, 333, 980, 389, 333, 667, 0, 389, 556,
+    250, 389, 500, 500, 500, 500, 275, 500, 333, 760, 276, 500, 675, 333, 760, 333,
+    400, 675, 300, 300, 333, 500, 523, 250, 333, 300, 310, 500, 750, 750, 750, 500,
+    611, 611, 611, 611, 611, 611, 889, 667, 611, 611, 611, 611, 333, 333, 333, 333,
+    722, 667, 722, 722, 722, 722, 722, 675, 722, 722, 722, 722, 722, 556, 611, 500,
+    500, 500, 500, 500, 500, 500, 667, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+    500, 500, 500, 500, 500, 500, 500, 675, 500, 500, 500, 500, 500, 444, 500, 444,
+];
+
+static TIMES_BOLD_ITALIC: [u16; 224] = [
+    250, 389, 555, 500, 500, 833, 778, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+    832, 667, 667, 667, 722, 667, 667, 722, 778, 389, 500, 667, 611, 889, 722, 722,
+    611, 722, 667, 556, 611, 722, 667, 889, 667, 611, 611, 333, 278, 333, 570, 500,
+    333, 500, 500, 444, 500, 444, 333, 500, 556, 278, 278, 500, 278, 778, 556, 500,
+    500, 500, 389, 389, 278, 556, 444, 667, 500, 444, 389, 348, 220, 348, 570, 0,
+    500, 0, 333, 500, 500, 1000, 500, 500, 333, 1000, 556, 333, 944, 0, 611, 0,
+    0, 333, 333, 500, 500, 350, 500, 1000, 333, 1000, 389, 333, 722, 0, 389, 611,
+    250, 389, 500, 500, 500, 500, 220, 500, 333, 747, 266, 500, 606, 333, 747, 333,
+    400, 570, 300, 300, 333, 576, 500, 250, 333, 300, 300, 500, 750, 750, 750, 500,
+    667, 667, 667, 667, 667, 667, 944, 667, 667, 667, 667, 667, 389, 389, 389, 389,
+    722, 722, 722, 722, 722, 722, 722, 570, 722, 722, 722, 722, 722, 611, 611, 500,
+    500, 500, 500, 500, 500, 500, 722, 444, 444, 444, 444, 444, 278, 278, 278, 278,
+    500, 556, 500, 500, 500, 500, 500, 570, 500, 556, 556, 556, 556, 444, 500, 444,
+];
+
+// by code of the built-in encoding, 0x20 ..= 0xFF
+static SYMBOL: [u16; 224] = [
+    250, 333, 713, 500, 549, 833, 778, 439, 333, 333, 500, 549, 250, 549, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 549, 549, 549, 444,
+    549, 722, 667, 722, 612, 611, 763, 603, 722, 333, 631, 722, 686, 889, 722, 722,
+    768, 741, 556, 592, 611, 690, 439, 768, 645, 795, 611, 333, 863, 333, 658, 500,
+    500, 631, 549, 549, 494, 439, 521, 411, 603, 329, 603, 549, 549, 576, 521, 549,
+    549, 521, 549, 603, 439, 576, 713, 686, 493, 686, 494, 480, 200, 480, 549, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    750, 620, 247, 549, 167, 713, 500, 753, 753, 753, 753, 1042, 987, 603, 987, 603,
+    400, 549, 411, 549, 549, 713, 494, 460, 549, 549, 549, 549, 1000, 603, 1000, 658,
+    823, 686, 795, 987, 768, 768, 823, 768, 768, 713, 713, 713, 713, 713, 713, 713,
+    768, 713, 790, 790, 890, 823, 549, 250, 713, 603, 603, 1042, 987, 603, 987, 603,
+    494, 329, 790, 790, 786, 713, 384, 384, 384, 384, 384, 384, 494, 494, 494, 494,
+    0, 329, 274, 686, 686, 686, 384, 384, 384, 384, 384, 384, 494, 494, 494, 0,
+];
+
+static ZAPF_DINGBATS: [u16; 224] = [
+    278, 974, 961, 974, 980, 719, 789, 790, 791, 690, 960, 939, 549, 855, 911, 933,
+    911, 945, 974, 755, 846, 762, 761, 571, 677, 763, 760, 759, 754, 494, 552, 537,
+    577, 692, 786, 788, 788, 790, 793, 794, 816, 823, 789, 841, 823, 833, 816, 831,
+    923, 744, 723, 749, 790, 792, 695, 776, 768, 792, 759, 707, 708, 682, 701, 826,
+    815, 789, 789, 707, 687, 696, 689, 786, 787, 713, 791, 785, 791, 873, 761, 762,
+    762, 759, 759, 892, 892, 788, 784, 438, 138, 277, 415, 392, 392, 668, 668, 0,
+    390, 390, 317, 317, 276, 276, 509, 509, 410, 410, 234, 234, 334, 334, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 732, 544, 544, 910, 667, 760, 760, 776, 595, 694, 626, 788, 788, 788, 788,
+    788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788,
+    788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788, 788,
+    788, 788, 788, 788, 894, 838, 1016, 458, 748, 924, 748, 918, 927, 928, 928, 834,
+    873, 828, 924, 924, 917, 930, 931, 463, 883, 836, 836, 867, 867, 696, 696, 874,
+    0, 874, 760, 946, 771, 865, 771, 771, 967, 771, 831, 873, 927, 970, 918, 0,
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::Base14;
+
+    #[test]
+    fn winansi_and_builtin_widths() {
+        std::assert_eq!(Base14::Helvetica.width('\u{2013}'), Some(556.));
+        std::assert_eq!(Base14::TimesRoman.width('é'), Some(444.));
+        std::assert_eq!(Base14::TimesBold.width('\u{201C}'), Some(500.));
+        std::assert_eq!(Base14::Helvetica.width('\u{3042}'), None);
+        // alpha
+        std::assert_eq!(Base14::Symbol.code_width(0x61), Some(631.));
+        std::assert_eq!(Base14::ZapfDingbats.code_width(0x21), Some(974.));
+        std::assert_eq!(Base14::ZapfDingbats.builtin_unicode(0x21), Some('\u{2701}'));
+        std::assert_eq!(Base14::ZapfDingbats.builtin_unicode(0xD5), Some('\u{2192}'));
+    }
+}
@@ -0,0 +1,128 @@
+//! Measuring what a page draws, without rendering it.
+use std::sync::Arc;
+use pathfinder_content::{fill::FillRule, outline::Outline};
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F, vector::Vector2F};
+use pdf::object::{Page, Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef};
+use pdf::font::Font as PdfFont;
+use pdf::error::PdfError;
+
+use crate::backend::{self, FillMode, SoftMask};
+use crate::tracer::TraceCache;
+use crate::{Backend, BBox, DrawMode, FontEntry, Shading, TextSpan, render_page};
+
+#[derive(Copy, Clone, Debug)]
+pub struct BoundsClipId(usize);
+
+/// A backend that only collects the union of the bounds of everything drawn, in device space.
+pub struct BoundsBackend<'a> {
+    cache: &'a TraceCache,
+    bbox: BBox,
+    /// bounds of each clip path, intersected with its parents
+    clips: Vec<Option<RectF>>,
+    view_box: RectF,
+    /// inside a soft mask group, which is not drawn itself
+    mask: bool,
+}
+impl<'a> BoundsBackend<'a> {
+    pub fn new(cache: &'a TraceCache) -> Self {
+        BoundsBackend {
+            cache,
+            bbox: BBox::empty(),
+            clips: vec![],
+            view_box: RectF::default(),
+            mask: false,
+        }
+    }
+    pub fn finish(self) -> BBox {
+        self.bbox
+    }
+    fn add(&mut self, r: RectF, clip: Option<BoundsClipId>) {
+        if self.mask {
+            return;
+        }
+        let r = match clip {
+            Some(BoundsClipId(id)) => match self.clips[id].and_then(|c| c.intersection(r)) {
+                Some(r) => r,
+                None => return
+            }
+            None => r
+        };
+        self.bbox.add(r);
+    }
+}
+
+/// The bounds of the unit square mapped by `transform`
+fn unit_square(transform: Transform2F) -> RectF {
+    transform * RectF::new(Vector2F::zero(), Vector2F::new(1.0, 1.0))
+}
+
+impl<'a> Backend for BoundsBackend<'a> {
+    type ClipPathId = BoundsClipId;
+
+    fn create_clip_path(&mut self, path: Outline, _fill_rule: FillRule, parent: Option<BoundsClipId>) -> BoundsClipId {
+        let mut r = Some(path.bounds());
+        if let Some(BoundsClipId(p)) = parent {
+            r = r.and_then(|r| self.clips[p].and_then(|p| p.intersection(r)));
+        }
+        self.clips.push(r);
+        BoundsClipId(self.clips.len() - 1)
+    }
+    fn draw(&mut self, outline: &Outline, mode: &DrawMode, _fill_rule: FillRule, transform: Transform2F, clip: Option<BoundsClipId>) {
+        if outline.contours().is_empty() {
+            return;
+        }
+        let mut r = outline.clone().transformed(&transform).bounds();
+        if let DrawMode::Stroke { stroke_mode, .. } | DrawMode::FillStroke { stroke_mode, .. } = mode {
+            // half the line width on each side, in device space
+            let scale = transform.m11().hypot(transform.m21()).max(transform.m12().hypot(transform.m22()));
+            let w = 0.5 * stroke_mode.style.line_width * scale;
+            r = r.dilate(Vector2F::splat(w));
+        }
+        self.add(r, clip);
+    }
+    fn set_view_box(&mut self, r: RectF) {
+        self.view_box = r;
+    }
+    fn draw_image(&mut self, _xref: Ref<XObject>, _im: &ImageXObject, _resources: &Resources, transform: Transform2F, _mode: backend::BlendMode, _alpha: f32, clip: Option<BoundsClipId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
+        self.add(unit_square(transform), clip);
+        Ok(())
+    }
+    fn draw_image_mask(&mut self, _xref: Ref<XObject>, _im: &ImageXObject, _fill: &FillMode, _resources: &Resources, transform: Transform2F, clip: Option<BoundsClipId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
+        self.add(unit_square(transform), clip);
+        Ok(())
+    }
+    fn draw_inline_image(&mut self, _im: &Arc<ImageXObject>, _resources: &Resources, transform: Transform2F, _mode: backend::BlendMode, _alpha: f32, clip: Option<BoundsClipId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
+        self.add(unit_square(transform), clip);
+        Ok(())
+    }
+    fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
+        self.cache.get_font(font_ref, resolve)
+    }
+    // glyphs are measured by draw_glyph
+    fn add_text(&mut self, _span: TextSpan, _clip: Option<BoundsClipId>) {}
+
+    fn draw_shading(&mut self, shading: &Shading, transform: Transform2F, _mode: backend::BlendMode, _alpha: f32, clip: Option<BoundsClipId>) {
+        // shadings fill the clip region, which is at most the page
+        let region = shading.region(transform, self.view_box);
+        if !region.contours().is_empty() {
+            self.add(region.bounds(), clip);
+        }
+    }
+    // the masked content is measured without the mask
+    fn begin_smask(&mut self, _mask: &SoftMask) {
+        self.mask = true;
+    }
+    fn end_smask(&mut self, _parent: Option<BoundsClipId>) -> Option<BoundsClipId> {
+        self.mask = false;
+        None
+    }
+}
+
+/// The tight bounding box of everything the page draws, in PDF user space of the page.
+///
+/// `None` if the page is blank.
+pub fn content_bounds(page: &Page, resolve: &impl Resolve, cache: &TraceCache) -> Result<Option<RectF>, PdfError> {
+    let mut backend = BoundsBackend::new(cache);
+    let root = render_page(&mut backend, resolve, page, Transform2F::default())?;
+    Ok(backend.finish().rect().map(|r| root.inverse() * r))
+}
@@ -1,27 +1,41 @@
 use std::path::{PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::hash::Hash;
 
 use pdf::object::*;
-use pdf::primitive::Name;
 use pdf::font::{Font as PdfFont};
-use pdf::error::{Result};
+use pdf::error::{Result, PdfError};
 
 use pathfinder_geometry::{
     vector::{Vector2I},
 };
+use pathfinder_color::ColorU;
 use pathfinder_content::{
     pattern::{Image},
 };
 
-use crate::BlendMode;
+use crate::{BlendMode, IccTransform, CmykConversion};
 
 use super::{fontentry::FontEntry};
-use super::image::load_image;
-use super::font::{load_font, StandardCache};
-use globalcache::{sync::SyncCache, ValueSize};
+use super::image::{load_image, load_stencil};
+use super::font::{FontCache, FontClass, FontDatabase, StandardCache};
+use globalcache::ValueSize;
+
+/// Memory of decoded images that `Cache` keeps by default, in bytes
+pub const DEFAULT_IMAGE_MEMORY: usize = 256 << 20;
 
 #[derive(Clone)]
 pub struct ImageResult(pub Arc<Result<Image>>);
+impl ImageResult {
+    /// The image, or a description of the error it failed to load with
+    pub fn get(&self) -> Result<&Image> {
+        match *self.0 {
+            Ok(ref image) => Ok(image),
+            Err(ref e) => Err(PdfError::Other { msg: format!("can't load image: {:?}", e) }),
+        }
+    }
+}
 impl ValueSize for ImageResult {
     fn size(&self) -> usize {
         match *self.0 {
@@ -31,12 +45,87 @@ impl ValueSize for ImageResult {
     }
 }
 
+/// Decoded images, with the least recently used ones dropped when they take more than `limit` bytes.
+struct ImageCache<K> {
+    inner: Mutex<ImageCacheInner<K>>,
+}
+struct ImageCacheInner<K> {
+    // image and when it was last used
+    entries: HashMap<K, (ImageResult, u64)>,
+    size: usize,
+    limit: usize,
+    clock: u64,
+}
+impl<K: Hash + Eq + Clone> ImageCache<K> {
+    fn new(limit: usize) -> Arc<Self> {
+        Arc::new(ImageCache {
+            inner: Mutex::new(ImageCacheInner { entries: HashMap::new(), size: 0, limit, clock: 0 })
+        })
+    }
+    fn get(&self, key: K, load: impl FnOnce() -> ImageResult) -> ImageResult {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let now = inner.clock;
+            if let Some((image, used)) = inner.entries.get_mut(&key) {
+                *used = now;
+                return image.clone();
+            }
+        }
+
+        // decode outside of the lock, so other pages can use the cache in the meantime
+        let image = load();
+        let mut inner = self.inner.lock().unwrap();
+        let now = inner.clock;
+        if let Some(&(ref image, _)) = inner.entries.get(&key) {
+            return image.clone();
+        }
+        inner.size += image.size();
+        inner.entries.insert(key.clone(), (image.clone(), now));
+        inner.evict(Some(&key));
+        image
+    }
+    fn limit(&self) -> usize {
+        self.inner.lock().unwrap().limit
+    }
+    fn set_limit(&self, limit: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.limit = limit;
+        inner.evict(None);
+    }
+    fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.size = 0;
+    }
+}
+impl<K: Hash + Eq + Clone> ImageCacheInner<K> {
+    /// drop the least recently used images until the rest fits into the limit, except `keep`
+    fn evict(&mut self, keep: Option<&K>) {
+        while self.size > self.limit {
+            let oldest = self.entries.iter()
+                .filter(|&(k, _)| Some(k) != keep)
+                .min_by_key(|&(_, &(_, used))| used)
+                .map(|(k, _)| k.clone());
+            match oldest.and_then(|k| self.entries.remove(&k)) {
+                Some((image, _)) => self.size -= image.size(),
+                None => break
+            }
+        }
+    }
+}
+
+/// Clones share the loaded fonts and images, so one cache can be used from several threads.
+#[derive(Clone)]
 pub struct Cache {
-    // shared mapping of fontname -> font
-    fonts: Arc<SyncCache<usize, Option<Arc<FontEntry>>>>,
-    images: Arc<SyncCache<(Ref<XObject>, BlendMode), ImageResult>>,
-    std: StandardCache,
-    missing_fonts: Vec<Name>,
+    // shared mapping of font object -> font entry
+    fonts: Arc<FontCache>,
+    /// by the rendering intent as u8, see `get_image`
+    images: Arc<ImageCache<(Ref<XObject>, BlendMode, u8)>>,
+    // image masks, by the color they are painted with
+    stencils: Arc<ImageCache<(Ref<XObject>, [u8; 4])>>,
+    icc: Option<Arc<dyn IccTransform>>,
+    cmyk: CmykConversion,
 }
 impl Cache {
     pub fn new() -> Cache {
@@ -50,39 +139,97 @@ impl Cache {
         if !standard_fonts.is_dir() {
             panic!("STANDARD_FONTS (or fonts/) is not directory.");
         }
+        Cache::with_font_database(Arc::new(FontDatabase::new(standard_fonts)))
+    }
+    /// A cache that loads standard and substitute fonts from `db`.
+    ///
+    /// Caches that share a database only parse each of its fonts once, even when they are used by different threads.
+    pub fn with_font_database(db: Arc<FontDatabase>) -> Cache {
         Cache {
-            fonts: SyncCache::new(),
-            images: SyncCache::new(),
-            std: StandardCache::new(standard_fonts),
-            missing_fonts: Vec::new(),
+            fonts: Arc::new(FontCache::new(StandardCache::with_database(db))),
+            images: ImageCache::new(DEFAULT_IMAGE_MEMORY),
+            stencils: ImageCache::new(DEFAULT_IMAGE_MEMORY),
+            icc: None,
+            cmyk: CmykConversion::default(),
         }
     }
     pub fn get_font(&mut self, pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, > {
-        let mut error = None;
-        let val = self.fonts.get(&**pdf_font as *const PdfFont as usize, |_| 
-            match load_font(pdf_font, resolve, &mut self.std) {
-                Ok(Some(f)) => Some(Arc::new(f)),
-                Ok(None) => {
-                    if let Some(ref name) = pdf_font.name {
-                        self.missing_fonts.push(name.clone());
-                    }
-                    None
-                },
-                Err(e) => {
-                    error = Some(e);
-                    None
-                }
-            }
-        );
-        match error {
-            None => Ok(val),
-            Some(e) => Err(e)
-        }
+        self.fonts.get(pdf_font, resolve)
+    }
+    /// Fonts tried in order when a non-embedded font of the given class can't be found.
+    ///
+    /// Applies to all clones of the cache. Fonts that were already loaded are loaded again.
+    pub fn set_fallback_fonts(&self, class: FontClass, names: Vec<String>) {
+        self.fonts.set_fallback_fonts(class, names);
+        self.fonts.clear();
+    }
+    /// Embolden and slant substitute fonts that stand in for missing bold and italic fonts. Off by default.
+    ///
+    /// Applies to all clones of the cache. Fonts that were already loaded are loaded again.
+    pub fn set_synthesize_styles(&self, synthesize: bool) {
+        self.fonts.synthesize_styles(synthesize);
+        self.fonts.clear();
+    }
+    /// Forget all loaded fonts.
+    pub fn clear_fonts(&mut self) {
+        self.fonts.clear();
     }
 
-    pub fn get_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> ImageResult {
-        self.images.get((xobject_ref, mode), |_|
-            ImageResult(Arc::new(load_image(im, resources, resolve, mode).map(|image|
+    /// Convert ICCBased colors with `icc` instead of approximating them by their alternate color space.
+    ///
+    /// Images that were already loaded are discarded.
+    pub fn set_icc_transform(&mut self, icc: impl IccTransform + 'static) {
+        self.icc = Some(Arc::new(icc));
+        self.images = ImageCache::new(self.images.limit());
+    }
+    pub fn icc_transform(&self) -> Option<Arc<dyn IccTransform>> {
+        self.icc.clone()
+    }
+    /// Convert DeviceCMYK colors and images with `cmyk`.
+    ///
+    /// Images that were already loaded are discarded.
+    pub fn set_cmyk_conversion(&mut self, cmyk: CmykConversion) {
+        self.cmyk = cmyk;
+        self.images = ImageCache::new(self.images.limit());
+    }
+    pub fn cmyk_conversion(&self) -> CmykConversion {
+        self.cmyk
+    }
+    /// Keep at most `bytes` of decoded images, and as much of image masks.
+    /// Images drawn again after they were dropped are decoded again.
+    ///
+    /// Applies to all clones of the cache.
+    pub fn set_image_memory_limit(&self, bytes: usize) {
+        self.images.set_limit(bytes);
+        self.stencils.set_limit(bytes);
+    }
+    /// Forget all decoded images.
+    pub fn clear_images(&self) {
+        self.images.clear();
+        self.stencils.clear();
+    }
+
+    /// `intent` only matters for ICC based images, but the image is cached separately for each intent it is drawn with.
+    pub fn get_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, intent: RenderingIntent) -> ImageResult {
+        let (icc, cmyk) = (self.icc.as_deref(), self.cmyk);
+        self.images.get((xobject_ref, mode, intent as u8), ||
+            ImageResult(Arc::new(load_image(im, resources, resolve, mode, icc, cmyk, intent).map(|image|
+                Image::new(Vector2I::new(im.width as i32, im.height as i32), Arc::new(image.into_data().into()))
+            )))
+        )
+    }
+    /// Inline images have no reference to cache them by, so they are decoded every time.
+    pub fn inline_image(&self, im: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, intent: RenderingIntent) -> Result<Image> {
+        let image = load_image(im, resources, resolve, mode, self.icc.as_deref(), self.cmyk, intent)?;
+        Ok(Image::new(Vector2I::new(im.width as i32, im.height as i32), Arc::new(image.into_data().into())))
+    }
+    pub fn inline_stencil(&self, im: &ImageXObject, resolve: &impl Resolve, color: ColorU) -> Result<Image> {
+        let image = load_stencil(im, resolve, color)?;
+        Ok(Image::new(Vector2I::new(im.width as i32, im.height as i32), Arc::new(image.into_data().into())))
+    }
+    pub fn get_stencil(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resolve: &impl Resolve, color: ColorU) -> ImageResult {
+        self.stencils.get((xobject_ref, [color.r, color.g, color.b, color.a]), ||
+            ImageResult(Arc::new(load_stencil(im, resolve, color).map(|image|
                 Image::new(Vector2I::new(im.width as i32, im.height as i32), Arc::new(image.into_data().into()))
             )))
         )
@@ -90,8 +237,12 @@ impl Cache {
 }
 impl Drop for Cache {
     fn drop(&mut self) {
+        // only report once, for the last clone
+        if Arc::strong_count(&self.fonts) > 1 {
+            return;
+        }
         info!("missing fonts:");
-        for name in self.missing_fonts.iter() {
+        for name in self.fonts.missing_fonts().iter() {
             info!("{}", name.as_str());
         }
     }
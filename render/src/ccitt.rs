@@ -0,0 +1,286 @@
+//! CCITT Group 3 and Group 4 fax decoding (`/CCITTFaxDecode`).
+use std::collections::HashMap;
+use once_cell::sync::Lazy;
+
+/// Decode parameters of a CCITTFaxDecode filter
+#[derive(Debug, Clone)]
+pub struct Params {
+    /// < 0: pure 2D (Group 4), 0: pure 1D (Group 3), > 0: mixed 1D and 2D (Group 3)
+    pub k: i32,
+    pub columns: u32,
+    /// 0 if unknown
+    pub rows: u32,
+    pub black_is_1: bool,
+    pub encoded_byte_align: bool,
+}
+
+/// Decode into a bitmap of 1 bit per pixel, with rows padded to whole bytes.
+///
+/// Black pixels are 0, unless `black_is_1` is set. Decoding stops at the first damaged row,
+/// the remaining rows are left white.
+pub fn decode(data: &[u8], params: &Params) -> Vec<u8> {
+    let columns = params.columns as i32;
+    let stride = (params.columns as usize + 7) / 8;
+    let mut reader = BitReader { data, pos: 0 };
+    let mut reference: Vec<i32> = vec![];
+    let mut out = Vec::with_capacity(stride * params.rows as usize);
+    let mut rows = 0;
+
+    while params.rows == 0 || rows < params.rows {
+        if reader.at_end() {
+            break;
+        }
+        let two_d = if params.k < 0 {
+            if params.encoded_byte_align {
+                reader.align();
+            }
+            true
+        } else {
+            if reader.skip_eol() {
+                // two EOLs in a row mark the end of the data
+                if reader.skip_eol() {
+                    break;
+                }
+            } else if params.encoded_byte_align {
+                reader.align();
+            }
+            // in mixed mode the tag bit selects 1D (1) or 2D (0)
+            params.k > 0 && reader.bit() == Some(false)
+        };
+
+        let line = match two_d {
+            true => reader.line_2d(&reference, columns),
+            false => reader.line_1d(columns),
+        };
+        let line = match line {
+            Some(line) => line,
+            None => {
+                if params.rows != 0 {
+                    warn!("CCITT data ends after {} of {} rows", rows, params.rows);
+                }
+                break;
+            }
+        };
+        write_row(&mut out, &line, columns, stride, params.black_is_1);
+        reference = line;
+        rows += 1;
+    }
+
+    // fill missing rows with white
+    let white = if params.black_is_1 { 0x00 } else { 0xff };
+    out.resize(stride * params.rows.max(rows) as usize, white);
+    out
+}
+
+/// `changes` holds the positions where the color changes, starting with white to black.
+fn write_row(out: &mut Vec<u8>, changes: &[i32], columns: i32, stride: usize, black_is_1: bool) {
+    let start = out.len();
+    out.resize(start + stride, if black_is_1 { 0x00 } else { 0xff });
+    let row = &mut out[start ..];
+
+    for span in changes.chunks(2) {
+        let from = span[0].max(0);
+        let to = span.get(1).cloned().unwrap_or(columns).min(columns);
+        for x in from .. to {
+            let mask = 0x80u8 >> (x % 8);
+            if black_is_1 {
+                row[x as usize / 8] |= mask;
+            } else {
+                row[x as usize / 8] &= !mask;
+            }
+        }
+    }
+}
+
+enum Mode {
+    Pass,
+    Horizontal,
+    Vertical(i32),
+    Eol,
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// in bits
+    pos: usize,
+}
+impl<'a> BitReader<'a> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len() * 8
+    }
+    fn get(&self, pos: usize) -> Option<bool> {
+        self.data.get(pos / 8).map(|&b| b & (0x80 >> (pos % 8)) != 0)
+    }
+    fn bit(&mut self) -> Option<bool> {
+        let bit = self.get(self.pos)?;
+        self.pos += 1;
+        Some(bit)
+    }
+    /// the next `n` bits, padded with zeros past the end of the data
+    fn peek(&self, n: usize) -> u16 {
+        (0 .. n).fold(0, |acc, i| acc << 1 | self.get(self.pos + i).unwrap_or(false) as u16)
+    }
+    fn align(&mut self) {
+        self.pos = (self.pos + 7) / 8 * 8;
+    }
+    /// Skip an EOL, with any fill bits before it.
+    fn skip_eol(&mut self) -> bool {
+        let mut zeros = 0;
+        while self.get(self.pos + zeros) == Some(false) {
+            zeros += 1;
+        }
+        if zeros >= 11 && self.get(self.pos + zeros) == Some(true) {
+            self.pos += zeros + 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn code(&mut self, table: &HashMap<(usize, u16), u16>) -> Option<u16> {
+        if self.at_end() {
+            return None;
+        }
+        for len in 2 ..= 13 {
+            if let Some(&run) = table.get(&(len, self.peek(len))) {
+                self.pos += len;
+                return Some(run);
+            }
+        }
+        None
+    }
+    /// A run length: any number of make-up codes followed by a terminating code.
+    fn run(&mut self, white: bool) -> Option<i32> {
+        let table = if white { &*WHITE } else { &*BLACK };
+        let mut total = 0;
+        loop {
+            let run = self.code(table)?;
+            total += run as i32;
+            if run < 64 {
+                return Some(total);
+            }
+        }
+    }
+    fn mode(&mut self) -> Option<Mode> {
+        let bits = self.peek(7);
+        let (len, mode) = match bits {
+            _ if bits >> 6 == 0b1 => (1, Mode::Vertical(0)),
+            _ if bits >> 4 == 0b011 => (3, Mode::Vertical(1)),
+            _ if bits >> 4 == 0b010 => (3, Mode::Vertical(-1)),
+            _ if bits >> 4 == 0b001 => (3, Mode::Horizontal),
+            _ if bits >> 3 == 0b0001 => (4, Mode::Pass),
+            _ if bits >> 1 == 0b000011 => (6, Mode::Vertical(2)),
+            _ if bits >> 1 == 0b000010 => (6, Mode::Vertical(-2)),
+            0b0000011 => (7, Mode::Vertical(3)),
+            0b0000010 => (7, Mode::Vertical(-3)),
+            _ if self.peek(12) == 1 => (12, Mode::Eol),
+            _ => return None
+        };
+        self.pos += len;
+        Some(mode)
+    }
+
+    fn line_1d(&mut self, columns: i32) -> Option<Vec<i32>> {
+        let mut line = vec![];
+        let mut pos = 0;
+        let mut white = true;
+        while pos < columns {
+            pos += self.run(white)?;
+            line.push(pos.min(columns));
+            white = !white;
+        }
+        Some(line)
+    }
+
+    fn line_2d(&mut self, reference: &[i32], columns: i32) -> Option<Vec<i32>> {
+        let mut line = vec![];
+        let mut a0 = -1;
+        let mut white = true;
+        let mut i = 0;
+        while a0 < columns {
+            // b1: the first change on the reference line right of a0 to the opposite color of a0
+            // even changes go from white to black
+            i = i.min(reference.len());
+            while i > 0 && reference[i - 1] > a0 {
+                i -= 1;
+            }
+            while i < reference.len() && (reference[i] <= a0 || (i % 2 == 0) != white) {
+                i += 1;
+            }
+            let b1 = reference.get(i).cloned().unwrap_or(columns);
+            let b2 = reference.get(i + 1).cloned().unwrap_or(columns);
+
+            match self.mode()? {
+                Mode::Pass => a0 = b2,
+                Mode::Horizontal => {
+                    let start = a0.max(0);
+                    let a1 = start + self.run(white)?;
+                    let a2 = a1 + self.run(!white)?;
+                    line.push(a1.min(columns));
+                    line.push(a2.min(columns));
+                    a0 = a2;
+                }
+                Mode::Vertical(d) => {
+                    let a1 = b1 + d;
+                    if a1 < 0 || a1 > columns {
+                        return None;
+                    }
+                    line.push(a1);
+                    a0 = a1;
+                    white = !white;
+                }
+                Mode::Eol => return None,
+            }
+        }
+        Some(line)
+    }
+}
+
+fn table(codes: &[&[(u16, &str)]]) -> HashMap<(usize, u16), u16> {
+    codes.iter().flat_map(|t| t.iter()).map(|&(run, code)| {
+        ((code.len(), u16::from_str_radix(code, 2).unwrap()), run)
+    }).collect()
+}
+
+static WHITE: Lazy<HashMap<(usize, u16), u16>> = Lazy::new(|| table(&[WHITE_CODES, WHITE_MAKEUP, EXTENDED_MAKEUP]));
+static BLACK: Lazy<HashMap<(usize, u16), u16>> = Lazy::new(|| table(&[BLACK_CODES, BLACK_MAKEUP, EXTENDED_MAKEUP]));
+
+const WHITE_CODES: &[(u16, &str)] = &[
+    (0, "00110101"), (1, "000111"), (2, "0111"), (3, "1000"), (4, "1011"), (5, "1100"), (6, "1110"), (7, "1111"),
+    (8, "10011"), (9, "10100"), (10, "00111"), (11, "01000"), (12, "001000"), (13, "000011"), (14, "110100"), (15, "110101"),
+    (16, "101010"), (17, "101011"), (18, "0100111"), (19, "0001100"), (20, "0001000"), (21, "0010111"), (22, "0000011"), (23, "0000100"),
+    (24, "0101000"), (25, "0101011"), (26, "0010011"), (27, "0100100"), (28, "0011000"), (29, "00000010"), (30, "00000011"), (31, "00011010"),
+    (32, "00011011"), (33, "00010010"), (34, "00010011"), (35, "00010100"), (36, "00010101"), (37, "00010110"), (38, "00010111"), (39, "00101000"),
+    (40, "00101001"), (41, "00101010"), (42, "00101011"), (43, "00101100"), (44, "00101101"), (45, "00000100"), (46, "00000101"), (47, "00001010"),
+    (48, "00001011"), (49, "01010010"), (50, "01010011"), (51, "01010100"), (52, "01010101"), (53, "00100100"), (54, "00100101"), (55, "01011000"),
+    (56, "01011001"), (57, "01011010"), (58, "01011011"), (59, "01001010"), (60, "01001011"), (61, "00110010"), (62, "00110011"), (63, "00110100"),
+];
+const WHITE_MAKEUP: &[(u16, &str)] = &[
+    (64, "11011"), (128, "10010"), (192, "010111"), (256, "0110111"), (320, "00110110"), (384, "00110111"), (448, "01100100"),
+    (512, "01100101"), (576, "01101000"), (640, "01100111"), (704, "011001100"), (768, "011001101"), (832, "011010010"),
+    (896, "011010011"), (960, "011010100"), (1024, "011010101"), (1088, "011010110"), (1152, "011010111"), (1216, "011011000"),
+    (1280, "011011001"), (1344, "011011010"), (1408, "011011011"), (1472, "010011000"), (1536, "010011001"), (1600, "010011010"),
+    (1664, "011000"), (1728, "010011011"),
+];
+const BLACK_CODES: &[(u16, &str)] = &[
+    (0, "0000110111"), (1, "010"), (2, "11"), (3, "10"), (4, "011"), (5, "0011"), (6, "0010"), (7, "00011"),
+    (8, "000101"), (9, "000100"), (10, "0000100"), (11, "0000101"), (12, "0000111"), (13, "00000100"), (14, "00000111"), (15, "000011000"),
+    (16, "0000010111"), (17, "0000011000"), (18, "0000001000"), (19, "00001100111"), (20, "00001101000"), (21, "00001101100"), (22, "00000110111"), (23, "00000101000"),
+    (24, "00000010111"), (25, "00000011000"), (26, "000011001010"), (27, "000011001011"), (28, "000011001100"), (29, "000011001101"), (30, "000001101000"), (31, "000001101001"),
+    (32, "000001101010"), (33, "000001101011"), (34, "000011010010"), (35, "000011010011"), (36, "000011010100"), (37, "000011010101"), (38, "000011010110"), (39, "000011010111"),
+    (40, "000001101100"), (41, "000001101101"), (42, "000011011010"), (43, "000011011011"), (44, "000001010100"), (45, "000001010101"), (46, "000001010110"), (47, "000001010111"),
+    (48, "000001100100"), (49, "000001100101"), (50, "000001010010"), (51, "000001010011"), (52, "000000100100"), (53, "000000110111"), (54, "000000111000"), (55, "000000100111"),
+    (56, "000000101000"), (57, "000001011000"), (58, "000001011001"), (59, "000000101011"), (60, "000000101100"), (61, "000001011010"), (62, "000001100110"), (63, "000001100111"),
+];
+const BLACK_MAKEUP: &[(u16, &str)] = &[
+    (64, "0000001111"), (128, "000011001000"), (192, "000011001001"), (256, "000001011011"), (320, "000000110011"), (384, "000000110100"),
+    (448, "000000110101"), (512, "0000001101100"), (576, "0000001101101"), (640, "0000001001010"), (704, "0000001001011"), (768, "0000001001100"),
+    (832, "0000001001101"), (896, "0000001110010"), (960, "0000001110011"), (1024, "0000001110100"), (1088, "0000001110101"), (1152, "0000001110110"),
+    (1216, "0000001110111"), (1280, "0000001010010"), (1344, "0000001010011"), (1408, "0000001010100"), (1472, "0000001010101"), (1536, "0000001011010"),
+    (1600, "0000001011011"), (1664, "0000001100100"), (1728, "0000001100101"),
+];
+const EXTENDED_MAKEUP: &[(u16, &str)] = &[
+    (1792, "00000001000"), (1856, "00000001100"), (1920, "00000001101"), (1984, "000000010010"), (2048, "000000010011"), (2112, "000000010100"),
+    (2176, "000000010101"), (2240, "000000010110"), (2304, "000000010111"), (2368, "000000011100"), (2432, "000000011101"), (2496, "000000011110"),
+    (2560, "000000011111"),
+];
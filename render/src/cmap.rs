@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use pdf::error::{PdfError, Result};
+
+/// A CMap that maps character codes to CIDs, as embedded in the /Encoding of Type0 fonts.
+#[derive(Debug, Default)]
+pub struct CMap {
+    codespace: Vec<CodespaceRange>,
+    ranges: Vec<CidRange>,
+    chars: HashMap<u32, u32>,
+    /// `/WMode 1`
+    pub vertical: bool,
+}
+
+#[derive(Debug)]
+struct CodespaceRange {
+    lo: Vec<u8>,
+    hi: Vec<u8>,
+}
+impl CodespaceRange {
+    fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.lo.len() && self.lo.iter().zip(self.hi.iter()).zip(data).all(|((&lo, &hi), &b)| lo <= b && b <= hi)
+    }
+}
+
+#[derive(Debug)]
+struct CidRange {
+    lo: u32,
+    hi: u32,
+    cid: u32,
+}
+
+#[derive(Debug)]
+enum Token<'a> {
+    Hex(Vec<u8>),
+    Int(u32),
+    Name(&'a [u8]),
+    Keyword(&'a [u8]),
+}
+
+fn tokens(data: &[u8]) -> impl Iterator<Item=Token> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        loop {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= data.len() {
+                return None;
+            }
+            match data[pos] {
+                b'%' => {
+                    while pos < data.len() && data[pos] != b'\n' && data[pos] != b'\r' {
+                        pos += 1;
+                    }
+                }
+                b'<' if data.get(pos + 1) == Some(&b'<') => pos += 2,
+                b'>' if data.get(pos + 1) == Some(&b'>') => pos += 2,
+                b'<' => {
+                    let start = pos + 1;
+                    let end = data[start..].iter().position(|&b| b == b'>').map(|n| start + n).unwrap_or(data.len());
+                    pos = end + 1;
+                    let digits: Vec<u8> = data[start..end].iter().filter_map(|&b| (b as char).to_digit(16)).map(|d| d as u8).collect();
+                    let bytes = digits.chunks(2).map(|c| c[0] << 4 | c.get(1).cloned().unwrap_or(0)).collect();
+                    return Some(Token::Hex(bytes));
+                }
+                b'[' | b']' | b'{' | b'}' => pos += 1,
+                b'(' => {
+                    // strings only appear in the CIDSystemInfo, skip them
+                    while pos < data.len() && data[pos] != b')' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+                c => {
+                    let start = pos;
+                    pos += 1;
+                    while pos < data.len() && !data[pos].is_ascii_whitespace() && !b"/<>[](){}%".contains(&data[pos]) {
+                        pos += 1;
+                    }
+                    let word = &data[start..pos];
+                    if c == b'/' {
+                        return Some(Token::Name(&word[1..]));
+                    }
+                    match std::str::from_utf8(word).ok().and_then(|s| s.parse().ok()) {
+                        Some(n) => return Some(Token::Int(n)),
+                        None => return Some(Token::Keyword(word)),
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Section {
+    Codespace,
+    CidRange,
+    CidChar,
+    Ignored,
+}
+
+fn code_value(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, &b| acc << 8 | b as u32)
+}
+
+impl CMap {
+    pub fn parse(data: &[u8]) -> Result<CMap> {
+        let mut cmap = CMap::default();
+        let mut args: Vec<Token> = Vec::new();
+        let mut section = None;
+
+        for token in tokens(data) {
+            match token {
+                Token::Keyword(b"begincodespacerange") => section = Some(Section::Codespace),
+                Token::Keyword(b"begincidrange") => section = Some(Section::CidRange),
+                Token::Keyword(b"begincidchar") => section = Some(Section::CidChar),
+                Token::Keyword(b"beginnotdefrange") | Token::Keyword(b"beginnotdefchar") |
+                Token::Keyword(b"beginbfrange") | Token::Keyword(b"beginbfchar") => section = Some(Section::Ignored),
+                Token::Keyword(k) if k.starts_with(b"end") => {
+                    if k == b"endcmap" {
+                        break;
+                    }
+                    section = None;
+                    args.clear();
+                }
+                Token::Keyword(b"usecmap") => {
+                    if let Some(Token::Name(name)) = args.last() {
+                        warn!("usecmap {} is not supported", String::from_utf8_lossy(name));
+                    }
+                    args.clear();
+                }
+                Token::Keyword(b"def") => {
+                    if let [.., Token::Name(b"WMode"), Token::Int(mode)] = args[..] {
+                        cmap.vertical = mode == 1;
+                    }
+                    args.clear();
+                }
+                Token::Keyword(_) => args.clear(),
+                t => {
+                    args.push(t);
+                    match (section, &args[..]) {
+                        (Some(Section::Codespace), [Token::Hex(lo), Token::Hex(hi)]) => {
+                            if lo.len() != hi.len() || lo.len() == 0 || lo.len() > 4 {
+                                return Err(PdfError::Other { msg: format!("invalid codespace range {:?} {:?}", lo, hi) });
+                            }
+                            cmap.codespace.push(CodespaceRange { lo: lo.clone(), hi: hi.clone() });
+                            args.clear();
+                        }
+                        (Some(Section::CidRange), [Token::Hex(lo), Token::Hex(hi), Token::Int(cid)]) => {
+                            cmap.ranges.push(CidRange { lo: code_value(lo), hi: code_value(hi), cid: *cid });
+                            args.clear();
+                        }
+                        (Some(Section::CidChar), [Token::Hex(code), Token::Int(cid)]) => {
+                            cmap.chars.insert(code_value(code), *cid);
+                            args.clear();
+                        }
+                        (Some(Section::Ignored), _) => args.clear(),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if cmap.codespace.is_empty() {
+            return Err(PdfError::Other { msg: "CMap without codespace".into() });
+        }
+        Ok(cmap)
+    }
+
+    /// Split off the next character code according to the codespace ranges.
+    ///
+    /// Returns the code and the number of bytes it occupies.
+    pub fn next_code(&self, data: &[u8]) -> Option<(u32, usize)> {
+        if data.is_empty() {
+            return None;
+        }
+        for len in 1 ..= 4 {
+            if self.codespace.iter().any(|r| r.lo.len() == len && r.matches(data)) {
+                return Some((code_value(&data[..len]), len));
+            }
+        }
+        // no match. skip as many bytes as the shortest code.
+        let len = self.codespace.iter().map(|r| r.lo.len()).min().unwrap_or(1).min(data.len());
+        Some((code_value(&data[..len]), len))
+    }
+
+    pub fn cid(&self, code: u32) -> Option<u32> {
+        if let Some(&cid) = self.chars.get(&code) {
+            return Some(cid);
+        }
+        self.ranges.iter().find(|r| r.lo <= code && code <= r.hi).map(|r| r.cid + (code - r.lo))
+    }
+
+    /// All codes with their CID
+    pub fn iter(&self) -> impl Iterator<Item=(u32, u32)> + '_ {
+        self.ranges.iter().flat_map(|r| (r.lo ..= r.hi).map(move |code| (code, r.cid + (code - r.lo))))
+            .chain(self.chars.iter().map(|(&code, &cid)| (code, cid)))
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::content::{Color, Rgb, Cmyk};
+use pdf::error::{PdfError, Result};
+use crate::{Fill, BlendMode};
+
+/// number of entries in the lookup table of a Separation tint transform
+const TINT_SAMPLES: usize = 256;
+
+/// DeviceN colors remembered before the cache starts over
+const DEVICE_N_ENTRIES: usize = 4096;
+
+/// Converts colors of `/ICCBased` color spaces using the embedded profile.
+///
+/// Without one, ICC colors are converted through the alternate color space, or the device color space with the same number of components.
+pub trait IccTransform: Send + Sync {
+    /// Convert colors with `components` values each, in the range 0 ..= 1, to RGB.
+    ///
+    /// Returns `None` if the profile can't be used.
+    fn transform(&self, profile: &[u8], components: usize, input: &[f32]) -> Option<Vec<f32>>;
+
+    /// Like `transform`, for 8 bit samples.
+    fn transform_u8(&self, profile: &[u8], components: usize, input: &[u8]) -> Option<Vec<u8>> {
+        let input: Vec<f32> = input.iter().map(|&b| b as f32 * (1.0 / 255.)).collect();
+        let rgb = self.transform(profile, components, &input)?;
+        Some(rgb.iter().map(|&c| (c.max(0.0).min(1.0) * 255.) as u8).collect())
+    }
+
+    /// Like `transform`, for colors painted with the rendering intent `intent`. The default ignores the intent.
+    fn transform_with_intent(&self, profile: &[u8], components: usize, input: &[f32], intent: RenderingIntent) -> Option<Vec<f32>> {
+        let _ = intent;
+        self.transform(profile, components, input)
+    }
+
+    /// Like `transform_u8`, for images with the rendering intent `intent`. The default ignores the intent.
+    fn transform_u8_with_intent(&self, profile: &[u8], components: usize, input: &[u8], intent: RenderingIntent) -> Option<Vec<u8>> {
+        let _ = intent;
+        self.transform_u8(profile, components, input)
+    }
+}
+
+/// The rendering intent named `name`, as in /RI, /Intent and the `ri` operator.
+/// Unknown names are `RelativeColorimetric`, as the spec asks.
+pub fn rendering_intent(name: &str) -> RenderingIntent {
+    match name {
+        "AbsoluteColorimetric" => RenderingIntent::AbsoluteColorimetric,
+        "Perceptual" => RenderingIntent::Perceptual,
+        "Saturation" => RenderingIntent::Saturation,
+        "RelativeColorimetric" => RenderingIntent::RelativeColorimetric,
+        _ => {
+            debug!("unknown rendering intent {}", name);
+            RenderingIntent::RelativeColorimetric
+        }
+    }
+}
+
+/// Whether painting in `cs` leaves no marks: the Separation `None` doesn't belong to any colorant.
+pub fn paints_nothing(cs: &ColorSpace) -> bool {
+    matches!(*cs, ColorSpace::Separation(ref name, _, _) if name.as_str() == "None")
+}
+
+/// How DeviceCMYK colors are converted to RGB.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CmykConversion {
+    /// `r = 1 - min(1, c + k)`: fast, but dark and saturated colors come out flat and too light.
+    Naive,
+    /// A polynomial fit of a coated (SWOP) press profile, close to what viewers show.
+    Approximate,
+}
+impl Default for CmykConversion {
+    fn default() -> Self {
+        CmykConversion::Naive
+    }
+}
+impl CmykConversion {
+    /// Components in the range 0 ..= 1
+    pub fn to_rgb(self, (c, m, y, k): (f32, f32, f32, f32)) -> (f32, f32, f32) {
+        let clamp = |f: f32| f.max(0.0).min(1.0);
+        let (c, m, y, k) = (clamp(c), clamp(m), clamp(y), clamp(k));
+        match self {
+            CmykConversion::Naive => (
+                1.0 - clamp(c + k),
+                1.0 - clamp(m + k),
+                1.0 - clamp(y + k),
+            ),
+            CmykConversion::Approximate => {
+                let r = 255.
+                    + c * (-4.387332384609988 * c + 54.48615194189176 * m + 18.82290502165302 * y + 212.25662451639585 * k - 285.2331026137004)
+                    + m * (1.7149763477362134 * m - 5.6096736904047315 * y - 17.873870861415444 * k - 5.497006427196366)
+                    + y * (-2.5217340131683033 * y - 21.248923337353073 * k + 17.5119270841813)
+                    + k * (-21.86122147463605 * k - 189.48180835922747);
+                let g = 255.
+                    + c * (8.841041422036149 * c + 60.118027045597366 * m + 6.871425592049007 * y + 31.159100130055922 * k - 79.2970844816548)
+                    + m * (-15.310361306967817 * m + 17.575251261109482 * y + 131.35250912493976 * k - 190.9453302588951)
+                    + y * (4.444339102852739 * y + 9.8632861493405 * k - 24.86741582555878)
+                    + k * (-20.737325471181034 * k - 187.80453709719578);
+                let b = 255.
+                    + c * (0.8842522430003296 * c + 8.078677503112928 * m + 30.89978309703729 * y - 0.23883238689178934 * k - 14.183576799673286)
+                    + m * (10.49593273432072 * m + 63.02378494754052 * y + 50.606957656360734 * k - 112.23884253719248)
+                    + y * (0.03296041114873217 * y + 115.60384449646641 * k - 193.58209356861505)
+                    + k * (-22.33816807309886 * k - 180.12613974708367);
+                (clamp(r / 255.), clamp(g / 255.), clamp(b / 255.))
+            }
+        }
+    }
+    /// Like `to_rgb`, for 8 bit samples.
+    pub fn to_rgb_u8(self, [c, m, y, k]: [u8; 4]) -> [u8; 3] {
+        match self {
+            CmykConversion::Naive => [
+                255 - c.saturating_add(k),
+                255 - m.saturating_add(k),
+                255 - y.saturating_add(k),
+            ],
+            CmykConversion::Approximate => {
+                let cvt = |b: u8| b as f32 * (1.0 / 255.);
+                let (r, g, b) = self.to_rgb((cvt(c), cvt(m), cvt(y), cvt(k)));
+                [(r * 255.).round() as u8, (g * 255.).round() as u8, (b * 255.).round() as u8]
+            }
+        }
+    }
+}
+
+/// Evaluated tint transforms of Separation and DeviceN color spaces and loaded ICC profiles.
+///
+/// Color spaces are identified by address, so a cache must not outlive the resources the color spaces belong to.
+/// Color spaces that are freed earlier, like the one of a shading, have to be removed with `forget`.
+pub struct ColorCache {
+    separation: HashMap<usize, Vec<(f32, f32, f32)>>,
+    device_n: HashMap<(usize, Vec<u32>), (f32, f32, f32)>,
+    profiles: HashMap<usize, Option<Arc<[u8]>>>,
+    icc: Option<Arc<dyn IccTransform>>,
+    cmyk: CmykConversion,
+    /// passed to the `IccTransform`
+    intent: RenderingIntent,
+}
+impl ColorCache {
+    pub fn new(icc: Option<Arc<dyn IccTransform>>, cmyk: CmykConversion) -> ColorCache {
+        ColorCache {
+            separation: HashMap::new(),
+            device_n: HashMap::new(),
+            profiles: HashMap::new(),
+            icc,
+            cmyk,
+            intent: RenderingIntent::RelativeColorimetric,
+        }
+    }
+    pub fn cmyk_conversion(&self) -> CmykConversion {
+        self.cmyk
+    }
+    /// The rendering intent of the colors converted next.
+    /// Tint transforms evaluated with a different intent are dropped, their alternate space may be ICC based.
+    pub fn set_intent(&mut self, intent: RenderingIntent) {
+        if intent != self.intent {
+            self.intent = intent;
+            if self.icc.is_some() {
+                self.separation.clear();
+                self.device_n.clear();
+            }
+        }
+    }
+    /// Drop what was cached for `cs` and the color spaces it contains, before it is freed and its address reused.
+    pub fn forget(&mut self, cs: &ColorSpace) {
+        let key = cs as *const ColorSpace as usize;
+        self.separation.remove(&key);
+        self.profiles.remove(&key);
+        self.device_n.retain(|k, _| k.0 != key);
+        match *cs {
+            ColorSpace::Separation(_, ref alt, _) | ColorSpace::DeviceN { ref alt, .. } => self.forget(alt),
+            ColorSpace::Indexed(ref base, _, _) => self.forget(base),
+            ColorSpace::Icc(ref icc) => if let Some(ref alt) = icc.info.alternate {
+                self.forget(alt);
+            },
+            _ => {}
+        }
+    }
+    fn cmyk2rgb(&self, cmyk: (f32, f32, f32, f32)) -> Fill {
+        cmyk2rgb(cmyk, self.cmyk)
+    }
+    fn icc_color(&mut self, cs: &ColorSpace, values: &[f32], resolve: &impl Resolve) -> Option<(f32, f32, f32)> {
+        let icc = self.icc.clone()?;
+        let stream = match *cs {
+            ColorSpace::Icc(ref stream) => stream,
+            _ => return None
+        };
+        let profile = self.profiles.entry(cs as *const ColorSpace as usize).or_insert_with(|| {
+            match stream.data(resolve) {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    warn!("can't read ICC profile: {:?}", e);
+                    None
+                }
+            }
+        }).clone()?;
+        match icc.transform_with_intent(&profile, values.len(), values, self.intent)?[..] {
+            [r, g, b] => Some((r, g, b)),
+            _ => None
+        }
+    }
+}
+
+pub fn convert_color<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache) -> Result<Fill> {
+    match convert_color2(cs, color, resources, resolve, mode, colors) {
+        Ok(color) => Ok(color),
+        Err(e) if resolve.options().allow_error_in_option => {
+            warn!("failed to convert color: {:?}", e);
+            Ok(Fill::Solid(0.0, 0.0, 0.0))
+        }
+        Err(e) => Err(e)
+    }
+}
+/// Convert the components of a color in `cs` to RGB.
+pub fn convert_values(cs: &ColorSpace, values: &[f32], resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache) -> Result<(f32, f32, f32)> {
+    let color = Color::Other(values.iter().map(|&v| Primitive::Number(v)).collect());
+    let mut cs = cs;
+    match convert_color2(&mut cs, &color, resources, resolve, mode, colors)? {
+        Fill::Solid(r, g, b) => Ok((r, g, b)),
+        Fill::Pattern(_) => Err(PdfError::Other { msg: "Pattern is not a valid color space here".into() })
+    }
+}
+#[allow(unused_variables)]
+fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache) -> Result<Fill> {
+    match *color {
+        Color::Gray(g) => {
+            *cs = &ColorSpace::DeviceGray;
+            Ok(gray2rgb(g))
+        }
+        Color::Rgb(rgb) => {
+            *cs = &ColorSpace::DeviceRGB;
+            let Rgb { red, green, blue } = rgb;
+            Ok(Fill::Solid(red, green, blue))
+        }
+        Color::Cmyk(cmyk) => {
+            *cs = &ColorSpace::DeviceCMYK;
+            let Cmyk { cyan, magenta, yellow, key } = cmyk;
+            Ok(colors.cmyk2rgb((cyan, magenta, yellow, key)))
+        }
+        Color::Other(ref args) => {
+            if let ColorSpace::Icc(_) = **cs {
+                let values = args.iter().map(|a| a.as_number()).collect::<Result<Vec<f32>>>()?;
+                if let Some((r, g, b)) = colors.icc_color(*cs, &values, resolve) {
+                    return Ok(Fill::Solid(r, g, b));
+                }
+            }
+            let cs = match **cs {
+                ColorSpace::Icc(ref icc) => {
+                    match icc.info.alternate {
+                        Some(ref alt) => alt,
+                        None => {
+                            match args.len() {
+                                3 => &ColorSpace::DeviceRGB,
+                                4 => &ColorSpace::DeviceCMYK,
+                                _ => return Err(PdfError::Other { msg: format!("ICC profile without alternate color space") })
+                            }
+                        }
+                    }
+                }
+                ColorSpace::Named(ref name) => {
+                    resources.color_spaces.get(name).ok_or_else(|| 
+                        PdfError::Other { msg: format!("named color space {} not found", name) }
+                    )?
+                }
+                _ => &**cs
+            };
+            
+            match *cs {
+                ColorSpace::Icc(_) => return Err(PdfError::Other { msg: format!("nested ICC color space") }),
+                ColorSpace::DeviceGray => {
+                    if args.len() != 1 {
+                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
+                    }
+                    let g = args[0].as_number()?;
+                    Ok(gray2rgb(g))
+                }
+                ColorSpace::CalGray(ref dict) => {
+                    if args.len() != 1 {
+                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
+                    }
+                    let g = args[0].as_number()?;
+                    Ok(gray2rgb(calgray2gray(dict, g)))
+                }
+                ColorSpace::DeviceRGB => {
+                    if args.len() != 3 {
+                        return Err(PdfError::Other { msg: format!("expected 3 color arguments, got {:?}", args) });
+                    }
+                    let r = args[0].as_number()?;
+                    let g = args[1].as_number()?;
+                    let b = args[2].as_number()?;
+                    Ok(Fill::Solid(r, g, b))
+                }
+                ColorSpace::CalRGB(ref dict) => {
+                    if args.len() != 3 {
+                        return Err(PdfError::Other { msg: format!("expected 3 color arguments, got {:?}", args) });
+                    }
+                    let abc = [args[0].as_number()?, args[1].as_number()?, args[2].as_number()?];
+                    let (r, g, b) = calrgb2rgb(dict, abc);
+                    Ok(Fill::Solid(r, g, b))
+                }
+                ColorSpace::DeviceCMYK | ColorSpace::CalCMYK(_) => {
+                    if args.len() != 4 {
+                        return Err(PdfError::Other { msg: format!("expected 4 color arguments, got {:?}", args) });
+                    }
+                    let c = args[0].as_number()?;
+                    let m = args[1].as_number()?;
+                    let y = args[2].as_number()?;
+                    let k = args[3].as_number()?;
+                    Ok(colors.cmyk2rgb((c, m, y, k)))
+                }
+                ColorSpace::DeviceN { ref alt, ref tint, .. } => {
+                    assert_eq!(args.len(), tint.input_dim());
+                    let input = args.iter().map(|a| a.as_number()).collect::<Result<Vec<f32>>>()?;
+
+                    let key: (usize, Vec<u32>) = (cs as *const ColorSpace as usize, input.iter().map(|x| x.to_bits()).collect());
+                    if let Some(&(r, g, b)) = colors.device_n.get(&key) {
+                        return Ok(Fill::Solid(r, g, b));
+                    }
+                    let mut out = vec![0.0; tint.output_dim()];
+                    tint.apply(&input, &mut out)?;
+                    let rgb = convert_values(alt, &out, resources, resolve, mode, colors)?;
+                    if colors.device_n.len() >= DEVICE_N_ENTRIES {
+                        colors.device_n.clear();
+                    }
+                    colors.device_n.insert(key, rgb);
+
+                    let (r, g, b) = rgb;
+                    Ok(Fill::Solid(r, g, b))
+                }
+                ColorSpace::Separation(ref name, ref alt, ref f) => {
+                    debug!("Separation(name={}, alt={:?}, f={:?}", name, alt, f);
+                    if args.len() != 1 {
+                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
+                    }
+                    let x = args[0].as_number()?;
+
+                    let key = cs as *const ColorSpace as usize;
+                    if !colors.separation.contains_key(&key) {
+                        let lut = (0 .. TINT_SAMPLES).map(|i| {
+                            let mut out = vec![0.0; f.output_dim()];
+                            f.apply(&[i as f32 / (TINT_SAMPLES - 1) as f32], &mut out)?;
+                            convert_values(alt, &out, resources, resolve, mode, colors)
+                        }).collect::<Result<Vec<_>>>()?;
+                        colors.separation.insert(key, lut);
+                    }
+                    let i = (x.max(0.0).min(1.0) * (TINT_SAMPLES - 1) as f32).round() as usize;
+                    let (r, g, b) = colors.separation[&key][i];
+                    Ok(Fill::Solid(r, g, b))
+                }
+                ColorSpace::Indexed(ref base, hival, ref lut) => {
+                    if args.len() != 1 {
+                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
+                    }
+                    // indices above hival are clamped
+                    let i = (args[0].as_integer()?.max(0) as usize).min(hival as usize);
+                    let ranges = lookup_ranges(base, resources, resolve)?;
+                    let (r, g, b) = convert_values(base, &lookup_values(&ranges, &lut[..], i), resources, resolve, mode, colors)?;
+                    Ok(Fill::Solid(r, g, b))
+                }
+                ColorSpace::Pattern => {
+                    // uncolored patterns have the color components before the name
+                    let name = try_opt!(args.last()).as_name()?;
+                    if let Some(&pat) = resources.pattern.get(name) {
+                        Ok(Fill::Pattern(pat))
+                    } else {
+                        unimplemented!("Pattern {} not found", name)
+                    }
+                }
+                ColorSpace::Other(_) if pattern_base(cs).is_some() => {
+                    let name = try_opt!(args.last()).as_name()?;
+                    match resources.pattern.get(name) {
+                        Some(&pat) => Ok(Fill::Pattern(pat)),
+                        None => unimplemented!("Pattern {} not found", name)
+                    }
+                }
+                ColorSpace::Other(ref p) => match p[..] {
+                    [Primitive::Name(ref name), ref dict] if name.as_str() == "Lab" => {
+                        if args.len() != 3 {
+                            return Err(PdfError::Other { msg: format!("expected 3 color arguments, got {:?}", args) });
+                        }
+                        let dict = dict.clone().resolve(resolve)?.into_dictionary()?;
+                        let lab = [args[0].as_number()?, args[1].as_number()?, args[2].as_number()?];
+                        let (r, g, b) = lab2rgb(&dict, lab);
+                        Ok(Fill::Solid(r, g, b))
+                    }
+                    _ => unimplemented!("Other Color space {:?}", p)
+                },
+                ColorSpace::Named(ref p) => unimplemented!("nested Named {:?}", p),
+            }
+        }
+    }
+}
+
+/// The RGB colors of an Indexed color space with the base `base`, for the indices 0 ..= hival
+pub fn indexed_palette(base: &ColorSpace, hival: u8, lookup: &[u8], resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache) -> Result<Vec<(f32, f32, f32)>> {
+    let ranges = lookup_ranges(base, resources, resolve)?;
+    let entries = hival as usize + 1;
+    if lookup.len() < entries * ranges.len() {
+        warn!("lookup table has {} bytes, expected {}", lookup.len(), entries * ranges.len());
+    }
+    (0 .. entries).map(|i| convert_values(base, &lookup_values(&ranges, lookup, i), resources, resolve, mode, colors)).collect()
+}
+
+/// The components of entry `index` of a lookup table, with the bytes scaled to `ranges`. Missing entries are zero.
+fn lookup_values(ranges: &[(f32, f32)], lookup: &[u8], index: usize) -> Vec<f32> {
+    let n = ranges.len();
+    ranges.iter().enumerate().map(|(j, &(min, max))| {
+        let b = lookup.get(index * n + j).cloned().unwrap_or(0);
+        min + b as f32 * (max - min) / 255.
+    }).collect()
+}
+
+/// The range of each component of `cs`, which the bytes of an Indexed lookup table are scaled to:
+/// 0 ..= 100 for L*, /Range for a* and b*, 0 ..= 1 otherwise.
+fn lookup_ranges(cs: &ColorSpace, resources: &Resources, resolve: &impl Resolve) -> Result<Vec<(f32, f32)>> {
+    let unit = |n: usize| vec![(0.0, 1.0); n];
+    Ok(match *cs {
+        ColorSpace::DeviceGray | ColorSpace::CalGray(_) | ColorSpace::Separation(..) => unit(1),
+        ColorSpace::DeviceRGB | ColorSpace::CalRGB(_) => unit(3),
+        ColorSpace::DeviceCMYK | ColorSpace::CalCMYK(_) => unit(4),
+        ColorSpace::Icc(ref icc) => unit(icc.info.components as usize),
+        ColorSpace::DeviceN { ref tint, .. } => unit(tint.input_dim()),
+        ColorSpace::Named(ref name) => match resources.color_spaces.get(name.as_str()) {
+            Some(ColorSpace::Named(_)) | None => return Err(PdfError::Other { msg: format!("named color space {} not found", name) }),
+            Some(cs) => lookup_ranges(cs, resources, resolve)?,
+        },
+        ColorSpace::Other(ref p) => match p[..] {
+            [Primitive::Name(ref name), ref dict] if name.as_str() == "Lab" => {
+                let dict = dict.clone().resolve(resolve)?.into_dictionary()?;
+                let [a_min, a_max, b_min, b_max] = cie_array(&dict, "Range", [-100., 100., -100., 100.]);
+                vec![(0.0, 100.0), (a_min, a_max), (b_min, b_max)]
+            }
+            _ => return Err(PdfError::Other { msg: format!("invalid base of an Indexed color space: {:?}", p) })
+        },
+        _ => return Err(PdfError::Other { msg: format!("invalid base of an Indexed color space: {:?}", cs) }),
+    })
+}
+
+/// The color an uncolored tiling pattern is painted with, given as components before the pattern name.
+///
+/// The components are in the base space of the pattern color space `cs`, `[/Pattern base]`.
+/// Without a base they are taken as gray, RGB or CMYK by their number.
+pub fn pattern_color(cs: &ColorSpace, color: &Color, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache) -> Option<Fill> {
+    let args = match *color {
+        Color::Other(ref args) => args,
+        _ => return None
+    };
+    let values = args[.. args.len().saturating_sub(1)].iter().map(|a| a.as_number()).collect::<Result<Vec<f32>>>().ok()?;
+    if let Some(base) = pattern_base(cs) {
+        if values.is_empty() {
+            return None;
+        }
+        let base = match ColorSpace::from_primitive(base.clone(), resolve) {
+            Ok(base) => base,
+            Err(e) => {
+                warn!("invalid base of the pattern color space: {:?}", e);
+                return None;
+            }
+        };
+        let rgb = match base {
+            ColorSpace::Named(ref name) => match resources.color_spaces.get(name.as_str()) {
+                Some(cs) => convert_values(cs, &values, resources, resolve, mode, colors),
+                None => Err(PdfError::Other { msg: format!("named color space {} not found", name) })
+            },
+            ref base => convert_values(base, &values, resources, resolve, mode, colors),
+        };
+        // the parsed base is freed here, like the color space of a shading
+        colors.forget(&base);
+        return match rgb {
+            Ok((r, g, b)) => Some(Fill::Solid(r, g, b)),
+            Err(e) => {
+                warn!("can't convert the color of an uncolored pattern: {:?}", e);
+                None
+            }
+        };
+    }
+    match values[..] {
+        [g] => Some(gray2rgb(g)),
+        [r, g, b] => Some(Fill::Solid(r, g, b)),
+        [c, m, y, k] => Some(colors.cmyk2rgb((c, m, y, k))),
+        _ => None
+    }
+}
+
+/// `base` of the color space `[/Pattern base]`
+fn pattern_base(cs: &ColorSpace) -> Option<&Primitive> {
+    match *cs {
+        ColorSpace::Other(ref p) => match p[..] {
+            [Primitive::Name(ref name), ref base] if name.as_str() == "Pattern" => Some(base),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+pub fn gray2rgb(g: f32) -> Fill {
+    Fill::Solid(g, g, g)
+}
+
+pub fn cmyk2rgb(cmyk: (f32, f32, f32, f32), conversion: CmykConversion) -> Fill {
+    let (r, g, b) = conversion.to_rgb(cmyk);
+    Fill::Solid(r, g, b)
+}
+
+/// D65, the white point of sRGB
+const D65: [f32; 3] = [0.9505, 1.0, 1.089];
+
+/// A numeric array entry of a CIE-based color space dictionary
+fn cie_array<const N: usize>(dict: &Dictionary, key: &str, default: [f32; N]) -> [f32; N] {
+    let mut out = default;
+    if let Some(Primitive::Array(ref a)) = dict.get(key) {
+        if a.len() == N {
+            for (o, p) in out.iter_mut().zip(a) {
+                *o = p.as_number().unwrap_or(*o);
+            }
+        } else {
+            warn!("/{} should have {} entries, found {:?}", key, N, a);
+        }
+    }
+    out
+}
+
+/// XYZ relative to `white_point` to sRGB, adapting the white point by scaling (von Kries in XYZ)
+fn xyz2rgb([x, y, z]: [f32; 3], white_point: [f32; 3]) -> (f32, f32, f32) {
+    let adapt = |i: usize, v: f32| if white_point[i] > 0.0 { v * D65[i] / white_point[i] } else { v };
+    let (x, y, z) = (adapt(0, x), adapt(1, y), adapt(2, z));
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+    (srgb_gamma(r), srgb_gamma(g), srgb_gamma(b))
+}
+
+fn srgb_gamma(c: f32) -> f32 {
+    let c = c.max(0.0).min(1.0);
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// `/Lab`: L* in 0 ..= 100, a* and b* clamped to /Range
+pub fn lab2rgb(dict: &Dictionary, [l, a, b]: [f32; 3]) -> (f32, f32, f32) {
+    let white_point = cie_array(dict, "WhitePoint", D65);
+    let [a_min, a_max, b_min, b_max] = cie_array(dict, "Range", [-100., 100., -100., 100.]);
+    let l = l.max(0.0).min(100.0);
+    let a = a.max(a_min).min(a_max);
+    let b = b.max(b_min).min(b_max);
+
+    let fy = (l + 16.) / 116.;
+    let fx = fy + a / 500.;
+    let fz = fy - b / 200.;
+    let g = |t: f32| if t >= 6. / 29. { t * t * t } else { 108. / 841. * (t - 4. / 29.) };
+    let xyz = [white_point[0] * g(fx), white_point[1] * g(fy), white_point[2] * g(fz)];
+    xyz2rgb(xyz, white_point)
+}
+
+/// `/CalRGB`: each component raised to its /Gamma, then mapped to XYZ by /Matrix
+pub fn calrgb2rgb(dict: &Dictionary, abc: [f32; 3]) -> (f32, f32, f32) {
+    let white_point = cie_array(dict, "WhitePoint", D65);
+    let gamma = cie_array(dict, "Gamma", [1.0; 3]);
+    let m = cie_array(dict, "Matrix", [1., 0., 0., 0., 1., 0., 0., 0., 1.]);
+    let [a, b, c] = [0, 1, 2].map(|i| abc[i].max(0.0).min(1.0).powf(gamma[i]));
+    let xyz = [
+        m[0] * a + m[3] * b + m[6] * c,
+        m[1] * a + m[4] * b + m[7] * c,
+        m[2] * a + m[5] * b + m[8] * c,
+    ];
+    xyz2rgb(xyz, white_point)
+}
+
+/// `/CalGray`: the gray value raised to /Gamma is the luminance
+pub fn calgray2gray(dict: &Dictionary, g: f32) -> f32 {
+    let gamma = dict.get("Gamma").and_then(|p| p.as_number().ok()).unwrap_or(1.0);
+    srgb_gamma(g.max(0.0).min(1.0).powf(gamma))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rgb((r, g, b): (f32, f32, f32), expected: (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 0.02;
+        assert!(close(r, expected.0) && close(g, expected.1) && close(b, expected.2), "{:?} != {:?}", (r, g, b), expected);
+    }
+
+    #[test]
+    fn lab_known_values() {
+        let d65 = Dictionary::new();
+        assert_rgb(lab2rgb(&d65, [100., 0., 0.]), (1., 1., 1.));
+        assert_rgb(lab2rgb(&d65, [0., 0., 0.]), (0., 0., 0.));
+        // sRGB red
+        assert_rgb(lab2rgb(&d65, [53.24, 80.09, 67.20]), (1., 0., 0.));
+
+        // white stays white with another white point
+        let mut d50 = Dictionary::new();
+        d50.insert("WhitePoint", Primitive::Array([0.9642, 1.0, 0.8249].iter().map(|&v| Primitive::Number(v)).collect()));
+        assert_rgb(lab2rgb(&d50, [100., 0., 0.]), (1., 1., 1.));
+    }
+}
@@ -0,0 +1,152 @@
+use pathfinder_geometry::{transform2d::Transform2F, rect::RectF};
+use pathfinder_color::ColorU;
+use crate::{OptionalContent, ShadingQuality, AcroForm};
+
+/// Grid in pixels that device coordinates are rounded to by `RenderConfig::deterministic`
+pub const SNAP_GRID: f32 = 1.0 / 16.0;
+
+/// Width in device space of strokes with a line width of 0, one pixel
+pub const HAIRLINE_WIDTH: f32 = 1.0;
+
+/// Anti-aliasing of rasterized output
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Antialias {
+    /// every pixel is either covered or not, for 1-bit output and pixel exact comparisons
+    None,
+    Standard,
+}
+impl Default for Antialias {
+    fn default() -> Self {
+        Antialias::Standard
+    }
+}
+
+/// Options for rendering a page, passed to `render_page_with_config`.
+///
+/// `RenderConfig::default()` renders like `render_page` with the identity transform.
+/// Settings that belong to loaded resources (fonts, ICC and CMYK conversion of images) stay on the `Cache`.
+#[derive(Debug, Clone)]
+pub struct RenderConfig {
+    /// applied to the page, which is laid out in millimeters
+    pub transform: Transform2F,
+    /// visibility of optional content
+    pub layers: OptionalContent,
+    /// skip operators that fail instead of returning the error.
+    /// `None` follows `ParseOptions::allow_invalid_ops` of the file.
+    pub lenient: Option<bool>,
+    /// overrides `Backend::shading_quality`
+    pub shading_quality: Option<ShadingQuality>,
+    /// overrides `Backend::recursion_limit`
+    pub recursion_limit: Option<usize>,
+    /// draw the appearance streams of annotations
+    pub annotations: bool,
+    /// used by `render_page_to_image`
+    pub antialias: Antialias,
+    /// below the page content in `build_scene` and `render_page_to_image`, white by default. `None` is transparent.
+    pub background: Option<ColorU>,
+    /// draw form fields with their current value instead of their appearance streams
+    pub forms: Option<AcroForm>,
+    /// reproducible output of `render_page_to_image`, for comparing against reference images
+    pub deterministic: bool,
+    /// the part of the page that is rendered, in the space of `page_bounds`. `None` renders all of it.
+    pub region: Option<RectF>,
+    /// thinner strokes are widened to this width in device space. Strokes with a line width of 0 are always
+    /// at least `HAIRLINE_WIDTH` wide.
+    pub min_stroke_width: f32,
+    /// fit glyph outlines to the pixel grid
+    pub hinting: bool,
+}
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            transform: Transform2F::default(),
+            layers: OptionalContent::new(),
+            lenient: None,
+            shading_quality: None,
+            recursion_limit: None,
+            annotations: true,
+            antialias: Antialias::Standard,
+            background: Some(ColorU::white()),
+            forms: None,
+            deterministic: false,
+            region: None,
+            min_stroke_width: 0.0,
+            hinting: false,
+        }
+    }
+}
+impl RenderConfig {
+    pub fn new() -> Self {
+        RenderConfig::default()
+    }
+    pub fn transform(mut self, transform: Transform2F) -> Self {
+        self.transform = transform;
+        self
+    }
+    /// Scale the page to `dpi` pixels per inch.
+    pub fn dpi(self, dpi: f32) -> Self {
+        self.transform(Transform2F::from_scale(dpi / 25.4))
+    }
+    pub fn layers(mut self, layers: OptionalContent) -> Self {
+        self.layers = layers;
+        self
+    }
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = Some(lenient);
+        self
+    }
+    pub fn shading_quality(mut self, quality: ShadingQuality) -> Self {
+        self.shading_quality = Some(quality);
+        self
+    }
+    pub fn recursion_limit(mut self, limit: usize) -> Self {
+        self.recursion_limit = Some(limit);
+        self
+    }
+    pub fn annotations(mut self, annotations: bool) -> Self {
+        self.annotations = annotations;
+        self
+    }
+    pub fn antialias(mut self, antialias: Antialias) -> Self {
+        self.antialias = antialias;
+        self
+    }
+    pub fn background(mut self, background: Option<ColorU>) -> Self {
+        self.background = background;
+        self
+    }
+    /// Generate the appearance of text fields, check boxes, radio buttons and combo boxes
+    /// from their value, using the defaults of `form`. Other widgets keep their /AP.
+    pub fn flatten_forms(mut self, form: AcroForm) -> Self {
+        self.forms = Some(form);
+        self
+    }
+    /// Render the same pixels regardless of small floating point differences between platforms.
+    ///
+    /// Path coordinates are rounded to `SNAP_GRID` pixels in device space, so an edge can't land on either
+    /// side of a pixel center depending on how the transforms were rounded. Shadings use
+    /// `ShadingQuality::default()` unless `shading_quality` is set, instead of the setting of the backend.
+    /// Combine with `Antialias::None` to also remove the coverage of edges from the comparison.
+    /// The output can still change between versions of this crate and of the rasterizer.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+    /// Only render `region` of the page, given in millimeters like `page_bounds`. Content outside of it is clipped,
+    /// and the view box is `transform * region`.
+    pub fn region(mut self, region: RectF) -> Self {
+        self.region = Some(region);
+        self
+    }
+    /// Widen strokes that are thinner than `width` in device space, so grid lines don't disappear at small sizes.
+    pub fn min_stroke_width(mut self, width: f32) -> Self {
+        self.min_stroke_width = width;
+        self
+    }
+    /// Move the horizontal edges of glyphs to whole pixels of device space, so small text is sharper.
+    /// Glyphs are no longer exactly the shape of the font, and rotated text is not fitted. Off by default.
+    pub fn hinting(mut self, hinting: bool) -> Self {
+        self.hinting = hinting;
+        self
+    }
+}
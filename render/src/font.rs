@@ -1,16 +1,21 @@
 use std::path::{PathBuf};
 use std::ops::Deref;
 use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use glyphmatcher::FontDb;
 use pdf::object::*;
-use pdf::font::{Font as PdfFont};
+use pdf::primitive::Name;
+use pdf::font::{Font as PdfFont, FontData, FontDescriptor, FontType, Flags};
 use pdf::error::{Result, PdfError};
 
 use font::{self};
 use std::sync::Arc;
 use super::FontEntry;
+use crate::base14::Base14;
 use globalcache::{sync::SyncCache, ValueSize};
 use std::hash::{Hash, Hasher};
+use pathfinder_geometry::transform2d::Transform2F;
 
 #[derive(Clone)]
 pub struct FontRc(Arc<dyn font::Font + Send + Sync + 'static>);
@@ -46,40 +51,236 @@ impl Hash for FontRc {
         Arc::as_ptr(&self.0).hash(state)
     }
 }
-pub struct StandardCache {
-    inner: Arc<SyncCache<String, Option<FontRc>>>,
+/// The fonts of a STANDARD_FONTS directory, listed in its fonts.json, and the substitutes picked for missing fonts.
+///
+/// Fonts are parsed once and shared by all caches built with the same database, see `Cache::with_font_database`,
+/// so pages rendered in parallel with separate caches don't parse them again.
+pub struct FontDatabase {
     dir: PathBuf,
     fonts: HashMap<String, String>,
-    dump: Dump,
+    files: Arc<SyncCache<String, Option<FontRc>>>,
+    /// fallback names -> name and font of the substitute
+    substitutes: Mutex<HashMap<Vec<String>, Option<(String, FontRc)>>>,
     font_db: Option<FontDb>,
-    require_unique_unicode: bool,
 }
-impl StandardCache {
+impl FontDatabase {
     pub fn new(dir: PathBuf) -> Self {
         let data = std::fs::read_to_string(dir.join("fonts.json")).expect("can't read fonts.json");
         let fonts: HashMap<String, String> = serde_json::from_str(&data).expect("fonts.json is invalid");
+        let db_path = dir.join("db");
+        let font_db = db_path.is_dir().then(|| FontDb::new(db_path));
 
+        FontDatabase {
+            dir,
+            fonts,
+            files: SyncCache::new(),
+            substitutes: Mutex::new(HashMap::new()),
+            font_db,
+        }
+    }
+    fn load_file(&self, file_name: &str) -> Option<FontRc> {
+        self.files.get(file_name.into(), |_| {
+            let data = match std::fs::read(self.dir.join(file_name)) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("can't open {} {:?}", file_name, e);
+                    return None;
+                }
+            };
+            match font::parse(&data) {
+                Ok(f) => Some(f.into()),
+                Err(e) => {
+                    warn!("Font Error: {:?}", e);
+                    return None;
+                }
+            }
+        })
+    }
+    /// The font listed as `name` in fonts.json
+    pub fn load_named(&self, name: &str) -> Option<FontRc> {
+        self.fonts.get(name).and_then(|file_name| self.load_file(file_name))
+    }
+    /// The first of `fallbacks` that can be loaded, for a non-embedded font that isn't in the database.
+    /// The choice is remembered, so each list of fallbacks is only looked up once, whatever font is missing.
+    pub fn substitute(&self, fallbacks: &[String]) -> Option<(String, FontRc)> {
+        if let Some(found) = self.substitutes.lock().unwrap().get(fallbacks) {
+            return found.clone();
+        }
+        let found = fallbacks.iter().find_map(|name| self.load_named(name).map(|f| (name.clone(), f)));
+        self.substitutes.lock().unwrap().entry(fallbacks.to_vec()).or_insert(found).clone()
+    }
+}
+
+pub struct StandardCache {
+    db: Arc<FontDatabase>,
+    dump: Dump,
+    require_unique_unicode: bool,
+    // set through shared references, so the settings reach all clones of a `Cache`
+    synthesize_styles: AtomicBool,
+    fallback: Mutex<FallbackFonts>,
+}
+impl StandardCache {
+    pub fn new(dir: PathBuf) -> Self {
+        StandardCache::with_database(Arc::new(FontDatabase::new(dir)))
+    }
+    pub fn with_database(db: Arc<FontDatabase>) -> Self {
         let dump = match std::env::var("DUMP_FONT").as_deref() {
             Err(_) => Dump::Never,
             Ok("always") => Dump::Always,
             Ok("error") => Dump::OnError,
             Ok(_) => Dump::Never
         };
-        let db_path = dir.join("db");
-        let font_db = db_path.is_dir().then(|| FontDb::new(db_path));
-
         StandardCache {
-            inner: SyncCache::new(),
-            dir,
-            fonts,
+            db,
             dump,
-            font_db,
             require_unique_unicode: false,
+            synthesize_styles: AtomicBool::new(false),
+            fallback: Mutex::new(FallbackFonts::default()),
         }
     }
     pub fn require_unique_unicode(&mut self, r: bool) {
         self.require_unique_unicode = r;
     }
+    /// Embolden and slant substitute fonts for missing bold and italic fonts.
+    pub fn synthesize_styles(&self, s: bool) {
+        self.synthesize_styles.store(s, Ordering::Relaxed);
+    }
+    /// Set the names (keys of fonts.json) that are tried in order when a font of the given class is missing.
+    pub fn set_fallback_fonts(&self, class: FontClass, names: Vec<String>) {
+        *self.fallback.lock().unwrap().get_mut(class) = names;
+    }
+}
+
+/// Rough classification of a font, used to pick a substitute for fonts that are not embedded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FontClass {
+    Serif,
+    Sans,
+    Mono,
+}
+impl FontClass {
+    pub fn of(pdf_font: &PdfFont) -> FontClass {
+        if let Some(desc) = font_descriptor(pdf_font) {
+            if desc.flags.contains(Flags::FixedPitch) {
+                return FontClass::Mono;
+            }
+            if desc.flags.contains(Flags::Serif) {
+                return FontClass::Serif;
+            }
+            return FontClass::Sans;
+        }
+
+        let name = pdf_font.name.as_ref().map(|n| n.as_str()).unwrap_or("");
+        if name.contains("Courier") || name.contains("Mono") {
+            FontClass::Mono
+        } else if name.contains("Times") || name.contains("Roman") || (name.contains("Serif") && !name.contains("Sans")) {
+            FontClass::Serif
+        } else {
+            FontClass::Sans
+        }
+    }
+}
+
+struct FallbackFonts {
+    serif: Vec<String>,
+    sans: Vec<String>,
+    mono: Vec<String>,
+}
+impl FallbackFonts {
+    fn get(&self, class: FontClass) -> &[String] {
+        match class {
+            FontClass::Serif => &self.serif,
+            FontClass::Sans => &self.sans,
+            FontClass::Mono => &self.mono,
+        }
+    }
+    fn get_mut(&mut self, class: FontClass) -> &mut Vec<String> {
+        match class {
+            FontClass::Serif => &mut self.serif,
+            FontClass::Sans => &mut self.sans,
+            FontClass::Mono => &mut self.mono,
+        }
+    }
+}
+impl Default for FallbackFonts {
+    fn default() -> Self {
+        let list = |names: &[&str]| names.iter().map(|&s| s.into()).collect();
+        FallbackFonts {
+            serif: list(&["Times-Roman", "Arial"]),
+            sans: list(&["Helvetica", "Arial"]),
+            mono: list(&["Courier", "Arial"]),
+        }
+    }
+}
+
+pub fn font_descriptor(pdf_font: &PdfFont) -> Option<&FontDescriptor> {
+    match pdf_font.data {
+        FontData::Type0(ref t0) => t0.descendant_fonts.get(0).and_then(|f| font_descriptor(f)),
+        FontData::Type1(ref info) | FontData::TrueType(ref info) => info.font_descriptor.as_ref(),
+        FontData::CIDFontType0(ref cid) | FontData::CIDFontType2(ref cid) => Some(&cid.font_descriptor),
+        _ => None
+    }
+}
+
+
+/// Memoizes the `FontEntry` built for each font dictionary.
+///
+/// Entries are keyed by the object id of the font, so a font that is shared by many pages
+/// is only built once. The cache can be shared between threads rendering different pages.
+pub struct FontCache {
+    entries: Mutex<HashMap<u64, Option<Arc<FontEntry>>>>,
+    missing: Mutex<Vec<Name>>,
+    std: StandardCache,
+}
+impl FontCache {
+    pub fn new(std: StandardCache) -> Self {
+        FontCache {
+            entries: Mutex::new(HashMap::new()),
+            missing: Mutex::new(Vec::new()),
+            std,
+        }
+    }
+    pub fn get(&self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>> {
+        let key = font_key(font_ref);
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            return Ok(entry.clone());
+        }
+
+        // build outside of the lock, so other fonts can be loaded in the meantime
+        let entry = match load_font(font_ref, resolve, &self.std)? {
+            Some(f) => Some(Arc::new(f)),
+            None => {
+                if let Some(ref name) = font_ref.name {
+                    self.missing.lock().unwrap().push(name.clone());
+                }
+                None
+            }
+        };
+        Ok(self.entries.lock().unwrap().entry(key).or_insert(entry).clone())
+    }
+    /// Drop all cached entries to bound memory use on huge files.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+    pub fn missing_fonts(&self) -> Vec<Name> {
+        self.missing.lock().unwrap().clone()
+    }
+    pub fn require_unique_unicode(&mut self, r: bool) {
+        self.std.require_unique_unicode(r);
+    }
+    pub fn set_fallback_fonts(&self, class: FontClass, names: Vec<String>) {
+        self.std.set_fallback_fonts(class, names);
+    }
+    pub fn synthesize_styles(&self, s: bool) {
+        self.std.synthesize_styles(s);
+    }
+}
+
+pub fn font_key(font_ref: &MaybeRef<PdfFont>) -> u64 {
+    match font_ref {
+        MaybeRef::Direct(ref shared) => shared.as_ref() as *const PdfFont as _,
+        MaybeRef::Indirect(re) => re.get_ref().get_inner().id as _
+    }
 }
 
 #[derive(Debug)]
@@ -92,10 +293,18 @@ enum Dump {
 pub fn load_font(font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve, cache: &StandardCache) -> Result<Option<FontEntry>> {
     let pdf_font = font_ref.clone();
     debug!("loading {:?}", pdf_font);
+
+    if pdf_font.subtype == FontType::Type3 {
+        return Ok(Some(FontEntry::build_type3(pdf_font, resolve)?));
+    }
     
+    let mut substitute = None;
+    let mut base14 = None;
+    let mut embedded = false;
     let font: FontRc = match pdf_font.embedded_data(resolve) {
         Some(Ok(data)) => {
             debug!("loading embedded font");
+            embedded = true;
             let font = font::parse(&data).map_err(|e| {
                 PdfError::Other { msg: format!("Font Error: {:?}", e) }
             });
@@ -109,43 +318,53 @@ pub fn load_font(font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve, cache: &S
         Some(Err(e)) => return Err(e),
         None => {
             debug!("no embedded font.");
-            let name = match pdf_font.name {
-                Some(ref name) => name.as_str(),
-                None => return Ok(None)
-            };
-            debug!("loading {name} instead");
-            match cache.fonts.get(name).or_else(|| cache.fonts.get("Arial")) {
-                Some(file_name) => {
-                    let val = cache.inner.get(file_name.clone(), |_| {
-                        let data = match std::fs::read(cache.dir.join(file_name)) {
-                            Ok(data) => data,
-                            Err(e) => {
-                                warn!("can't open {} for {:?} {:?}", file_name, pdf_font.name, e);
-                                return None;
-                            }
-                        };
-                        match font::parse(&data) {
-                            Ok(f) => Some(f.into()),
-                            Err(e) => {
-                                warn!("Font Error: {:?}", e);
-                                return None;
-                            }
+            base14 = pdf_font.name.as_ref().and_then(|name| Base14::from_name(name.as_str()));
+            let requested = pdf_font.name.as_ref().map(|name| name.as_str());
+            match requested.and_then(|name| cache.db.load_named(name)) {
+                Some(f) => f,
+                None => {
+                    let class = FontClass::of(&pdf_font);
+                    let fallbacks = cache.fallback.lock().unwrap().get(class).to_vec();
+                    match cache.db.substitute(&fallbacks) {
+                        Some((name, f)) => {
+                            info!("substituting {name} ({class:?}) for {:?}", pdf_font.name);
+                            substitute = Some(name);
+                            f
                         }
-                    });
-                    match val {
-                        Some(f) => f,
                         None => {
+                            warn!("no font for {:?}", pdf_font.name);
                             return Ok(None);
                         }
                     }
                 }
-                None => {
-                    warn!("no font for {:?}", pdf_font.name);
-                    return Ok(None);
-                }
             }
         }
     };
 
-    Ok(Some(FontEntry::build(font, pdf_font, cache.font_db.as_ref(), resolve, cache.require_unique_unicode)?))
+    let mut entry = FontEntry::build(font, pdf_font, base14, cache.db.font_db.as_ref(), resolve, cache.require_unique_unicode)?;
+    if substitute.is_some() && cache.synthesize_styles.load(Ordering::Relaxed) {
+        let (bold, italic) = requested_style(&entry.pdf_font);
+        entry.faux_bold = bold;
+        if italic {
+            entry.faux_italic = true;
+            entry.font_matrix = Transform2F::row_major(1., FAUX_ITALIC_SHEAR, 0., 0., 1., 0.) * entry.font_matrix;
+        }
+    }
+    entry.embedded = embedded;
+    entry.substitute = substitute;
+    Ok(Some(entry))
+}
+
+/// slant of synthesized italics, about 12°
+const FAUX_ITALIC_SHEAR: f32 = 0.21;
+
+/// (bold, italic) from the descriptor flags and the font name
+fn requested_style(pdf_font: &PdfFont) -> (bool, bool) {
+    let name = pdf_font.name.as_ref().map(|n| n.as_str()).unwrap_or("");
+    let flags = font_descriptor(pdf_font).map(|d| d.flags);
+    let bold = flags.map_or(false, |f| f.contains(Flags::ForceBold))
+        || name.contains("Bold") || name.contains("Black") || name.contains("Heavy");
+    let italic = flags.map_or(false, |f| f.contains(Flags::Italic))
+        || name.contains("Italic") || name.contains("Oblique");
+    (bold, italic)
 }
@@ -1,27 +1,61 @@
 use std::collections::{HashMap, HashSet};
-use font::{self, GlyphId, TrueTypeFont, CffFont, Type1Font, OpenTypeFont};
+use std::sync::{Arc, Mutex};
+use std::convert::TryInto;
+use font::{self, Glyph, GlyphId, TrueTypeFont, CffFont, Type1Font, OpenTypeFont};
 use glyphmatcher::FontDb;
-use itertools::Itertools;
+use itertools::{Itertools, Either};
 use pdf::encoding::BaseEncoding;
-use pdf::font::{Font as PdfFont, Widths, CidToGidMap};
-use pdf::object::{Resolve, MaybeRef};
+use pdf::font::{Font as PdfFont, FontType, Widths, CidToGidMap, Flags};
+use pdf::object::{Resolve, MaybeRef, Stream, Object};
+use pdf::primitive::{Primitive, Dictionary};
+use pathfinder_geometry::{vector::Vector2F, transform2d::Transform2F};
 use pdf::error::PdfError;
 use pdf_encoding::{Encoding, glyphname_to_unicode};
 use istring::SmallString;
-use crate::font::FontRc;
+use crate::font::{FontRc, font_descriptor, font_key};
+use crate::type3::Type3Font;
+use crate::cmap::CMap;
+use crate::base14::Base14;
 
 pub struct FontEntry {
-    pub font: FontRc,
+    /// the outline font. `None` for Type3 fonts
+    pub font: Option<FontRc>,
+    pub type3: Option<Type3Font>,
     pub pdf_font: MaybeRef<PdfFont>,
     pub cmap: HashMap<u16, (GlyphId, Option<SmallString>)>,
     pub widths: Option<Widths>,
+    /// /FirstChar and /LastChar of a simple font without /MissingWidth, `widths` don't apply outside of them
+    pub width_range: Option<(u16, u16)>,
     pub is_cid: bool,
+    /// embedded CMap that splits strings into codes and maps them to CIDs (the keys of `cmap`)
+    pub code_map: Option<CMap>,
+    /// present if the font uses vertical writing mode
+    pub vertical: Option<VerticalMetrics>,
     pub name: String,
+    /// the font program (or the glyph procedures of a Type3 font) is in the PDF
+    pub embedded: bool,
+    /// the name has a subset tag like `ABCDEF+`, the embedded font only has the glyphs the document uses
+    pub subset: bool,
+    /// /Subtype of the font dictionary
+    pub font_type: FontType,
+    /// name of the font used in place of a missing, non-embedded font
+    pub substitute: Option<String>,
+    /// the standard 14 font this non-embedded font refers to
+    pub base14: Option<Base14>,
+    /// maps glyph space to text space: /FontMatrix of Type3 fonts or of the font program, for example of Type1 fonts.
+    /// Includes the slant of a synthesized italic.
+    pub font_matrix: Transform2F,
+    /// the substitute is emboldened by stroking glyphs along with filling them
+    pub faux_bold: bool,
+    /// the substitute is slanted to stand in for an italic font
+    pub faux_italic: bool,
+    /// outlines by glyph id, filled by `glyph`
+    glyphs: Mutex<HashMap<u32, Option<Arc<Glyph>>>>,
 }
 
 
 impl FontEntry {
-    pub fn build(font: FontRc, pdf_font: MaybeRef<PdfFont>, font_db: Option<&FontDb>, resolve: &impl Resolve, require_unique_unicode: bool) -> Result<FontEntry, PdfError> {
+    pub fn build(font: FontRc, pdf_font: MaybeRef<PdfFont>, base14: Option<Base14>, font_db: Option<&FontDb>, resolve: &impl Resolve, require_unique_unicode: bool) -> Result<FontEntry, PdfError> {
         let mut is_cid = pdf_font.is_cid();
 
         let name = match pdf_font.data {
@@ -33,11 +67,40 @@ impl FontEntry {
         let base_encoding = encoding.as_ref().map(|e| &e.base);
         
         let to_unicode = t!(pdf_font.to_unicode(resolve).transpose());
+        let font_dict = if pdf_font.is_cid() { raw_font_dict(&pdf_font, resolve) } else { None };
+        let mut encoding_name = None;
+        let code_map = match font_dict.as_ref().and_then(|d| raw_encoding(d, resolve)) {
+            Some(RawEncoding::CMap(Ok(cmap))) => Some(cmap),
+            Some(RawEncoding::CMap(Err(e))) => {
+                warn!("invalid embedded CMap: {:?}", e);
+                None
+            }
+            Some(RawEncoding::Name(name)) => {
+                encoding_name = Some(name);
+                None
+            }
+            None => None
+        };
+        let vertical = match code_map {
+            Some(ref cmap) => cmap.vertical,
+            None => encoding_name.as_deref().map_or(false, |n| n.ends_with("-V"))
+        };
+        let vertical = vertical.then(|| {
+            let descendant = font_dict.as_ref()
+                .and_then(|d| resolve_entry(d, "DescendantFonts", resolve))
+                .and_then(|p| p.into_array().ok())
+                .and_then(|a| a.into_iter().next())
+                .and_then(|p| p.resolve(resolve).ok())
+                .and_then(|p| p.into_dictionary().ok());
+            VerticalMetrics::parse(descendant.as_ref(), resolve)
+        });
         let mut font_codepoints = None;
 
         let font_cmap = font.downcast_ref::<TrueTypeFont>().and_then(|ttf| ttf.cmap.as_ref())
         .or_else(|| font.downcast_ref::<OpenTypeFont>().and_then(|otf| otf.cmap.as_ref()));
 
+        // whether glyph_unicode is derived from glyph names or a cmap of the font, rather than guessed
+        let has_glyph_names = font.downcast_ref::<Type1Font>().is_some() || font_cmap.is_some() || font.downcast_ref::<CffFont>().is_some();
         let glyph_unicode: HashMap<GlyphId, SmallString> = {
             if let Some(type1) = font.downcast_ref::<Type1Font>() {
                 debug!("Font is Type1");
@@ -105,6 +168,10 @@ impl FontEntry {
                                 *uni = Some(s.into());
                             }
                         }
+                    } else if has_glyph_names {
+                        for (gid, uni) in map.values_mut() {
+                            *uni = glyph_unicode.get(gid).cloned();
+                        }
                     }
                     map
                 }
@@ -112,15 +179,22 @@ impl FontEntry {
                     data.iter().enumerate().map(|(cid, &gid)| {
                         let unicode = match to_unicode {
                             Some(ref u) => u.get(cid as u16).map(|s| s.into()),
-                            None => glyph_unicode.get(&GlyphId(gid as u32)).cloned()
+                            None if has_glyph_names => glyph_unicode.get(&GlyphId(gid as u32)).cloned(),
+                            None => None
                         };
                         (cid as u16, (GlyphId(gid as u32), unicode))
                     }).collect()
                 }
             }
-        } else if base_encoding == Some(&BaseEncoding::IdentityH) {
+        } else if base_encoding == Some(&BaseEncoding::IdentityH) || encoding_name.as_deref() == Some("Identity-V") {
             is_cid = true;
             build_map()
+        } else if code_map.is_some() {
+            is_cid = true;
+            match font.downcast_ref::<CffFont>() {
+                Some(cff) if cff.cid => cff.sid_map.iter().map(|(&cid, &gid)| (cid as u16, (GlyphId(gid as u32), None))).collect(),
+                _ => (0 .. font.num_glyphs()).map(|n| (n as u16, (GlyphId(n as u32), None))).collect()
+            }
         } else {
             let mut cmap = HashMap::<u16, (GlyphId, Option<SmallString>)>::new();
             
@@ -130,6 +204,9 @@ impl FontEntry {
                 Some(BaseEncoding::WinAnsiEncoding) => Some(Encoding::WinAnsiEncoding),
                 Some(BaseEncoding::MacRomanEncoding) => Some(Encoding::MacRomanEncoding),
                 Some(BaseEncoding::MacExpertEncoding) => Some(Encoding::AdobeExpert),
+                // the built-in encodings of the standard fonts. ZapfDingbats is filled in below.
+                None if base14 == Some(Base14::Symbol) => Some(Encoding::AdobeSymbol),
+                None if base14.map_or(false, |b| !b.is_symbolic()) => Some(Encoding::AdobeStandard),
                 ref e => {
                     warn!("unsupported pdf encoding {:?}", e);
                     None
@@ -139,6 +216,10 @@ impl FontEntry {
             let font_encoding = font.encoding();
             debug!("{:?} -> {:?}", source_encoding, font_encoding);
 
+            // symbolic TrueType fonts have a (3, 0) cmap with the codes at 0xF000 + code
+            let symbolic = font_cmap.is_some() && font_descriptor(&pdf_font).map_or(false, |d| d.flags.contains(Flags::Symbolic));
+            let symbol_gid = |b: u32| symbolic.then(|| font.gid_for_codepoint(0xf000 + b)).flatten();
+
             match (source_encoding, font_encoding) {
                 (Some(source), Some(dest)) => {
                     if let Some(transcoder) = source.to(dest) {
@@ -152,13 +233,23 @@ impl FontEntry {
                     }
                 },
                 (Some(enc), None) => {
-                    if let Some(encoder) = enc.to(Encoding::Unicode) {
-                        for b in 0 .. 256 {
-                            let unicode = encoder.translate(b as u32);
-                            if let Some(gid) = unicode.and_then(|c| font.gid_for_unicode_codepoint(c)) {
-                                cmap.insert(b, (gid, unicode.and_then(std::char::from_u32).map(|c| c.into())));
-                                debug!("{} -> {:?}", b, gid);
-                            }
+                    let encoder = enc.to(Encoding::Unicode);
+                    for b in 0 .. 256 {
+                        let unicode = encoder.as_ref().and_then(|e| e.translate(b as u32));
+                        if let Some(gid) = symbol_gid(b) {
+                            let unicode = unicode.and_then(std::char::from_u32).map(|c| c.into())
+                                .or_else(|| glyph_unicode.get(&gid).cloned());
+                            cmap.insert(b as u16, (gid, unicode));
+                        } else if let Some(gid) = unicode.and_then(|c| font.gid_for_unicode_codepoint(c)) {
+                            cmap.insert(b as u16, (gid, unicode.and_then(std::char::from_u32).map(|c| c.into())));
+                            debug!("{} -> {:?}", b, gid);
+                        }
+                    }
+                }
+                _ if symbolic && font_cmap.is_some() => {
+                    for b in 0 .. 256 {
+                        if let Some(gid) = symbol_gid(b).or_else(|| font.gid_for_codepoint(b)) {
+                            cmap.insert(b as u16, (gid, glyph_unicode.get(&gid).cloned()));
                         }
                     }
                 }
@@ -177,21 +268,8 @@ impl FontEntry {
                     
                 }
             }
-            if let Some(encoding) = encoding {
-                for (&cp, name) in encoding.differences.iter() {
-                    let uni = glyphname_to_unicode(name);
-                    let gid = font.gid_for_name(&name).or_else(||
-                        uni.and_then(|s| s.chars().next()).and_then(|cp| font.gid_for_unicode_codepoint(cp as u32))
-                    ).or_else(||
-                        font.gid_for_codepoint(cp)
-                    ).unwrap_or(GlyphId(cp));
-                    
-                    let unicode = uni.map(|s| s.into())
-                        .or_else(|| std::char::from_u32(0xf000 + gid.0).map(SmallString::from));
-                    
-                    debug!("{} -> gid {:?}, unicode {:?}", cp, gid, unicode);
-                    cmap.insert(cp as u16, (gid, unicode));
-                }
+            if let Some(ref encoding) = encoding {
+                apply_differences(&mut cmap, &encoding.differences, &font);
             } else {
                 if let Some(ref u) = to_unicode {
                     debug!("using to_unicode to build cmap");
@@ -213,7 +291,16 @@ impl FontEntry {
                     }
                 }
             }
-            
+            // ZapfDingbats without /Encoding: the built-in encoding, by unicode if the substitute has the dingbats
+            if encoding.is_none() && to_unicode.is_none() && base14 == Some(Base14::ZapfDingbats) {
+                for (b, c) in (0 ..= 255u8).filter_map(|b| Base14::ZapfDingbats.builtin_unicode(b).map(|c| (b as u16, c))) {
+                    if let Some(gid) = font.gid_for_unicode_codepoint(c as u32) {
+                        cmap.insert(b, (gid, Some(c.into())));
+                    } else if let Some((_, uni @ None)) = cmap.get_mut(&b) {
+                        *uni = Some(c.into());
+                    }
+                }
+            }
 
             if cmap.len() == 0 {
                 is_cid = true;
@@ -223,6 +310,33 @@ impl FontEntry {
             }
         };
         
+        // composite fonts normally encode with a CMap, but some carry /Differences as well
+        if is_cid {
+            if let Some(ref encoding) = encoding {
+                if !encoding.differences.is_empty() {
+                    debug!("applying /Differences to a composite font");
+                    // the differences are by code, the cmap by CID
+                    let by_cid: HashMap<u32, String> = encoding.differences.iter().filter_map(|(&code, name)| {
+                        let cid = match code_map {
+                            Some(ref code_map) => cid_u16(code_map.cid(code)?)? as u32,
+                            None => code
+                        };
+                        Some((cid, name.clone()))
+                    }).collect();
+                    apply_differences(&mut cmap, &by_cid, &font);
+                }
+            }
+        }
+
+        // ToUnicode is keyed by character code, the cmap by CID
+        if let (Some(ref code_map), Some(ref to_unicode)) = (&code_map, &to_unicode) {
+            for (code, s) in to_unicode.iter() {
+                if let Some(entry) = code_map.cid(code as u32).and_then(cid_u16).and_then(|cid| cmap.get_mut(&cid)) {
+                    entry.1 = Some(s.into());
+                }
+            }
+        }
+
         if let Some(font_db) = font_db {
             if let Some(name) = name {
                 let ps_name = name.split("+").nth(1).unwrap_or(name);
@@ -258,7 +372,14 @@ impl FontEntry {
         }
 
         let widths = pdf_font.widths(resolve)?;
-        let name = pdf_font.name.as_ref().ok_or_else(|| PdfError::Other { msg: "font has no name".into() })?.as_str().into();
+        let width_range = match is_cid {
+            false => raw_font_dict(&pdf_font, resolve).and_then(|d| width_range(&d, resolve)),
+            true => None
+        };
+        let name = match pdf_font.name {
+            Some(ref name) => name.as_str().into(),
+            None => synthetic_name(&pdf_font),
+        };
 
         if require_unique_unicode {
             let mut next_code = 0xE000;
@@ -309,19 +430,410 @@ impl FontEntry {
 
         }
         
+        let font_matrix = program_font_matrix(&font);
         Ok(FontEntry {
-            font,
+            font_matrix,
+            font: Some(font),
+            type3: None,
+            font_type: pdf_font.subtype,
             pdf_font,
             cmap,
             is_cid,
+            code_map,
+            vertical,
             widths,
+            width_range,
+            subset: is_subset_name(&name),
             name,
+            embedded: false,
+            substitute: None,
+            base14,
+            faux_bold: false,
+            faux_italic: false,
+            glyphs: Mutex::new(HashMap::new()),
         })
     }
 }
 
+impl FontEntry {
+    pub fn build_type3(pdf_font: MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<FontEntry, PdfError> {
+        let dict = match pdf_font.data {
+            pdf::font::FontData::Other(ref dict) => dict,
+            _ => return Err(PdfError::Other { msg: "Type3 font without font dictionary".into() })
+        };
+        let no_differences = HashMap::new();
+        let differences = pdf_font.encoding().map(|e| &e.differences).unwrap_or(&no_differences);
+        let type3 = Type3Font::parse(dict, differences, resolve)?;
+
+        let to_unicode = t!(pdf_font.to_unicode(resolve).transpose());
+        let cmap = type3.glyphs.keys().map(|&code| {
+            let unicode = match to_unicode {
+                Some(ref u) => u.get(code).map(|s| s.into()),
+                None => differences.get(&(code as u32)).and_then(|name| glyphname_to_unicode(name)).map(|s| s.into())
+            };
+            (code, (GlyphId(code as u32), unicode))
+        }).collect();
+
+        let name = match pdf_font.name {
+            Some(ref name) => name.as_str().into(),
+            None => synthetic_name(&pdf_font),
+        };
+        Ok(FontEntry {
+            font: None,
+            font_matrix: type3.font_matrix,
+            type3: Some(type3),
+            font_type: pdf_font.subtype,
+            pdf_font,
+            cmap,
+            widths: None,
+            width_range: None,
+            is_cid: false,
+            code_map: None,
+            vertical: None,
+            subset: is_subset_name(&name),
+            name,
+            embedded: true,
+            substitute: None,
+            base14: None,
+            faux_bold: false,
+            faux_italic: false,
+            glyphs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The width of `code` in glyph space as given by the PDF: from /Widths, or from the
+    /// metrics of a standard 14 font. `None` if the glyph of the font program should be used.
+    ///
+    /// Codes outside /FirstChar../LastChar only get a width from /Widths with an explicit /MissingWidth.
+    pub fn width(&self, code: u16) -> Option<f32> {
+        let in_range = self.width_range.map_or(true, |(first, last)| (first ..= last).contains(&code));
+        if let Some(widths) = self.widths.as_ref().filter(|_| in_range) {
+            return Some(widths.get(code as usize));
+        }
+        let base14 = self.base14?;
+        if base14.is_symbolic() {
+            // only the built-in encoding has known codes
+            return match self.pdf_font.encoding() {
+                None => base14.code_width(u8::try_from(code).ok()?),
+                Some(_) => None
+            };
+        }
+        let c = self.cmap.get(&code)?.1.as_ref()?.chars().next()?;
+        base14.width(c)
+    }
+
+    /// Advance width of the glyph for `code` in glyph space, i.e. 1/1000 of the font size.
+    ///
+    /// Taken from the /Widths of the font (or the standard 14 metrics), then from the glyph itself and
+    /// finally from the /MissingWidth of the font descriptor.
+    pub fn advance(&self, code: u16) -> f32 {
+        if let Some(width) = self.width(code) {
+            return width;
+        }
+        if let Some(ref type3) = self.type3 {
+            if let Some(glyph) = type3.glyphs.get(&code) {
+                return 1000. * self.font_matrix.m11() * glyph.width;
+            }
+        } else if self.font.is_some() {
+            let gid = self.cmap.get(&code).map(|&(gid, _)| gid).unwrap_or(GlyphId(code as u32));
+            if let Some(glyph) = self.glyph(gid) {
+                return 1000. * self.font_matrix.m11() * glyph.metrics.advance;
+            }
+        }
+        font_descriptor(&self.pdf_font).map(|d| d.missing_width).unwrap_or(0.)
+    }
+
+    /// The outline of `gid`. Each glyph is only outlined once per font.
+    pub fn glyph(&self, gid: GlyphId) -> Option<Arc<Glyph>> {
+        let font = self.font.as_ref()?;
+        if let Some(glyph) = self.glyphs.lock().unwrap().get(&gid.0) {
+            return glyph.clone();
+        }
+        let glyph = font.glyph(gid).map(Arc::new);
+        self.glyphs.lock().unwrap().entry(gid.0).or_insert(glyph).clone()
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        self.vertical.is_some()
+    }
+
+    /// Split a PDF string into character codes.
+    ///
+    /// With an embedded CMap, codes are split according to its codespace and mapped to CIDs.
+    /// Otherwise CID fonts use two bytes per code and simple fonts a single byte.
+    pub fn codes<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item=u16> + 'a {
+        self.codes_and_spaces(data).map(|(code, _)| code)
+    }
+
+    /// Like `codes`, with whether each code is the single byte code 32, the only one word spacing (Tw) applies to.
+    ///
+    /// Two byte codes never get word spacing, even if one of their bytes is 32 or they map to CID 32.
+    pub fn codes_and_spaces<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item=(u16, bool)> + 'a {
+        match (&self.code_map, self.is_cid) {
+            (Some(code_map), _) => Either::Left(cmap_codes(code_map, data)),
+            (None, true) => Either::Right(Either::Left(data.chunks_exact(2).map(|s| (u16::from_be_bytes(s.try_into().unwrap()), false)))),
+            (None, false) => Either::Right(Either::Right(data.iter().map(|&b| (b as u16, b == 0x20)))),
+        }
+    }
+
+    /// Decode a PDF string into unicode text.
+    ///
+    /// Codes without a known unicode value are mapped into the private use area at 0xf000.
+    pub fn decode_to_string(&self, data: &[u8]) -> String {
+        let mut text = String::with_capacity(data.len());
+        for code in self.codes(data) {
+            match self.cmap.get(&code) {
+                Some((_, Some(uni))) => text.push_str(uni),
+                Some((gid, None)) => text.extend(std::char::from_u32(0xf000 + gid.0)),
+                None => text.extend(std::char::from_u32(0xf000 + code as u32)),
+            }
+        }
+        text
+    }
+}
+
+/// The CIDs of the codes in `data`, with whether the code is the single byte code 32.
+///
+/// Unmapped codes use CID 0 (.notdef). Codes mapped to CIDs above 0xFFFF are skipped.
+fn cmap_codes<'a>(code_map: &'a CMap, data: &'a [u8]) -> impl Iterator<Item=(u16, bool)> + 'a {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        loop {
+            let (code, len) = code_map.next_code(&data[pos..])?;
+            pos += len;
+            let cid = match code_map.cid(code) {
+                Some(cid) => match cid_u16(cid) {
+                    Some(cid) => cid,
+                    None => continue
+                },
+                None => 0
+            };
+            return Some((cid, len == 1 && code == 0x20));
+        }
+    })
+}
+
+/// CIDs have 16 bits here. Larger ones, from broken CMaps and /W2 arrays, are dropped and logged instead of wrapping around.
+fn cid_u16(cid: u32) -> Option<u16> {
+    match u16::try_from(cid) {
+        Ok(cid) => Some(cid),
+        Err(_) => {
+            warn!("CID {} is out of range", cid);
+            None
+        }
+    }
+}
+
+/// Remap the codes of a /Differences array to the named glyphs.
+fn apply_differences(cmap: &mut HashMap<u16, (GlyphId, Option<SmallString>)>, differences: &HashMap<u32, String>, font: &FontRc) {
+    for (&cp, name) in differences.iter() {
+        let uni = glyphname_to_unicode(name);
+        let gid = font.gid_for_name(&name).or_else(||
+            uni.and_then(|s| s.chars().next()).and_then(|cp| font.gid_for_unicode_codepoint(cp as u32))
+        ).or_else(||
+            font.gid_for_codepoint(cp)
+        ).unwrap_or(GlyphId(cp));
+
+        let unicode = uni.map(|s| s.into())
+            .or_else(|| std::char::from_u32(0xf000 + gid.0).map(SmallString::from));
+
+        debug!("{} -> gid {:?}, unicode {:?}", cp, gid, unicode);
+        cmap.insert(cp as u16, (gid, unicode));
+    }
+}
+
+/// Subset fonts are named with six upper case letters and a '+' in front of the font name.
+pub(crate) fn is_subset_name(name: &str) -> bool {
+    let b = name.as_bytes();
+    b.len() > 7 && b[6] == b'+' && b[.. 6].iter().all(|c| c.is_ascii_uppercase())
+}
+
+/// A stable name for fonts without /BaseFont, derived from the object reference.
+fn synthetic_name(pdf_font: &MaybeRef<PdfFont>) -> String {
+    match pdf_font {
+        MaybeRef::Indirect(re) => {
+            let r = re.get_ref().get_inner();
+            format!("Font-{}-{}", r.id, r.gen)
+        }
+        MaybeRef::Direct(_) => format!("Font-{:x}", font_key(pdf_font)),
+    }
+}
+
+/// The font dictionary as it appears in the file.
+///
+/// pdf::font::Font doesn't keep everything we need (encoding streams, vertical metrics).
+fn raw_font_dict(pdf_font: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Option<Dictionary> {
+    let font_ref = match pdf_font {
+        MaybeRef::Indirect(re) => re.get_ref().get_inner(),
+        MaybeRef::Direct(_) => return None
+    };
+    match resolve.resolve(font_ref).and_then(|p| p.into_dictionary()) {
+        Ok(dict) => Some(dict),
+        Err(e) => {
+            warn!("can't read font dictionary: {:?}", e);
+            None
+        }
+    }
+}
+
+/// /FirstChar and /LastChar (or the end of /Widths) of a simple font, if its descriptor has no /MissingWidth
+fn width_range(dict: &Dictionary, resolve: &impl Resolve) -> Option<(u16, u16)> {
+    let first = resolve_entry(dict, "FirstChar", resolve)?.as_integer().ok()?;
+    if let Some(Primitive::Dictionary(descriptor)) = resolve_entry(dict, "FontDescriptor", resolve) {
+        if descriptor.get("MissingWidth").is_some() {
+            return None;
+        }
+    }
+    let last = match resolve_entry(dict, "LastChar", resolve).and_then(|p| p.as_integer().ok()) {
+        Some(last) => last,
+        None => first + resolve_entry(dict, "Widths", resolve)?.into_array().ok()?.len() as i32 - 1,
+    };
+    let clamp = |c: i32| c.clamp(0, u16::MAX as i32) as u16;
+    Some((clamp(first), clamp(last)))
+}
+
+fn resolve_entry(dict: &Dictionary, key: &str, resolve: &impl Resolve) -> Option<Primitive> {
+    match dict.get(key)? {
+        Primitive::Reference(r) => match resolve.resolve(*r) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                warn!("can't resolve /{}: {:?}", key, e);
+                None
+            }
+        },
+        p => Some(p.clone())
+    }
+}
+
+enum RawEncoding {
+    Name(String),
+    CMap(Result<CMap, PdfError>),
+}
+/// Read the /Encoding of a Type0 font, which is either the name of a predefined CMap or an embedded CMap.
+fn raw_encoding(dict: &Dictionary, resolve: &impl Resolve) -> Option<RawEncoding> {
+    match resolve_entry(dict, "Encoding", resolve)? {
+        Primitive::Name(name) => Some(RawEncoding::Name(name.as_str().into())),
+        p @ Primitive::Stream(_) => Some(RawEncoding::CMap(
+            Stream::<()>::from_primitive(p, resolve)
+                .and_then(|stream| stream.data(resolve))
+                .and_then(|data| CMap::parse(&data))
+        )),
+        _ => None
+    }
+}
+
+/// Vertical metrics of a CID font from /W2 and /DW2, in units of 1/1000 em.
+pub struct VerticalMetrics {
+    /// (vy, w1y)
+    default: (f32, f32),
+    /// cid -> (w1y, vx, vy)
+    metrics: HashMap<u16, (f32, f32, f32)>,
+}
+impl VerticalMetrics {
+    fn parse(descendant: Option<&Dictionary>, resolve: &impl Resolve) -> VerticalMetrics {
+        let mut vm = VerticalMetrics {
+            default: (880., -1000.),
+            metrics: HashMap::new(),
+        };
+        let dict = match descendant {
+            Some(d) => d,
+            None => return vm
+        };
+        let numbers = |p: Primitive| -> Option<Vec<Primitive>> { p.into_array().ok() };
+
+        if let Some(dw2) = resolve_entry(dict, "DW2", resolve).and_then(numbers) {
+            if let [ref vy, ref w1y] = dw2[..] {
+                if let (Ok(vy), Ok(w1y)) = (vy.as_number(), w1y.as_number()) {
+                    vm.default = (vy, w1y);
+                }
+            }
+        }
+        if let Some(w2) = resolve_entry(dict, "W2", resolve).and_then(numbers) {
+            let mut i = 0;
+            while i < w2.len() {
+                let first = match w2[i].as_integer() {
+                    Ok(n) => match u32::try_from(n).ok().and_then(cid_u16) {
+                        Some(cid) => cid,
+                        None => break
+                    },
+                    Err(_) => break
+                };
+                match w2.get(i + 1) {
+                    // c [w1y vx vy ...]
+                    Some(&Primitive::Array(ref list)) => {
+                        for (j, m) in list.chunks_exact(3).enumerate() {
+                            let cid = match cid_u16(first as u32 + j as u32) {
+                                Some(cid) => cid,
+                                None => break
+                            };
+                            if let (Ok(w1y), Ok(vx), Ok(vy)) = (m[0].as_number(), m[1].as_number(), m[2].as_number()) {
+                                vm.metrics.insert(cid, (w1y, vx, vy));
+                            }
+                        }
+                        i += 2;
+                    }
+                    // c_first c_last w1y vx vy
+                    Some(last) => {
+                        let last = last.as_integer().map(|n| n.clamp(0, u16::MAX as i32) as u16).unwrap_or(first);
+                        let m = w2.get(i + 2 .. i + 5).unwrap_or(&[]);
+                        if let [ref w1y, ref vx, ref vy] = *m {
+                            if let (Ok(w1y), Ok(vx), Ok(vy)) = (w1y.as_number(), vx.as_number(), vy.as_number()) {
+                                for cid in first ..= last {
+                                    vm.metrics.insert(cid, (w1y, vx, vy));
+                                }
+                            }
+                        }
+                        i += 5;
+                    }
+                    None => break
+                }
+            }
+        }
+        vm
+    }
+
+    /// Returns the vertical displacement w1y and the position vector v for the given CID.
+    ///
+    /// `width` is the horizontal width of the glyph, used for the default vx.
+    pub fn get(&self, cid: u16, width: f32) -> (f32, Vector2F) {
+        match self.metrics.get(&cid) {
+            Some(&(w1y, vx, vy)) => (w1y, Vector2F::new(vx, vy)),
+            None => {
+                let (vy, w1y) = self.default;
+                (w1y, Vector2F::new(0.5 * width, vy))
+            }
+        }
+    }
+}
+
+/// The /FontMatrix of the font program, or the usual 1/1000 scale if it can't be inverted
+fn program_font_matrix(font: &FontRc) -> Transform2F {
+    let m = font.font_matrix();
+    let det = m.m11() * m.m22() - m.m12() * m.m21();
+    if det.is_finite() && det.abs() > 1e-12 {
+        m
+    } else {
+        warn!("degenerate font matrix {:?}", m);
+        Transform2F::from_scale(0.001)
+    }
+}
+
 impl globalcache::ValueSize for FontEntry {
     fn size(&self) -> usize {
         1 // TODO
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_cids_are_skipped() {
+        let cmap = CMap::parse(b"1 begincodespacerange <0000> <FFFF> endcodespacerange\n\
+            2 begincidrange <0001> <0002> 100 <0010> <0011> 65535 endcidrange endcmap").unwrap();
+        // 0x0011 maps to CID 65536, 0x0020 is unmapped
+        let codes: Vec<_> = cmap_codes(&cmap, &[0x00, 0x01, 0x00, 0x11, 0x00, 0x10, 0x00, 0x20, 0x00, 0x02]).collect();
+        std::assert_eq!(codes, [(100, false), (65535, false), (0, false), (101, false)]);
+    }
+}
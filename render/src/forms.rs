@@ -0,0 +1,200 @@
+//! Interactive form fields (/AcroForm), for drawing their values instead of possibly stale /AP streams.
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::Result;
+
+/// fields nested deeper than this are not searched for inherited entries
+const MAX_DEPTH: usize = 32;
+
+// /Ff bits
+const MULTILINE: u32 = 1 << 12;
+const PASSWORD: u32 = 1 << 13;
+const RADIO: u32 = 1 << 15;
+const PUSHBUTTON: u32 = 1 << 16;
+const COMBO: u32 = 1 << 17;
+
+/// The document wide defaults of the interactive form.
+///
+/// Pass it to `RenderConfig::flatten_forms` to draw the widgets of fields with their current values.
+#[derive(Debug, Clone, Default)]
+pub struct AcroForm {
+    /// /DA, for fields that don't have their own
+    pub default_appearance: Option<Vec<u8>>,
+    /// /Q
+    pub quadding: i32,
+    /// /DR, with the fonts named in /DA
+    pub resources: Option<Resources>,
+}
+impl AcroForm {
+    /// Read /AcroForm of the document catalog. `None` if the document has no form.
+    pub fn from_catalog(catalog: &Catalog, resolve: &impl Resolve) -> Result<Option<Self>> {
+        match catalog.other.get("AcroForm") {
+            Some(p) => AcroForm::parse(p, resolve).map(Some),
+            None => Ok(None)
+        }
+    }
+    pub fn parse(acro_form: &Primitive, resolve: &impl Resolve) -> Result<Self> {
+        let dict = acro_form.clone().resolve(resolve)?.into_dictionary()?;
+        let resources = match dict.get("DR") {
+            Some(p) => Some(Resources::from_primitive(p.clone(), resolve)?),
+            None => None
+        };
+        Ok(AcroForm {
+            default_appearance: string(&dict, "DA", resolve),
+            quadding: dict.get("Q").and_then(|q| q.as_integer().ok()).unwrap_or(0),
+            resources,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldKind {
+    Text,
+    CheckBox,
+    RadioButton,
+    PushButton,
+    ComboBox,
+    ListBox,
+    Signature,
+}
+
+/// The field a widget annotation belongs to, with the inheritable entries looked up in its /Parent chain
+#[derive(Debug, Clone)]
+pub struct FormField {
+    pub kind: FieldKind,
+    /// the fully qualified name, the /T of the field and its parents joined by '.'
+    pub name: String,
+    /// /V
+    pub value: Option<Primitive>,
+    /// /DA
+    pub default_appearance: Option<Vec<u8>>,
+    /// /Q, 0 is left aligned, 1 centered and 2 right aligned
+    pub quadding: Option<i32>,
+    /// /Ff
+    pub flags: u32,
+    /// /Opt of choice fields, as pairs of export value and displayed text
+    pub options: Vec<(String, String)>,
+}
+impl FormField {
+    /// `None` if `annot` is not the widget of a field.
+    pub fn from_widget(annot: &Annot, resolve: &impl Resolve) -> Option<FormField> {
+        if annot.subtype.as_str() != "Widget" {
+            return None;
+        }
+        let mut kind = None;
+        let mut names = vec![];
+        let mut value = None;
+        let mut default_appearance = None;
+        let mut quadding = None;
+        let mut flags = None;
+        let mut options = None;
+
+        let mut dict = annot.other.clone();
+        for _ in 0 .. MAX_DEPTH {
+            if kind.is_none() {
+                kind = match dict.get("FT") {
+                    Some(Primitive::Name(ref ft)) => Some(ft.as_str().to_owned()),
+                    _ => None
+                };
+            }
+            if let Some(Primitive::String(t)) = dict.get("T").and_then(|t| t.clone().resolve(resolve).ok()) {
+                names.push(t.to_string_lossy());
+            }
+            if value.is_none() {
+                value = dict.get("V").and_then(|v| v.clone().resolve(resolve).ok());
+            }
+            if default_appearance.is_none() {
+                default_appearance = string(&dict, "DA", resolve);
+            }
+            if quadding.is_none() {
+                quadding = dict.get("Q").and_then(|q| q.as_integer().ok());
+            }
+            if flags.is_none() {
+                flags = dict.get("Ff").and_then(|f| f.as_integer().ok());
+            }
+            if options.is_none() {
+                options = dict.get("Opt").and_then(|o| choice_options(o, resolve));
+            }
+            dict = match dict.get("Parent").and_then(|p| p.clone().resolve(resolve).ok()).and_then(|p| p.into_dictionary().ok()) {
+                Some(parent) => parent,
+                None => break
+            };
+        }
+        let flags = flags.unwrap_or(0) as u32;
+        let kind = match kind?.as_str() {
+            "Tx" => FieldKind::Text,
+            "Btn" if flags & PUSHBUTTON != 0 => FieldKind::PushButton,
+            "Btn" if flags & RADIO != 0 => FieldKind::RadioButton,
+            "Btn" => FieldKind::CheckBox,
+            "Ch" if flags & COMBO != 0 => FieldKind::ComboBox,
+            "Ch" => FieldKind::ListBox,
+            "Sig" => FieldKind::Signature,
+            ft => {
+                debug!("unknown field type {}", ft);
+                return None;
+            }
+        };
+        names.reverse();
+        Some(FormField {
+            kind,
+            name: names.join("."),
+            value,
+            default_appearance,
+            quadding,
+            flags,
+            options: options.unwrap_or_default(),
+        })
+    }
+    pub fn is_multiline(&self) -> bool {
+        self.kind == FieldKind::Text && self.flags & MULTILINE != 0
+    }
+    pub fn is_password(&self) -> bool {
+        self.kind == FieldKind::Text && self.flags & PASSWORD != 0
+    }
+    /// The text shown for the value of text fields and combo boxes.
+    pub fn text(&self) -> Option<String> {
+        let value = match self.value {
+            Some(Primitive::String(ref s)) => s.to_string_lossy(),
+            // the first of several selected options
+            Some(Primitive::Array(ref values)) => match values.first() {
+                Some(Primitive::String(ref s)) => s.to_string_lossy(),
+                _ => return None
+            },
+            _ => return None
+        };
+        match self.options.iter().find(|(export, _)| *export == value) {
+            Some((_, display)) => Some(display.clone()),
+            None => Some(value)
+        }
+    }
+    /// The appearance state selected by the value of check boxes and radio buttons, `Off` if none is.
+    pub fn state(&self) -> &str {
+        match self.value {
+            Some(Primitive::Name(ref n)) => n.as_str(),
+            _ => "Off"
+        }
+    }
+}
+
+fn string(dict: &Dictionary, key: &str, resolve: &impl Resolve) -> Option<Vec<u8>> {
+    match dict.get(key)?.clone().resolve(resolve).ok()? {
+        Primitive::String(s) => Some(s.as_bytes().to_vec()),
+        _ => None
+    }
+}
+
+/// /Opt entries are either the displayed text, or an array of export value and text
+fn choice_options(opt: &Primitive, resolve: &impl Resolve) -> Option<Vec<(String, String)>> {
+    let options = opt.clone().resolve(resolve).ok()?.into_array().ok()?;
+    Some(options.iter().filter_map(|o| match o.clone().resolve(resolve).ok()? {
+        Primitive::String(s) => {
+            let s = s.to_string_lossy();
+            Some((s.clone(), s))
+        }
+        Primitive::Array(pair) => match pair[..] {
+            [Primitive::String(ref export), Primitive::String(ref text)] => Some((export.to_string_lossy(), text.to_string_lossy())),
+            _ => None
+        },
+        _ => None
+    }).collect())
+}
@@ -1,26 +1,37 @@
-use pathfinder_content::stroke::StrokeStyle;
+use pathfinder_content::stroke::{StrokeStyle, LineJoin};
 use pathfinder_renderer::{paint::PaintId, scene::ClipPath};
-use pdf::object::ColorSpace;
+use pdf::object::{ColorSpace, Resolve, RenderingIntent};
+use pdf::primitive::Primitive;
+use pdf::function::Function;
+use pdf::error::{PdfError, Result};
+use std::sync::Arc;
 
 use pathfinder_geometry::{transform2d::Transform2F, rect::RectF};
-use crate::{Fill, backend::Stroke, Backend};
+use crate::{Fill, backend::{Stroke, Blend}, Backend, colorspace::paints_nothing};
 
 pub struct GraphicsState<'a, B: Backend> {
     pub transform: Transform2F,
     pub stroke_style: StrokeStyle,
+    /// kept separately since it only applies to miter joins
+    pub miter_limit: f32,
 
     pub fill_color: Fill,
     pub fill_color_alpha: f32,
+    /// color of uncolored tiling patterns
+    pub fill_pattern_color: Fill,
     pub fill_paint: Option<PaintId>,
     pub stroke_color: Fill,
     pub stroke_color_alpha: f32,
+    /// color of uncolored tiling patterns used for stroking
+    pub stroke_pattern_color: Fill,
     pub stroke_paint: Option<PaintId>,
     pub clip_path_id: Option<B::ClipPathId>,
     pub clip_path: Option<ClipPath>,
     pub clip_path_rect: Option<RectF>,
     pub fill_color_space: &'a ColorSpace,
     pub stroke_color_space: &'a ColorSpace,
-    pub dash_pattern: Option<(&'a [f32], f32)>,
+    /// `None` for solid lines
+    pub dash_pattern: Option<(Arc<[f32]>, f32)>,
 
     pub stroke_alpha: f32,
     pub fill_alpha: f32,
@@ -28,12 +39,22 @@ pub struct GraphicsState<'a, B: Backend> {
     pub overprint_fill: bool,
     pub overprint_stroke: bool,
     pub overprint_mode: i32,
+    pub blend: Blend,
+    /// /TR or /TR2, applied to the fill and stroke colors
+    pub transfer: Option<Arc<Transfer>>,
+    /// colors as set, before the transfer function
+    pub fill_color_source: Fill,
+    pub stroke_color_source: Fill,
+    /// /RI or `ri`, for converting ICC based colors
+    pub rendering_intent: RenderingIntent,
 }
 
 impl<'a, B: Backend> Clone for GraphicsState<'a, B> {
     fn clone(&self) -> Self {
         GraphicsState {
             clip_path: self.clip_path.clone(),
+            dash_pattern: self.dash_pattern.clone(),
+            transfer: self.transfer.clone(),
             .. *self
         }
     }
@@ -41,7 +62,22 @@ impl<'a, B: Backend> Clone for GraphicsState<'a, B> {
 
 
 impl<'a, B: Backend> GraphicsState<'a, B> {
+    /// Whether filling and stroking leave marks, they don't in the Separation `None`.
+    pub fn marks(&self) -> (bool, bool) {
+        (!paints_nothing(self.fill_color_space), !paints_nothing(self.stroke_color_space))
+    }
+    /// Colors set before a new transfer function are mapped by it as well.
+    pub fn set_transfer(&mut self, transfer: Option<Arc<Transfer>>) {
+        self.transfer = transfer;
+        self.set_fill_color(self.fill_color_source);
+        self.set_stroke_color(self.stroke_color_source);
+    }
     pub fn set_fill_color(&mut self, fill: Fill) {
+        self.fill_color_source = fill;
+        let fill = match self.transfer {
+            Some(ref tr) => tr.apply(fill),
+            None => fill
+        };
         if fill != self.fill_color {
             self.fill_color = fill;
             self.fill_paint = None;
@@ -55,6 +91,11 @@ impl<'a, B: Backend> GraphicsState<'a, B> {
         }
     }
     pub fn set_stroke_color(&mut self, fill: Fill) {
+        self.stroke_color_source = fill;
+        let fill = match self.transfer {
+            Some(ref tr) => tr.apply(fill),
+            None => fill
+        };
         if fill != self.stroke_color {
             self.stroke_color = fill;
             self.stroke_paint = None;
@@ -63,14 +104,92 @@ impl<'a, B: Backend> GraphicsState<'a, B> {
     pub fn set_stroke_alpha(&mut self, alpha: f32) {
         let a = self.stroke_alpha * alpha;
         if a != self.stroke_color_alpha {
-            self.stroke_alpha = a;
+            self.stroke_color_alpha = a;
             self.stroke_paint = None;
         }
     }
+    pub fn set_line_join(&mut self, join: LineJoin) {
+        self.stroke_style.line_join = match join {
+            LineJoin::Miter(_) => LineJoin::Miter(self.miter_limit),
+            join => join
+        };
+    }
+    pub fn set_miter_limit(&mut self, limit: f32) {
+        self.miter_limit = limit;
+        if let LineJoin::Miter(_) = self.stroke_style.line_join {
+            self.stroke_style.line_join = LineJoin::Miter(limit);
+        }
+    }
+    /// An empty pattern, or one without any length, is solid.
+    pub fn set_dash(&mut self, pattern: &[f32], phase: f32) {
+        let total: f32 = pattern.iter().sum();
+        if pattern.is_empty() || total <= 0.0 || pattern.iter().any(|&d| d < 0.0) {
+            self.dash_pattern = None;
+            return;
+        }
+        // an odd number of entries repeats with on and off swapped
+        let pattern: Arc<[f32]> = match pattern.len() % 2 {
+            0 => pattern.into(),
+            _ => pattern.iter().chain(pattern).cloned().collect(),
+        };
+        let period: f32 = pattern.iter().sum();
+        self.dash_pattern = Some((pattern, phase.rem_euclid(period)));
+    }
     pub fn stroke(&self) -> Stroke {
         Stroke {
             style: self.stroke_style,
-            dash_pattern: self.dash_pattern.map(|(a, p)| (a.into(), p))
+            dash_pattern: self.dash_pattern.as_ref().map(|(a, p)| (a.to_vec(), *p))
+        }
+    }
+}
+
+/// number of samples of each transfer function
+const TRANSFER_SAMPLES: usize = 256;
+
+/// A transfer function, sampled for the red, green and blue components.
+///
+/// Black generation and undercolor removal only apply when converting to CMYK, so they are ignored.
+pub struct Transfer {
+    lut: [Vec<f32>; 3],
+}
+impl Transfer {
+    /// `/TR` or `/TR2`: a function for all components, an array of four (red, green, blue, gray),
+    /// or /Identity or /Default, which give `None`.
+    pub fn parse(p: &Primitive, resolve: &impl Resolve) -> Result<Option<Transfer>> {
+        let sample = |p: &Primitive| -> Result<Option<Vec<f32>>> {
+            match *p {
+                Primitive::Name(_) => Ok(None),
+                ref p => {
+                    let f = Function::from_primitive(p.clone(), resolve)?;
+                    (0 .. TRANSFER_SAMPLES).map(|i| {
+                        let mut out = [0.0];
+                        f.apply(&[i as f32 / (TRANSFER_SAMPLES - 1) as f32], &mut out)?;
+                        Ok(out[0].max(0.0).min(1.0))
+                    }).collect::<Result<Vec<f32>>>().map(Some)
+                }
+            }
+        };
+        let identity = || (0 .. TRANSFER_SAMPLES).map(|i| i as f32 / (TRANSFER_SAMPLES - 1) as f32).collect::<Vec<f32>>();
+        match p.clone().resolve(resolve)? {
+            Primitive::Array(ref parts) => {
+                if parts.len() != 4 {
+                    return Err(PdfError::Other { msg: format!("transfer function array with {} entries", parts.len()) });
+                }
+                let mut luts = parts[.. 3].iter().map(|p| sample(p)).collect::<Result<Vec<_>>>()?;
+                if luts.iter().all(|l| l.is_none()) {
+                    return Ok(None);
+                }
+                let mut lut = || luts.remove(0).unwrap_or_else(identity);
+                Ok(Some(Transfer { lut: [lut(), lut(), lut()] }))
+            }
+            ref p => Ok(sample(p)?.map(|lut| Transfer { lut: [lut.clone(), lut.clone(), lut] }))
+        }
+    }
+    pub fn apply(&self, fill: Fill) -> Fill {
+        let map = |lut: &[f32], v: f32| lut[(v.max(0.0).min(1.0) * (TRANSFER_SAMPLES - 1) as f32).round() as usize];
+        match fill {
+            Fill::Solid(r, g, b) => Fill::Solid(map(&self.lut[0], r), map(&self.lut[1], g), map(&self.lut[2], b)),
+            fill => fill
         }
     }
 }
@@ -0,0 +1,80 @@
+//! Grid fitting of glyph outlines, for text that stays legible at small sizes.
+//!
+//! Only vertical positions are fitted: the horizontal edges of a glyph (the baseline, x-height, cap height and the
+//! top and bottom of horizontal stems) are moved to whole device pixels, and everything between them is
+//! interpolated. Advance widths are not changed, so the text keeps its layout.
+use pathfinder_content::outline::{Outline, Contour};
+use pathfinder_geometry::{transform2d::Transform2F, vector::Vector2F};
+
+/// edges closer than this in glyph space are the same edge
+const EDGE_EPSILON: f32 = 1e-3;
+
+/// `path` (in glyph space) with its horizontal edges on whole pixels of the device space of `transform`.
+/// `None` if the glyph is rotated or skewed, or has no horizontal edges.
+pub fn hint_outline(path: &Outline, transform: Transform2F) -> Option<Outline> {
+    let (sy, ty) = (transform.m22(), transform.translation().y());
+    if transform.m21().abs() > 1e-4 * sy.abs() || transform.m12().abs() > 1e-4 * transform.m11().abs() || sy == 0.0 {
+        return None;
+    }
+
+    // y of the on-curve points that have a horizontal tangent, in device space
+    let mut edges = vec![];
+    for contour in path.contours() {
+        let n = contour.len();
+        for i in 0 .. n {
+            if !contour.flags_of(i).is_empty() {
+                continue;
+            }
+            let y = contour.position_of(i).y();
+            let prev = contour.position_of((i + n - 1) % n).y();
+            let next = contour.position_of((i + 1) % n).y();
+            let edge = sy * y + ty;
+            if edge.is_finite() && ((y - prev).abs() < EDGE_EPSILON || (y - next).abs() < EDGE_EPSILON) {
+                edges.push(edge);
+            }
+        }
+    }
+    if edges.is_empty() {
+        return None;
+    }
+    edges.sort_by(f32::total_cmp);
+    edges.dedup_by(|a, b| (*a - *b).abs() < 0.01);
+
+    // (device y, fitted device y). edges at least half a pixel apart stay at least one pixel apart,
+    // so thin stems don't disappear.
+    let mut fitted: Vec<(f32, f32)> = Vec::with_capacity(edges.len());
+    for &y in &edges {
+        let mut f = y.round();
+        if let Some(&(py, pf)) = fitted.last() {
+            if y - py >= 0.5 && f <= pf {
+                f = pf + 1.0;
+            }
+        }
+        fitted.push((y, f));
+    }
+    let fit = |y: f32| {
+        let d = sy * y + ty;
+        let i = fitted.partition_point(|&(e, _)| e < d);
+        let f = match (i.checked_sub(1).map(|i| fitted[i]), fitted.get(i)) {
+            (Some((y0, f0)), Some(&(y1, f1))) => f0 + (d - y0) * (f1 - f0) / (y1 - y0),
+            (Some((y0, f0)), None) => d + f0 - y0,
+            (None, Some(&(y1, f1))) => d + f1 - y1,
+            (None, None) => d,
+        };
+        (f - ty) / sy
+    };
+
+    let mut hinted = Outline::new();
+    for contour in path.contours() {
+        let mut c = Contour::new();
+        for i in 0 .. contour.len() {
+            let p = contour.position_of(i);
+            c.push_point(Vector2F::new(p.x(), fit(p.y())), contour.flags_of(i), true);
+        }
+        if contour.is_closed() {
+            c.close();
+        }
+        hinted.push_contour(c);
+    }
+    Some(hinted)
+}
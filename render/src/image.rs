@@ -1,12 +1,18 @@
 use image::{RgbaImage, ImageBuffer, Rgba};
 use pdf::object::*;
+use pdf::enc::{StreamFilter, CCITTFaxDecodeParams, JBIG2DecodeParams};
 use pdf::error::PdfError;
+use pdf::primitive::Primitive;
 use pathfinder_color::ColorU;
+use pathfinder_content::outline::{Outline, Contour};
+use pathfinder_geometry::{rect::RectF, vector::Vector2F};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 
-use crate::BlendMode;
+use crate::{BlendMode, IccTransform, CmykConversion, ccitt, jbig2};
+use crate::colorspace::{rendering_intent, indexed_palette, ColorCache};
 
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct ImageData<'a> {
@@ -106,10 +112,130 @@ fn resize_alpha(data: &[u8], src_width: u32, src_height: u32, dest_width: u32, d
     Some(dest.into_raw())
 }
 
-pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> Result<ImageData<'static>, PdfError> {
+/// The decoded samples of the image, and the color space of JPX data
+fn image_samples(image: &ImageXObject, resolve: &impl Resolve) -> Result<(Arc<[u8]>, Option<&'static ColorSpace>), PdfError> {
     let raw_data = image.image_data(resolve)?;
+    match image.inner.filters.last() {
+        // image_data leaves fax and JBIG2 data encoded
+        Some(StreamFilter::CCITTFaxDecode(ref params)) => Ok((fax_decode(&raw_data, params, image)?.into(), None)),
+        Some(StreamFilter::JBIG2Decode(ref params)) => Ok((jbig2_decode(&raw_data, params, image, resolve)?.into(), None)),
+        Some(StreamFilter::DCTDecode(_)) => {
+            let (encoded, _) = image.raw_image_data(resolve)?;
+            let info = match jpeg_info(&encoded) {
+                Some(info) => info,
+                None => {
+                    warn!("can't read the JPEG markers");
+                    return Ok((raw_data, None));
+                }
+            };
+            debug!("JPEG: {} components, progressive: {}, APP14 transform: {:?}", info.components, info.progressive, info.adobe_transform);
+            let cs = match info.components {
+                1 => Some(&ColorSpace::DeviceGray),
+                3 => Some(&ColorSpace::DeviceRGB),
+                4 => Some(&ColorSpace::DeviceCMYK),
+                _ => None
+            };
+            if info.components == 4 {
+                return Ok((cmyk_jpeg_samples(raw_data, info.adobe_transform), cs));
+            }
+            Ok((raw_data, cs))
+        }
+        Some(StreamFilter::JPXDecode) => match jpx_decode(&raw_data) {
+            Ok((data, cs)) => Ok((data.into(), cs)),
+            Err(e) => {
+                warn!("can't decode JPX image: {:?}", e);
+                Err(e)
+            }
+        }
+        _ => Ok((raw_data, None))
+    }
+}
+
+/// Load an /ImageMask image: painted pixels get `color`, all others are transparent.
+pub fn load_stencil(image: &ImageXObject, resolve: &impl Resolve, color: ColorU) -> Result<ImageData<'static>, PdfError> {
+    let (data, _) = image_samples(image, resolve)?;
+    let (width, height) = (image.width as usize, image.height as usize);
+    // /Decode [1 0] paints the 1 samples instead of the 0 samples
+    let inverted = match image.decode.as_deref() {
+        Some(&[d0, d1, ..]) => d0 > d1,
+        _ => false
+    };
+    let transparent = ColorU::new(color.r, color.g, color.b, 0);
+
+    let mut pixels = Vec::with_capacity(width * height);
+    if data.len() == width * height {
+        // unpacked to one byte per pixel
+        pixels.extend(data.iter().map(|&v| if (v < 128) != inverted { color } else { transparent }));
+    } else {
+        let stride = (width + 7) / 8;
+        if data.len() < stride * height {
+            return Err(PdfError::Other { msg: format!("image mask has {} bytes, expected {}", data.len(), stride * height) });
+        }
+        for row in data.chunks(stride).take(height) {
+            pixels.extend((0 .. width).map(|x| {
+                let bit = row[x / 8] & (0x80u8 >> (x % 8)) != 0;
+                if bit == inverted { color } else { transparent }
+            }));
+        }
+    }
+    ImageData::new(pixels, width as u32, height as u32).ok_or_else(|| PdfError::Other { msg: "size mismatch".into() })
+}
+
+/// The painted pixels of the image mask `image` as rectangles in the unit square, like `load_stencil` paints them.
+///
+/// Each run of pixels in a row becomes a rectangle, which the same run in the following rows extends.
+pub(crate) fn stencil_outline(image: &ImageXObject, resolve: &impl Resolve) -> Result<Outline, PdfError> {
+    let stencil = load_stencil(image, resolve, ColorU::white())?;
+    let (width, height) = (image.width as usize, image.height as usize);
+    let mut outline = Outline::new();
+    if width == 0 || height == 0 {
+        return Ok(outline);
+    }
+    let (sx, sy) = (1.0 / width as f32, 1.0 / height as f32);
+    // (first x, end x) -> first row of the runs that are still open
+    let mut open: HashMap<(usize, usize), usize> = HashMap::new();
+    let close = |outline: &mut Outline, (x0, x1): (usize, usize), y0: usize, y1: usize| {
+        let rect = RectF::from_points(Vector2F::new(x0 as f32 * sx, 1.0 - y1 as f32 * sy), Vector2F::new(x1 as f32 * sx, 1.0 - y0 as f32 * sy));
+        outline.push_contour(Contour::from_rect(rect));
+    };
+    for (y, row) in stencil.data().chunks_exact(width).enumerate() {
+        let mut runs = vec![];
+        let mut x = 0;
+        while x < width {
+            if row[x].a == 0 {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < width && row[x].a != 0 {
+                x += 1;
+            }
+            runs.push((start, x));
+        }
+        let ended: Vec<_> = open.keys().filter(|run| !runs.contains(run)).cloned().collect();
+        for run in ended {
+            let y0 = open.remove(&run).unwrap();
+            close(&mut outline, run, y0, y);
+        }
+        for run in runs {
+            open.entry(run).or_insert(y);
+        }
+    }
+    for (run, y0) in open {
+        close(&mut outline, run, y0, height);
+    }
+    Ok(outline)
+}
+
+/// `intent` is the rendering intent of the graphics state, used for ICC based images without an /Intent.
+pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, icc: Option<&dyn IccTransform>, cmyk: CmykConversion, intent: RenderingIntent) -> Result<ImageData<'static>, PdfError> {
+    // color space of the JPEG or JPX data, used if the image has none
+    let (raw_data, jpx_cs) = image_samples(image, resolve)?;
 
     let pixel_count = image.width as usize * image.height as usize;
+    if pixel_count == 0 {
+        return Err(PdfError::Other { msg: format!("image has no pixels ({}x{})", image.width, image.height) });
+    }
 
     if raw_data.len() % pixel_count != 0 {
         warn!("invalid data length {} bytes for {} pixels", raw_data.len(), pixel_count);
@@ -145,30 +271,50 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
             let mask_width = mask.width as usize;
             let mask_height = mask.height as usize;
             let bits_per_component = mask.bits_per_component.ok_or_else(|| PdfError::Other { msg: format!("no bits per component")})?;
-            let bits = mask_width * mask_height * bits_per_component as usize;
-            assert_eq!(data.len(), (bits + 7) / 8);
-
-            let mut alpha: Data = match bits_per_component {
-                1 => data.iter().flat_map(|&b| (0..8).map(move |i| ex(b >> i, 1))).collect::<Vec<u8>>().into(),
-                2 => data.iter().flat_map(|&b| (0..4).map(move |i| ex(b >> 2*i, 2))).collect::<Vec<u8>>().into(),
-                4 => data.iter().flat_map(|&b| (0..2).map(move |i| ex(b >> 4*i, 4))).collect::<Vec<u8>>().into(),
-                8 => data,
-                12 => data.chunks_exact(3).flat_map(|c| [c[0], c[1] << 4 | c[2] >> 4]).collect::<Vec<u8>>().into(),
-                16 => data.chunks_exact(2).map(|c| c[0]).collect::<Vec<u8>>().into(),
-                n => return Err(PdfError::Other { msg: format!("invalid bits per component {}", n)})
+            let stride = (mask_width * bits_per_component as usize + 7) / 8;
+            if data.len() < stride * mask_height {
+                return Err(PdfError::Other { msg: format!("SMask has {} bytes, expected {}", data.len(), stride * mask_height) });
+            }
+
+            let samples: Data = match bits_per_component {
+                8 if data.len() == mask_width * mask_height => data,
+                n => unpack_gray(&data, mask_width, mask_height, n as usize)?.into(),
+            };
+            let mut alpha: Data = match decode_samples(&samples, 1, 8, mask.decode.as_deref(), None) {
+                Cow::Owned(v) => v.into(),
+                Cow::Borrowed(_) => samples,
             };
             if mask.width != image.width || mask.height != image.height {
-                alpha = resize_alpha(&*alpha, mask.width, mask.height, image.width, image.height).unwrap().into();
+                alpha = resize_alpha(&*alpha, mask.width, mask.height, image.width, image.height)
+                    .ok_or_else(|| PdfError::Other { msg: format!("can't scale {}x{} SMask to the image", mask.width, mask.height) })?
+                    .into();
             }
             alpha
         }
         None => Data::Slice(&[][..])
     };
-    #[inline]
-    fn ex(b: u8, bits: u8) -> u8 {
-        b & ((1 << bits) - 1)
-    }
-    
+    // color key masking on the raw samples. a SMask takes precedence.
+    let alpha = match image.mask {
+        Some(Primitive::Array(ref ranges)) if mask.is_none() => {
+            let ranges = ranges.iter().map(|p| p.as_integer().map(|i| i.max(0) as u32)).collect::<Result<Vec<u32>, _>>()?;
+            match color_key(&raw_data, image, &ranges) {
+                Some(keyed) => keyed.into(),
+                None => {
+                    warn!("color key mask {:?} does not match the image", ranges);
+                    alpha
+                }
+            }
+        }
+        _ => alpha
+    };
+    // color the image was pre-blended with, in the color space of the image
+    let matte = match mask {
+        Some(ref mask) => match mask.other.get("Matte") {
+            Some(p) => Some(t!(t!(p.clone().resolve(resolve)).into_array()).iter().map(|n| n.as_number()).collect::<Result<Vec<f32>, _>>()?),
+            None => None
+        },
+        None => None
+    };
     fn resolve_cs<'a>(cs: &'a ColorSpace, resources: &'a Resources) -> Option<&'a ColorSpace> {
         match cs {
             ColorSpace::Icc(icc) => {
@@ -182,14 +328,117 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
                     }
                 }
             }
-            ColorSpace::Named(ref name) => resources.color_spaces.get(name),
+            // inline images use abbreviations
+            ColorSpace::Named(ref name) => match name.as_str() {
+                "G" | "DeviceGray" => Some(&ColorSpace::DeviceGray),
+                "RGB" | "DeviceRGB" => Some(&ColorSpace::DeviceRGB),
+                "CMYK" | "DeviceCMYK" => Some(&ColorSpace::DeviceCMYK),
+                name => {
+                    let cs = resources.color_spaces.get(name);
+                    if cs.is_none() {
+                        warn!("unknown color space {}", name);
+                    }
+                    cs
+                }
+            }
             _ => Some(cs),
         }
     }
 
-    let cs = image.color_space.as_ref().and_then(|cs| resolve_cs(cs, &resources));
-    let alpha = alpha.iter().cloned().chain(std::iter::repeat(255));
-    let data_ratio = (raw_data.len() * 8) / pixel_count;
+    /// A base space color given by 8 bit components
+    fn base_color(base: &ColorSpace, c: &[u8], resources: &Resources, mode: BlendMode, cmyk: CmykConversion) -> Result<ColorU, PdfError> {
+        Ok(match (resolve_cs(base, resources), c) {
+            (Some(ColorSpace::DeviceGray), &[g]) => ColorU::new(g, g, g, 255),
+            (Some(ColorSpace::DeviceRGB), c) if c.len() == 3 => rgb2rgba(c, 255, mode),
+            (Some(ColorSpace::DeviceCMYK), &[c, m, y, k]) => cmyk2color([c, m, y, k], 255, mode, cmyk),
+            (Some(ColorSpace::Separation(_, ref alt, ref func)), &[t]) => {
+                let v = t as f32 / 255.;
+                match resolve_cs(alt, resources) {
+                    Some(ColorSpace::DeviceGray) => {
+                        let mut g = [0.];
+                        func.apply(&[v], &mut g)?;
+                        let g = (g[0].clamp(0., 1.) * 255.) as u8;
+                        ColorU::new(g, g, g, 255)
+                    }
+                    Some(ColorSpace::DeviceRGB) => {
+                        let mut c = [0.; 3];
+                        func.apply(&[v], &mut c)?;
+                        let [r, g, b] = c;
+                        let [r, g, b] = rgb2rgb(r, g, b, mode);
+                        ColorU::new(r, g, b, 255)
+                    }
+                    Some(ColorSpace::DeviceCMYK) => {
+                        let mut c = [0.; 4];
+                        func.apply(&[v], &mut c)?;
+                        let [c, m, y, k] = c.map(|x| (x.clamp(0., 1.) * 255.) as u8);
+                        cmyk2color([c, m, y, k], 255, mode, cmyk)
+                    }
+                    _ => return Err(PdfError::Other { msg: format!("Separation with alternate {:?} as a device base", alt) }),
+                }
+            }
+            _ => return Err(PdfError::Other { msg: format!("{:?} with {} components as a device base", base, c.len()) }),
+        })
+    }
+    /// The colors of an indexed color space, for the indices 0 ..= hival
+    #[allow(clippy::too_many_arguments)]
+    fn palette(base: &ColorSpace, hival: u8, lookup: &[u8], resources: &Resources, resolve: &impl Resolve, mode: BlendMode, icc: Option<&dyn IccTransform>, cmyk: CmykConversion, intent: RenderingIntent) -> Result<Vec<ColorU>, PdfError> {
+        // ICC based bases use the embedded profile if there is a transform
+        let named = match *base {
+            ColorSpace::Named(ref name) => resources.color_spaces.get(name.as_str()),
+            _ => None
+        };
+        if let Some(colors) = icc.and_then(|icc| icc_palette(named.unwrap_or(base), hival, lookup, resolve, icc, intent)) {
+            return Ok(colors.chunks_exact(3).map(|c| rgb2rgba(c, 255, mode)).collect());
+        }
+        let is_device = |cs: &ColorSpace| matches!(resolve_cs(cs, resources), Some(ColorSpace::DeviceGray | ColorSpace::DeviceRGB | ColorSpace::DeviceCMYK));
+        let n = match resolve_cs(base, resources) {
+            Some(ColorSpace::DeviceGray) => 1,
+            Some(ColorSpace::DeviceRGB) => 3,
+            Some(ColorSpace::DeviceCMYK) => 4,
+            Some(ColorSpace::Separation(_, ref alt, _)) if is_device(alt) => 1,
+            // other bases go through the conversion of fill colors
+            _ => return general_palette(base, hival, lookup, resources, resolve, mode, cmyk, intent),
+        };
+        let entries = hival as usize + 1;
+        if lookup.len() < entries * n {
+            warn!("lookup table has {} bytes, expected {}", lookup.len(), entries * n);
+        }
+        (0 .. entries).map(|i| {
+            // missing entries are zero
+            let mut c = [0; 4];
+            for (j, c) in c[..n].iter_mut().enumerate() {
+                *c = lookup.get(i * n + j).cloned().unwrap_or(0);
+            }
+            base_color(base, &c[..n], resources, mode, cmyk)
+        }).collect()
+    }
+
+    /// The colors of an indexed color space with any base
+    #[allow(clippy::too_many_arguments)]
+    fn general_palette(base: &ColorSpace, hival: u8, lookup: &[u8], resources: &Resources, resolve: &impl Resolve, mode: BlendMode, cmyk: CmykConversion, intent: RenderingIntent) -> Result<Vec<ColorU>, PdfError> {
+        let mut colors = ColorCache::new(None, cmyk);
+        colors.set_intent(intent);
+        let palette = indexed_palette(base, hival, lookup, resources, resolve, mode, &mut colors)?;
+        Ok(palette.into_iter().map(|(r, g, b)| {
+            let [r, g, b] = rgb2rgb(r.clamp(0., 1.), g.clamp(0., 1.), b.clamp(0., 1.), mode);
+            ColorU::new(r, g, b, 255)
+        }).collect())
+    }
+
+    let inline_cs = image.color_space.as_ref().and_then(|cs| inline_indexed(cs, resolve));
+    let cs = inline_cs.as_ref().or(image.color_space.as_ref()).and_then(|cs| resolve_cs(cs, &resources)).or(jpx_cs);
+    // the ICC conversion already applies /Decode and the /Matte, which is given in the ICC color space
+    let (raw_data, cs, decode, matte) = match icc_to_rgb(image, &raw_data, pixel_count, icc, intent, matte.as_deref(), &*alpha, resolve) {
+        Some(rgb) => (rgb.into(), Some(&ColorSpace::DeviceRGB), None, None),
+        None => (raw_data, cs, image.decode.as_deref(), matte)
+    };
+    let alpha_samples = &*alpha;
+    let alpha = alpha_samples.iter().cloned().chain(std::iter::repeat(255));
+    // single component samples of less than 8 bits are packed, with every row starting on a byte boundary.
+    // the fax and JBIG2 decoders already produce one byte per pixel.
+    let bits = image.bits_per_component.unwrap_or(8) as usize;
+    let packed = matches!(bits, 1 | 2 | 4) && raw_data.len() < pixel_count;
+    let data_ratio = if packed { bits } else { (raw_data.len() * 8) / pixel_count };
     // dbg!(data_ratio);
 
     debug!("CS: {cs:?}");
@@ -197,12 +446,19 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
     let data = match data_ratio {
         1 | 2 | 4 | 8 => {
             let pixel_data: Cow<[u8]> = match data_ratio {
-                1 => raw_data.iter().flat_map(|&b| (0..8).map(move |i| ex(b >> i, 1))).take(pixel_count).collect::<Vec<u8>>().into(),
-                2 => raw_data.iter().flat_map(|&b| (0..4).map(move |i| ex(b >> 2*i, 2))).take(pixel_count).collect::<Vec<u8>>().into(),
-                4 => raw_data.iter().flat_map(|&b| (0..2).map(move |i| ex(b >> 4*i, 4))).take(pixel_count).collect::<Vec<u8>>().into(),
+                1 | 2 | 4 => unpack_samples(&raw_data, image.width as usize, image.height as usize, data_ratio)?.into(),
                 8 => Cow::Borrowed(&raw_data[..pixel_count]),
                 n => return Err(PdfError::Other { msg: format!("invalid bits per component {}", n)})
             };
+            let hival = match cs {
+                Some(&ColorSpace::Indexed(_, hival, _)) => Some(hival as usize),
+                _ => None
+            };
+            let pixel_data = decode_samples(&pixel_data, 1, data_ratio, decode, hival);
+            let pixel_data = match hival {
+                Some(_) => pixel_data,
+                None => unmatte(pixel_data, 1, matte.as_deref(), alpha_samples)
+            };
             let pixel_data: &[u8] = &*pixel_data;
             // dbg!(&cs);
             match cs {
@@ -211,28 +467,11 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
                     pixel_data.iter().zip(alpha).map(|(&g, a)| ColorU { r: g, g: g, b: g, a }).collect()
                 }
                 Some(&ColorSpace::Indexed(ref base, hival, ref lookup)) => {
-                    match resolve_cs(&**base, resources) {
-                        Some(ColorSpace::DeviceRGB) => {
-                            let mut data = Vec::with_capacity(pixel_data.len());
-                            for (&b, a) in pixel_data.iter().zip(alpha) {
-                                let off = b as usize * 3;
-                                let c = lookup.get(off .. off + 3).ok_or(PdfError::Bounds { index: off, len: lookup.len() })?;
-                                data.push(rgb2rgba(c, a, mode));
-                            }
-                            data
-                        }
-                        Some(ColorSpace::DeviceCMYK) => {
-                            debug!("indexed CMYK {}", lookup.len());
-                            let mut data = Vec::with_capacity(pixel_data.len());
-                            for (&b, a) in pixel_data.iter().zip(alpha) {
-                                let off = b as usize * 4;
-                                let c = lookup.get(off .. off + 4).ok_or(PdfError::Bounds { index: off, len: lookup.len() })?;
-                                data.push(cmyk2color(c.try_into().unwrap(), a, BlendMode::Darken));
-                            }
-                            data
-                        }
-                        _ => unimplemented!("base cs={:?}", base),
-                    }
+                    let palette = palette(base, hival, lookup, resources, resolve, mode, icc, cmyk, intent)?;
+                    // indices above hival are clamped
+                    pixel_data.iter().zip(alpha).map(|(&i, a)| {
+                        ColorU { a, .. palette[(i as usize).min(hival as usize)] }
+                    }).collect()
                 }
                 Some(&ColorSpace::Separation(_, ref alt, ref func)) => {
                     let mut lut = [[0u8; 3]; 256];
@@ -251,7 +490,7 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
                                 let mut c = [0.; 4];
                                 func.apply(&[i as f32 / 255.], &mut c)?;
                                 let [c, m, y, k] = c;
-                                *rgb = cmyk2rgb([(c * 255.) as u8, (m * 255.) as u8, (y * 255.) as u8, (k * 255.) as u8], mode);
+                                *rgb = cmyk2rgb([(c * 255.) as u8, (m * 255.) as u8, (y * 255.) as u8, (k * 255.) as u8], mode, cmyk);
                             }
                         }
                         _ => unimplemented!("alt cs={:?}", alt),
@@ -273,13 +512,15 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
             if !matches!(cs, Some(ColorSpace::DeviceRGB)) {
                 info!("image has data/pixel ratio of 3, but colorspace is {:?}", cs);
             }
-            raw_data[..pixel_count * 3].chunks_exact(3).zip(alpha).map(|(c, a)| rgb2rgba(c, a, mode)).collect()
+            let rgb = unmatte(decode_samples(&raw_data[..pixel_count * 3], 3, 8, decode, None), 3, matte.as_deref(), alpha_samples);
+            rgb.chunks_exact(3).zip(alpha).map(|(c, a)| rgb2rgba(c, a, mode)).collect()
         }
         32 => {
             if !matches!(cs, Some(ColorSpace::DeviceCMYK)) {
                 info!("image has data/pixel ratio of 4, but colorspace is {:?}", cs);
             }
-            cmyk2color_arr(&raw_data[..pixel_count * 4], alpha, mode)
+            let samples = unmatte(decode_samples(&raw_data[..pixel_count * 4], 4, 8, decode, None), 4, matte.as_deref(), alpha_samples);
+            cmyk2color_arr(&samples, alpha, mode, cmyk)
         }
         _ => unimplemented!("data/pixel ratio {}", data_ratio),
     };
@@ -297,6 +538,377 @@ pub fn load_image(image: &ImageXObject, resources: &Resources, resolve: &impl Re
     }
 }
 
+/// The thumbnail image embedded in the page (/Thumb), if it has one.
+pub fn embedded_thumbnail(page: &Page, resolve: &impl Resolve, icc: Option<&dyn IccTransform>, cmyk: CmykConversion) -> Result<Option<ImageData<'static>>, PdfError> {
+    let thumb = match page.other.get("Thumb") {
+        Some(p) => p.clone(),
+        None => return Ok(None)
+    };
+    let image = ImageXObject { inner: t!(Stream::<ImageDict>::from_primitive(thumb, resolve)) };
+    let resources = t!(page.resources());
+    load_image(&image, &resources, resolve, BlendMode::Overlay, icc, cmyk, RenderingIntent::RelativeColorimetric).map(Some)
+}
+
+/// `[/I base hival lookup]` of an inline image, which is not parsed as an indexed color space
+fn inline_indexed(cs: &ColorSpace, resolve: &impl Resolve) -> Option<ColorSpace> {
+    let parts = match *cs {
+        ColorSpace::Other(ref parts) => parts,
+        _ => return None
+    };
+    let (base, hival, lookup) = match parts.as_slice() {
+        [Primitive::Name(ref i), base, hival, lookup] if i.as_str() == "I" || i.as_str() == "Indexed" => (base, hival, lookup),
+        _ => return None
+    };
+    let base = match *base {
+        Primitive::Name(ref name) => ColorSpace::Named(name.clone()),
+        ref p => match ColorSpace::from_primitive(p.clone(), resolve) {
+            Ok(cs) => cs,
+            Err(e) => {
+                warn!("invalid base of indexed color space: {:?}", e);
+                return None;
+            }
+        }
+    };
+    let hival = hival.as_integer().ok()?.clamp(0, 255) as u8;
+    let lookup: Arc<[u8]> = match lookup.clone().resolve(resolve).ok()? {
+        Primitive::String(s) => s.as_bytes().into(),
+        p @ Primitive::Stream(_) => Stream::<()>::from_primitive(p, resolve).ok()?.data(resolve).ok()?,
+        p => {
+            warn!("invalid lookup table {:?}", p);
+            return None;
+        }
+    };
+    Some(ColorSpace::Indexed(Box::new(base), hival, lookup))
+}
+
+/// Decode CCITT fax data into one byte per pixel
+fn fax_decode(data: &[u8], params: &CCITTFaxDecodeParams, image: &ImageXObject) -> Result<Vec<u8>, PdfError> {
+    if params.columns == 0 {
+        return Err(PdfError::Other { msg: "CCITTFaxDecode with 0 columns".into() });
+    }
+    let params = ccitt::Params {
+        k: params.k,
+        columns: params.columns as u32,
+        rows: match params.rows {
+            0 => image.height as u32,
+            n => n as u32
+        },
+        black_is_1: params.black_is_1,
+        encoded_byte_align: params.encoded_byte_align,
+    };
+    let bitmap = ccitt::decode(data, &params);
+    let stride = (params.columns as usize + 7) / 8;
+    let width = image.width as usize;
+
+    let mut pixels = Vec::with_capacity(width * params.rows as usize);
+    for row in bitmap.chunks_exact(stride) {
+        pixels.extend((0 .. width).map(|x| {
+            match row.get(x / 8) {
+                Some(&b) if b & (0x80 >> (x % 8)) != 0 => 255,
+                _ => 0
+            }
+        }));
+    }
+    Ok(pixels)
+}
+
+/// Decode JBIG2 data into one byte per pixel
+fn jbig2_decode(data: &[u8], params: &JBIG2DecodeParams, image: &ImageXObject, resolve: &impl Resolve) -> Result<Vec<u8>, PdfError> {
+    let globals = match params.globals {
+        Some(ref globals) => Some(t!(globals.data(resolve))),
+        None => None
+    };
+    let bitmap = jbig2::decode(data, globals.as_deref(), image.width as usize * image.height as usize).map_err(|msg| PdfError::Other { msg: format!("JBIG2: {}", msg) })?;
+
+    let (width, height) = (image.width as usize, image.height as usize);
+    if bitmap.width != width || bitmap.height != height {
+        warn!("JBIG2 page is {}x{}, image is {}x{}", bitmap.width, bitmap.height, width, height);
+    }
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0 .. height {
+        pixels.extend((0 .. width).map(|x| {
+            // JBIG2 uses 1 for black
+            match x < bitmap.width && y < bitmap.height && bitmap.data[y * bitmap.width + x] != 0 {
+                true => 0,
+                false => 255
+            }
+        }));
+    }
+    Ok(pixels)
+}
+
+/// What the markers of a JPEG (DCTDecode) stream say about its samples
+struct JpegInfo {
+    components: u8,
+    /// the transform of the Adobe APP14 marker: 0 for RGB or CMYK, 1 for YCbCr and 2 for YCCK
+    adobe_transform: Option<u8>,
+    progressive: bool,
+}
+
+/// The CMYK samples of a four component JPEG.
+///
+/// Writers of the Adobe APP14 marker store CMYK (transform 0) and YCCK (transform 2) inverted, like Photoshop does.
+/// Without the marker the samples are used as stored.
+fn cmyk_jpeg_samples(samples: Arc<[u8]>, adobe_transform: Option<u8>) -> Arc<[u8]> {
+    match adobe_transform {
+        Some(0) | Some(2) => samples.iter().map(|&v| 255 - v).collect::<Vec<u8>>().into(),
+        _ => samples
+    }
+}
+
+/// Read the markers up to the first frame header. `None` if `data` is not a JPEG.
+fn jpeg_info(data: &[u8]) -> Option<JpegInfo> {
+    if data.get(.. 2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut adobe_transform = None;
+    let mut pos = 2;
+    loop {
+        // markers can be padded with any number of 0xFF
+        while *data.get(pos)? == 0xFF && *data.get(pos + 1)? == 0xFF {
+            pos += 1;
+        }
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let segment = data.get(pos + 4 .. pos + 2 + len)?;
+        match marker {
+            // APP14
+            0xEE if segment.starts_with(b"Adobe") => adobe_transform = segment.get(11).cloned(),
+            // SOF0 to SOF15, without DHT, JPG and DAC
+            0xC0 ..= 0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => return Some(JpegInfo {
+                components: *segment.get(5)?,
+                adobe_transform,
+                progressive: matches!(marker, 0xC2 | 0xC6 | 0xCA | 0xCE),
+            }),
+            // start of scan before a frame header
+            0xDA => return None,
+            _ => {}
+        }
+        pos += 2 + len;
+    }
+}
+
+/// Decode JPEG 2000 data into 8 bit interleaved samples and the matching color space
+#[cfg(feature = "jpx")]
+fn jpx_decode(data: &[u8]) -> Result<(Vec<u8>, Option<&'static ColorSpace>), PdfError> {
+    use jpeg2k::ColorSpace as JpxColorSpace;
+
+    let image = jpeg2k::Image::from_bytes(data).map_err(|e| PdfError::Other { msg: format!("JPX: {:?}", e) })?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let components = image.components();
+
+    let (n, cs) = match (image.color_space(), components.len()) {
+        (JpxColorSpace::Gray, _) | (_, 1) | (_, 2) => (1, &ColorSpace::DeviceGray),
+        (JpxColorSpace::CMYK, _) => (4, &ColorSpace::DeviceCMYK),
+        (JpxColorSpace::SRGB, _) | (_, 3) => (3, &ColorSpace::DeviceRGB),
+        (_, 4) => (4, &ColorSpace::DeviceCMYK),
+        (cs, n) => return Err(PdfError::Other { msg: format!("JPX: {} components in color space {:?}", n, cs) })
+    };
+    if components.len() < n {
+        return Err(PdfError::Other { msg: format!("JPX: {} components, expected {}", components.len(), n) });
+    }
+    if components[.. n].iter().any(|c| c.width() as usize != width || c.height() as usize != height) {
+        return Err(PdfError::Other { msg: "JPX: subsampled components are not supported".into() });
+    }
+
+    let mut out = vec![0u8; width * height * n];
+    for (i, c) in components[.. n].iter().enumerate() {
+        let scale = 255. / ((1u64 << c.precision()) - 1) as f32;
+        for (j, &v) in c.data().iter().take(width * height).enumerate() {
+            out[j * n + i] = (v as f32 * scale) as u8;
+        }
+    }
+    Ok((out, Some(cs)))
+}
+
+#[cfg(not(feature = "jpx"))]
+fn jpx_decode(_data: &[u8]) -> Result<(Vec<u8>, Option<&'static ColorSpace>), PdfError> {
+    Err(PdfError::Other { msg: "JPXDecode needs the jpx feature".into() })
+}
+
+/// Convert 8 bit ICCBased image data to RGB using the embedded profile
+#[allow(clippy::too_many_arguments)]
+fn icc_to_rgb(image: &ImageXObject, data: &[u8], pixel_count: usize, icc: Option<&dyn IccTransform>, intent: RenderingIntent, matte: Option<&[f32]>, alpha: &[u8], resolve: &impl Resolve) -> Option<Vec<u8>> {
+    let icc = icc?;
+    let stream = match image.color_space {
+        Some(ColorSpace::Icc(ref stream)) => stream,
+        _ => return None
+    };
+    if image.bits_per_component != Some(8) {
+        return None;
+    }
+    let n = stream.info.components as usize;
+    let data = unmatte(decode_samples(data.get(.. pixel_count * n)?, n, 8, image.decode.as_deref(), None), n, matte, alpha);
+    let profile = match stream.data(resolve) {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!("can't read ICC profile: {:?}", e);
+            return None;
+        }
+    };
+    // /Intent of the image, otherwise the one of the graphics state
+    let intent = image.other.get("Intent").and_then(|p| p.as_name().ok()).map(rendering_intent).unwrap_or(intent);
+    icc.transform_u8_with_intent(&profile, n, &data, intent)
+}
+
+/// The RGB colors of an indexed color space with an ICC based base, converted with the embedded profile
+fn icc_palette(cs: &ColorSpace, hival: u8, lookup: &[u8], resolve: &impl Resolve, icc: &dyn IccTransform, intent: RenderingIntent) -> Option<Vec<u8>> {
+    let stream = match *cs {
+        ColorSpace::Icc(ref stream) => stream,
+        _ => return None
+    };
+    let n = stream.info.components as usize;
+    // missing entries are zero
+    let mut entries = lookup.get(.. (hival as usize + 1) * n).unwrap_or(lookup).to_vec();
+    entries.resize((hival as usize + 1) * n, 0);
+    let profile = match stream.data(resolve) {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!("can't read ICC profile: {:?}", e);
+            return None;
+        }
+    };
+    icc.transform_u8_with_intent(&profile, n, &entries, intent)
+}
+
+/// Apply a /Decode array to unpacked samples of `bits` bits with `n` interleaved components.
+///
+/// Samples are scaled to 0 ..= 255, except for indexed images (`hival` is set) where /Decode maps to the palette index.
+fn decode_samples<'a>(data: &'a [u8], n: usize, bits: usize, decode: Option<&[f32]>, hival: Option<usize>) -> Cow<'a, [u8]> {
+    if decode.is_none() && (bits == 8 || hival.is_some()) {
+        return Cow::Borrowed(data);
+    }
+    let max = ((1 << bits) - 1) as f32;
+    let luts: Vec<Vec<u8>> = (0 .. n).map(|i| {
+        let (d_min, d_max) = match decode.and_then(|d| d.get(2 * i .. 2 * i + 2)) {
+            Some(&[d_min, d_max]) => (d_min, d_max),
+            _ if hival.is_some() => (0.0, max),
+            _ => (0.0, 1.0)
+        };
+        (0 ..= max as usize).map(|v| {
+            let x = d_min + v as f32 * (d_max - d_min) / max;
+            match hival {
+                Some(hival) => x.round().max(0.0).min(hival as f32) as u8,
+                None => (x.max(0.0).min(1.0) * 255.).round() as u8,
+            }
+        }).collect()
+    }).collect();
+
+    data.iter().enumerate().map(|(i, &v)| luts[i % n].get(v as usize).cloned().unwrap_or(0)).collect::<Vec<u8>>().into()
+}
+
+/// Alpha values for color key masking: pixels whose samples all lie in the /Mask ranges are transparent.
+///
+/// For indexed images the ranges and samples are palette indices.
+fn color_key(data: &[u8], image: &ImageXObject, ranges: &[u32]) -> Option<Vec<u8>> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let n = ranges.len() / 2;
+    if n == 0 || ranges.len() % 2 != 0 {
+        return None;
+    }
+    let mut bits = image.bits_per_component.unwrap_or(8) as usize;
+    let mut ranges: Cow<[u32]> = ranges.into();
+    // fax and JBIG2 data is already unpacked to one byte per sample
+    if bits < 8 && data.len() == width * height * n {
+        let scale = 255 / ((1 << bits) - 1);
+        ranges = ranges.iter().map(|&r| r * scale).collect::<Vec<u32>>().into();
+        bits = 8;
+    }
+    let stride = (width * n * bits + 7) / 8;
+    if data.len() < stride * height {
+        return None;
+    }
+
+    let sample = |row: &[u8], i: usize| -> u32 {
+        match bits {
+            16 => row[2 * i] as u32 * 256 + row[2 * i + 1] as u32,
+            8 => row[i] as u32,
+            _ => {
+                let bit = i * bits;
+                ((row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1)) as u32
+            }
+        }
+    };
+    let mut alpha = Vec::with_capacity(width * height);
+    for row in data.chunks(stride).take(height) {
+        alpha.extend((0 .. width).map(|x| {
+            let keyed = (0 .. n).all(|i| {
+                let v = sample(row, x * n + i);
+                ranges[2 * i] <= v && v <= ranges[2 * i + 1]
+            });
+            if keyed { 0 } else { 255 }
+        }));
+    }
+    Some(alpha)
+}
+
+/// Unpack a single component image with rows padded to whole bytes into 8 bit samples
+/// Split packed 1, 2 or 4 bit samples (most significant bits first) into one byte each, without scaling them.
+fn unpack_samples(data: &[u8], width: usize, height: usize, bits: usize) -> Result<Vec<u8>, PdfError> {
+    let stride = (width * bits + 7) / 8;
+    if data.len() < stride * height {
+        return Err(PdfError::Other { msg: format!("image has {} bytes, expected {}", data.len(), stride * height) });
+    }
+    let mask = (1u8 << bits) - 1;
+    let mut out = Vec::with_capacity(width * height);
+    for row in data.chunks_exact(stride).take(height) {
+        out.extend((0 .. width).map(|x| {
+            let bit = x * bits;
+            (row[bit / 8] >> (8 - bits - bit % 8)) & mask
+        }));
+    }
+    Ok(out)
+}
+
+fn unpack_gray(data: &[u8], width: usize, height: usize, bits: usize) -> Result<Vec<u8>, PdfError> {
+    let stride = (width * bits + 7) / 8;
+    let max = ((1u32 << bits.min(16)) - 1) as f32;
+    let mut out = Vec::with_capacity(width * height);
+    for row in data.chunks(stride).take(height) {
+        for x in 0 .. width {
+            let v = match bits {
+                1 | 2 | 4 => {
+                    let bit = x * bits;
+                    let shift = 8 - bits - bit % 8;
+                    ((row[bit / 8] >> shift) & ((1 << bits) - 1)) as f32 / max
+                }
+                8 => row[x] as f32 / max,
+                16 => (row[2 * x] as u32 * 256 + row[2 * x + 1] as u32) as f32 / max,
+                n => return Err(PdfError::Other { msg: format!("invalid bits per component {}", n)})
+            };
+            out.push((v * 255.).round() as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Undo the pre-blending with the /Matte color: c = m + (c' - m) / a
+fn unmatte<'a>(samples: Cow<'a, [u8]>, n: usize, matte: Option<&[f32]>, alpha: &[u8]) -> Cow<'a, [u8]> {
+    let matte = match matte {
+        Some(matte) if matte.len() == n && alpha.len() * n == samples.len() => matte,
+        Some(matte) => {
+            warn!("Matte {:?} does not match the image", matte);
+            return samples;
+        }
+        None => return samples
+    };
+    let mut samples = samples.into_owned();
+    for (pixel, &a) in samples.chunks_exact_mut(n).zip(alpha) {
+        if a == 0 {
+            continue;
+        }
+        let a = a as f32 / 255.;
+        for (c, &m) in pixel.iter_mut().zip(matte) {
+            let v = m + (*c as f32 / 255. - m) / a;
+            *c = (v.max(0.0).min(1.0) * 255.).round() as u8;
+        }
+    }
+    samples.into()
+}
+
 fn rgb2rgba(c: &[u8], a: u8, mode: BlendMode) -> ColorU {
     match mode {
         BlendMode::Overlay => {
@@ -319,42 +931,146 @@ fn rgb2rgb(r: f32, g: f32, b: f32, mode: BlendMode) -> [u8; 3] {
     }
     
 }
-/*
-red = 1.0 – min ( 1.0, cyan + black )
-green = 1.0 – min ( 1.0, magenta + black )
-blue = 1.0 – min ( 1.0, yellow + black )
-*/
-
+/// `Overlay` images store inverted components
 #[inline]
-fn cmyk2rgb([c, m, y, k]: [u8; 4], mode: BlendMode) -> [u8; 3] {
+fn cmyk2rgb([c, m, y, k]: [u8; 4], mode: BlendMode, cmyk: CmykConversion) -> [u8; 3] {
     match mode {
-        BlendMode::Darken => {
-            let r = 255 - c.saturating_add(k);
-            let g = 255 - m.saturating_add(k);
-            let b = 255 - y.saturating_add(k);
-            [r, g, b]
-        }
-        BlendMode::Overlay => {
-            let (c, m, y, k) = (255 - c, 255 - m, 255 - y, 255 - k);
-            let r = 255 - c.saturating_add(k);
-            let g = 255 - m.saturating_add(k);
-            let b = 255 - y.saturating_add(k);
-            [r, g, b]
-        }
+        BlendMode::Darken => cmyk.to_rgb_u8([c, m, y, k]),
+        BlendMode::Overlay => cmyk.to_rgb_u8([255 - c, 255 - m, 255 - y, 255 - k]),
     }
 }
 
 #[inline]
-fn cmyk2color(cmyk: [u8; 4], a: u8, mode: BlendMode) -> ColorU {
-    let [r, g, b] = cmyk2rgb(cmyk, mode);
+fn cmyk2color(c: [u8; 4], a: u8, mode: BlendMode, cmyk: CmykConversion) -> ColorU {
+    let [r, g, b] = cmyk2rgb(c, mode, cmyk);
     ColorU::new(r, g, b, a)
 }
 
-fn cmyk2color_arr(data: &[u8], alpha: impl Iterator<Item=u8>, mode: BlendMode) -> Vec<ColorU> {
+fn cmyk2color_arr(data: &[u8], alpha: impl Iterator<Item=u8>, mode: BlendMode, cmyk: CmykConversion) -> Vec<ColorU> {
     data.chunks_exact(4).zip(alpha).map(|(c, a)| {
         let mut buf = [0; 4];
         buf.copy_from_slice(c);
-        cmyk2color(buf, a, mode)
+        cmyk2color(buf, a, mode, cmyk)
     }).collect()
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_pdf, load};
+
+    /// The markers of a four component baseline JPEG, with an APP14 marker if `transform` is set
+    fn cmyk_jpeg_markers(transform: Option<u8>) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        if let Some(transform) = transform {
+            data.extend_from_slice(&[0xFF, 0xEE, 0, 14]);
+            data.extend_from_slice(b"Adobe");
+            data.extend_from_slice(&[0, 100, 0, 0, 0, 0, transform]);
+        }
+        data.extend_from_slice(&[0xFF, 0xC0, 0, 20, 8, 0, 1, 0, 1, 4]);
+        for id in 1 ..= 4 {
+            data.extend_from_slice(&[id, 0x11, 0]);
+        }
+        data
+    }
+
+    fn cmyk_samples(transform: Option<u8>) -> Vec<u8> {
+        let info = jpeg_info(&cmyk_jpeg_markers(transform)).unwrap();
+        std::assert_eq!(info.components, 4);
+        std::assert_eq!(info.adobe_transform, transform);
+        cmyk_jpeg_samples(Arc::from(&[0, 55, 200, 255][..]), info.adobe_transform).to_vec()
+    }
+
+    #[test]
+    fn adobe_cmyk_jpeg_is_inverted() {
+        std::assert_eq!(cmyk_samples(Some(0)), [255, 200, 55, 0]);
+    }
+
+    #[test]
+    fn adobe_ycck_jpeg_is_inverted() {
+        std::assert_eq!(cmyk_samples(Some(2)), [255, 200, 55, 0]);
+    }
+
+    #[test]
+    fn cmyk_jpeg_without_app14_is_kept() {
+        std::assert_eq!(cmyk_samples(None), [0, 55, 200, 255]);
+    }
+
+    #[test]
+    fn unmatte_known_matte() {
+        // alpha 0 stays, alpha 255 is not blended, alpha 0.2 against the matte 0.5 gives back black and white
+        let samples = unmatte(Cow::Borrowed(&[10, 200, 102, 153][..]), 1, Some(&[0.5]), &[0, 255, 51, 51]);
+        std::assert_eq!(&*samples, &[10, 200, 0, 255][..]);
+
+        // a matte with the wrong number of components is ignored
+        let samples = unmatte(Cow::Borrowed(&[102, 153][..]), 1, Some(&[0.5, 0.5]), &[51, 51]);
+        std::assert_eq!(&*samples, &[102, 153][..]);
+    }
+
+    /// gray to (g, g, 255 - g), for the profile "icc!" only
+    struct MockIcc;
+    impl IccTransform for MockIcc {
+        fn transform(&self, _profile: &[u8], _components: usize, _input: &[f32]) -> Option<Vec<f32>> {
+            None
+        }
+        fn transform_u8(&self, profile: &[u8], components: usize, input: &[u8]) -> Option<Vec<u8>> {
+            if profile != b"icc!" || components != 1 {
+                return None;
+            }
+            Some(input.iter().flat_map(|&g| [g, g, 255 - g]).collect())
+        }
+    }
+
+    /// The colors of the 2×1 thumbnail with the indices 0 and 1 in `color_space`
+    fn indexed_thumbnail(color_space: &str, objects: &[&str]) -> Vec<ColorU> {
+        let image = format!("<< /Width 2 /Height 1 /ColorSpace {} /BitsPerComponent 8 /Filter /ASCIIHexDecode /Length 5 >>\nstream\n0001>\nendstream", color_space);
+        let mut all = vec![image.as_str()];
+        all.extend_from_slice(objects);
+        let file = load(build_pdf("", "/MediaBox [0 0 10 10] /Resources << >> /Thumb 5 0 R", b"", &all));
+        let page = file.get_page(0).unwrap();
+        let image = embedded_thumbnail(&page, &file.resolver(), None, CmykConversion::Naive).unwrap().unwrap();
+        image.data().to_vec()
+    }
+
+    fn assert_white_and_black(colors: &[ColorU]) {
+        for c in [colors[0].r, colors[0].g, colors[0].b] {
+            assert!(c >= 250, "{:?} is not white", colors[0]);
+        }
+        for c in [colors[1].r, colors[1].g, colors[1].b] {
+            assert!(c <= 5, "{:?} is not black", colors[1]);
+        }
+    }
+
+    #[test]
+    fn indexed_lab_image() {
+        let colors = indexed_thumbnail("[/Indexed [/Lab << /WhitePoint [0.9505 1 1.089] >>] 1 <FF8080 008080>]", &[]);
+        assert_white_and_black(&colors);
+    }
+
+    #[test]
+    fn indexed_separation_image() {
+        // the tint goes from L* 100 to L* 0
+        let colors = indexed_thumbnail("[/Indexed [/Separation /Spot [/Lab << /WhitePoint [0.9505 1 1.089] >>] 6 0 R] 1 <00FF>]", &[
+            "<< /FunctionType 2 /Domain [0 1] /C0 [100 0 0] /C1 [0 0 0] /N 1 >>",
+        ]);
+        assert_white_and_black(&colors);
+    }
+
+    #[test]
+    fn icc_image_uses_the_transform() {
+        let file = load(build_pdf("", "/MediaBox [0 0 10 10] /Resources << >> /Thumb 5 0 R", b"", &[
+            "<< /Width 2 /Height 1 /ColorSpace [/ICCBased 6 0 R] /BitsPerComponent 8 /Length 2 >>\nstream\nAZ\nendstream",
+            "<< /N 1 /Length 4 >>\nstream\nicc!\nendstream",
+        ]));
+        let page = file.get_page(0).unwrap();
+        let resolver = file.resolver();
+
+        let image = embedded_thumbnail(&page, &resolver, Some(&MockIcc), CmykConversion::Naive).unwrap().unwrap();
+        std::assert_eq!(image.data(), &[ColorU::new(b'A', b'A', 255 - b'A', 255), ColorU::new(b'Z', b'Z', 255 - b'Z', 255)][..]);
+
+        // without a transform the samples are gray
+        let image = embedded_thumbnail(&page, &resolver, None, CmykConversion::Naive).unwrap().unwrap();
+        std::assert_eq!(image.data()[0], ColorU::new(b'A', b'A', b'A', 255));
+    }
+}
@@ -0,0 +1,165 @@
+//! What a page's resources contain, for tools that diagnose rendering problems without rendering.
+use pdf::object::*;
+use pdf::font::FontType;
+use pdf::encoding::BaseEncoding;
+use pdf::error::Result;
+use crate::Cache;
+use crate::fontentry::is_subset_name;
+
+/// The fonts, images and color spaces of a resource dictionary, sorted by their resource names.
+#[derive(Debug, Clone)]
+pub struct ResourceInfo {
+    pub fonts: Vec<FontInfo>,
+    pub images: Vec<ImageInfo>,
+    /// names of the form XObjects, which have resources of their own
+    pub forms: Vec<String>,
+    pub color_spaces: Vec<ColorSpaceInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FontInfo {
+    /// the key in /Font
+    pub resource: String,
+    /// /BaseFont
+    pub name: Option<String>,
+    /// the name has a subset tag, see `FontEntry::subset`
+    pub subset: bool,
+    pub subtype: FontType,
+    pub is_cid: bool,
+    /// the base encoding of /Encoding, `None` if there is no /Encoding
+    pub encoding: Option<BaseEncoding>,
+    /// number of /Differences entries
+    pub differences: usize,
+    pub source: FontSource,
+}
+
+/// Where the glyphs of a font came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FontSource {
+    Embedded,
+    Type3,
+    /// not embedded, a font of the same name was found in `STANDARD_FONTS`
+    Standard,
+    /// not embedded, drawn with the named fallback font
+    Substitute(String),
+    /// no font could be loaded, the text is not drawn
+    Missing,
+    /// the font failed to load with this error
+    Error(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    /// the key in /XObject
+    pub resource: String,
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_component: Option<i32>,
+    /// `None` for image masks and JPEG 2000 images that specify it themselves
+    pub color_space: Option<String>,
+    pub image_mask: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ColorSpaceInfo {
+    /// the key in /ColorSpace
+    pub resource: String,
+    pub description: String,
+}
+
+impl ResourceInfo {
+    /// The resources of `page`. Fonts are loaded through `cache`, the same way rendering would.
+    pub fn of_page(page: &Page, resolve: &impl Resolve, cache: &mut Cache) -> Result<ResourceInfo> {
+        let resources = t!(page.resources());
+        Ok(ResourceInfo::of(&resources, resolve, cache))
+    }
+    pub fn of(resources: &Resources, resolve: &impl Resolve, cache: &mut Cache) -> ResourceInfo {
+        let mut fonts: Vec<_> = resources.fonts.iter().map(|(name, font)| {
+            let (source, is_cid) = match cache.get_font(font, resolve) {
+                Ok(Some(e)) => {
+                    let source = if e.type3.is_some() {
+                        FontSource::Type3
+                    } else if e.embedded {
+                        FontSource::Embedded
+                    } else {
+                        match e.substitute {
+                            Some(ref s) => FontSource::Substitute(s.clone()),
+                            None => FontSource::Standard,
+                        }
+                    };
+                    (source, e.is_cid)
+                }
+                Ok(None) => (FontSource::Missing, font.subtype == FontType::Type0),
+                Err(e) => (FontSource::Error(format!("{:?}", e)), font.subtype == FontType::Type0),
+            };
+            let encoding = font.encoding();
+            FontInfo {
+                resource: name.as_str().into(),
+                name: font.name.as_ref().map(|n| n.as_str().into()),
+                subset: font.name.as_ref().map_or(false, |n| is_subset_name(n.as_str())),
+                subtype: font.subtype,
+                is_cid,
+                encoding: encoding.map(|e| e.base.clone()),
+                differences: encoding.map_or(0, |e| e.differences.len()),
+                source,
+            }
+        }).collect();
+        fonts.sort_by(|a, b| a.resource.cmp(&b.resource));
+
+        let mut images = vec![];
+        let mut forms = vec![];
+        for (name, &r) in resources.xobjects.iter() {
+            let xobject = match resolve.get(r) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("can't read XObject {}: {:?}", name.as_str(), e);
+                    continue;
+                }
+            };
+            match *xobject {
+                XObject::Image(ref im) => images.push(ImageInfo {
+                    resource: name.as_str().into(),
+                    width: im.width,
+                    height: im.height,
+                    bits_per_component: im.bits_per_component,
+                    color_space: im.color_space.as_ref().map(describe_color_space),
+                    image_mask: im.image_mask,
+                }),
+                XObject::Form(_) => forms.push(name.as_str().to_owned()),
+                XObject::Postscript(_) => {}
+            }
+        }
+        images.sort_by(|a, b| a.resource.cmp(&b.resource));
+        forms.sort();
+
+        let mut color_spaces: Vec<_> = resources.color_spaces.iter().map(|(name, cs)| ColorSpaceInfo {
+            resource: name.as_str().into(),
+            description: describe_color_space(cs),
+        }).collect();
+        color_spaces.sort_by(|a, b| a.resource.cmp(&b.resource));
+
+        ResourceInfo { fonts, images, forms, color_spaces }
+    }
+}
+
+/// A short description like `Indexed(DeviceRGB, 256 colors)` or `Separation(PANTONE 185 C, alternate DeviceCMYK)`
+pub fn describe_color_space(cs: &ColorSpace) -> String {
+    match *cs {
+        ColorSpace::DeviceGray => "DeviceGray".into(),
+        ColorSpace::DeviceRGB => "DeviceRGB".into(),
+        ColorSpace::DeviceCMYK => "DeviceCMYK".into(),
+        ColorSpace::CalGray(_) => "CalGray".into(),
+        ColorSpace::CalRGB(_) => "CalRGB".into(),
+        ColorSpace::CalCMYK(_) => "CalCMYK".into(),
+        ColorSpace::Icc(ref icc) => match icc.info.alternate {
+            Some(ref alt) => format!("ICCBased(alternate {})", describe_color_space(alt)),
+            None => "ICCBased".into(),
+        },
+        ColorSpace::Indexed(ref base, hival, _) => format!("Indexed({}, {} colors)", describe_color_space(base), hival as u32 + 1),
+        ColorSpace::Separation(ref name, ref alt, _) => format!("Separation({}, alternate {})", name, describe_color_space(alt)),
+        ColorSpace::DeviceN { ref alt, ref tint, .. } => format!("DeviceN({} colorants, alternate {})", tint.input_dim(), describe_color_space(alt)),
+        ColorSpace::Pattern => "Pattern".into(),
+        ColorSpace::Named(ref name) => name.as_str().into(),
+        ref cs => format!("{:?}", cs),
+    }
+}
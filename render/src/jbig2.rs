@@ -0,0 +1,373 @@
+//! JBIG2 decoding (`/JBIG2Decode`), limited to generic regions.
+//!
+//! Symbol dictionaries, text, pattern/halftone and refinement regions are skipped with a warning.
+use crate::ccitt;
+
+/// A bilevel image, one byte per pixel. 1 is black.
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Bitmap {
+    fn new(width: usize, height: usize, value: u8) -> Bitmap {
+        Bitmap { width, height, data: vec![value; width * height] }
+    }
+    fn get(&self, x: i32, y: i32) -> u8 {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return 0;
+        }
+        self.data[y as usize * self.width + x as usize]
+    }
+    fn grow(&mut self, height: usize, value: u8) {
+        if height > self.height {
+            self.data.resize(self.width * height, value);
+            self.height = height;
+        }
+    }
+    fn compose(&mut self, region: &Bitmap, x: usize, y: usize, op: u8) {
+        for ry in 0 .. region.height {
+            let py = y + ry;
+            if py >= self.height {
+                break;
+            }
+            for rx in 0 .. region.width.min(self.width.saturating_sub(x)) {
+                let src = region.data[ry * region.width + rx];
+                let dst = &mut self.data[py * self.width + x + rx];
+                *dst = match op {
+                    0 => *dst | src,
+                    1 => *dst & src,
+                    2 => *dst ^ src,
+                    3 => !(*dst ^ src) & 1,
+                    _ => src,
+                };
+            }
+        }
+    }
+}
+
+/// Decode an embedded JBIG2 stream. `globals` is the content of the /JBIG2Globals stream.
+///
+/// The page and its regions may have at most `max_pixels` pixels, the size of the image they are decoded for.
+/// Larger ones are rejected before anything is allocated for them.
+pub fn decode(data: &[u8], globals: Option<&[u8]>, max_pixels: usize) -> Result<Bitmap, String> {
+    let mut page = None;
+    if let Some(globals) = globals {
+        decode_segments(globals, &mut page, max_pixels)?;
+    }
+    decode_segments(data, &mut page, max_pixels)?;
+    page.ok_or_else(|| "no page information segment".into())
+}
+
+struct Page {
+    bitmap: Bitmap,
+    default_pixel: u8,
+    /// height is unknown and grows with the regions
+    striped: bool,
+}
+
+fn check_size(width: usize, height: usize, max_pixels: usize) -> Result<(), String> {
+    match width.checked_mul(height) {
+        Some(n) if n <= max_pixels => Ok(()),
+        _ => Err(format!("{}x{} pixels exceed the image size", width, height))
+    }
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, String> {
+    match data.get(pos .. pos + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err("unexpected end of data".into())
+    }
+}
+fn u8_at(data: &[u8], pos: usize) -> Result<u8, String> {
+    data.get(pos).cloned().ok_or_else(|| "unexpected end of data".into())
+}
+
+fn decode_segments(data: &[u8], page_slot: &mut Option<Page>, max_pixels: usize) -> Result<(), String> {
+    let mut pos = 0;
+    while pos < data.len() {
+        // segment header
+        let number = u32_at(data, pos)?;
+        let flags = u8_at(data, pos + 4)?;
+        let seg_type = flags & 0x3f;
+        let page_association_size = if flags & 0x40 != 0 { 4 } else { 1 };
+
+        let referred = u8_at(data, pos + 5)?;
+        let mut referred_count = (referred >> 5) as usize;
+        pos += 6;
+        if referred_count == 7 {
+            referred_count = (u32_at(data, pos - 1)? & 0x1fff_ffff) as usize;
+            pos += 3 + (referred_count + 8) / 8;
+        } else if referred_count > 4 {
+            return Err(format!("invalid referred-to segment count {}", referred_count));
+        }
+        let number_size = match number {
+            0 ..= 256 => 1,
+            257 ..= 65536 => 2,
+            _ => 4
+        };
+        pos += referred_count * number_size + page_association_size;
+        let length = u32_at(data, pos)?;
+        pos += 4;
+
+        let end = match length {
+            // only allowed for immediate generic regions, which then end the data
+            0xffff_ffff => data.len(),
+            n => pos + n as usize,
+        };
+        let segment = data.get(pos .. end).ok_or("segment exceeds data")?;
+        pos = end;
+
+        match seg_type {
+            // page information
+            48 => {
+                let width = u32_at(segment, 0)? as usize;
+                let height = u32_at(segment, 4)?;
+                let flags = u8_at(segment, 16)?;
+                let default_pixel = (flags >> 2) & 1;
+                let striped = height == 0xffff_ffff;
+                let height = if striped { 0 } else { height as usize };
+                check_size(width, height, max_pixels)?;
+                *page_slot = Some(Page {
+                    bitmap: Bitmap::new(width, height, default_pixel),
+                    default_pixel,
+                    striped,
+                });
+            }
+            // immediate (lossless) generic region
+            38 | 39 => {
+                let page = page_slot.as_mut().ok_or("region before page information")?;
+                let region = RegionInfo::parse(segment)?;
+                check_size(region.width, region.height, max_pixels)?;
+                if page.striped {
+                    check_size(page.bitmap.width, region.y.saturating_add(region.height), max_pixels)?;
+                }
+                let bitmap = generic_region(&segment[17 ..], region.width, region.height)?;
+                if page.striped {
+                    page.bitmap.grow(region.y.saturating_add(region.height), page.default_pixel);
+                }
+                page.bitmap.compose(&bitmap, region.x, region.y, region.op);
+            }
+            // end of page, end of stripe, end of file
+            49 | 50 | 51 => {}
+            // profiles, tables, extensions
+            52 | 53 | 62 => {}
+            t => warn!("JBIG2 segment type {} is not supported", t),
+        }
+    }
+    Ok(())
+}
+
+struct RegionInfo {
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    /// external combination operator
+    op: u8,
+}
+impl RegionInfo {
+    fn parse(data: &[u8]) -> Result<RegionInfo, String> {
+        Ok(RegionInfo {
+            width: u32_at(data, 0)? as usize,
+            height: u32_at(data, 4)? as usize,
+            x: u32_at(data, 8)? as usize,
+            y: u32_at(data, 12)? as usize,
+            op: u8_at(data, 16)? & 7
+        })
+    }
+}
+
+// template pixels relative to the current one, without the adaptive ones
+const TEMPLATES: [&[(i32, i32)]; 4] = [
+    &[(-1, -2), (0, -2), (1, -2), (-2, -1), (-1, -1), (0, -1), (1, -1), (2, -1), (-4, 0), (-3, 0), (-2, 0), (-1, 0)],
+    &[(-1, -2), (0, -2), (1, -2), (2, -2), (-2, -1), (-1, -1), (0, -1), (1, -1), (2, -1), (-3, 0), (-2, 0), (-1, 0)],
+    &[(-1, -2), (0, -2), (1, -2), (-2, -1), (-1, -1), (0, -1), (1, -1), (-2, 0), (-1, 0)],
+    &[(-3, -1), (-2, -1), (-1, -1), (0, -1), (1, -1), (-4, 0), (-3, 0), (-2, 0), (-1, 0)],
+];
+// context of the SLTP bit for typical prediction
+const TPGDON_CONTEXTS: [usize; 4] = [0x9b25, 0x0795, 0x00e5, 0x0195];
+
+fn generic_region(data: &[u8], width: usize, height: usize) -> Result<Bitmap, String> {
+    let flags = u8_at(data, 0)?;
+    let mmr = flags & 1 != 0;
+    let template = ((flags >> 1) & 3) as usize;
+    let tpgdon = flags & 8 != 0;
+    if width == 0 || height == 0 {
+        return Ok(Bitmap::new(width, height, 0));
+    }
+
+    if mmr {
+        let params = ccitt::Params {
+            k: -1,
+            columns: width as u32,
+            rows: height as u32,
+            black_is_1: true,
+            encoded_byte_align: false,
+        };
+        let packed = ccitt::decode(&data[1 ..], &params);
+        let stride = (width + 7) / 8;
+        let mut bitmap = Bitmap::new(width, height, 0);
+        for (y, row) in packed.chunks_exact(stride).take(height).enumerate() {
+            for x in 0 .. width {
+                bitmap.data[y * width + x] = (row[x / 8] >> (7 - x % 8)) & 1;
+            }
+        }
+        return Ok(bitmap);
+    }
+
+    let at_count = if template == 0 { 4 } else { 1 };
+    let at = data.get(1 .. 1 + 2 * at_count).ok_or("missing adaptive template pixels")?;
+    let mut pixels: Vec<(i32, i32)> = TEMPLATES[template].to_vec();
+    pixels.extend(at.chunks_exact(2).map(|p| (p[0] as i8 as i32, p[1] as i8 as i32)));
+    pixels.sort_by_key(|&(x, y)| (y, x));
+
+    let mut decoder = ArithDecoder::new(&data[1 + 2 * at_count ..]);
+    let mut contexts = vec![0u8; 1 << pixels.len()];
+    let mut bitmap = Bitmap::new(width, height, 0);
+    let mut ltp = false;
+
+    for y in 0 .. height {
+        if tpgdon {
+            ltp ^= decoder.bit(&mut contexts[TPGDON_CONTEXTS[template]]) != 0;
+            if ltp {
+                // same as the row above
+                if y > 0 {
+                    bitmap.data.copy_within((y - 1) * width .. y * width, y * width);
+                }
+                continue;
+            }
+        }
+        for x in 0 .. width {
+            let context = pixels.iter().fold(0, |cx, &(dx, dy)| {
+                cx << 1 | bitmap.get(x as i32 + dx, y as i32 + dy) as usize
+            });
+            bitmap.data[y * width + x] = decoder.bit(&mut contexts[context]);
+        }
+    }
+    Ok(bitmap)
+}
+
+/// (Qe, next index after MPS, next index after LPS, switch MPS)
+const QE: [(u32, u8, u8, bool); 47] = [
+    (0x5601, 1, 1, true), (0x3401, 2, 6, false), (0x1801, 3, 9, false), (0x0AC1, 4, 12, false),
+    (0x0521, 5, 29, false), (0x0221, 38, 33, false), (0x5601, 7, 6, true), (0x5401, 8, 14, false),
+    (0x4801, 9, 14, false), (0x3801, 10, 14, false), (0x3001, 11, 17, false), (0x2401, 12, 18, false),
+    (0x1C01, 13, 20, false), (0x1601, 29, 21, false), (0x5601, 15, 14, true), (0x5401, 16, 14, false),
+    (0x5101, 17, 15, false), (0x4801, 18, 16, false), (0x3801, 19, 17, false), (0x3401, 20, 18, false),
+    (0x3001, 21, 19, false), (0x2801, 22, 19, false), (0x2401, 23, 20, false), (0x2201, 24, 21, false),
+    (0x1C01, 25, 22, false), (0x1801, 26, 23, false), (0x1601, 27, 24, false), (0x1401, 28, 25, false),
+    (0x1201, 29, 26, false), (0x1101, 30, 27, false), (0x0AC1, 31, 28, false), (0x09C1, 32, 29, false),
+    (0x08A1, 33, 30, false), (0x0521, 34, 31, false), (0x0441, 35, 32, false), (0x02A1, 36, 33, false),
+    (0x0221, 37, 34, false), (0x0141, 38, 35, false), (0x0111, 39, 36, false), (0x0085, 40, 37, false),
+    (0x0049, 41, 38, false), (0x0025, 42, 39, false), (0x0015, 43, 40, false), (0x0009, 44, 41, false),
+    (0x0005, 45, 42, false), (0x0001, 45, 43, false), (0x5601, 46, 46, false),
+];
+
+/// The MQ arithmetic decoder (annex E)
+struct ArithDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    chigh: u32,
+    clow: u32,
+    ct: u32,
+    a: u32,
+}
+impl<'a> ArithDecoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        let mut d = ArithDecoder {
+            data,
+            pos: 0,
+            chigh: data.first().cloned().unwrap_or(0xff) as u32,
+            clow: 0,
+            ct: 0,
+            a: 0,
+        };
+        d.byte_in();
+        d.chigh = ((d.chigh << 7) & 0xffff) | ((d.clow >> 9) & 0x7f);
+        d.clow = (d.clow << 7) & 0xffff;
+        d.ct -= 7;
+        d.a = 0x8000;
+        d
+    }
+    fn byte(&self, pos: usize) -> u32 {
+        self.data.get(pos).cloned().unwrap_or(0xff) as u32
+    }
+    fn byte_in(&mut self) {
+        if self.byte(self.pos) == 0xff {
+            if self.byte(self.pos + 1) > 0x8f {
+                self.clow += 0xff00;
+                self.ct = 8;
+            } else {
+                self.pos += 1;
+                self.clow += self.byte(self.pos) << 9;
+                self.ct = 7;
+            }
+        } else {
+            self.pos += 1;
+            self.clow += self.byte(self.pos) << 8;
+            self.ct = 8;
+        }
+        if self.clow > 0xffff {
+            self.chigh += self.clow >> 16;
+            self.clow &= 0xffff;
+        }
+    }
+    /// `cx` holds the state index in the upper bits and the MPS in the lowest bit.
+    fn bit(&mut self, cx: &mut u8) -> u8 {
+        let mut index = (*cx >> 1) as usize;
+        let mut mps = *cx & 1;
+        let (qe, nmps, nlps, switch) = QE[index];
+        let mut a = self.a - qe;
+        let d;
+        if self.chigh < qe {
+            // LPS exchange
+            if a < qe {
+                a = qe;
+                d = mps;
+                index = nmps as usize;
+            } else {
+                a = qe;
+                d = 1 ^ mps;
+                if switch {
+                    mps = d;
+                }
+                index = nlps as usize;
+            }
+        } else {
+            self.chigh -= qe;
+            if a & 0x8000 != 0 {
+                self.a = a;
+                return mps;
+            }
+            // MPS exchange
+            if a < qe {
+                d = 1 ^ mps;
+                if switch {
+                    mps = d;
+                }
+                index = nlps as usize;
+            } else {
+                d = mps;
+                index = nmps as usize;
+            }
+        }
+        // renormalize
+        loop {
+            if self.ct == 0 {
+                self.byte_in();
+            }
+            a <<= 1;
+            self.chigh = ((self.chigh << 1) & 0xffff) | ((self.clow >> 15) & 1);
+            self.clow = (self.clow << 1) & 0xffff;
+            self.ct -= 1;
+            if a & 0x8000 != 0 {
+                break;
+            }
+        }
+        self.a = a;
+        *cx = (index as u8) << 1 | mps;
+        d
+    }
+}
@@ -25,16 +25,59 @@ mod backend;
 pub mod tracer;
 mod image;
 mod scene;
+mod svg;
+mod bounds;
 mod font;
+mod base14;
+mod type3;
+mod cmap;
+mod shading;
+mod colorspace;
+mod ccitt;
+mod jbig2;
+mod annot;
+mod ocg;
+mod text;
+mod structure;
+mod forms;
+mod links;
+mod inspect;
+mod config;
+mod hinting;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "rasterize")]
+mod raster;
+#[cfg(test)]
+mod test_util;
 
-pub use cache::{Cache};
-pub use fontentry::{FontEntry};
-pub use backend::{DrawMode, Backend, BlendMode, FillMode};
-pub use scene::SceneBackend;
-pub use crate::image::{load_image, ImageData};
+pub use cache::{Cache, DEFAULT_IMAGE_MEMORY};
+pub use fontentry::{FontEntry, VerticalMetrics};
+pub use base14::Base14;
+pub use type3::{Type3Font, Type3Glyph};
+pub use cmap::CMap;
+pub use shading::{Shading, ShadingKind, ShadingQuality};
+pub use colorspace::{IccTransform, CmykConversion};
+pub use backend::{DrawMode, Backend, BlendMode, Blend, FillMode, SoftMask, SoftMaskKind, TransparencyGroup, DEFAULT_RECURSION_LIMIT};
+pub use scene::{SceneBackend, build_scene};
+pub use svg::{SvgBackend, SvgClipPathId};
+pub use bounds::{BoundsBackend, BoundsClipId, content_bounds};
+pub use crate::font::{FontCache, FontClass, FontDatabase};
+pub use crate::image::{load_image, embedded_thumbnail, ImageData};
+pub use ocg::OptionalContent;
+pub use structure::{StructTree, StructElement, StructKid};
+pub use forms::{AcroForm, FormField, FieldKind};
+pub use links::{Destinations, Destination, View, Link, LinkTarget};
+pub use inspect::{ResourceInfo, FontInfo, FontSource, ImageInfo, ColorSpaceInfo, describe_color_space};
+pub use config::{RenderConfig, Antialias, SNAP_GRID, HAIRLINE_WIDTH};
+pub use text::{extract_text, extract_lines, extract_tagged_text, group_lines, for_each_glyph, TextItem, TaggedText, CharPosition, Line, Word};
+#[cfg(feature = "parallel")]
+pub use parallel::render_pages;
+#[cfg(feature = "rasterize")]
+pub use raster::{render_page_to_image, render_tile_to_image, render_thumbnail, ThumbnailSource};
 use custom_debug_derive::Debug;
 
-use pdf::{object::*, content::TextMode};
+use pdf::{object::*, content::{TextMode, Op, Content, parse_ops}};
 use pdf::error::PdfError;
 use pathfinder_geometry::{
     vector::{Vector2F},
@@ -42,6 +85,7 @@ use pathfinder_geometry::{
 };
 use renderstate::RenderState;
 use std::sync::Arc;
+use std::ops::ControlFlow;
 use itertools::Itertools;
 const SCALE: f32 = 25.4 / 72.;
 
@@ -74,37 +118,187 @@ impl From<RectF> for BBox {
 }
 
 
+fn rect_mm(Rect { left, right, top, bottom }: Rect, scale: f32) -> RectF {
+    RectF::from_points(
+        Vector2F::new(left.min(right), bottom.min(top)),
+        Vector2F::new(left.max(right), bottom.max(top))
+    ) * scale
+}
+/// Millimeters per unit of the page's user space: 1/72 inch, times /UserUnit
+fn page_scale(page: &Page) -> f32 {
+    match page.other.get("UserUnit").and_then(|p| p.as_number().ok()) {
+        Some(unit) if unit > 0.0 => SCALE * unit,
+        Some(unit) => {
+            warn!("invalid /UserUnit {}", unit);
+            SCALE
+        }
+        None => SCALE
+    }
+}
+/// The visible part of the page in millimeters: the crop box, limited to the media box
+fn media_bounds(page: &Page) -> RectF {
+    let scale = page_scale(page);
+    let media = rect_mm(page.media_box().expect("no media box"), scale);
+    match page.crop_box() {
+        Ok(crop) => rect_mm(crop, scale).intersection(media).unwrap_or(media),
+        Err(_) => media
+    }
+}
+/// The area `render_page` draws into with the identity transform, in millimeters, including the /UserUnit.
+/// Width and height are swapped for pages rotated by 90 or 270 degrees.
 pub fn page_bounds(page: &Page) -> RectF {
-    let Rect { left, right, top, bottom } = page.media_box().expect("no media box");
-    RectF::from_points(Vector2F::new(left, bottom), Vector2F::new(right, top)) * SCALE
+    let size = media_bounds(page).size();
+    match page_rotation(page) {
+        90 | 270 => RectF::new(Vector2F::zero(), Vector2F::new(size.y(), size.x())),
+        _ => RectF::new(Vector2F::zero(), size),
+    }
 }
-pub fn render_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, transform: Transform2F) -> Result<Transform2F, PdfError> {
-    let bounds = page_bounds(page);
-    let rotate = Transform2F::from_rotation(page.rotate as f32 * std::f32::consts::PI / 180.);
+/// Maps the user space of the page (points, y up, unrotated) to the space `render_page` draws in with the identity
+/// transform: millimeters with the origin at the top left of the crop box, y down, turned upright by /Rotate.
+///
+/// Pass `page_transform(page).inverse()` as the transform to get coordinates in the page's user space.
+pub fn page_transform(page: &Page) -> Transform2F {
+    let bounds = media_bounds(page);
+    let scale = page_scale(page);
+    let rotate = Transform2F::from_rotation(page_rotation(page) as f32 * std::f32::consts::PI / 180.);
     let br = rotate * RectF::new(Vector2F::zero(), bounds.size());
     let translate = Transform2F::from_translation(Vector2F::new(
         -br.min_x().min(br.max_x()),
         -br.min_y().min(br.max_y()),
     ));
-    let view_box = transform * translate * br;
-    backend.set_view_box(view_box);
-    
-    let root_transformation = transform
-        * translate
+    translate
         * rotate
-        * Transform2F::row_major(SCALE, 0.0, -bounds.min_x(), 0.0, -SCALE, bounds.max_y());
-    
+        * Transform2F::row_major(scale, 0.0, -bounds.min_x(), 0.0, -scale, bounds.max_y())
+}
+/// /Rotate of the page or the closest ancestor that has one, as 0, 90, 180 or 270 degrees clockwise.
+pub fn page_rotation(page: &Page) -> i32 {
+    let mut rotate = page.rotate;
+    // a missing /Rotate reads as 0, so an explicit 0 can't override an inherited value
+    if rotate == 0 {
+        let mut node = Some(page.parent.clone());
+        while let Some(tree) = node {
+            if let Some(r) = tree.other.get("Rotate").and_then(|p| p.as_integer().ok()) {
+                rotate = r;
+                break;
+            }
+            node = tree.parent.clone();
+        }
+    }
+    if rotate % 90 != 0 {
+        warn!("/Rotate {} is not a multiple of 90", rotate);
+    }
+    rotate.rem_euclid(360) / 90 * 90
+}
+/// The operators of the content streams in `contents`, parsed as one stream.
+/// The operands of an operator can be in the previous stream. The streams are separated by whitespace, so the last token
+/// of one stream never merges with the first of the next.
+pub fn content_operations(contents: &Content, resolve: &impl Resolve) -> Result<Vec<Op>, PdfError> {
+    let mut data = vec![];
+    for part in contents.parts.iter() {
+        data.extend_from_slice(&t!(part.data(resolve)));
+        data.push(b'\n');
+    }
+    parse_ops(&data, resolve)
+}
+pub fn render_page(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, transform: Transform2F) -> Result<Transform2F, PdfError> {
+    render_page_with_layers(backend, resolve, page, transform, &OptionalContent::new())
+}
+/// Like `render_page`, but content of layers that are off in `layers` is not drawn.
+/// Use `OptionalContent::from_catalog` for the default visibility of the document.
+pub fn render_page_with_layers(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, transform: Transform2F, layers: &OptionalContent) -> Result<Transform2F, PdfError> {
+    let config = RenderConfig::default().transform(transform).layers(layers.clone());
+    render_page_with_config(backend, resolve, page, &config)
+}
+/// Render the page with the options of `config`.
+pub fn render_page_with_config(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, config: &RenderConfig) -> Result<Transform2F, PdfError> {
+    let root_transformation = render_page_with_config_and_progress(backend, resolve, page, config, |_| ControlFlow::Continue(()))?;
+    Ok(root_transformation.expect("not cancelled"))
+}
+
+/// Position in the content stream of the page being rendered
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// operators drawn so far
+    pub op: usize,
+    /// number of operators of the page
+    pub total: usize,
+}
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.op as f32 / self.total as f32
+        }
+    }
+}
+
+/// Like `render_page_with_layers`, calling `progress` before each operator of the page.
+///
+/// If `progress` returns `ControlFlow::Break`, rendering stops and `Ok(None)` is returned.
+/// What was drawn so far is left in the backend.
+pub fn render_page_with_progress(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, transform: Transform2F, layers: &OptionalContent, progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<Option<Transform2F>, PdfError> {
+    let config = RenderConfig::default().transform(transform).layers(layers.clone());
+    render_page_with_config_and_progress(backend, resolve, page, &config, progress)
+}
+/// Like `render_page_with_progress`, with the options of `config`.
+pub fn render_page_with_config_and_progress(backend: &mut impl Backend, resolve: &impl Resolve, page: &Page, config: &RenderConfig, mut progress: impl FnMut(Progress) -> ControlFlow<()>) -> Result<Option<Transform2F>, PdfError> {
+    let transform = config.transform;
+    backend.set_view_box(transform * config.region.unwrap_or_else(|| page_bounds(page)));
+
+    let root_transformation = transform * page_transform(page);
+
     let resources = t!(page.resources());
 
     let contents = try_opt!(page.contents.as_ref());
-    let ops = contents.operations(resolve)?;
-    let mut renderstate = RenderState::new(backend, resolve, &resources, root_transformation);
+    let ops = content_operations(contents, resolve)?;
+    let mut renderstate = page_state(backend, resolve, &resources, page, config);
     for (i, op) in ops.iter().enumerate() {
+        if progress(Progress { op: i, total: ops.len() }).is_break() {
+            info!("cancelled at op {} of {}", i, ops.len());
+            renderstate.end_text_group();
+            drop(renderstate);
+            backend.set_blend_mode(Blend::Normal);
+            return Ok(None);
+        }
         debug!("op {}: {:?}", i, op);
         renderstate.draw_op(op, i)?;
     }
+    // a text object that isn't closed by ET
+    renderstate.end_text_group();
+    drop(renderstate);
 
-    Ok(root_transformation)
+    if config.annotations {
+        // the content is already drawn, so broken /Annots only lose the annotations
+        let annotations = match page.annotations.load(resolve) {
+            Ok(annotations) => annotations,
+            Err(e) => {
+                warn!("can't read the annotations: {:?}", e);
+                return Ok(Some(root_transformation));
+            }
+        };
+        for annot in annotations.iter() {
+            // each annotation starts from the initial graphics state, whatever the page content left behind
+            let mut state = page_state(backend, resolve, &resources, page, config);
+            if let Err(e) = state.draw_annotation(annot) {
+                warn!("can't draw annotation: {:?}", e);
+            }
+        }
+    }
+
+    Ok(Some(root_transformation))
+}
+/// The initial state of the page content: transformed to device space and clipped to the crop box and the region.
+fn page_state<'a, R: Resolve, B: Backend>(backend: &'a mut B, resolve: &'a R, resources: &'a Resources, page: &Page, config: &'a RenderConfig) -> RenderState<'a, R, B> {
+    let root_transformation = config.transform * page_transform(page);
+    let mut state = RenderState::new(backend, resolve, resources, root_transformation);
+    state.set_config(config);
+    // everything outside of the crop box is cut off
+    state.clip_rect(media_bounds(page) * (1.0 / page_scale(page)));
+    if let Some(region) = config.region {
+        state.clip_rect(page_transform(page).inverse() * region);
+    }
+    state
 }
 pub fn render_pattern(backend: &mut impl Backend, pattern: &Pattern, resolve: &impl Resolve) -> Result<(), PdfError> {
     match pattern {
@@ -135,8 +329,10 @@ impl Fill {
 
 #[derive(Debug)]
 pub struct TextSpan {
-    // A rect with the origin at the baseline, a height of 1em and width that corresponds to the advance width.
+    // A rect with the origin at the (raised) baseline, a height of 1em and width that corresponds to the advance width.
     pub rect: RectF,
+    // The corners of the em box, raised by the text rise, in device space: baseline start, baseline end, top end, top start
+    pub quad: [Vector2F; 4],
 
     // width in textspace units (before applying transform)
     pub width: f32,
@@ -154,6 +350,8 @@ pub struct TextSpan {
     pub transform: Transform2F,
     pub mode: TextMode,
     pub op_nr: usize,
+    /// /MCID of the marked content the text is in, see `StructTree`
+    pub mcid: Option<i32>,
 }
 impl TextSpan {
     pub fn parts(&self) -> impl Iterator<Item=Part> + '_ {
@@ -185,9 +383,36 @@ pub struct Part<'a> {
     pub width: f32,
     pub offset: usize,
 }
+/// A glyph of shown text, passed to `Backend::text_glyph`
+#[derive(Debug, Clone, Copy)]
+pub struct TextGlyph<'a> {
+    /// character code in the font
+    pub code: u16,
+    pub unicode: Option<&'a str>,
+    /// the em box of the glyph, as wide as its advance, in device space: baseline start, baseline end, top end, top start
+    pub quad: [Vector2F; 4],
+    /// in text space, including character and word spacing
+    pub advance: f32,
+}
 #[derive(Debug, Clone, Copy)]
 pub struct TextChar {
     pub offset: usize,
     pub pos: f32,
     pub width: f32,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{page_pdf, load};
+
+    #[test]
+    fn unknown_ops_in_compatibility_section() {
+        // strict parsing still accepts unknown operators between BX and EX, also nested ones
+        let file = load(page_pdf(20.0, 20.0, b"BX 1 2 vendorop BX /A otherop EX EX 0 0 10 10 re f"));
+        let page = file.get_page(0).unwrap();
+        let mut cache = Cache::new();
+        let mut backend = SceneBackend::new(&mut cache);
+        let config = RenderConfig::new().lenient(false);
+        render_page_with_config(&mut backend, &file.resolver(), &page, &config).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! Link annotations and the destinations they go to, for making rendered pages clickable.
+use std::collections::{HashMap, HashSet};
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::Result;
+use pathfinder_geometry::{vector::Vector2F, rect::RectF};
+use crate::renderstate::rect_f;
+
+/// name trees and page trees nested deeper than this are not read
+const MAX_DEPTH: usize = 32;
+
+/// How the destination page is shown. Coordinates are in the user space of that page, `None` keeps the current value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum View {
+    /// the point (`left`, `top`) at the top left of the window, magnified by `zoom`
+    Xyz { left: Option<f32>, top: Option<f32>, zoom: Option<f32> },
+    /// the whole page
+    Fit,
+    /// the width of the page, with `top` at the top of the window
+    FitH { top: Option<f32> },
+    /// the height of the page, with `left` at the left of the window
+    FitV { left: Option<f32> },
+    /// the rectangle
+    FitR(RectF),
+    /// like `Fit`, `FitH` and `FitV`, for the bounding box of the page content
+    FitB,
+    FitBH { top: Option<f32> },
+    FitBV { left: Option<f32> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destination {
+    /// 0 based
+    pub page: u32,
+    pub view: View,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkTarget {
+    /// a page of this document
+    GoTo(Destination),
+    /// a URI action
+    Uri(String),
+    /// a destination that could not be resolved, by the name it was given
+    Unresolved(String),
+    /// any other action, by its /S
+    Action(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// /Rect in the user space of the page. Use `page_transform` for the rendered page.
+    pub rect: RectF,
+    pub target: LinkTarget,
+}
+
+/// The page numbers and named destinations of a document, to resolve link targets with.
+pub struct Destinations {
+    pages: HashMap<PlainRef, u32>,
+    /// named destinations of the /Dests name tree and the older /Dests dictionary
+    named: HashMap<String, Primitive>,
+}
+impl Destinations {
+    pub fn from_catalog(catalog: &Catalog, resolve: &impl Resolve) -> Result<Self> {
+        let mut page_refs = vec![];
+        collect_pages(&catalog.pages, resolve, &mut page_refs, &mut HashSet::new(), 0)?;
+        let pages = page_refs.into_iter().enumerate().map(|(i, r)| (r, i as u32)).collect();
+
+        let mut named = HashMap::new();
+        if let Some(dests) = catalog.other.get("Dests") {
+            for (name, dest) in t!(dests.clone().resolve(resolve)).into_dictionary()?.iter() {
+                named.insert(name.as_str().to_owned(), dest.clone());
+            }
+        }
+        let dests = match catalog.other.get("Names") {
+            Some(names) => t!(names.clone().resolve(resolve)).into_dictionary()?.get("Dests").cloned(),
+            None => None
+        };
+        if let Some(tree) = dests {
+            read_name_tree(&tree, resolve, &mut named, &mut HashSet::new(), 0);
+        }
+        Ok(Destinations { pages, named })
+    }
+    /// The 0 based index of the page object `page`.
+    pub fn page_index(&self, page: PlainRef) -> Option<u32> {
+        self.pages.get(&page).cloned()
+    }
+    /// Resolve /Dest, or /D of a GoTo action: an explicit destination array, or the name of one.
+    pub fn resolve(&self, dest: &Primitive, resolve: &impl Resolve) -> Option<Destination> {
+        let explicit = match dest.clone().resolve(resolve).ok()? {
+            Primitive::Name(name) => self.named.get(name.as_str())?.clone().resolve(resolve).ok()?,
+            Primitive::String(name) => self.named.get(&name.to_string_lossy())?.clone().resolve(resolve).ok()?,
+            p => p
+        };
+        // named destinations may be a dictionary with the array in /D
+        let array = match explicit {
+            Primitive::Dictionary(dict) => dict.get("D")?.clone().resolve(resolve).ok()?.into_array().ok()?,
+            p => p.into_array().ok()?
+        };
+        let page = match *array.get(0)? {
+            Primitive::Reference(r) => self.page_index(r)?,
+            // destinations of remote go-to actions use page numbers
+            Primitive::Integer(n) => n.max(0) as u32,
+            _ => return None
+        };
+        let n = |i: usize| match array.get(i) {
+            Some(p) => p.as_number().ok(),
+            None => None
+        };
+        let view = match array.get(1).and_then(|p| p.as_name().ok())? {
+            "XYZ" => View::Xyz { left: n(2), top: n(3), zoom: n(4).filter(|&z| z != 0.0) },
+            "Fit" => View::Fit,
+            "FitH" => View::FitH { top: n(2) },
+            "FitV" => View::FitV { left: n(2) },
+            "FitR" => View::FitR(RectF::from_points(
+                Vector2F::new(n(2)?, n(3)?).min(Vector2F::new(n(4)?, n(5)?)),
+                Vector2F::new(n(2)?, n(3)?).max(Vector2F::new(n(4)?, n(5)?)),
+            )),
+            "FitB" => View::FitB,
+            "FitBH" => View::FitBH { top: n(2) },
+            "FitBV" => View::FitBV { left: n(2) },
+            v => {
+                debug!("unknown destination view {}", v);
+                return None;
+            }
+        };
+        Some(Destination { page, view })
+    }
+    /// The links of `page`, in the order of its annotations.
+    pub fn page_links(&self, page: &Page, resolve: &impl Resolve) -> Result<Vec<Link>> {
+        let annotations = match page.annotations.load(resolve) {
+            Ok(annotations) => annotations,
+            Err(e) => {
+                warn!("can't read the annotations: {:?}", e);
+                return Ok(vec![]);
+            }
+        };
+        let mut links = vec![];
+        for annot in annotations.iter() {
+            if annot.subtype.as_str() != "Link" {
+                continue;
+            }
+            let rect = match annot.rect {
+                Some(rect) => rect_f(rect),
+                None => continue
+            };
+            if let Some(target) = self.link_target(&annot.other, resolve) {
+                links.push(Link { rect, target });
+            }
+        }
+        Ok(links)
+    }
+    fn link_target(&self, annot: &Dictionary, resolve: &impl Resolve) -> Option<LinkTarget> {
+        let unresolved = |dest: &Primitive| match dest.clone().resolve(resolve) {
+            Ok(Primitive::Name(name)) => LinkTarget::Unresolved(name.as_str().into()),
+            Ok(Primitive::String(name)) => LinkTarget::Unresolved(name.to_string_lossy()),
+            _ => LinkTarget::Unresolved(String::new()),
+        };
+        if let Some(dest) = annot.get("Dest") {
+            return Some(self.resolve(dest, resolve).map(LinkTarget::GoTo).unwrap_or_else(|| unresolved(dest)));
+        }
+        let action = annot.get("A")?.clone().resolve(resolve).ok()?.into_dictionary().ok()?;
+        match action.get("S").and_then(|s| s.as_name().ok())? {
+            "GoTo" => {
+                let dest = action.get("D")?;
+                Some(self.resolve(dest, resolve).map(LinkTarget::GoTo).unwrap_or_else(|| unresolved(dest)))
+            }
+            "URI" => match action.get("URI")?.clone().resolve(resolve).ok()? {
+                Primitive::String(uri) => Some(LinkTarget::Uri(uri.to_string_lossy())),
+                _ => None
+            },
+            s => Some(LinkTarget::Action(s.into()))
+        }
+    }
+}
+
+fn collect_pages(tree: &PageTree, resolve: &impl Resolve, out: &mut Vec<PlainRef>, visited: &mut HashSet<PlainRef>, depth: usize) -> Result<()> {
+    if depth >= MAX_DEPTH {
+        warn!("page tree nested more than {} levels deep", MAX_DEPTH);
+        return Ok(());
+    }
+    for &kid in &tree.kids {
+        if !visited.insert(kid.get_inner()) {
+            warn!("page tree node {:?} appears twice", kid.get_inner());
+            continue;
+        }
+        match *t!(resolve.get(kid)) {
+            PagesNode::Tree(ref t) => collect_pages(t, resolve, out, visited, depth + 1)?,
+            PagesNode::Leaf(_) => out.push(kid.get_inner()),
+        }
+    }
+    Ok(())
+}
+
+/// Add the entries of the name tree `node` to `out`
+fn read_name_tree(node: &Primitive, resolve: &impl Resolve, out: &mut HashMap<String, Primitive>, visited: &mut HashSet<PlainRef>, depth: usize) {
+    if depth >= MAX_DEPTH {
+        warn!("name tree nested more than {} levels deep", MAX_DEPTH);
+        return;
+    }
+    if let Primitive::Reference(r) = *node {
+        if !visited.insert(r) {
+            warn!("name tree node {:?} appears twice", r);
+            return;
+        }
+    }
+    let node = match node.clone().resolve(resolve).and_then(|n| n.into_dictionary()) {
+        Ok(node) => node,
+        Err(e) => {
+            warn!("invalid name tree node: {:?}", e);
+            return;
+        }
+    };
+    if let Some(Ok(names)) = node.get("Names").map(|n| n.clone().resolve(resolve).and_then(|n| n.into_array())) {
+        // [key value key value ...]
+        for pair in names.chunks_exact(2) {
+            if let Ok(Primitive::String(key)) = pair[0].clone().resolve(resolve) {
+                out.insert(key.to_string_lossy(), pair[1].clone());
+            }
+        }
+    }
+    if let Some(Ok(kids)) = node.get("Kids").map(|k| k.clone().resolve(resolve).and_then(|k| k.into_array())) {
+        for kid in &kids {
+            read_name_tree(kid, resolve, out, visited, depth + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{build_pdf, load};
+
+    #[test]
+    fn named_destination_in_nested_name_tree() {
+        // the leaf also lists the root as a kid, which is only read once
+        let file = load(build_pdf(
+            "/Names << /Dests 5 0 R >>",
+            "/MediaBox [0 0 20 20] /Annots [8 0 R]",
+            b"",
+            &[
+                "<< /Kids [6 0 R] >>",
+                "<< /Limits [(a) (a)] /Names [(a) 7 0 R] /Kids [5 0 R] >>",
+                "<< /D [3 0 R /XYZ 10 20 0] >>",
+                "<< /Type /Annot /Subtype /Link /Rect [0 0 5 5] /Dest (a) >>",
+            ]
+        ));
+        let resolver = file.resolver();
+        let dests = Destinations::from_catalog(file.get_root(), &resolver).unwrap();
+        let links = dests.page_links(&file.get_page(0).unwrap(), &resolver).unwrap();
+        std::assert_eq!(links.len(), 1);
+        std::assert_eq!(links[0].target, LinkTarget::GoTo(Destination {
+            page: 0,
+            view: View::Xyz { left: Some(10.0), top: Some(20.0), zoom: None },
+        }));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::{HashMap, HashSet};
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::{PdfError, Result};
+
+/// Visibility of optional content groups (layers), identified by their /Name.
+#[derive(Debug, Clone, Default)]
+pub struct OptionalContent {
+    /// all groups, in document order
+    names: Vec<String>,
+    /// hidden in the default configuration
+    off: HashSet<String>,
+    /// set by the user
+    overrides: HashMap<String, bool>,
+}
+impl OptionalContent {
+    /// Everything is visible.
+    pub fn new() -> Self {
+        OptionalContent::default()
+    }
+    /// Read /OCProperties of the document catalog, using the default configuration /D.
+    pub fn from_catalog(catalog: &Catalog, resolve: &impl Resolve) -> Result<Self> {
+        match catalog.other.get("OCProperties") {
+            Some(p) => OptionalContent::parse(p, resolve),
+            None => Ok(OptionalContent::new())
+        }
+    }
+    pub fn parse(oc_properties: &Primitive, resolve: &impl Resolve) -> Result<Self> {
+        let dict = oc_properties.clone().resolve(resolve)?.into_dictionary()?;
+        let names = match dict.get("OCGs") {
+            Some(p) => group_names(p, resolve)?,
+            None => return Err(PdfError::MissingEntry { typ: "OCProperties", field: "OCGs".into() })
+        };
+        let mut off = HashSet::new();
+        if let Some(config) = dict.get("D") {
+            let config = config.clone().resolve(resolve)?.into_dictionary()?;
+            match config.get("BaseState") {
+                Some(Primitive::Name(ref state)) if state.as_str() == "OFF" => {
+                    off.extend(names.iter().cloned());
+                }
+                _ => {}
+            }
+            if let Some(p) = config.get("ON") {
+                for name in group_names(p, resolve)? {
+                    off.remove(&name);
+                }
+            }
+            if let Some(p) = config.get("OFF") {
+                off.extend(group_names(p, resolve)?);
+            }
+        }
+        Ok(OptionalContent { names, off, overrides: HashMap::new() })
+    }
+    /// Show or hide the group `name`, regardless of the configuration in the file.
+    pub fn set_visible(&mut self, name: &str, visible: bool) {
+        self.overrides.insert(name.into(), visible);
+    }
+    pub fn is_visible(&self, name: &str) -> bool {
+        match self.overrides.get(name) {
+            Some(&visible) => visible,
+            None => !self.off.contains(name)
+        }
+    }
+    /// All groups and whether they are visible
+    pub fn layers(&self) -> impl Iterator<Item=(&str, bool)> + '_ {
+        self.names.iter().map(move |name| (name.as_str(), self.is_visible(name)))
+    }
+
+    /// Whether the content of an optional content group or membership dictionary is visible.
+    pub fn group_visible(&self, group: &Dictionary, resolve: &impl Resolve) -> bool {
+        match group.get("Type") {
+            Some(Primitive::Name(ref t)) if t.as_str() == "OCMD" => {}
+            _ => return match group_name(group, resolve) {
+                Some(name) => self.is_visible(&name),
+                None => true
+            }
+        }
+        if group.get("VE").is_some() {
+            debug!("visibility expressions are not supported, using /OCGs and /P");
+        }
+        let visible: Vec<bool> = match group.get("OCGs") {
+            Some(p) => match group_names(p, resolve) {
+                Ok(names) => names.iter().map(|name| self.is_visible(name)).collect(),
+                Err(_) => return true
+            },
+            None => return true
+        };
+        if visible.is_empty() {
+            return true;
+        }
+        match group.get("P") {
+            Some(Primitive::Name(ref p)) if p.as_str() == "AllOn" => visible.iter().all(|&v| v),
+            Some(Primitive::Name(ref p)) if p.as_str() == "AnyOff" => visible.iter().any(|&v| !v),
+            Some(Primitive::Name(ref p)) if p.as_str() == "AllOff" => visible.iter().all(|&v| !v),
+            _ => visible.iter().any(|&v| v),
+        }
+    }
+}
+
+fn group_name(group: &Dictionary, resolve: &impl Resolve) -> Option<String> {
+    match group.get("Name")?.clone().resolve(resolve).ok()? {
+        Primitive::String(s) => Some(s.to_string_lossy()),
+        _ => None
+    }
+}
+
+/// names of a group or an array of groups
+fn group_names(p: &Primitive, resolve: &impl Resolve) -> Result<Vec<String>> {
+    match p.clone().resolve(resolve)? {
+        Primitive::Array(groups) => Ok(groups.into_iter().filter_map(|g| {
+            let dict = g.resolve(resolve).ok()?.into_dictionary().ok()?;
+            group_name(&dict, resolve)
+        }).collect()),
+        Primitive::Dictionary(dict) => Ok(group_name(&dict, resolve).into_iter().collect()),
+        p => Err(PdfError::UnexpectedPrimitive { expected: "Array or Dictionary", found: p.get_debug_name() })
+    }
+}
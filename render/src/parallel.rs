@@ -0,0 +1,38 @@
+//! Rendering several pages at once, on the rayon thread pool.
+use std::ops::Range;
+use std::sync::Arc;
+use pdf::file::{File, Cache as ObjectCache, Log};
+use pdf::any::AnySync;
+use pdf::error::PdfError;
+use pathfinder_renderer::scene::Scene;
+use rayon::prelude::*;
+
+use crate::{Cache, FontEntry, SceneBackend, RenderConfig, render_page_with_config};
+
+// everything shared between the workers
+const _: fn() = || {
+    fn shared<T: Send + Sync>() {}
+    shared::<Cache>();
+    shared::<FontEntry>();
+    shared::<RenderConfig>();
+};
+
+/// Render the pages in `pages` in parallel, sharing the fonts and images loaded by `cache`.
+///
+/// The results are in page order.
+pub fn render_pages<B, OC, SC, L>(file: &File<B, OC, SC, L>, pages: Range<u32>, cache: &Cache, config: &RenderConfig) -> Vec<Result<Scene, PdfError>>
+where
+    B: pdf::backend::Backend + Sync,
+    OC: ObjectCache<Result<AnySync, Arc<PdfError>>> + Sync,
+    SC: ObjectCache<Result<Arc<[u8]>, Arc<PdfError>>> + Sync,
+    L: Log + Sync,
+{
+    pages.into_par_iter().map(|n| {
+        let page = file.get_page(n)?;
+        let resolver = file.resolver();
+        let mut cache = cache.clone();
+        let mut backend = SceneBackend::new(&mut cache);
+        render_page_with_config(&mut backend, &resolver, &page, config)?;
+        Ok(backend.finish())
+    }).collect()
+}
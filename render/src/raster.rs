@@ -0,0 +1,185 @@
+//! Rendering straight to pixels, using pathfinder_rasterize.
+use std::sync::Arc;
+use pdf::file::{File, Cache as ObjectCache, Log};
+use pdf::any::AnySync;
+use pdf::error::PdfError;
+use pathfinder_rasterize::Rasterizer;
+use image::RgbaImage;
+use pathfinder_geometry::{rect::RectF, transform2d::Transform2F};
+use pathfinder_renderer::scene::Scene;
+
+use crate::{Cache, RenderConfig, Antialias, build_scene, embedded_thumbnail};
+use crate::scene::build_scenes;
+
+/// Render page `page` (0 based) with the options of `config`, for example at `RenderConfig::dpi` pixels per inch.
+///
+/// Pages with a /UserUnit are rendered at their physical size, since `render_page` includes it.
+///
+/// The first row of the image is the top of the page, after applying /Rotate.
+///
+/// The content is painted over `RenderConfig::background`, so a page that paints its own background covers it.
+pub fn render_page_to_image<B, OC, SC, L>(file: &File<B, OC, SC, L>, page: u32, config: &RenderConfig, cache: &mut Cache) -> Result<RgbaImage, PdfError>
+where
+    B: pdf::backend::Backend,
+    OC: ObjectCache<Result<AnySync, Arc<PdfError>>>,
+    SC: ObjectCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log,
+{
+    // without anti-aliasing the coverage of each pixel is thresholded. The coverage scene tells it apart from the
+    // alpha of the content, against a transparent page. The background is filled in afterwards.
+    let image = match config.antialias {
+        Antialias::None => {
+            let (scene, coverage, _) = build_scenes(file, page, &config.clone().background(None), true, cache)?;
+            let mut image = rasterize(scene)?;
+            if let Some(coverage) = coverage {
+                let coverage = rasterize(coverage)?;
+                let background = config.background.map_or([0; 4], |c| [c.r, c.g, c.b, c.a]);
+                threshold_coverage(&mut image, &coverage, background);
+            }
+            image
+        }
+        Antialias::Standard => rasterize(build_scene(file, page, config, cache)?.0)?,
+    };
+    Ok(image)
+}
+
+/// Render the part `tile` of page `page` (0 based), for example to show a deep zoom of a large page.
+///
+/// `tile` is in millimeters, in the space of `page_bounds`. The image covers `config.transform * tile`,
+/// so at `RenderConfig::dpi` it is `tile.width() / 25.4 * dpi` pixels wide. Only the content inside the tile is drawn.
+pub fn render_tile_to_image<B, OC, SC, L>(file: &File<B, OC, SC, L>, page: u32, tile: RectF, config: &RenderConfig, cache: &mut Cache) -> Result<RgbaImage, PdfError>
+where
+    B: pdf::backend::Backend,
+    OC: ObjectCache<Result<AnySync, Arc<PdfError>>>,
+    SC: ObjectCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log,
+{
+    // move the tile to the origin of the image
+    let origin = (config.transform * tile).origin();
+    let config = config.clone()
+        .transform(Transform2F::from_translation(-origin) * config.transform)
+        .region(tile);
+    render_page_to_image(file, page, &config, cache)
+}
+
+/// Where `render_thumbnail` gets the image from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ThumbnailSource {
+    /// the thumbnail embedded in the file (/Thumb), rendering the page if there is none
+    Embedded,
+    /// always render the page
+    Render,
+}
+
+/// A preview of page `page` (0 based). Rendered pages use the options of `config`, usually with a low `RenderConfig::dpi`.
+///
+/// Embedded thumbnails are returned at their own size and don't include annotations.
+pub fn render_thumbnail<B, OC, SC, L>(file: &File<B, OC, SC, L>, page: u32, config: &RenderConfig, source: ThumbnailSource, cache: &mut Cache) -> Result<RgbaImage, PdfError>
+where
+    B: pdf::backend::Backend,
+    OC: ObjectCache<Result<AnySync, Arc<PdfError>>>,
+    SC: ObjectCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log,
+{
+    if source == ThumbnailSource::Embedded {
+        let p = file.get_page(page)?;
+        let icc = cache.icc_transform();
+        match embedded_thumbnail(&p, &file.resolver(), icc.as_deref(), cache.cmyk_conversion()) {
+            Ok(Some(thumb)) => {
+                let (width, height) = (thumb.width(), thumb.height());
+                if let Some(image) = RgbaImage::from_raw(width, height, thumb.rgba_data().to_vec()) {
+                    return Ok(image);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("can't decode the thumbnail of page {}: {:?}", page, e),
+        }
+    }
+    render_page_to_image(file, page, config, cache)
+}
+
+fn rasterize(scene: Scene) -> Result<RgbaImage, PdfError> {
+    let image = Rasterizer::new().rasterize(scene, None);
+    // pathfinder_rasterize uses a different version of the image crate
+    let (width, height) = image.dimensions();
+    RgbaImage::from_raw(width, height, image.into_raw())
+        .ok_or_else(|| PdfError::Other { msg: "rasterizer returned a truncated image".into() })
+}
+
+/// Make every pixel either covered by the content or `background`, using the alpha of `coverage` (the coverage scene).
+///
+/// Covered pixels keep the alpha of the content painted there and are composited over `background`.
+/// Edges between two painted objects keep their blended color, only the edges against the page are hard.
+fn threshold_coverage(image: &mut RgbaImage, coverage: &RgbaImage, background: [u8; 4]) {
+    for (px, cov) in image.pixels_mut().zip(coverage.pixels()) {
+        let covered = cov.0[3];
+        px.0 = match px.0 {
+            _ if covered < 128 => background,
+            [r, g, b, a] => {
+                // the alpha of the content, without the partial coverage of the pixel
+                let alpha = (a as f32 / covered as f32).min(1.0);
+                let over = |c: u8, bg: u8| (c as f32 * alpha + bg as f32 * (1.0 - alpha)).round() as u8;
+                let [br, bg, bb, ba] = background;
+                [over(r, br), over(g, bg), over(b, bb), over(255, ba)]
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{page_pdf, build_pdf, load};
+
+    fn render(file: &pdf::file::CachedFile<Vec<u8>>) -> RgbaImage {
+        let config = RenderConfig::new().dpi(72.0).antialias(Antialias::None);
+        render_page_to_image(file, 0, &config, &mut Cache::new()).unwrap()
+    }
+
+    #[test]
+    fn thin_line_is_dropped() {
+        // covers 30% of the bottom row and the whole row above
+        let file = load(page_pdf(20.0, 20.0, b"0 g 0 0 20 0.3 re f 0 1 20 1 re f"));
+        let image = render(&file);
+        std::assert_eq!(image.get_pixel(10, 19).0, [255, 255, 255, 255]);
+        std::assert_eq!(image.get_pixel(10, 18).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn translucent_fill_is_kept() {
+        // fully covered, but with an alpha below one half
+        let file = load(build_pdf("", "/MediaBox [0 0 20 20] /Resources << /ExtGState << /G << /ca 0.4 >> >> >>",
+            b"/G gs 0 g 0 0 20 20 re f", &[]));
+        let image = render(&file);
+        let [r, g, b, a] = image.get_pixel(10, 10).0;
+        std::assert_eq!(a, 255);
+        assert!(r == g && g == b && (150 ..= 156).contains(&r), "{:?}", (r, g, b));
+    }
+
+    #[test]
+    fn snap_grid_hides_small_offsets() {
+        // edges a fraction of the snap grid apart give the same pixels, also with anti-aliasing
+        let config = RenderConfig::new().dpi(72.0).deterministic(true);
+        let exact = render_page_to_image(&load(page_pdf(20.0, 20.0, b"0 g 0 0 10 20 re f")), 0, &config, &mut Cache::new()).unwrap();
+        let offset = render_page_to_image(&load(page_pdf(20.0, 20.0, b"0 g 0.001 0 10.02 20 re f")), 0, &config, &mut Cache::new()).unwrap();
+        assert!(exact == offset);
+        std::assert_eq!(offset.get_pixel(9, 10).0, [0, 0, 0, 255]);
+        std::assert_eq!(offset.get_pixel(10, 10).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn shading_stays_in_clip() {
+        // a black to white gradient across the page, painted by sh inside 5 5 10 10
+        let file = load(build_pdf("", "/MediaBox [0 0 20 20] /Resources << /Shading << /Sh 5 0 R >> >>",
+            b"5 5 10 10 re W n /Sh sh", &[
+            "<< /ShadingType 2 /ColorSpace /DeviceGray /Coords [0 0 20 0] /Extend [true true] \
+                /Function << /FunctionType 2 /Domain [0 1] /C0 [0] /C1 [1] /N 1 >> >>",
+        ]));
+        let image = render(&file);
+        for &(x, y) in &[(2, 10), (17, 10), (10, 2), (10, 17)] {
+            std::assert_eq!(image.get_pixel(x, y).0, [255, 255, 255, 255], "({}, {})", x, y);
+        }
+        let (left, right) = (image.get_pixel(6, 10).0, image.get_pixel(13, 10).0);
+        assert!(left[0] < right[0] && right[0] < 255, "{:?} {:?}", left, right);
+    }
+}
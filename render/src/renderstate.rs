@@ -1,11 +1,17 @@
 use pathfinder_content::outline::ContourIterFlags;
 use pathfinder_renderer::scene::ClipPath;
 use pdf::object::*;
-use pdf::primitive::{Primitive, Dictionary};
-use pdf::content::{Op, Matrix, Point, Rect, Color, Rgb, Cmyk, Winding, FormXObject};
+use pdf::primitive::{Primitive, Dictionary, Name};
+use pdf::content::{Op, Matrix, Point, Rect, Winding, FormXObject, Color};
 use pdf::error::{PdfError, Result};
-use pdf::content::TextDrawAdjusted;
-use crate::backend::{Backend, BlendMode, Stroke, FillMode};
+use pdf::content::{TextDrawAdjusted, TextMode};
+use pdf::content::{LineCap as PdfLineCap, LineJoin as PdfLineJoin};
+use crate::backend::{Backend, BlendMode, Blend, Stroke, FillMode, SoftMask, SoftMaskKind, TransparencyGroup};
+use pdf::function::Function;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::rc::Rc;
+use std::cell::Cell;
 
 use pathfinder_geometry::{
     vector::Vector2F,
@@ -17,13 +23,31 @@ use pathfinder_content::{
     outline::{Outline, Contour},
 };
 use super::{
-    graphicsstate::GraphicsState,
-    textstate::{TextState, Span},
+    graphicsstate::{GraphicsState, Transfer},
+    textstate::{TextState, Span, TextModeExt},
     DrawMode,
     TextSpan,
+    TextChar,
+    TextGlyph,
     Fill,
+    FontEntry,
+    Shading,
+    ShadingQuality,
+    RenderConfig,
+    annot::{self, BorderKind},
+    forms::{AcroForm, FormField, FieldKind},
+    colorspace::{ColorCache, convert_color, pattern_color, rendering_intent, paints_nothing},
+    ocg::OptionalContent,
+    image::stencil_outline,
 };
 
+/// upper limit for the number of pattern cells painted in one fill
+const MAX_TILES: usize = 10_000;
+
+/// upper limit for the number of forms, pattern cells and Type3 glyphs drawn for one page,
+/// so content that draws its children many times on each level can't explode
+const MAX_NESTED_DRAWS: usize = 200_000;
+
 trait Cvt {
     type Out;
     fn cvt(self) -> Self::Out;
@@ -50,30 +74,58 @@ impl Cvt for Rect {
         )
     }
 }
-impl Cvt for Winding {
-    type Out = FillRule;
+impl Cvt for PdfLineCap {
+    type Out = LineCap;
     fn cvt(self) -> Self::Out {
         match self {
-            Winding::NonZero => FillRule::Winding,
-            Winding::EvenOdd => FillRule::EvenOdd
+            PdfLineCap::Butt => LineCap::Butt,
+            PdfLineCap::Round => LineCap::Round,
+            PdfLineCap::Square => LineCap::Square,
         }
     }
 }
-impl Cvt for Rgb {
-    type Out = (f32, f32, f32);
+impl Cvt for PdfLineJoin {
+    type Out = LineJoin;
     fn cvt(self) -> Self::Out {
-        let Rgb { red, green, blue } = self;
-        (red, green, blue)
+        // the miter limit is filled in by the graphics state
+        match self {
+            PdfLineJoin::Miter => LineJoin::Miter(10.0),
+            PdfLineJoin::Round => LineJoin::Round,
+            PdfLineJoin::Bevel => LineJoin::Bevel,
+        }
     }
 }
-impl Cvt for Cmyk {
-    type Out = (f32, f32, f32, f32);
+impl Cvt for Winding {
+    type Out = FillRule;
     fn cvt(self) -> Self::Out {
-        let Cmyk { cyan, magenta, yellow, key } = self;
-        (cyan, magenta, yellow, key)
+        match self {
+            Winding::NonZero => FillRule::Winding,
+            Winding::EvenOdd => FillRule::EvenOdd
+        }
     }
 }
 
+/// The alpha and blend mode a knockout text object is composited with, restored at ET
+#[derive(Copy, Clone)]
+struct TextGroup {
+    fill_alpha: f32,
+    stroke_alpha: f32,
+    fill_color_alpha: f32,
+    stroke_color_alpha: f32,
+    blend: Blend,
+    /// the alphas used inside the group, to tell whether `gs` changed them
+    group_fill_alpha: f32,
+    group_stroke_alpha: f32,
+}
+
+/// An open marked content sequence (BMC or BDC)
+#[derive(Copy, Clone)]
+struct MarkedContent {
+    visible: bool,
+    /// /MCID, linking the content to the structure tree. Only read in the page content stream.
+    mcid: Option<i32>,
+}
+
 pub struct RenderState<'a, R: Resolve, B: Backend> {
     graphics_state: GraphicsState<'a, B>,
     text_state: TextState,
@@ -82,7 +134,32 @@ pub struct RenderState<'a, R: Resolve, B: Backend> {
     current_contour: Contour,
     resolve: &'a R,
     resources: &'a Resources,
+    /// resources of the content this is nested in, innermost last. Searched for names missing in `resources`.
+    enclosing: Vec<&'a Resources>,
     backend: &'a mut B,
+    colors: ColorCache,
+    /// maps pattern space to device space, the CTM at the start of the content stream
+    pattern_transform: Transform2F,
+    /// ignore color operators and sampled images, for uncolored patterns and d1 glyphs
+    color_locked: bool,
+    /// set by W and W*, applied by the next path painting operator
+    pending_clip: Option<FillRule>,
+    /// glyphs shown with a clipping text mode since BT, in device space
+    text_clip: Option<Outline>,
+    /// the current text object is drawn as a transparency group
+    text_group: Option<TextGroup>,
+    /// layer visibility, everything is visible if `None`
+    optional_content: Option<&'a OptionalContent>,
+    /// the open marked content sequences
+    marked_content: Vec<MarkedContent>,
+    /// number of forms, patterns and Type3 glyphs this content is nested in
+    depth: usize,
+    /// form XObjects and tiling patterns being drawn, to detect content that draws itself
+    forms: Vec<PlainRef>,
+    /// nested content streams drawn so far, shared by everything drawn for the page
+    nested_draws: Rc<Cell<usize>>,
+    /// overrides of the backend and file settings
+    config: Option<&'a RenderConfig>,
 }
 
 impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
@@ -91,6 +168,8 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             transform: root_transformation,
             fill_color: Fill::black(),
             fill_color_alpha: 1.0,
+            fill_pattern_color: Fill::black(),
+            stroke_pattern_color: Fill::black(),
             fill_paint: None,
             fill_alpha: 1.0,
             stroke_color: Fill::black(),
@@ -104,16 +183,23 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             stroke_color_space: &ColorSpace::DeviceRGB,
             stroke_style: StrokeStyle {
                 line_cap: LineCap::Butt,
-                line_join: LineJoin::Miter(1.0),
+                line_join: LineJoin::Miter(10.0),
                 line_width: 1.0,
             },
+            miter_limit: 10.0,
             dash_pattern: None,
             overprint_fill: false,
             overprint_stroke: false,
             overprint_mode: 0,
+            blend: Blend::Normal,
+            transfer: None,
+            fill_color_source: Fill::black(),
+            stroke_color_source: Fill::black(),
+            rendering_intent: RenderingIntent::RelativeColorimetric,
         };
         let text_state = TextState::new();
         let stack = vec![];
+        let colors = ColorCache::new(backend.icc_transform(), backend.cmyk_conversion());
         let current_outline = Outline::new();
         let current_contour = Contour::new();
 
@@ -124,22 +210,439 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             current_outline,
             current_contour,
             resources,
+            enclosing: vec![],
             resolve,
             backend,
+            colors,
+            pattern_transform: root_transformation,
+            color_locked: false,
+            pending_clip: None,
+            text_clip: None,
+            text_group: None,
+            optional_content: None,
+            marked_content: vec![],
+            depth: 0,
+            forms: vec![],
+            nested_draws: Rc::new(Cell::new(0)),
+            config: None,
+        }
+    }
+    /// Use the layers and overrides of `config`.
+    pub fn set_config(&mut self, config: &'a RenderConfig) {
+        self.optional_content = Some(&config.layers);
+        self.config = Some(config);
+    }
+    fn lenient(&self) -> bool {
+        self.config.and_then(|c| c.lenient).unwrap_or_else(|| self.resolve.options().allow_invalid_ops)
+    }
+    fn shading_quality(&self) -> ShadingQuality {
+        match self.config {
+            Some(&RenderConfig { shading_quality: Some(quality), .. }) => quality,
+            Some(&RenderConfig { deterministic: true, .. }) => ShadingQuality::default(),
+            _ => self.backend.shading_quality()
         }
     }
-    fn draw(&mut self, mode: &DrawMode, fill_rule: FillRule) {
+    /// The stroke of path painting operators, with hairlines and thin lines widened to their device width.
+    fn stroke(&self) -> Stroke {
+        self.graphics_state.stroke().widened(self.graphics_state.transform, self.config)
+    }
+    /// Whether content nested one level deeper may be drawn.
+    fn nesting_allowed(&self) -> bool {
+        let limit = self.config.and_then(|c| c.recursion_limit).unwrap_or_else(|| self.backend.recursion_limit());
+        if self.depth >= limit {
+            warn!("content nested more than {} levels deep, skipped", limit);
+            return false;
+        }
+        self.spend_nested_draw()
+    }
+    /// Count one more nested content stream, `false` once the page has drawn `MAX_NESTED_DRAWS`.
+    fn spend_nested_draw(&self) -> bool {
+        let n = self.nested_draws.get() + 1;
+        self.nested_draws.set(n);
+        if n == MAX_NESTED_DRAWS + 1 {
+            warn!("more than {} forms, pattern cells and Type3 glyphs on the page, the rest is skipped", MAX_NESTED_DRAWS);
+        }
+        n <= MAX_NESTED_DRAWS
+    }
+    /// Intersect the clip region with `rect`, in user space.
+    pub fn clip_rect(&mut self, rect: RectF) {
+        self.current_outline = Outline::from_rect(rect);
+        self.pending_clip = Some(FillRule::Winding);
+        self.apply_clip();
+        self.current_outline.clear();
+    }
+    pub fn set_optional_content(&mut self, optional_content: &'a OptionalContent) {
+        self.optional_content = Some(optional_content);
+    }
+    /// Look up a named resource, in the resources of this content first and then in those of the enclosing content.
+    /// Forms and Type3 glyphs often rely on the page for resources they don't list themselves.
+    fn resource<T>(&self, get: impl Fn(&'a Resources) -> Option<&'a T>) -> Option<&'a T> {
+        std::iter::once(self.resources).chain(self.enclosing.iter().rev().copied()).find_map(get)
+    }
+    /// The first of the resources `resource` searches that `has` is true for, `self.resources` if there is none.
+    /// For functions that look names up in a single resource dictionary.
+    fn resources_with(&self, has: impl Fn(&'a Resources) -> bool) -> &'a Resources {
+        self.resource(|r| Some(r).filter(|&r| has(r))).unwrap_or(self.resources)
+    }
+    /// The resources with the named color space of the shading `p`
+    fn shading_resources(&self, p: &Primitive) -> &'a Resources {
+        let dict = match p.clone().resolve(self.resolve) {
+            Ok(Primitive::Dictionary(dict)) => dict,
+            Ok(Primitive::Stream(stream)) => stream.info,
+            _ => return self.resources
+        };
+        match dict.get("ColorSpace") {
+            Some(Primitive::Name(ref name)) => self.resources_with(|r| r.color_spaces.get(name.as_str()).is_some()),
+            _ => self.resources
+        }
+    }
+    /// The resources with the pattern that `color` (of sc/scn) names
+    fn color_resources(&self, color: &Color) -> &'a Resources {
+        match *color {
+            Color::Other(ref args) => match args.last().and_then(|a| a.as_name().ok()) {
+                Some(name) => self.resources_with(|r| r.pattern.get(name).is_some()),
+                None => self.resources
+            },
+            _ => self.resources
+        }
+    }
+    /// The resources with the named color space of the inline image `image`
+    fn image_resources(&self, image: &ImageXObject) -> &'a Resources {
+        match image.color_space {
+            Some(ColorSpace::Named(ref name)) => self.resources_with(|r| r.color_spaces.get(name.as_str()).is_some()),
+            _ => self.resources
+        }
+    }
+    /// `enclosing` for content nested in this one that has its own resources
+    fn enclosing_resources(&self) -> Vec<&'a Resources> {
+        let mut enclosing = self.enclosing.clone();
+        enclosing.push(self.resources);
+        enclosing
+    }
+    /// inside a marked content sequence of a layer that is off
+    fn hidden(&self) -> bool {
+        self.marked_content.iter().any(|m| !m.visible)
+    }
+    /// /MCID of the innermost marked content sequence that has one
+    fn mcid(&self) -> Option<i32> {
+        self.marked_content.iter().rev().find_map(|m| m.mcid)
+    }
+    /// Nested content belongs to the marked content it is drawn in
+    fn nested_marked_content(&self) -> Vec<MarkedContent> {
+        self.mcid().map(|mcid| MarkedContent { visible: true, mcid: Some(mcid) }).into_iter().collect()
+    }
+    /// Whether the content of /OC properties (a group or membership dictionary) is visible.
+    fn oc_visible(&self, properties: &Primitive) -> bool {
+        let oc = match self.optional_content {
+            Some(oc) => oc,
+            None => return true
+        };
+        let group = match *properties {
+            Primitive::Reference(_) => properties.clone().resolve(self.resolve).and_then(|p| p.into_dictionary()).map(Cow::Owned),
+            ref p => self.get_properties(p).map(Cow::Borrowed)
+        };
+        match group {
+            Ok(group) => oc.group_visible(&group, self.resolve),
+            Err(e) => {
+                warn!("invalid optional content: {:?}", e);
+                true
+            }
+        }
+    }
+    fn draw(&mut self, mode: DrawMode, fill_rule: FillRule) {
         self.flush();
-        self.backend.draw(&self.current_outline, mode, fill_rule, self.graphics_state.transform, self.graphics_state.clip_path_id);
+        let pattern = |f: &FillMode| matches!(f.color, Fill::Pattern(_));
+        let (fill_marks, stroke_marks) = self.graphics_state.marks();
+        match mode.painted(fill_marks, stroke_marks) {
+            // fill and stroke are painted separately when either uses a pattern
+            Some(DrawMode::FillStroke { ref fill, ref stroke, ref stroke_mode }) if pattern(fill) || pattern(stroke) => {
+                self.draw_fill(fill, fill_rule);
+                self.draw_stroke(stroke, stroke_mode);
+            }
+            Some(DrawMode::Fill { ref fill }) if pattern(fill) => self.draw_fill(fill, fill_rule),
+            Some(DrawMode::Stroke { ref stroke, ref stroke_mode }) if pattern(stroke) => self.draw_stroke(stroke, stroke_mode),
+            Some(ref mode) => self.backend.draw(&self.current_outline, mode, fill_rule, self.graphics_state.transform, self.graphics_state.clip_path_id),
+            None => {}
+        }
+        self.apply_clip();
         self.current_outline.clear();
     }
-    #[allow(unused_variables)]
+    fn draw_fill(&mut self, fill: &FillMode, fill_rule: FillRule) {
+        if let Fill::Pattern(pattern) = fill.color {
+            let outline = self.current_outline.clone();
+            match self.paint_pattern(pattern, &outline, fill, fill_rule, self.graphics_state.fill_pattern_color) {
+                Ok(true) => return,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("can't fill with pattern: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let mode = DrawMode::Fill { fill: fill.clone() };
+        self.backend.draw(&self.current_outline, &mode, fill_rule, self.graphics_state.transform, self.graphics_state.clip_path_id);
+    }
+    fn draw_stroke(&mut self, stroke: &FillMode, stroke_mode: &Stroke) {
+        if let Fill::Pattern(pattern) = stroke.color {
+            // the pattern is painted in the area covered by the stroke
+            let outline = stroke_mode.outline(&self.current_outline);
+            match self.paint_pattern(pattern, &outline, stroke, FillRule::Winding, self.graphics_state.stroke_pattern_color) {
+                Ok(true) => return,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!("can't stroke with pattern: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let mode = DrawMode::Stroke { stroke: stroke.clone(), stroke_mode: stroke_mode.clone() };
+        self.backend.draw(&self.current_outline, &mode, FillRule::Winding, self.graphics_state.transform, self.graphics_state.clip_path_id);
+    }
+    /// Intersect the clip region with the current path, if W or W* was used.
+    fn apply_clip(&mut self) {
+        let fill_rule = match self.pending_clip.take() {
+            Some(rule) => rule,
+            None => return
+        };
+        let path = self.current_outline.clone().transformed(&self.graphics_state.transform);
+        self.clip_device(path, fill_rule);
+    }
+    /// Intersect the clip region with `path`, in device space.
+    fn clip_device(&mut self, mut path: Outline, fill_rule: FillRule) {
+        let new_rect = to_rect(&path);
+        let gs = &self.graphics_state;
+        let polygon = |r: RectF| [r.origin(), r.upper_right(), r.lower_right(), r.lower_left()];
+
+        // rectangles are intersected directly, everything else is nested in the parent clip path
+        let (path, fill_rule, rect, parent) = match (gs.clip_path_id, gs.clip_path_rect, new_rect, gs.clip_path.as_ref()) {
+            (None, _, r, _) => (path, fill_rule, r, None),
+            (Some(_), Some(r1), Some(r2), _) => {
+                let r = r1.intersection(r2).unwrap_or_default();
+                (Outline::from_rect(r), FillRule::Winding, Some(r), None)
+            }
+            (Some(_), Some(r), None, _) => {
+                path.clip_against_polygon(&polygon(r));
+                (path, fill_rule, None, None)
+            }
+            (Some(_), None, Some(r), Some(old)) => {
+                let mut outline = old.outline().clone();
+                outline.clip_against_polygon(&polygon(r));
+                (outline, old.fill_rule(), None, old.clip_path())
+            }
+            (Some(p), None, _, _) => (path, fill_rule, None, Some(p)),
+        };
+
+        let id = self.backend.create_clip_path(path.clone(), fill_rule, parent);
+        let mut clip = ClipPath::new(path);
+        clip.set_fill_rule(fill_rule);
+        clip.set_clip_path(parent);
+        self.graphics_state.clip_path_id = Some(id);
+        self.graphics_state.clip_path = Some(clip);
+        self.graphics_state.clip_path_rect = rect;
+    }
+    /// The bounds of the clip region in device space, `None` if nothing is clipped.
+    fn clip_bounds(&self) -> Option<RectF> {
+        let gs = &self.graphics_state;
+        gs.clip_path_id?;
+        gs.clip_path_rect.or_else(|| gs.clip_path.as_ref().map(|c| c.outline().bounds()))
+    }
+    /// Fill the image mask `im` with the pattern of `fill`, through the outline of its painted pixels.
+    /// Returns false if it isn't filled with a pattern, or the pattern has to be painted by the backend.
+    fn pattern_image_mask(&mut self, im: &ImageXObject, fill: &FillMode) -> bool {
+        let pattern = match fill.color {
+            Fill::Pattern(pattern) => pattern,
+            _ => return false
+        };
+        let outline = match stencil_outline(im, self.resolve) {
+            Ok(outline) => outline,
+            Err(e) => {
+                warn!("can't read image mask: {:?}", e);
+                return true;
+            }
+        };
+        match self.paint_pattern(pattern, &outline, fill, FillRule::Winding, self.graphics_state.fill_pattern_color) {
+            Ok(painted) => painted,
+            Err(e) => {
+                warn!("can't fill image mask with pattern: {:?}", e);
+                true
+            }
+        }
+    }
+    /// Fill `outline`, in user space, with a pattern. `color` is used by uncolored tiling patterns.
+    /// Returns false if the pattern has to be painted by the backend.
+    fn paint_pattern(&mut self, pattern: Ref<Pattern>, outline: &Outline, fill: &FillMode, fill_rule: FillRule, color: Fill) -> Result<bool> {
+        let pattern_ref = pattern.get_inner();
+        let pattern = self.resolve.get(pattern)?;
+        let outline = outline.clone().transformed(&self.graphics_state.transform);
+        let bounds = outline.bounds();
+        let clip = self.backend.create_clip_path(outline, fill_rule, self.graphics_state.clip_path_id);
+
+        let (dict, ops) = match *pattern {
+            Pattern::Stream(ref dict, ref ops) => (dict, ops),
+            Pattern::Dict(ref dict) => {
+                let p = dict.get("Shading").ok_or(PdfError::MissingEntry { typ: "Pattern", field: "Shading".into() })?;
+                let matrix = match dict.get("Matrix") {
+                    Some(p) => {
+                        let m = p.clone().resolve(self.resolve)?.into_array()?
+                            .iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?;
+                        match m[..] {
+                            [a, b, c, d, e, f] => Transform2F::row_major(a, c, e, b, d, f),
+                            _ => return Err(PdfError::Other { msg: format!("pattern Matrix {:?}", m) })
+                        }
+                    }
+                    None => Transform2F::default()
+                };
+                let quality = self.shading_quality();
+                self.colors.set_intent(self.graphics_state.rendering_intent);
+                let resources = self.shading_resources(p);
+                let shading = t!(Shading::parse(p, resources, self.resolve, fill.mode, &mut self.colors, quality));
+                self.backend.draw_shading(&shading, self.pattern_transform * matrix, fill.mode, fill.alpha, Some(clip));
+                return Ok(true);
+            }
+        };
+
+        let matrix = self.pattern_transform * dict.matrix.map(|m| m.cvt()).unwrap_or_default();
+        let cell = rect_f(dict.bbox);
+        let (x_step, y_step) = (dict.x_step.abs(), dict.y_step.abs());
+        if x_step == 0.0 || y_step == 0.0 {
+            return Err(PdfError::Other { msg: format!("pattern step ({}, {})", dict.x_step, dict.y_step) });
+        }
+
+        // the fill bounds in pattern space
+        let inv = matrix.inverse();
+        let corners = [bounds.origin(), bounds.upper_right(), bounds.lower_right(), bounds.lower_left()].map(|p| inv * p);
+        let min = corners.iter().fold(corners[0], |a, &b| a.min(b));
+        let max = corners.iter().fold(corners[0], |a, &b| a.max(b));
+        // a singular /Matrix or tiny steps give ranges that are not finite or too large to count
+        let range = |min: f32, max: f32, cell_min: f32, cell_max: f32, step: f32| -> Option<(i64, i64)> {
+            let first = ((min as f64 - cell_max as f64) / step as f64).floor();
+            let last = ((max as f64 - cell_min as f64) / step as f64).ceil();
+            let limit = (1u64 << 53) as f64;
+            if !first.is_finite() || !last.is_finite() || first.abs() > limit || last.abs() > limit {
+                return None;
+            }
+            Some((first as i64, last as i64))
+        };
+        let (i0, i1, j0, j1) = match (range(min.x(), max.x(), cell.min_x(), cell.max_x(), x_step), range(min.y(), max.y(), cell.min_y(), cell.max_y(), y_step)) {
+            (Some((i0, i1)), Some((j0, j1))) => (i0, i1, j0, j1),
+            _ => {
+                warn!("pattern tiles can't be counted (matrix {:?}, step ({}, {}))", matrix, dict.x_step, dict.y_step);
+                return Ok(true);
+            }
+        };
+        let count = |first: i64, last: i64| last.checked_sub(first).and_then(|n| n.checked_add(1)).map(|n| n.max(0) as u64);
+        let tiles = match (count(i0, i1), count(j0, j1)) {
+            (Some(columns), Some(rows)) => columns.checked_mul(rows),
+            _ => None
+        };
+        match tiles {
+            Some(tiles) if tiles <= MAX_TILES as u64 => {}
+            _ => {
+                warn!("pattern needs {:?} tiles", tiles);
+                return Ok(true);
+            }
+        }
+
+        if self.forms.contains(&pattern_ref) {
+            warn!("pattern {:?} paints itself, skipped", pattern_ref);
+            return Ok(true);
+        }
+        if !self.nesting_allowed() {
+            return Ok(true);
+        }
+        let resources = self.resolve.get(dict.resources)?;
+        let uncolored = dict.paint_type == Some(2);
+        let mut forms = self.forms.clone();
+        forms.push(pattern_ref);
+        'tiles: for j in j0 ..= j1 {
+            for i in i0 ..= i1 {
+                if !self.spend_nested_draw() {
+                    break 'tiles;
+                }
+                let transform = matrix * Transform2F::from_translation(Vector2F::new(i as f32 * x_step, j as f32 * y_step));
+                let cell_clip = self.backend.create_clip_path(Outline::from_rect(cell).transformed(&transform), FillRule::Winding, Some(clip));
+
+                let mut inner = RenderState::new(&mut *self.backend, self.resolve, &*resources, transform);
+                inner.graphics_state.clip_path_id = Some(cell_clip);
+                inner.graphics_state.blend = self.graphics_state.blend;
+                inner.graphics_state.set_fill_alpha(fill.alpha);
+                inner.graphics_state.set_stroke_alpha(fill.alpha);
+                if uncolored {
+                    inner.graphics_state.set_fill_color(color);
+                    inner.graphics_state.set_stroke_color(color);
+                    inner.color_locked = true;
+                }
+                inner.optional_content = self.optional_content;
+                inner.depth = self.depth + 1;
+                inner.forms = forms.clone();
+                inner.nested_draws = self.nested_draws.clone();
+                inner.config = self.config;
+                inner.marked_content = self.nested_marked_content();
+                let result = ops.iter().enumerate().try_for_each(|(k, op)| {
+                    debug!(" pattern op {}: {:?}", k, op);
+                    inner.draw_op(op, k)
+                });
+                inner.end_text_group();
+                result?;
+            }
+        }
+        self.backend.set_blend_mode(self.graphics_state.blend);
+        Ok(true)
+    }
+    /// With lenient parsing (`ParseOptions::allow_invalid_ops`, the default), an operator that fails is logged and skipped,
+    /// so a damaged stream still draws everything else. Strict parsing returns the first error.
+    /// `RenderConfig::lenient` overrides the parse options.
+    ///
+    /// The compatibility operators BX and EX, and unknown operators between them, are handled by the content parser
+    /// of the pdf crate and never reach this function, so unknown operators can't be told apart from
+    /// damaged ones here.
     pub fn draw_op(&mut self, op: &'a Op, op_nr: usize) -> Result<()> {
+        match self.draw_op_inner(op, op_nr) {
+            Err(e) if self.lenient() => {
+                warn!("skipping op {} {:?}: {:?}", op_nr, op, e);
+                Ok(())
+            }
+            r => r
+        }
+    }
+    #[allow(unused_variables)]
+    fn draw_op_inner(&mut self, op: &'a Op, op_nr: usize) -> Result<()> {
         self.backend.inspect_op(op);
         self.backend.bug_op(op_nr);
+        if self.hidden() {
+            match *op {
+                Op::Stroke | Op::Fill { .. } | Op::FillAndStroke { .. } => {
+                    // the path may still be used as a clip
+                    self.flush();
+                    self.apply_clip();
+                    self.current_outline.clear();
+                    return Ok(());
+                }
+                Op::Shade { .. } | Op::XObject { .. } | Op::InlineImage { .. } => return Ok(()),
+                // text is still laid out (and clips), so the text after it starts at the right place
+                _ => {}
+            }
+        }
         match *op {
-            Op::BeginMarkedContent { .. } => {}
-            Op::EndMarkedContent { .. } => {}
+            Op::BeginMarkedContent { ref tag, ref properties } => {
+                let visible = match properties {
+                    Some(ref p) if tag.as_str() == "OC" => self.oc_visible(p),
+                    _ => true
+                };
+                // MCIDs of forms and glyphs refer to their own stream, not to the page
+                let mcid = match properties {
+                    Some(ref p) if self.depth == 0 => self.get_properties(p).ok()
+                        .and_then(|d| d.get("MCID"))
+                        .and_then(|p| p.as_integer().ok()),
+                    _ => None
+                };
+                self.marked_content.push(MarkedContent { visible, mcid });
+            }
+            Op::EndMarkedContent { .. } => {
+                if self.marked_content.pop().is_none() {
+                    debug!("EMC without BMC/BDC");
+                }
+            }
             Op::MarkedContentPoint { .. } => {}
             Op::Close => {
                 self.current_contour.close();
@@ -159,22 +662,23 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 self.current_outline.push_contour(Contour::from_rect(rect.cvt()));
             },
             Op::EndPath => {
-                self.current_contour.clear();
+                self.flush();
+                self.apply_clip();
                 self.current_outline.clear();
             }
             Op::Stroke => {
-                self.draw(&DrawMode::Stroke { 
+                self.draw(DrawMode::Stroke { 
                     stroke: FillMode {
                         color: self.graphics_state.stroke_color,
                         alpha: self.graphics_state.stroke_color_alpha,
                         mode: self.blend_mode_stroke(),
                     },
-                    stroke_mode: self.graphics_state.stroke()},
+                    stroke_mode: self.stroke()},
                     FillRule::Winding
                 );
             },
             Op::FillAndStroke { winding } => {
-                self.draw(&DrawMode::FillStroke {
+                self.draw(DrawMode::FillStroke {
                     fill: FillMode {
                         color: self.graphics_state.fill_color,
                         alpha: self.graphics_state.fill_color_alpha,
@@ -185,11 +689,11 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                         alpha: self.graphics_state.stroke_color_alpha,
                         mode: self.blend_mode_stroke()
                     },
-                    stroke_mode: self.graphics_state.stroke()
+                    stroke_mode: self.stroke()
                 }, winding.cvt());
             }
             Op::Fill { winding } => {
-                self.draw(&DrawMode::Fill {
+                self.draw(DrawMode::Fill {
                     fill: FillMode {
                         color: self.graphics_state.fill_color,
                         alpha: self.graphics_state.fill_color_alpha,
@@ -197,40 +701,34 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                     },
             }, winding.cvt());
             }
-            Op::Shade { ref name } => {},
+            Op::Shade { ref name } => {
+                let p = self.resource(|r| r.shadings.get(name)).ok_or(PdfError::NotFound { word: name.as_str().into() })?;
+                let mode = self.blend_mode_fill();
+                let quality = self.shading_quality();
+                self.colors.set_intent(self.graphics_state.rendering_intent);
+                let resources = self.shading_resources(p);
+                let mut shading = t!(Shading::parse(p, resources, self.resolve, mode, &mut self.colors, quality));
+                // sh ignores the background
+                shading.background = None;
+                // the shading covers the clip region. limiting it to the bounds of the clip keeps it off the rest
+                // of the page also where the backend can't apply the clip path, like in soft masks.
+                if let Some(clip) = self.clip_bounds() {
+                    let area = self.graphics_state.transform.inverse() * clip;
+                    shading.bbox = match shading.bbox {
+                        Some(bbox) => bbox.intersection(area),
+                        None => Some(area)
+                    };
+                    match shading.bbox {
+                        Some(r) if r.width() > 0.0 && r.height() > 0.0 => {}
+                        _ => return Ok(())
+                    }
+                }
+                self.backend.draw_shading(&shading, self.graphics_state.transform, mode, self.graphics_state.fill_color_alpha, self.graphics_state.clip_path_id);
+            },
             Op::Clip { winding } => {
+                // takes effect after the next path painting operator
                 self.flush();
-                let mut path = self.current_outline.clone().transformed(&self.graphics_state.transform);
-                let clip_path_rect = to_rect(&path);
-
-                let (path, r, parent) = match (self.graphics_state.clip_path_rect, clip_path_rect, self.graphics_state.clip_path_id) {
-                    (Some(r1), Some(r2), Some(p)) => {
-                        let r = r1.intersection(r2).unwrap_or_default();
-                        (Outline::from_rect(r), Some(r), None)
-                    }
-                    (Some(r), None, Some(p)) => {
-                        path.clip_against_polygon(&[r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]);
-                        (path, None, None)
-                    }
-                    (None, Some(r), Some(p)) => {
-                        let mut path = self.graphics_state.clip_path.as_ref().unwrap().outline.clone();
-                        path.clip_against_polygon(&[r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]);
-                        (path, None, None)
-                    }
-                    (None, Some(r), None) => {
-                        (path, Some(r), None)
-                    }
-                    (None, None, Some(p)) => (path, None, Some(p)),
-                    (None, None, None) => (path, None, None),
-                    _ => unreachable!()
-                };
-
-                let id = self.backend.create_clip_path(path.clone(), winding.cvt(), parent);
-                self.graphics_state.clip_path_id = Some(id);
-                let mut clip = ClipPath::new(path);
-                clip.set_fill_rule(winding.cvt());
-                self.graphics_state.clip_path = Some(clip);
-                self.graphics_state.clip_path_rect = r;
+                self.pending_clip = Some(winding.cvt());
             },
 
             Op::Save => {
@@ -238,6 +736,9 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             },
             Op::Restore => {
                 let (g, t) = self.stack.pop().ok_or_else(|| pdf::error::PdfError::Other { msg: "graphcs stack is empty".into() })?;
+                if g.blend != self.graphics_state.blend {
+                    self.backend.set_blend_mode(g.blend);
+                }
                 self.graphics_state = g;
                 self.text_state = t;
             },
@@ -246,19 +747,43 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 self.graphics_state.transform = self.graphics_state.transform * matrix.cvt();
             }
             Op::LineWidth { width } => self.graphics_state.stroke_style.line_width = width,
-            Op::Dash { ref pattern, phase } => self.graphics_state.dash_pattern = Some((&*pattern, phase)),
-            Op::LineJoin { join } => {},
-            Op::LineCap { cap } => {},
-            Op::MiterLimit { limit } => {},
+            Op::Dash { ref pattern, phase } => self.graphics_state.set_dash(pattern, phase),
+            Op::LineJoin { join } => self.graphics_state.set_line_join(join.cvt()),
+            Op::LineCap { cap } => self.graphics_state.stroke_style.line_cap = cap.cvt(),
+            Op::MiterLimit { limit } => self.graphics_state.set_miter_limit(limit),
             Op::Flatness { tolerance } => {},
             Op::GraphicsState { ref name } => {
-                let gs = try_opt!(self.resources.graphics_states.get(name));
+                let gs = try_opt!(self.resource(|r| r.graphics_states.get(name)));
                 debug!("GS: {gs:?}");
                 if let Some(lw) = gs.line_width {
                     self.graphics_state.stroke_style.line_width = lw;
                 }
-                self.graphics_state.set_fill_alpha(gs.fill_alpha.unwrap_or(1.0));
-                self.graphics_state.set_stroke_alpha(gs.stroke_alpha.unwrap_or(1.0));
+                if let Some(cap) = gs.line_cap {
+                    self.graphics_state.stroke_style.line_cap = cap.cvt();
+                }
+                if let Some(limit) = gs.miter_limit {
+                    self.graphics_state.set_miter_limit(limit);
+                }
+                if let Some(join) = gs.line_join {
+                    self.graphics_state.set_line_join(join.cvt());
+                }
+                if let Some(ref dash) = gs.dash_pattern {
+                    // [array phase]
+                    match dash[..] {
+                        [ref pattern, ref phase] => {
+                            let pattern = pattern.clone().resolve(self.resolve)?.into_array()?
+                                .iter().map(|n| n.as_number()).collect::<Result<Vec<f32>>>()?;
+                            self.graphics_state.set_dash(&pattern, phase.as_number()?);
+                        }
+                        _ => warn!("invalid /D {:?}", dash)
+                    }
+                }
+                if let Some(ca) = gs.fill_alpha {
+                    self.graphics_state.set_fill_alpha(ca);
+                }
+                if let Some(ca) = gs.stroke_alpha {
+                    self.graphics_state.set_stroke_alpha(ca);
+                }
                 
                 if let Some((font_ref, size)) = gs.font {
                     let font = self.resolve.get(font_ref)?;
@@ -280,16 +805,63 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 if let Some(m) = gs.overprint_mode {
                     self.graphics_state.overprint_mode = m;
                 }
+                if self.graphics_state.overprint_fill || self.graphics_state.overprint_stroke {
+                    self.backend.overprint(self.graphics_state.overprint_fill, self.graphics_state.overprint_stroke, self.graphics_state.overprint_mode);
+                }
+                if let Some(ref bm) = gs.blend_mode {
+                    // an array lists alternatives, in order of preference
+                    let blend = match *bm {
+                        Primitive::Name(ref name) => Blend::from_name(name),
+                        Primitive::Array(ref names) => names.iter().filter_map(|n| n.as_name().ok()).find_map(Blend::from_name),
+                        _ => None
+                    };
+                    match blend {
+                        Some(blend) => {
+                            self.graphics_state.blend = blend;
+                            self.backend.set_blend_mode(blend);
+                        }
+                        None => warn!("unknown blend mode {:?}", bm)
+                    }
+                }
+                // TR2 takes precedence, both may be present for older readers
+                if let Some(tr) = gs._other.get("TR2").or_else(|| gs._other.get("TR")) {
+                    let transfer = t!(Transfer::parse(tr, self.resolve));
+                    self.graphics_state.set_transfer(transfer.map(Arc::new));
+                }
+                if let Some(ref smask) = gs.smask {
+                    self.soft_mask(smask)?;
+                }
+                if let Some(tk) = gs._other.get("TK") {
+                    self.text_state.knockout = tk.as_bool()?;
+                }
+                if let Some(ri) = gs._other.get("RI") {
+                    self.graphics_state.rendering_intent = rendering_intent(ri.as_name()?);
+                }
             },
+            Op::StrokeColor { .. } | Op::FillColor { .. } | Op::FillColorSpace { .. } | Op::StrokeColorSpace { .. } if self.color_locked => {}
             Op::StrokeColor { ref color } => {
                 let mode = self.blend_mode_stroke();
-                let color = t!(convert_color(&mut self.graphics_state.stroke_color_space, color, &self.resources, self.resolve, mode));
-                self.graphics_state.set_stroke_color(color);
+                self.colors.set_intent(self.graphics_state.rendering_intent);
+                let resources = self.color_resources(color);
+                let stroke = t!(convert_color(&mut self.graphics_state.stroke_color_space, color, resources, self.resolve, mode, &mut self.colors));
+                if let Fill::Pattern(_) = stroke {
+                    if let Some(c) = pattern_color(self.graphics_state.stroke_color_space, color, resources, self.resolve, mode, &mut self.colors) {
+                        self.graphics_state.stroke_pattern_color = c;
+                    }
+                }
+                self.graphics_state.set_stroke_color(stroke);
             },
             Op::FillColor { ref color } => {
                 let mode = self.blend_mode_fill();
-                let color = t!(convert_color(&mut self.graphics_state.fill_color_space, color, &self.resources, self.resolve, mode));
-                self.graphics_state.set_fill_color(color);
+                self.colors.set_intent(self.graphics_state.rendering_intent);
+                let resources = self.color_resources(color);
+                let fill = t!(convert_color(&mut self.graphics_state.fill_color_space, color, resources, self.resolve, mode, &mut self.colors));
+                if let Fill::Pattern(_) = fill {
+                    if let Some(c) = pattern_color(self.graphics_state.fill_color_space, color, resources, self.resolve, mode, &mut self.colors) {
+                        self.graphics_state.fill_pattern_color = c;
+                    }
+                }
+                self.graphics_state.set_fill_color(fill);
             },
             Op::FillColorSpace { ref name } => {
                 self.graphics_state.fill_color_space = self.color_space(name)?;
@@ -299,15 +871,26 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                 self.graphics_state.stroke_color_space = self.color_space(name)?;
                 self.graphics_state.set_stroke_color(Fill::black());
             },
-            Op::RenderingIntent { intent } => {},
-            Op::BeginText => self.text_state.reset_matrix(),
-            Op::EndText => {},
+            Op::RenderingIntent { intent } => self.graphics_state.rendering_intent = intent,
+            Op::BeginText => {
+                self.text_state.reset_matrix();
+                self.text_clip = None;
+                // BT without ET before it
+                self.end_text_group();
+                self.begin_text_group();
+            }
+            Op::EndText => {
+                self.end_text_group();
+                if let Some(outline) = self.text_clip.take() {
+                    self.clip_device(outline, FillRule::Winding);
+                }
+            }
             Op::CharSpacing { char_space } => self.text_state.char_space = char_space,
             Op::WordSpacing { word_space } => self.text_state.word_space = word_space,
             Op::TextScaling { horiz_scale } => self.text_state.horiz_scale = 0.01 * horiz_scale,
             Op::Leading { leading } => self.text_state.leading = leading,
             Op::TextFont { ref name, size } => {
-                let font = match self.resources.fonts.get(name) {
+                let font = match self.resource(|r| r.fonts.get(name)) {
                     Some(font_ref) => {
                         self.backend.get_font(font_ref, self.resolve)?
                     },
@@ -330,38 +913,67 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             Op::TextDraw { ref text } => {
                 let fill_mode = self.blend_mode_fill();
                 let stroke_mode = self.blend_mode_stroke();
-                self.text(|backend, text_state, graphics_state, span| {
-                    text_state.draw_text(backend, graphics_state, &text.data, span, fill_mode, stroke_mode);
-                }, op_nr);
+                self.text(|state, span| {
+                    state.draw_text(&text.data, span, fill_mode, stroke_mode)
+                }, op_nr)?;
             },
             Op::TextDrawAdjusted { ref array } => {
                 let fill_mode = self.blend_mode_fill();
                 let stroke_mode = self.blend_mode_stroke();
-                self.text(|backend, text_state, graphics_state, span| {
+                self.text(|state, span| {
                     for arg in array {
                         match *arg {
                             TextDrawAdjusted::Text(ref data) => {
-                                text_state.draw_text(backend, graphics_state, data.as_bytes(), span, fill_mode, stroke_mode);
+                                state.draw_text(data.as_bytes(), span, fill_mode, stroke_mode)?;
                             },
                             TextDrawAdjusted::Spacing(offset) => {
-                                // because why not PDF…
-                                let advance = text_state.advance(-0.001 * offset);
-                                span.width += advance;
+                                span.width += state.text_state.adjust(offset);
                             }
                         }
                     }
-                }, op_nr);
+                    Ok(())
+                }, op_nr)?;
             },
             Op::XObject { ref name } => {
-                let &xobject_ref = self.resources.xobjects.get(name).ok_or(PdfError::NotFound { word: name.as_str().into()})?;
+                let &xobject_ref = self.resource(|r| r.xobjects.get(name)).ok_or(PdfError::NotFound { word: name.as_str().into()})?;
+                // images are loaded with the resources they were found in
+                let resources = self.resources_with(|r| r.xobjects.get(name).is_some());
                 let xobject = self.resolve.get(xobject_ref)?;
                 let mode = self.blend_mode_fill();
                 match *xobject {
+                    XObject::Image(ref im) if im.image_mask && !self.graphics_state.marks().0 => {
+                        debug!("image mask in the Separation None");
+                    }
+                    XObject::Image(ref im) if im.color_space.as_ref().map_or(false, |cs| self.no_marks(cs)) => {
+                        debug!("image in the Separation None");
+                    }
+                    XObject::Image(ref im) if im.image_mask => {
+                        let fill = FillMode {
+                            color: self.graphics_state.fill_color,
+                            alpha: self.graphics_state.fill_color_alpha,
+                            mode,
+                        };
+                        if !self.pattern_image_mask(im, &fill) {
+                            self.backend.draw_image_mask(xobject_ref, im, &fill, resources, self.graphics_state.transform, self.graphics_state.clip_path_id, self.resolve)?;
+                        }
+                    }
+                    XObject::Image(_) if self.color_locked => {
+                        debug!("sampled image in a shape-only content stream");
+                    }
                     XObject::Image(ref im) => {
-                        self.backend.draw_image(xobject_ref, im, self.resources, self.graphics_state.transform, mode, self.graphics_state.clip_path_id, self.resolve);
+                        self.backend.set_rendering_intent(self.graphics_state.rendering_intent);
+                        self.backend.draw_image(xobject_ref, im, resources, self.graphics_state.transform, mode, self.graphics_state.fill_color_alpha, self.graphics_state.clip_path_id, self.resolve)?;
                     }
                     XObject::Form(ref content) => {
-                        self.draw_form(content)?;
+                        let r = xobject_ref.get_inner();
+                        if self.forms.contains(&r) {
+                            warn!("form {:?} draws itself, skipped", r);
+                        } else {
+                            self.forms.push(r);
+                            let result = self.draw_form(content);
+                            self.forms.pop();
+                            result?;
+                        }
                     }
                     XObject::Postscript(ref ps) => {
                         let data = ps.data(self.resolve)?;
@@ -370,45 +982,108 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
                     }
                 }
             },
+            Op::InlineImage { ref image } if image.image_mask && !self.graphics_state.marks().0 => {
+                debug!("image mask in the Separation None");
+            }
+            Op::InlineImage { ref image } if image.color_space.as_ref().map_or(false, |cs| self.no_marks(cs)) => {
+                debug!("image in the Separation None");
+            }
+            Op::InlineImage { ref image } if image.image_mask => {
+                let fill = FillMode {
+                    color: self.graphics_state.fill_color,
+                    alpha: self.graphics_state.fill_color_alpha,
+                    mode: self.blend_mode_fill(),
+                };
+                if !self.pattern_image_mask(image, &fill) {
+                    let resources = self.image_resources(image);
+                    self.backend.draw_inline_image_mask(image, &fill, resources, self.graphics_state.transform, self.graphics_state.clip_path_id, self.resolve)?;
+                }
+            }
+            Op::InlineImage { .. } if self.color_locked => {
+                debug!("sampled image in a shape-only content stream");
+            }
             Op::InlineImage { ref image } => {
                 let mode = self.blend_mode_fill();
-                self.backend.draw_inline_image(image, &self.resources, self.graphics_state.transform, mode, self.graphics_state.clip_path_id, self.resolve);
+                let resources = self.image_resources(image);
+                self.backend.set_rendering_intent(self.graphics_state.rendering_intent);
+                self.backend.draw_inline_image(image, resources, self.graphics_state.transform, mode, self.graphics_state.fill_color_alpha, self.graphics_state.clip_path_id, self.resolve)?;
             }
         }
 
         Ok(())
     }
 
+    /// Overprinting is approximated by multiplying with the backdrop (`BlendMode::Darken`), where it would be visible:
+    /// for spot colors, and with /OPM 1 for DeviceCMYK, where zero components leave the backdrop alone.
+    /// Process colors in other color spaces paint all colorants, so they knock out as usual.
+    fn overprints(&self, cs: &ColorSpace) -> bool {
+        let cs = match *cs {
+            ColorSpace::Named(ref name) => match self.resource(|r| r.color_spaces.get(name)) {
+                Some(cs) => cs,
+                None => return false
+            },
+            ref cs => cs
+        };
+        match *cs {
+            ColorSpace::Separation(..) | ColorSpace::DeviceN { .. } => true,
+            ColorSpace::DeviceCMYK => self.graphics_state.overprint_mode == 1,
+            _ => false
+        }
+    }
+    /// Images in the Separation `None` are not drawn, their color space may be a resource name.
+    fn no_marks(&self, cs: &ColorSpace) -> bool {
+        match *cs {
+            ColorSpace::Named(ref name) => self.resource(|r| r.color_spaces.get(name)).map_or(false, paints_nothing),
+            ref cs => paints_nothing(cs)
+        }
+    }
     fn blend_mode_fill(&self) -> BlendMode {
-        if self.graphics_state.overprint_fill {
+        if self.graphics_state.overprint_fill && self.overprints(self.graphics_state.fill_color_space) {
             BlendMode::Darken
         } else {
             BlendMode::Overlay
         }
     }
     fn blend_mode_stroke(&self) -> BlendMode {
-        if self.graphics_state.overprint_stroke {
+        if self.graphics_state.overprint_stroke && self.overprints(self.graphics_state.stroke_color_space) {
             BlendMode::Darken
         } else {
             BlendMode::Overlay
         }
     }
 
-    fn text(&mut self, inner: impl FnOnce(&mut B, &mut TextState, &mut GraphicsState<B>, &mut Span), op_nr: usize) {
+    fn text(&mut self, inner: impl FnOnce(&mut Self, &mut Span) -> Result<()>, op_nr: usize) -> Result<()> {
         let mut span = Span::default();
         let tm = self.text_state.text_matrix;
-        let origin = tm.translation();
 
-        inner(&mut self.backend, &mut self.text_state, &mut self.graphics_state, &mut span);
+        inner(self, &mut span)?;
 
-        let transform = self.graphics_state.transform * tm * Transform2F::from_scale(Vector2F::new(1.0, -1.0));
-        let p1 = origin;
-        let p2 = (tm * Transform2F::from_translation(Vector2F::new(span.width, self.text_state.font_size))).translation();
+        if self.text_state.mode.is_clip() {
+            // even text without glyphs clips, to nothing
+            let clip = self.text_clip.get_or_insert_with(Outline::new);
+            for contour in span.clip.take().into_iter().flat_map(|o| o.into_contours()) {
+                clip.push_contour(contour);
+            }
+        }
+
+        let (rise, size) = (self.text_state.rise, self.text_state.font_size);
+        // the rise moves the baseline, but not the origin of the next text
+        let transform = self.graphics_state.transform * tm * Transform2F::from_translation(Vector2F::new(0., rise)) * Transform2F::from_scale(Vector2F::new(1.0, -1.0));
+        let p1 = tm * Vector2F::new(0., rise);
+        let p2 = tm * Vector2F::new(span.width, rise + size);
         let clip = self.graphics_state.clip_path_id;
+        let quad = [
+            Vector2F::new(0., rise), Vector2F::new(span.width, rise),
+            Vector2F::new(span.width, rise + size), Vector2F::new(0., rise + size)
+        ].map(|p| self.graphics_state.transform * tm * p);
 
+        if self.hidden() {
+            return Ok(());
+        }
         debug!("text {}", span.text);
         self.backend.add_text(TextSpan {
             rect: self.graphics_state.transform * RectF::from_points(p1.min(p2), p1.max(p2)),
+            quad,
             width: span.width,
             bbox: span.bbox.rect(),
             text: span.text,
@@ -419,8 +1094,121 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             alpha: self.graphics_state.fill_color_alpha,
             mode: self.text_state.mode,
             transform,
-            op_nr
+            op_nr,
+            mcid: self.mcid(),
         }, clip);
+        Ok(())
+    }
+
+    fn draw_text(&mut self, data: &[u8], span: &mut Span, fill_mode: BlendMode, stroke_mode: BlendMode) -> Result<()> {
+        match self.text_state.font_entry.clone() {
+            Some(e) if e.type3.is_some() => self.draw_type3(&e, data, span),
+            _ => {
+                let hidden = self.hidden();
+                self.text_state.draw_text(self.backend, &self.graphics_state, data, span, fill_mode, stroke_mode, self.config, hidden);
+                Ok(())
+            }
+        }
+    }
+
+    fn draw_type3(&mut self, e: &FontEntry, data: &[u8], span: &mut Span) -> Result<()> {
+        let type3 = e.type3.as_ref().unwrap();
+        let tr = Transform2F::row_major(
+            self.text_state.horiz_scale * self.text_state.font_size, 0., 0.,
+            0., self.text_state.font_size, self.text_state.rise
+        ) * e.font_matrix;
+        let (resources, enclosing) = match type3.resources {
+            Some(ref r) => (r, self.enclosing_resources()),
+            None => (self.resources, self.enclosing.clone())
+        };
+
+        let hidden = self.hidden();
+        for (code, is_space) in e.codes_and_spaces(data) {
+            let glyph = type3.glyphs.get(&code);
+            let width = glyph.map(|g| tr.m11() * g.width).unwrap_or(0.0);
+
+            if let Some(glyph) = glyph {
+                if !hidden && !matches!(self.text_state.mode, TextMode::Invisible) && self.nesting_allowed() {
+                    // the glyph converts colors with the cache of the text, it is handed back below
+                    let placeholder = ColorCache::new(None, self.colors.cmyk_conversion());
+                    let colors = std::mem::replace(&mut self.colors, placeholder);
+                    let mut inner = RenderState {
+                        graphics_state: GraphicsState {
+                            transform: self.graphics_state.transform * self.text_state.text_matrix * tr,
+                            .. self.graphics_state.clone()
+                        },
+                        text_state: TextState::new(),
+                        resources,
+                        enclosing: enclosing.clone(),
+                        stack: vec![],
+                        current_outline: Outline::new(),
+                        current_contour: Contour::new(),
+                        backend: self.backend,
+                        resolve: self.resolve,
+                        colors,
+                        pattern_transform: self.pattern_transform,
+                        // d1 glyphs are painted in the current color, whatever they set
+                        color_locked: self.color_locked || glyph.shape_only,
+                        pending_clip: None,
+                        text_clip: None,
+                        text_group: None,
+                        optional_content: self.optional_content,
+                        marked_content: self.nested_marked_content(),
+                        depth: self.depth + 1,
+                        forms: self.forms.clone(),
+                        nested_draws: self.nested_draws.clone(),
+                        config: self.config,
+                    };
+                    let result = glyph.ops.iter().enumerate().try_for_each(|(i, op)| {
+                        debug!(" glyph op {}: {:?}", i, op);
+                        inner.draw_op(op, i)
+                    });
+                    inner.end_text_group();
+                    self.colors = inner.colors;
+                    self.backend.set_blend_mode(self.graphics_state.blend);
+                    result?;
+                }
+            } else {
+                debug!("no glyph for code {}", code);
+            }
+
+            let mut advance = self.text_state.char_space * self.text_state.horiz_scale + width;
+            if is_space {
+                advance += self.text_state.word_space * self.text_state.horiz_scale;
+            }
+            let unicode = match e.cmap.get(&code) {
+                Some(&(_, Some(ref s))) => Some(&**s),
+                _ => None
+            };
+            let (rise, size) = (self.text_state.rise, self.text_state.font_size);
+            let quad = [
+                Vector2F::new(0., rise), Vector2F::new(width, rise),
+                Vector2F::new(width, rise + size), Vector2F::new(0., rise + size)
+            ].map(|p| self.graphics_state.transform * self.text_state.text_matrix * p);
+            if !hidden {
+                self.backend.text_glyph(&TextGlyph { code, unicode, quad, advance });
+            }
+
+            self.text_state.text_matrix = self.text_state.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+
+            let offset = span.text.len();
+            if let Some(&(_, Some(ref s))) = e.cmap.get(&code) {
+                span.text.push_str(s);
+                span.chars.push(TextChar {
+                    offset,
+                    pos: span.width,
+                    width
+                });
+            }
+            span.width += advance;
+        }
+        // the resources of the font may be freed before the cache, which identifies color spaces by address
+        if let Some(ref r) = type3.resources {
+            for cs in r.color_spaces.values() {
+                self.colors.forget(cs);
+            }
+        }
+        Ok(())
     }
 
     fn color_space(&self, name: &str) -> Result<&'a ColorSpace> {
@@ -431,7 +1219,7 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
             "Pattern" => return Ok(&ColorSpace::Pattern),
             _ => {}
         }
-        match self.resources.color_spaces.get(name) {
+        match self.resource(|r| r.color_spaces.get(name)) {
             Some(cs) => Ok(cs),
             None => Err(PdfError::Other { msg: format!("color space {:?} not present", name) })
         }
@@ -443,42 +1231,524 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
         }
     }
     fn draw_form(&mut self, form: &FormXObject) -> Result<()> {
-        let graphics_state = GraphicsState {
+        if !self.nesting_allowed() {
+            return Ok(());
+        }
+        let mut graphics_state = GraphicsState {
             stroke_alpha: self.graphics_state.stroke_color_alpha,
             fill_alpha: self.graphics_state.fill_color_alpha,
             clip_path_id: self.graphics_state.clip_path_id,
-            clip_path: self.graphics_state.clip_path.clone(),
-            .. self.graphics_state
+            .. self.graphics_state.clone()
         };
-        let resources = match form.dict().resources {
-            Some(ref r) => &*r,
-            None => self.resources
+        let group = form.dict().group.as_ref().and_then(|g| self.transparency_group(g));
+        // a group that is composited as a whole doesn't need its own buffer if it is opaque and normal
+        let grouped = match group {
+            Some(ref g) if g.alpha < 1.0 || g.blend != Blend::Normal || g.isolated => self.backend.begin_group(g),
+            _ => false
         };
+        if grouped {
+            // the alpha and blend mode apply to the group result
+            graphics_state.fill_alpha = 1.0;
+            graphics_state.stroke_alpha = 1.0;
+            graphics_state.fill_color_alpha = 1.0;
+            graphics_state.stroke_color_alpha = 1.0;
+            graphics_state.fill_paint = None;
+            graphics_state.stroke_paint = None;
+            graphics_state.blend = Blend::Normal;
+            self.backend.set_blend_mode(Blend::Normal);
+        }
+        let (resources, enclosing): (&Resources, _) = match form.dict().resources {
+            Some(ref r) => (&*r, self.enclosing_resources()),
+            None => (self.resources, self.enclosing.clone())
+        };
+
+        // the content is in the coordinate system of /Matrix, and clipped to /BBox in it
+        let dict = form.dict();
+        let transform = self.graphics_state.transform * dict.matrix.map(|m| m.cvt()).unwrap_or_default();
+        graphics_state.transform = transform;
 
+        let colors = ColorCache::new(self.backend.icc_transform(), self.backend.cmyk_conversion());
         let mut inner = RenderState {
             graphics_state: graphics_state,
             text_state: self.text_state.clone(),
             resources,
+            enclosing,
             stack: vec![],
             current_outline: Outline::new(),
             current_contour: Contour::new(),
             backend: self.backend,
             resolve: self.resolve,
+            colors,
+            pattern_transform: transform,
+            color_locked: self.color_locked,
+            pending_clip: None,
+            text_clip: None,
+            text_group: None,
+            optional_content: self.optional_content,
+            marked_content: self.nested_marked_content(),
+            depth: self.depth + 1,
+            forms: self.forms.clone(),
+            nested_draws: self.nested_draws.clone(),
+            config: self.config,
         };
-        
+        inner.clip_rect(rect_f(dict.bbox));
+
         let ops = t!(form.operations(self.resolve));
-        for (i, op) in ops.iter().enumerate() {
+        let result = ops.iter().enumerate().try_for_each(|(i, op)| {
             debug!(" form op {}: {:?}", i, op);
-            inner.draw_op(op, i)?;
+            inner.draw_op(op, i)
+        });
+        inner.end_text_group();
+        if grouped {
+            self.backend.end_group();
         }
+        self.backend.set_blend_mode(self.graphics_state.blend);
 
+        result
+    }
+    /// With text knockout (/TK true, the default), the glyphs of a text object don't composite with each other:
+    /// overlapping glyphs of semi-transparent text are only painted once. This draws the text object opaque
+    /// into a group that is composited with the alpha and blend mode at BT, if the backend can draw groups.
+    /// Otherwise every glyph is composited separately, as with /TK false.
+    /// Alpha set by `gs` inside the text object applies within the group.
+    fn begin_text_group(&mut self) {
+        let gs = &self.graphics_state;
+        if !self.text_state.knockout || gs.fill_color_alpha >= 1.0 && gs.stroke_color_alpha >= 1.0 {
+            return;
+        }
+        // the group only has one alpha, the larger of the two. the other is relative to it
+        let alpha = gs.fill_color_alpha.max(gs.stroke_color_alpha);
+        let group = TransparencyGroup {
+            isolated: false,
+            knockout: true,
+            alpha,
+            blend: gs.blend,
+            clip: gs.clip_path_id,
+        };
+        let saved = TextGroup {
+            fill_alpha: gs.fill_alpha,
+            stroke_alpha: gs.stroke_alpha,
+            fill_color_alpha: gs.fill_color_alpha,
+            stroke_color_alpha: gs.stroke_color_alpha,
+            blend: gs.blend,
+            group_fill_alpha: gs.fill_color_alpha / alpha.max(1e-6),
+            group_stroke_alpha: gs.stroke_color_alpha / alpha.max(1e-6),
+        };
+        if !self.backend.begin_group(&group) {
+            return;
+        }
+        let gs = &mut self.graphics_state;
+        gs.fill_alpha = 1.0;
+        gs.stroke_alpha = 1.0;
+        gs.fill_color_alpha = saved.group_fill_alpha;
+        gs.stroke_color_alpha = saved.group_stroke_alpha;
+        gs.fill_paint = None;
+        gs.stroke_paint = None;
+        gs.blend = Blend::Normal;
+        self.backend.set_blend_mode(Blend::Normal);
+        self.text_group = Some(saved);
+    }
+    /// Close the group of the text object, at ET or wherever the content stops without one.
+    ///
+    /// Only what the group overrode is restored. An alpha or blend mode that `gs` set inside the text object stays,
+    /// with the alpha of an enclosing group applied again.
+    pub(crate) fn end_text_group(&mut self) {
+        let saved = match self.text_group.take() {
+            Some(saved) => saved,
+            None => return
+        };
+        self.backend.end_group();
+        let gs = &mut self.graphics_state;
+        gs.fill_color_alpha = match gs.fill_color_alpha {
+            a if a == saved.group_fill_alpha => saved.fill_color_alpha,
+            a => saved.fill_alpha * a
+        };
+        gs.stroke_color_alpha = match gs.stroke_color_alpha {
+            a if a == saved.group_stroke_alpha => saved.stroke_color_alpha,
+            a => saved.stroke_alpha * a
+        };
+        gs.fill_alpha = saved.fill_alpha;
+        gs.stroke_alpha = saved.stroke_alpha;
+        gs.fill_paint = None;
+        gs.stroke_paint = None;
+        if gs.blend == Blend::Normal {
+            gs.blend = saved.blend;
+        }
+        self.backend.set_blend_mode(gs.blend);
+    }
+    /// A /Group dictionary of a form, if it is a transparency group
+    fn transparency_group(&self, group: &Dictionary) -> Option<TransparencyGroup<B::ClipPathId>> {
+        match group.get("S") {
+            Some(Primitive::Name(ref s)) if s.as_str() == "Transparency" => {}
+            _ => return None
+        }
+        let flag = |key: &str| matches!(group.get(key), Some(Primitive::Boolean(true)));
+        let knockout = flag("K");
+        if knockout {
+            debug!("knockout groups are drawn like normal groups");
+        }
+        Some(TransparencyGroup {
+            isolated: flag("I"),
+            knockout,
+            alpha: self.graphics_state.fill_color_alpha,
+            blend: self.graphics_state.blend,
+            clip: self.graphics_state.clip_path_id,
+        })
+    }
+    /// Draw the normal appearance of an annotation, selected by /AS if there are several.
+    pub fn draw_annotation(&mut self, annot: &Annot) -> Result<()> {
+        // hidden
+        if annot.annot_flags & 2 != 0 {
+            return Ok(());
+        }
+        if let Some(oc) = annot.other.get("OC") {
+            if !self.oc_visible(oc) {
+                return Ok(());
+            }
+        }
+        if let (Some(form), Some(rect)) = (self.config.and_then(|c| c.forms.as_ref()), annot.rect) {
+            if let Some(field) = FormField::from_widget(annot, self.resolve) {
+                match field.kind {
+                    FieldKind::Text | FieldKind::ComboBox => return self.draw_text_field(annot, &field, form, rect_f(rect)),
+                    FieldKind::CheckBox | FieldKind::RadioButton => return self.draw_button_field(annot, &field, form, rect_f(rect)),
+                    _ => {}
+                }
+            }
+        }
+        let (ap, rect) = match (annot.appearance_streams.as_ref(), annot.rect) {
+            (Some(ap), Some(rect)) => (ap, rect),
+            (None, Some(rect)) => return self.draw_annotation_fallback(annot, rect_f(rect)),
+            _ => return Ok(())
+        };
+        let normal = self.resolve.get(ap.normal)?;
+        let form = match *normal {
+            AppearanceStreamEntry::Single(ref form) => form,
+            AppearanceStreamEntry::Dict(ref states) => match annot.appearance_state.as_ref().and_then(|state| states.get(state)) {
+                Some(AppearanceStreamEntry::Single(ref form)) => form,
+                _ => return Ok(())
+            }
+        };
+        self.draw_appearance(form, rect_f(rect))
+    }
+    /// Synthesize the appearance of Square, Circle, Line, FreeText and Link annotations that have no /AP.
+    fn draw_annotation_fallback(&mut self, annot: &Annot, rect: RectF) -> Result<()> {
+        let style = annot::border_style(annot, self.resolve, self.colors.cmyk_conversion());
+        let inset = Vector2F::splat(style.width * 0.5);
+        let inner = RectF::from_points(rect.origin() + inset, (rect.lower_right() - inset).max(rect.origin() + inset));
+
+        let (contour, fill) = match annot.subtype.as_str() {
+            "Square" | "FreeText" | "Link" if style.kind == BorderKind::Underline => {
+                let y = rect.min_y() + style.width * 0.5;
+                let mut contour = Contour::new();
+                contour.push_endpoint(Vector2F::new(rect.min_x(), y));
+                contour.push_endpoint(Vector2F::new(rect.max_x(), y));
+                (contour, None)
+            }
+            "Square" | "FreeText" => (Contour::from_rect(inner), style.fill),
+            // only the border
+            "Link" => (Contour::from_rect(inner), None),
+            "Circle" => (annot::ellipse(inner), style.fill),
+            "Line" => match annot.line.as_deref() {
+                Some(&[x1, y1, x2, y2]) => {
+                    let mut contour = Contour::new();
+                    contour.push_endpoint(Vector2F::new(x1, y1));
+                    contour.push_endpoint(Vector2F::new(x2, y2));
+                    (contour, None)
+                }
+                _ => return Ok(())
+            },
+            _ => return Ok(())
+        };
+        self.draw_annotation_shape(contour, fill, style.stroke, style.width, style.dash.clone());
+        let bevel = match style.kind {
+            BorderKind::Beveled => Some((Fill::Solid(1.0, 1.0, 1.0), match fill {
+                Some(Fill::Solid(r, g, b)) => Fill::Solid(r * 0.5, g * 0.5, b * 0.5),
+                _ => Fill::Solid(0.5, 0.5, 0.5),
+            })),
+            BorderKind::Inset => Some((Fill::Solid(0.5, 0.5, 0.5), Fill::Solid(0.75, 0.75, 0.75))),
+            _ => None
+        };
+        if let (Some((light, dark)), Some(_), "Square" | "FreeText" | "Link") = (bevel, style.stroke, annot.subtype.as_str()) {
+            let (top_left, bottom_right) = annot::bevel(rect, style.width);
+            self.draw_annotation_shape(top_left, Some(light), None, 0.0, None);
+            self.draw_annotation_shape(bottom_right, Some(dark), None, 0.0, None);
+        }
+
+        if annot.subtype.as_str() == "FreeText" {
+            let da = annot::DefaultAppearance::parse(annot, self.resolve, self.colors.cmyk_conversion());
+            // the /DR of the form has the fonts of /DA that the appearance resources lack
+            let form = self.config.and_then(|c| c.forms.as_ref());
+            let font = match self.annotation_font(da.font.as_ref(), form)? {
+                Some(font) => font,
+                None => {
+                    debug!("no font for FreeText annotation");
+                    return Ok(());
+                }
+            };
+            let padding = 2.0 + style.width;
+            let lines: Vec<_> = annot::text_lines(annot).into_iter().enumerate().map(|(i, line)| {
+                let baseline = rect.max_y() - padding - da.font_size * (1.0 + 1.2 * i as f32);
+                (line, Vector2F::new(rect.min_x() + padding, baseline))
+            }).collect();
+            self.draw_text_lines(font, da.color, da.font_size, &lines)?;
+        }
         Ok(())
     }
-    #[allow(dead_code)]
+    /// Fill and stroke the outline of an annotation. Nothing is stroked with a width of 0.
+    fn draw_annotation_shape(&mut self, contour: Contour, fill: Option<Fill>, stroke: Option<Fill>, width: f32, dash: Option<Vec<f32>>) {
+        let mut outline = Outline::new();
+        outline.push_contour(contour);
+
+        let fill_mode = |color| FillMode { color, alpha: 1.0, mode: BlendMode::Overlay };
+        let stroke_mode = Stroke {
+            dash_pattern: dash.map(|d| (d, 0.0)),
+            style: StrokeStyle {
+                line_cap: LineCap::Butt,
+                line_join: LineJoin::Miter(10.0),
+                line_width: width,
+            },
+        };
+        let stroke = stroke.filter(|_| width > 0.0);
+        let mode = match (fill, stroke) {
+            (Some(fill), Some(stroke)) => Some(DrawMode::FillStroke { fill: fill_mode(fill), stroke: fill_mode(stroke), stroke_mode }),
+            (Some(fill), None) => Some(DrawMode::Fill { fill: fill_mode(fill) }),
+            (None, Some(stroke)) => Some(DrawMode::Stroke { stroke: fill_mode(stroke), stroke_mode }),
+            (None, None) => None,
+        };
+        if let Some(mode) = mode {
+            self.backend.draw(&outline, &mode, FillRule::Winding, self.graphics_state.transform, self.graphics_state.clip_path_id);
+        }
+    }
+    /// The font `name` of a /DA, from the page resources or the /DR of the form.
+    fn annotation_font(&mut self, name: Option<&Name>, form: Option<&AcroForm>) -> Result<Option<Arc<FontEntry>>> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(None)
+        };
+        let resources = self.resources;
+        match resources.fonts.get(name).or_else(|| form?.resources.as_ref()?.fonts.get(name)) {
+            Some(font) => self.backend.get_font(font, self.resolve),
+            None => Ok(None)
+        }
+    }
+    /// Draw each line of text starting at its origin on the baseline.
+    fn draw_text_lines(&mut self, font: Arc<FontEntry>, color: Fill, font_size: f32, lines: &[(Vec<u8>, Vector2F)]) -> Result<()> {
+        let saved = (self.graphics_state.clone(), self.text_state.clone());
+        self.graphics_state.set_fill_color(color);
+        self.text_state = TextState::new();
+        self.text_state.font_entry = Some(font);
+        self.text_state.font_size = font_size;
+
+        let mut result = Ok(());
+        for (line, origin) in lines {
+            self.text_state.set_matrix(Transform2F::from_translation(*origin));
+            result = self.text(|state, span| state.draw_text(line, span, BlendMode::Overlay, BlendMode::Overlay), 0);
+            if result.is_err() {
+                break;
+            }
+        }
+        let (g, t) = saved;
+        self.graphics_state = g;
+        self.text_state = t;
+        result
+    }
+    /// The /MK background and border of a widget. Returns the width of the border.
+    fn draw_widget_box(&mut self, annot: &Annot, rect: RectF) -> f32 {
+        let (background, border) = annot::widget_colors(annot, self.resolve, self.colors.cmyk_conversion());
+        let width = match border {
+            Some(_) => annot::border_style(annot, self.resolve, self.colors.cmyk_conversion()).width,
+            None => 0.0
+        };
+        let inset = Vector2F::splat(width * 0.5);
+        let inner = RectF::from_points(rect.origin() + inset, (rect.lower_right() - inset).max(rect.origin() + inset));
+        self.draw_annotation_shape(Contour::from_rect(inner), background, border, width, None);
+        width
+    }
+    /// Draw the value of a text field or combo box with the font, size and color of its /DA.
+    fn draw_text_field(&mut self, annot: &Annot, field: &FormField, form: &AcroForm, rect: RectF) -> Result<()> {
+        let border = self.draw_widget_box(annot, rect);
+        let text = match field.text() {
+            Some(text) if !text.is_empty() => text,
+            _ => return Ok(())
+        };
+        let text = match field.is_password() {
+            true => text.chars().map(|_| '*').collect(),
+            false => text
+        };
+        let da = self.field_appearance(field, form);
+        let font = match self.annotation_font(da.font.as_ref(), Some(form))? {
+            Some(font) => font,
+            None => {
+                debug!("no font for field {}", field.name);
+                return Ok(());
+            }
+        };
+        let padding = Vector2F::splat(2.0 + border);
+        let inner = RectF::from_points(rect.origin() + padding, (rect.lower_right() - padding).max(rect.origin() + padding));
+        // width at a font size of 1
+        let measure = |line: &str| {
+            let data = annot::encode_simple(line);
+            font.codes(&data).map(|code| font.advance(code)).sum::<f32>() * 0.001
+        };
+
+        let (font_size, lines) = if field.is_multiline() {
+            let font_size = if da.auto_size { 12.0 } else { da.font_size };
+            (font_size, annot::wrap_text(&text, inner.width() / font_size, &measure))
+        } else {
+            let line = text.replace(|c: char| c == '\r' || c == '\n', " ");
+            let font_size = match da.auto_size {
+                true => match measure(&line) {
+                    w if w > 0.0 => inner.height().min(inner.width() / w),
+                    _ => inner.height()
+                },
+                false => da.font_size
+            };
+            (font_size, vec![line])
+        };
+        if font_size <= 0.0 {
+            return Ok(());
+        }
+        let align = field.quadding.unwrap_or(form.quadding).clamp(0, 2) as f32 * 0.5;
+        let lines: Vec<_> = lines.iter().enumerate().map(|(i, line)| {
+            let x = inner.min_x() + (inner.width() - measure(line) * font_size) * align;
+            let baseline = match field.is_multiline() {
+                true => inner.max_y() - font_size * (1.0 + 1.2 * i as f32),
+                // cap height centered
+                false => inner.center().y() - 0.35 * font_size,
+            };
+            (annot::encode_simple(line), Vector2F::new(x, baseline))
+        }).collect();
+
+        let saved = self.graphics_state.clone();
+        self.clip_rect(inner);
+        let result = self.draw_text_lines(font, da.color, font_size, &lines);
+        self.graphics_state = saved;
+        result
+    }
+    /// Draw a check box or radio button in the state given by the field value, rather than by /AS.
+    fn draw_button_field(&mut self, annot: &Annot, field: &FormField, form: &AcroForm, rect: RectF) -> Result<()> {
+        let state = field.state();
+        if let Some(ref ap) = annot.appearance_streams {
+            let normal = self.resolve.get(ap.normal)?;
+            if let AppearanceStreamEntry::Dict(ref states) = *normal {
+                // the buttons of a radio group share the value, each is on for its own state
+                return match states.iter().find(|(name, _)| name.as_str() == state) {
+                    Some((_, AppearanceStreamEntry::Single(ref form))) => self.draw_appearance(form, rect),
+                    _ => Ok(())
+                };
+            }
+        }
+        self.draw_widget_box(annot, rect);
+        // without appearance states, the on state of a radio button is not known
+        let on = match field.kind {
+            FieldKind::RadioButton => annot.appearance_state.as_ref().map_or(false, |s| s.as_str() == state),
+            _ => true
+        };
+        if state == "Off" || !on {
+            return Ok(());
+        }
+        let da = self.field_appearance(field, form);
+        let size = rect.width().min(rect.height());
+        let mut outline = Outline::new();
+        outline.push_contour(annot::check_mark(rect));
+        let mode = DrawMode::Stroke {
+            stroke: FillMode { color: da.color, alpha: 1.0, mode: BlendMode::Overlay },
+            stroke_mode: Stroke {
+                dash_pattern: None,
+                style: StrokeStyle {
+                    line_cap: LineCap::Round,
+                    line_join: LineJoin::Round,
+                    line_width: size * 0.1,
+                },
+            },
+        };
+        self.backend.draw(&outline, &mode, FillRule::Winding, self.graphics_state.transform, self.graphics_state.clip_path_id);
+        Ok(())
+    }
+    fn field_appearance(&self, field: &FormField, form: &AcroForm) -> annot::DefaultAppearance {
+        let data = field.default_appearance.as_deref().or(form.default_appearance.as_deref()).unwrap_or(b"");
+        annot::DefaultAppearance::from_data(data, self.resolve, self.colors.cmyk_conversion())
+    }
+    /// Draw `form` transformed so that its bounding box covers `rect`.
+    fn draw_appearance(&mut self, form: &FormXObject, rect: RectF) -> Result<()> {
+        let dict = form.dict();
+        let matrix = dict.matrix.map(|m| m.cvt()).unwrap_or_default();
+        let bbox = matrix * rect_f(dict.bbox);
+        if bbox.width() == 0.0 || bbox.height() == 0.0 {
+            return Ok(());
+        }
+        let fit = Transform2F::from_translation(rect.origin())
+            * Transform2F::from_scale(rect.size() / bbox.size())
+            * Transform2F::from_translation(-bbox.origin());
+
+        // draw_form applies /Matrix
+        let saved = self.graphics_state.clone();
+        self.graphics_state.transform = self.graphics_state.transform * fit;
+        let result = self.draw_form(form);
+        self.graphics_state = saved;
+        result
+    }
+    fn soft_mask(&mut self, smask: &Primitive) -> Result<()> {
+        let dict = match *smask {
+            Primitive::Dictionary(ref dict) => dict,
+            // /None. the mask ends with the graphics state it was set in.
+            _ => return Ok(())
+        };
+        let kind = match dict.get("S") {
+            Some(Primitive::Name(ref n)) if n.as_str() == "Alpha" => SoftMaskKind::Alpha,
+            _ => SoftMaskKind::Luminosity,
+        };
+        let group_ref = match dict.get("G") {
+            Some(&Primitive::Reference(r)) => Ref::<XObject>::new(r),
+            _ => return Err(PdfError::MissingEntry { typ: "SMask", field: "G".into() })
+        };
+        let group = self.resolve.get(group_ref)?;
+        let group = match *group {
+            XObject::Form(ref form) => form,
+            _ => return Err(PdfError::Other { msg: "SMask group is not a form".into() })
+        };
+        let backdrop = match dict.get("BC") {
+            Some(p) => {
+                let c = p.clone().resolve(self.resolve)?.into_array()?
+                    .iter().map(|c| c.as_number()).collect::<Result<Vec<f32>>>()?;
+                match c[..] {
+                    [g] => Some((g, g, g)),
+                    [r, g, b] => Some((r, g, b)),
+                    [c, m, y, k] => Some(self.colors.cmyk_conversion().to_rgb((c, m, y, k))),
+                    _ => None
+                }
+            }
+            None => None
+        };
+        let transfer = match dict.get("TR") {
+            Some(Primitive::Name(_)) | None => None,
+            Some(p) => Some(t!(Function::from_primitive(p.clone(), self.resolve))),
+        };
+
+        self.backend.begin_smask(&SoftMask {
+            kind,
+            group,
+            backdrop,
+            transfer: transfer.as_ref(),
+            transform: self.graphics_state.transform,
+        });
+        let result = self.draw_form(group);
+        let clip = self.backend.end_smask(self.graphics_state.clip_path_id);
+        result?;
+
+        if let Some(id) = clip {
+            // later clips are nested in the mask
+            self.graphics_state.clip_path_id = Some(id);
+            self.graphics_state.clip_path = None;
+            self.graphics_state.clip_path_rect = None;
+        }
+        Ok(())
+    }
+
     fn get_properties<'b>(&'b self, p: &'b Primitive) -> Result<&'b Dictionary> {
         match p {
             Primitive::Dictionary(ref dict) => Ok(dict),
-            Primitive::Name(ref name) => self.resources.properties.get(name.as_str())
+            Primitive::Name(ref name) => self.resource(|r| r.properties.get(name.as_str()))
                 .map(|rc| &**rc)
                 .ok_or_else(|| {
                     PdfError::MissingEntry { typ: "Properties", field: name.into() }
@@ -491,191 +1761,13 @@ impl<'a, R: Resolve, B: Backend> RenderState<'a, R, B> {
     }
 }
 
-fn convert_color<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, resolve: &impl Resolve, mode: BlendMode) -> Result<Fill> {
-    match convert_color2(cs, color, resources, mode) {
-        Ok(color) => Ok(color),
-        Err(e) if resolve.options().allow_error_in_option => {
-            warn!("failed to convert color: {:?}", e);
-            Ok(Fill::Solid(0.0, 0.0, 0.0))
-        }
-        Err(e) => Err(e)
-    }
-}
-#[allow(unused_variables)]
-fn convert_color2<'a>(cs: &mut &'a ColorSpace, color: &Color, resources: &Resources, mode: BlendMode) -> Result<Fill> {
-    match *color {
-        Color::Gray(g) => {
-            *cs = &ColorSpace::DeviceGray;
-            Ok(gray2rgb(g))
-        }
-        Color::Rgb(rgb) => {
-            *cs = &ColorSpace::DeviceRGB;
-            let (r, g, b) = rgb.cvt();
-            Ok(Fill::Solid(r, g, b))
-        }
-        Color::Cmyk(cmyk) => {
-            *cs = &ColorSpace::DeviceCMYK;
-            Ok(cmyk2rgb(cmyk.cvt(), mode))
-        }
-        Color::Other(ref args) => {
-            let cs = match **cs {
-                ColorSpace::Icc(ref icc) => {
-                    match icc.info.alternate {
-                        Some(ref alt) => alt,
-                        None => {
-                            match args.len() {
-                                3 => &ColorSpace::DeviceRGB,
-                                4 => &ColorSpace::DeviceCMYK,
-                                _ => return Err(PdfError::Other { msg: format!("ICC profile without alternate color space") })
-                            }
-                        }
-                    }
-                }
-                ColorSpace::Named(ref name) => {
-                    resources.color_spaces.get(name).ok_or_else(|| 
-                        PdfError::Other { msg: format!("named color space {} not found", name) }
-                    )?
-                }
-                _ => &**cs
-            };
-            
-            match *cs {
-                ColorSpace::Icc(_) => return Err(PdfError::Other { msg: format!("nested ICC color space") }),
-                ColorSpace::DeviceGray | ColorSpace::CalGray(_) => {
-                    if args.len() != 1 {
-                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
-                    }
-                    let g = args[0].as_number()?;
-                    Ok(gray2rgb(g))
-                }
-                ColorSpace::DeviceRGB | ColorSpace::CalRGB(_) => {
-                    if args.len() != 3 {
-                        return Err(PdfError::Other { msg: format!("expected 3 color arguments, got {:?}", args) });
-                    }
-                    let r = args[0].as_number()?;
-                    let g = args[1].as_number()?;
-                    let b = args[2].as_number()?;
-                    Ok(Fill::Solid(r, g, b))
-                }
-                ColorSpace::DeviceCMYK | ColorSpace::CalCMYK(_) => {
-                    if args.len() != 4 {
-                        return Err(PdfError::Other { msg: format!("expected 4 color arguments, got {:?}", args) });
-                    }
-                    let c = args[0].as_number()?;
-                    let m = args[1].as_number()?;
-                    let y = args[2].as_number()?;
-                    let k = args[3].as_number()?;
-                    Ok(cmyk2rgb((c, m, y, k), mode))
-                }
-                ColorSpace::DeviceN { ref names, ref alt, ref tint, ref attr } => {
-                    assert_eq!(args.len(), tint.input_dim());
-                    let mut input = vec![0.; args.len()];
-                    for (i, a) in input.iter_mut().zip(args.iter()) {
-                        *i = a.as_number()?;
-                    }
-                    let mut out = vec![0.0; tint.output_dim()];
-                    tint.apply(&input, &mut out)?;
-
-                    let alt = match **alt {
-                        ColorSpace::Icc(ref icc) => icc.info.alternate.as_ref().map(|b| &**b),
-                        ref a => Some(a),
-                    };
-                    match alt {
-                        Some(ColorSpace::DeviceGray) => Ok(Fill::Solid(out[0], out[0], out[0])),
-                        Some(ColorSpace::DeviceRGB) => {
-                            Ok(Fill::Solid(out[0], out[1], out[2]))
-                        }
-                        Some(ColorSpace::DeviceCMYK) => {
-                            Ok(cmyk2rgb((out[0], out[1], out[2], out[3]), mode))
-                        }
-                        _ => unimplemented!("DeviceN colorspace")
-                    }
-                }
-                ColorSpace::Separation(ref name, ref alt, ref f) => {
-                    debug!("Separation(name={}, alt={:?}, f={:?}", name, alt, f);
-                    if args.len() != 1 {
-                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
-                    }
-                    let x = args[0].as_number()?;
-                    let cs = match **alt {
-                        ColorSpace::Icc(ref info) => &**info.alternate.as_ref().ok_or(
-                            PdfError::Other { msg: format!("no alternate color space in ICC profile {:?}", info) }
-                        )?,
-                        _ => alt,
-                    };
-                    match cs {
-                        &ColorSpace::DeviceCMYK => {
-                            let mut cmyk = [0.0; 4];
-                            f.apply(&[x], &mut cmyk)?;
-                            let [c, m, y, k] = cmyk;
-                            //debug!("c={c}, m={m}, y={y}, k={k}");
-                            Ok(cmyk2rgb((c, m, y, k), mode))
-                        },
-                        &ColorSpace::DeviceRGB => {
-                            let mut rgb = [0.0, 0.0, 0.0];
-                            f.apply(&[x], &mut rgb)?;
-                            let [r, g, b] = rgb;
-                            //debug!("r={r}, g={g}, b={b}");
-                            Ok(Fill::Solid(r, g, b))
-                        },
-                        &ColorSpace::DeviceGray => {
-                            let mut gray = [0.0];
-                            f.apply(&[x], &mut gray)?;
-                            let [gray] = gray;
-                            //debug!("gray={gray}");
-                            Ok(Fill::Solid(gray, gray, gray))
-                        }
-                        c => unimplemented!("Separation(alt={:?})", c)
-                    }
-                }
-                ColorSpace::Indexed(ref cs, hival, ref lut) => {
-                    if args.len() != 1 {
-                        return Err(PdfError::Other { msg: format!("expected 1 color arguments, got {:?}", args) });
-                    }
-                    let i = args[0].as_integer()?;
-                    match **cs {
-                        ColorSpace::DeviceRGB => {
-                            let c = &lut[3 * i as usize ..];
-                            let cvt = |b: u8| b as f32;
-                            Ok(Fill::Solid(cvt(c[0]), cvt(c[1]), cvt(c[2])))
-                        }
-                        ColorSpace::DeviceCMYK => {
-                            let c = &lut[4 * i as usize ..];
-                            let cvt = |b: u8| b as f32;
-                            Ok(cmyk2rgb((cvt(c[0]), cvt(c[1]), cvt(c[2]), cvt(c[3])), mode))
-                        }
-                        ref base => unimplemented!("Indexed colorspace with base {:?}", base)
-                    }
-                }
-                ColorSpace::Pattern => {
-                    let name = args[0].as_name()?;
-                    if let Some(&pat) = resources.pattern.get(name) {
-                        Ok(Fill::Pattern(pat))
-                    } else {
-                        unimplemented!("Pattern {} not found", name)
-                    }
-                }
-                ColorSpace::Other(ref p) => unimplemented!("Other Color space {:?}", p),
-                ColorSpace::Named(ref p) => unimplemented!("nested Named {:?}", p),
-            }
-        }
-    }
-}
-
-fn gray2rgb(g: f32) -> Fill {
-    Fill::Solid(g, g, g)
-}
-
-fn cmyk2rgb((c, m, y, k): (f32, f32, f32, f32), mode: BlendMode) -> Fill {
-    let clamp = |f| if f > 1.0 { 1.0 } else { f };
-    Fill::Solid(
-        1.0 - clamp(c + k),
-        1.0 - clamp(m + k),
-        1.0 - clamp(y + k),
+pub(crate) fn rect_f(pdf::object::Rect { left, right, top, bottom }: pdf::object::Rect) -> RectF {
+    RectF::from_points(
+        Vector2F::new(left.min(right), bottom.min(top)),
+        Vector2F::new(left.max(right), bottom.max(top))
     )
 }
 
-
 fn to_rect(o: &Outline) -> Option<RectF> {
     if o.contours().len() != 1 {
         return None;
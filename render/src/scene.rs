@@ -1,41 +1,230 @@
 use pathfinder_color::{ColorF, ColorU};
 use pathfinder_content::{
     fill::FillRule,
-    stroke::{OutlineStrokeToFill},
-    outline::Outline,
-    pattern::{Pattern},
-    dash::OutlineDash,
+    outline::{Outline, Contour},
+    pattern::{Pattern, Image},
+    gradient::Gradient,
 };
 use pathfinder_renderer::{
-    scene::{DrawPath, ClipPath, ClipPathId, Scene},
+    scene::{DrawPath, ClipPath, ClipPathId, RenderTarget, RenderTargetId, Scene},
     paint::{PaintId, Paint},
 };
 use pathfinder_geometry::{
-    vector::{Vector2F},
+    vector::{Vector2F, Vector2I},
     rect::RectF, transform2d::Transform2F,
+    line_segment::LineSegment2F,
 };
-use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef};
-use crate::backend;
+use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, RenderingIntent};
+use pdf::file::{File, Cache as ObjectCache, Log};
+use pdf::any::AnySync;
+use crate::backend::{self, Blend, FillMode, SoftMask, SoftMaskKind, TransparencyGroup};
 
-use super::{FontEntry, TextSpan, DrawMode, Backend, Fill, Cache};
+use super::{FontEntry, TextSpan, DrawMode, Backend, Fill, Cache, Shading, ShadingKind, ShadingQuality, IccTransform, CmykConversion};
+use crate::{RenderConfig, SNAP_GRID, render_page_with_config};
+use crate::shading::triangle;
 use pdf::font::Font as PdfFont;
 use pdf::error::PdfError;
 use std::sync::Arc;
+use std::collections::HashMap;
 
 pub struct SceneBackend<'a> {
     scene: Scene,
     cache: &'a mut Cache,
+    mask: Option<MaskBuilder>,
+    view_box: RectF,
+    shading_quality: ShadingQuality,
+    blend: Blend,
+    /// of the images, see `Backend::set_rendering_intent`
+    intent: RenderingIntent,
+    overprint: bool,
+    /// open transparency groups and the render targets they are drawn into, in `scene` and `coverage`
+    groups: Vec<(RenderTargetId, Option<RenderTargetId>, Vector2I, TransparencyGroup<ClipPathId>)>,
+    recursion_limit: usize,
+    /// painted over the view box before the page, `None` leaves it transparent
+    background: Option<ColorU>,
+    /// device coordinates are rounded to multiples of this
+    snap: Option<f32>,
+    /// the same paths painted opaque white, see `set_coverage`
+    coverage: Option<Scene>,
+    /// clip paths as created, in the space of the scene
+    clips: HashMap<ClipPathId, (Outline, FillRule, Option<ClipPathId>)>,
+    /// copies of `clips` moved into the space of the render targets of groups
+    group_clips: HashMap<ClipPathId, ClipPathId>,
 }
+
+/// Collects the painted area of a soft mask group.
+///
+/// Pathfinder has no soft masks, so the mask is approximated by a clip path covering
+/// everything in the group that is painted with a mask value of at least one half.
+struct MaskBuilder {
+    kind: SoftMaskKind,
+    transfer: Option<Vec<f32>>,
+    outline: Outline,
+    /// the backdrop already lets everything through
+    open: bool,
+}
+impl MaskBuilder {
+    fn value(&self, fill: &FillMode) -> f32 {
+        let v = match (self.kind, fill.color) {
+            (SoftMaskKind::Alpha, _) => fill.alpha,
+            (SoftMaskKind::Luminosity, Fill::Solid(r, g, b)) => luminosity(r, g, b),
+            (SoftMaskKind::Luminosity, Fill::Pattern(_)) => 1.0,
+        };
+        self.transfer(v)
+    }
+    fn transfer(&self, v: f32) -> f32 {
+        match self.transfer {
+            Some(ref lut) => lut[(v.max(0.0).min(1.0) * 255.) as usize],
+            None => v
+        }
+    }
+    fn add(&mut self, outline: Outline) {
+        for contour in outline.into_contours() {
+            self.outline.push_contour(contour);
+        }
+    }
+}
+
+/// `outline` with every point rounded to a multiple of `grid`
+fn snap_outline(outline: &Outline, grid: f32) -> Outline {
+    let mut snapped = Outline::new();
+    for contour in outline.contours() {
+        let mut c = Contour::new();
+        for i in 0 .. contour.len() {
+            let p = (contour.position_of(i) * (1.0 / grid)).round() * grid;
+            c.push_point(p, contour.flags_of(i), true);
+        }
+        if contour.is_closed() {
+            c.close();
+        }
+        snapped.push_contour(c);
+    }
+    snapped
+}
+
+fn luminosity(r: f32, g: f32, b: f32) -> f32 {
+    0.30 * r + 0.59 * g + 0.11 * b
+}
+
 impl<'a> SceneBackend<'a> {
     pub fn new(cache: &'a mut Cache) -> Self {
         let scene = Scene::new();
         SceneBackend {
             scene,
-            cache
+            cache,
+            mask: None,
+            view_box: RectF::default(),
+            shading_quality: ShadingQuality::default(),
+            blend: Blend::Normal,
+            intent: RenderingIntent::RelativeColorimetric,
+            overprint: false,
+            groups: vec![],
+            recursion_limit: backend::DEFAULT_RECURSION_LIMIT,
+            background: Some(ColorU::white()),
+            snap: None,
+            coverage: None,
+            clips: HashMap::new(),
+            group_clips: HashMap::new(),
         }
     }
+    /// How finely function-based and mesh shadings are approximated.
+    pub fn set_shading_quality(&mut self, quality: ShadingQuality) {
+        self.shading_quality = quality;
+    }
+    /// How deeply forms, patterns and Type3 glyphs may be nested, see `Backend::recursion_limit`.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+    /// The color of the page below its content, white by default. Takes effect with the next `set_view_box`.
+    pub fn set_background(&mut self, background: Option<ColorU>) {
+        self.background = background;
+    }
+    /// Round all path coordinates in device space to multiples of `grid`, see `RenderConfig::deterministic`.
+    pub fn set_snap_grid(&mut self, grid: Option<f32>) {
+        self.snap = grid.filter(|&g| g > 0.0);
+    }
+    /// Also build a scene of the area covered by the content, with every path painted opaque white.
+    /// Rasterizing it tells the coverage of a pixel apart from the alpha of the objects painted there.
+    ///
+    /// Call it before rendering the page. Clip paths get the same ids in both scenes.
+    pub(crate) fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = if coverage { Some(Scene::new()) } else { None };
+    }
+    /// Whether the content turned on overprinting, which is only approximated.
+    pub fn used_overprint(&self) -> bool {
+        self.overprint
+    }
+    /// The scene, with groups that were left open (by content that ended early) composited.
     pub fn finish(self) -> Scene {
-        self.scene
+        self.finish_with_coverage().0
+    }
+    /// The scene and the coverage scene, if `set_coverage` was called.
+    pub(crate) fn finish_with_coverage(mut self) -> (Scene, Option<Scene>) {
+        while !self.groups.is_empty() {
+            warn!("transparency group not closed");
+            self.end_group();
+        }
+        (self.scene, self.coverage)
+    }
+    /// add `draw_path` to the scene, and its area to the coverage scene. It is clipped by a clip path of the scene.
+    fn push_draw_path(&mut self, mut draw_path: DrawPath) {
+        let clip = self.group_clip(draw_path.clip_path());
+        draw_path.set_clip_path(clip);
+        if let Some(ref mut coverage) = self.coverage {
+            let paint = coverage.push_paint(&Paint::from_color(ColorU::white()));
+            let mut covered = DrawPath::new(draw_path.outline().clone(), paint);
+            covered.set_clip_path(draw_path.clip_path());
+            covered.set_fill_rule(draw_path.fill_rule());
+            coverage.push_draw_path(covered);
+        }
+        self.scene.push_draw_path(draw_path);
+    }
+    fn push_clip_path(&mut self, clip: ClipPath) -> ClipPathId {
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.push_clip_path(clip.clone());
+        }
+        self.scene.push_clip_path(clip)
+    }
+    /// Render targets of groups only cover the view box, so their content is moved by this.
+    fn group_offset(&self) -> Vector2F {
+        match self.groups.is_empty() {
+            true => Vector2F::zero(),
+            false => -self.view_box.origin()
+        }
+    }
+    /// from the space of the scene to the render target things are drawn into
+    fn frame(&self) -> Transform2F {
+        Transform2F::from_translation(self.group_offset())
+    }
+    /// `clip` (from `create_clip_path`) for the render target things are drawn into
+    fn group_clip(&mut self, clip: Option<ClipPathId>) -> Option<ClipPathId> {
+        let id = clip?;
+        let offset = self.group_offset();
+        if offset == Vector2F::zero() {
+            return Some(id);
+        }
+        if let Some(&moved) = self.group_clips.get(&id) {
+            return Some(moved);
+        }
+        let (outline, fill_rule, parent) = match self.clips.get(&id) {
+            Some(c) => c.clone(),
+            None => return Some(id)
+        };
+        let parent = self.group_clip(parent);
+        let mut clip = ClipPath::new(outline.transformed(&Transform2F::from_translation(offset)));
+        clip.set_fill_rule(fill_rule);
+        clip.set_clip_path(parent);
+        let moved = self.push_clip_path(clip);
+        self.group_clips.insert(id, moved);
+        Some(moved)
+    }
+    /// `outline` in device space, in the render target it is drawn into
+    fn device_outline(&self, outline: Outline, transform: Transform2F) -> Outline {
+        let outline = outline.transformed(&(self.frame() * transform));
+        match self.snap {
+            Some(grid) => snap_outline(&outline, grid),
+            None => outline
+        }
     }
     fn paint(&mut self, fill: Fill, alpha: f32) -> PaintId {
         let paint = match fill {
@@ -46,93 +235,375 @@ impl<'a> SceneBackend<'a> {
         };
         self.scene.push_paint(&paint)
     }
+    fn blend_mode(&self, mode: backend::BlendMode) -> pathfinder_content::effects::BlendMode {
+        use pathfinder_content::effects::BlendMode as B;
+        match (self.blend, mode) {
+            (Blend::Normal, crate::BlendMode::Darken) => B::Multiply,
+            (Blend::Normal, crate::BlendMode::Overlay) => B::Overlay,
+            (Blend::Multiply, _) => B::Multiply,
+            (Blend::Screen, _) => B::Screen,
+            (Blend::Overlay, _) => B::Overlay,
+            (Blend::Darken, _) => B::Darken,
+            (Blend::Lighten, _) => B::Lighten,
+            (Blend::ColorDodge, _) => B::ColorDodge,
+            (Blend::ColorBurn, _) => B::ColorBurn,
+            (Blend::HardLight, _) => B::HardLight,
+            (Blend::SoftLight, _) => B::SoftLight,
+            (Blend::Difference, _) => B::Difference,
+            (Blend::Exclusion, _) => B::Exclusion,
+            (Blend::Hue, _) => B::Hue,
+            (Blend::Saturation, _) => B::Saturation,
+            (Blend::Color, _) => B::Color,
+            (Blend::Luminosity, _) => B::Luminosity,
+        }
+    }
+    /// paint `image` into the unit square mapped by `transform`
+    fn paint_image(&mut self, image: &Image, transform: Transform2F, mode: backend::BlendMode, alpha: f32, clip: Option<ClipPathId>) {
+        let transform = self.frame() * transform;
+        let size_f = image.size().to_f32();
+        let faded;
+        let image = match alpha {
+            a if a >= 1.0 => image,
+            a => {
+                let pixels = image.pixels().iter().map(|c| ColorU { a: (c.a as f32 * a) as u8, .. *c }).collect();
+                faded = Image::new(image.size(), Arc::new(pixels));
+                &faded
+            }
+        };
+        let outline = Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0)));
+        let im_tr = transform
+            * Transform2F::from_scale(Vector2F::new(1.0 / size_f.x(), -1.0 / size_f.y()))
+            * Transform2F::from_translation(Vector2F::new(0.0, -size_f.y()));
+
+        let mut pattern = Pattern::from_image(image.clone());
+        pattern.apply_transform(im_tr);
+        let paint = Paint::from_pattern(pattern);
+        let paint_id = self.scene.push_paint(&paint);
+        let mut draw_path = DrawPath::new(outline, paint_id);
+        draw_path.set_clip_path(clip);
+        draw_path.set_blend_mode(self.blend_mode(mode));
+
+        self.push_draw_path(draw_path);
+    }
 }
 impl<'a> Backend for SceneBackend<'a> {
     type ClipPathId = ClipPathId;
     fn create_clip_path(&mut self, path: Outline, fill_rule: FillRule, parent: Option<Self::ClipPathId>) -> Self::ClipPathId {
-        let mut clip = ClipPath::new(path);
+        // clips are kept in the space of the scene, groups get their own copies
+        let path = match self.snap {
+            Some(grid) => snap_outline(&path, grid),
+            None => path
+        };
+        let mut clip = ClipPath::new(path.clone());
         clip.set_fill_rule(fill_rule);
         clip.set_clip_path(parent);
-        self.scene.push_clip_path(clip)
+        let id = self.push_clip_path(clip);
+        self.clips.insert(id, (path, fill_rule, parent));
+        id
     }
     fn set_view_box(&mut self, view_box: RectF) {
+        self.view_box = view_box;
         self.scene.set_view_box(view_box);
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.set_view_box(view_box);
+        }
 
-        let white = self.scene.push_paint(&Paint::from_color(ColorU::white()));
-        self.scene.push_draw_path(DrawPath::new(Outline::from_rect(view_box), white));
-
+        if let Some(background) = self.background {
+            let paint = self.scene.push_paint(&Paint::from_color(background));
+            self.scene.push_draw_path(DrawPath::new(Outline::from_rect(view_box), paint));
+        }
     }
     fn draw(&mut self, outline: &Outline, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
+        if let Some(ref mut mask) = self.mask {
+            let fill = match mode {
+                DrawMode::Fill { fill } | DrawMode::FillStroke { fill, .. } => fill,
+                DrawMode::Stroke { stroke, .. } => stroke,
+            };
+            if mask.value(fill) >= 0.5 {
+                mask.add(outline.clone().transformed(&transform));
+            }
+            return;
+        }
         match mode {
             DrawMode::Fill { fill } | DrawMode::FillStroke {fill, .. } => {
                 let paint = self.paint(fill.color, fill.alpha);
-                let mut draw_path = DrawPath::new(outline.clone().transformed(&transform), paint);
+                let mut draw_path = DrawPath::new(self.device_outline(outline.clone(), transform), paint);
                 draw_path.set_clip_path(clip);
                 draw_path.set_fill_rule(fill_rule);
-                draw_path.set_blend_mode(blend_mode(fill.mode));
-                self.scene.push_draw_path(draw_path);
+                draw_path.set_blend_mode(self.blend_mode(fill.mode));
+                self.push_draw_path(draw_path);
             }
             _ => {}
         }
         match mode {
             DrawMode::Stroke { stroke, stroke_mode }| DrawMode::FillStroke { stroke, stroke_mode, .. } => {
                 let paint = self.paint(stroke.color, stroke.alpha);
-                let contour = match stroke_mode.dash_pattern {
-                    Some((ref pat, phase)) => {
-                        let dashed = OutlineDash::new(outline, &*pat, phase).into_outline();
-                        let mut stroke = OutlineStrokeToFill::new(&dashed, stroke_mode.style);
-                        stroke.offset();
-                        stroke.into_outline()
-                    }
-                    None => {
-                        let mut stroke = OutlineStrokeToFill::new(outline, stroke_mode.style);
-                        stroke.offset();
-                        stroke.into_outline()
-                    }
-                };
-                let mut draw_path = DrawPath::new(contour.transformed(&transform), paint);
+                let contour = stroke_mode.outline(outline);
+                let mut draw_path = DrawPath::new(self.device_outline(contour, transform), paint);
                 draw_path.set_clip_path(clip);
-                draw_path.set_fill_rule(fill_rule);
+                // the stroke outline overlaps itself, the fill rule only applies to the fill
+                draw_path.set_fill_rule(FillRule::Winding);
 
-            draw_path.set_blend_mode(blend_mode(stroke.mode));
-                self.scene.push_draw_path(draw_path);
+            draw_path.set_blend_mode(self.blend_mode(stroke.mode));
+                self.push_draw_path(draw_path);
             }
             _ => {}
         }
     }
-    fn draw_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>,  resolve: &impl Resolve) {
-        if let Ok(ref image) = *self.cache.get_image(xobject_ref, im, resources, resolve, mode).0 {
-            let size = image.size();
-            let size_f = size.to_f32();
-            let outline = Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0)));
-            let im_tr = transform
-                * Transform2F::from_scale(Vector2F::new(1.0 / size_f.x(), -1.0 / size_f.y()))
-                * Transform2F::from_translation(Vector2F::new(0.0, -size_f.y()));
-
-            let mut pattern = Pattern::from_image(image.clone());
-            pattern.apply_transform(im_tr);
-            let paint = Paint::from_pattern(pattern);
-            let paint_id = self.scene.push_paint(&paint);
-            let mut draw_path = DrawPath::new(outline, paint_id);
-            draw_path.set_clip_path(clip);
-            draw_path.set_blend_mode(blend_mode(mode));
-
-            self.scene.push_draw_path(draw_path);
+    fn draw_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, alpha: f32, clip: Option<ClipPathId>,  resolve: &impl Resolve) -> Result<(), PdfError> {
+        if let Some(ref mut mask) = self.mask {
+            mask.add(Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0))));
+            return Ok(());
         }
+        let image = self.cache.get_image(xobject_ref, im, resources, resolve, mode, self.intent);
+        self.paint_image(image.get()?, transform, mode, alpha, clip);
+        Ok(())
     }
-    fn draw_inline_image(&mut self, _im: &Arc<ImageXObject>, _resources: &Resources, _transform: Transform2F, mode: backend::BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) {
-
+    fn draw_image_mask(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, fill: &FillMode, _resources: &Resources, transform: Transform2F, clip: Option<ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if let Some(ref mut mask) = self.mask {
+            if mask.value(fill) >= 0.5 {
+                mask.add(Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0))));
+            }
+            return Ok(());
+        }
+        let color = match fill.color {
+            Fill::Solid(r, g, b) => ColorF::new(r, g, b, fill.alpha).to_u8(),
+            Fill::Pattern(_) => ColorU::black(),
+        };
+        let image = self.cache.get_stencil(xobject_ref, im, resolve, color);
+        // the alpha is part of the stencil color
+        self.paint_image(image.get()?, transform, fill.mode, 1.0, clip);
+        Ok(())
+    }
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, alpha: f32, clip: Option<ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if let Some(ref mut mask) = self.mask {
+            mask.add(Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0))));
+            return Ok(());
+        }
+        let image = self.cache.inline_image(im, resources, resolve, mode, self.intent)?;
+        self.paint_image(&image, transform, mode, alpha, clip);
+        Ok(())
+    }
+    fn draw_inline_image_mask(&mut self, im: &Arc<ImageXObject>, fill: &FillMode, _resources: &Resources, transform: Transform2F, clip: Option<ClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if let Some(ref mut mask) = self.mask {
+            if mask.value(fill) >= 0.5 {
+                mask.add(Outline::from_rect(transform * RectF::new(Vector2F::default(), Vector2F::new(1.0, 1.0))));
+            }
+            return Ok(());
+        }
+        let color = match fill.color {
+            Fill::Solid(r, g, b) => ColorF::new(r, g, b, fill.alpha).to_u8(),
+            Fill::Pattern(_) => ColorU::black(),
+        };
+        let image = self.cache.inline_stencil(im, resolve, color)?;
+        self.paint_image(&image, transform, fill.mode, 1.0, clip);
+        Ok(())
     }
 
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
         self.cache.get_font(font_ref, resolve)
     }
     fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>) {}
+
+    fn icc_transform(&self) -> Option<Arc<dyn IccTransform>> {
+        self.cache.icc_transform()
+    }
+    fn cmyk_conversion(&self) -> CmykConversion {
+        self.cache.cmyk_conversion()
+    }
+    fn set_blend_mode(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+    fn set_rendering_intent(&mut self, intent: RenderingIntent) {
+        self.intent = intent;
+    }
+    fn overprint(&mut self, _fill: bool, _stroke: bool, _mode: i32) {
+        self.overprint = true;
+    }
+    fn begin_group(&mut self, group: &TransparencyGroup<ClipPathId>) -> bool {
+        // soft masks are only collected as outlines
+        if self.mask.is_some() {
+            return false;
+        }
+        // the render target covers the view box, see `group_offset`
+        let size = self.view_box.size().ceil().to_i32();
+        if size.x() <= 0 || size.y() <= 0 {
+            return false;
+        }
+        // non-isolated groups would start with a copy of the backdrop. Starting transparent only differs for blend modes inside the group.
+        let target = self.scene.push_render_target(RenderTarget::new(size, String::new()));
+        let covered = self.coverage.as_mut().map(|c| c.push_render_target(RenderTarget::new(size, String::new())));
+        self.groups.push((target, covered, size, group.clone()));
+        true
+    }
+    fn end_group(&mut self) {
+        let (target, covered, size, group) = match self.groups.pop() {
+            Some(g) => g,
+            None => return
+        };
+        self.scene.pop_render_target();
+        // composite the render target at the view box, in the render target of an enclosing group at its origin
+        let frame = Transform2F::from_translation(self.view_box.origin()) * self.frame();
+        let rect = Outline::from_rect(frame * RectF::new(Vector2F::zero(), size.to_f32()));
+        let clip = self.group_clip(group.clip);
+        if let (Some(coverage), Some(covered)) = (self.coverage.as_mut(), covered) {
+            // the group covers what its content covers, regardless of its alpha
+            coverage.pop_render_target();
+            let mut pattern = Pattern::from_render_target(covered, size);
+            pattern.apply_transform(frame);
+            let paint = coverage.push_paint(&Paint::from_pattern(pattern));
+            let mut draw_path = DrawPath::new(rect.clone(), paint);
+            draw_path.set_clip_path(clip);
+            coverage.push_draw_path(draw_path);
+        }
+
+        let mut pattern = Pattern::from_render_target(target, size);
+        pattern.apply_transform(frame);
+        let mut paint = Paint::from_pattern(pattern);
+        paint.set_opacity(group.alpha);
+        let paint = self.scene.push_paint(&paint);
+        let mut draw_path = DrawPath::new(rect, paint);
+        draw_path.set_clip_path(clip);
+        let blend = std::mem::replace(&mut self.blend, group.blend);
+        draw_path.set_blend_mode(self.blend_mode(crate::BlendMode::Overlay));
+        self.blend = blend;
+        self.scene.push_draw_path(draw_path);
+    }
+    fn shading_quality(&self) -> ShadingQuality {
+        self.shading_quality
+    }
+    fn recursion_limit(&self) -> usize {
+        self.recursion_limit
+    }
+
+    fn draw_shading(&mut self, shading: &Shading, transform: Transform2F, mode: backend::BlendMode, alpha: f32, clip: Option<ClipPathId>) {
+        let outline = shading.region(transform, self.view_box);
+        if let Some(ref mut mask) = self.mask {
+            mask.add(outline);
+            return;
+        }
+        // meshes go through `device_outline`, everything else is moved into the render target here
+        let frame = self.frame();
+        let outline = outline.transformed(&frame);
+        if let Some((r, g, b)) = shading.background {
+            let paint = self.scene.push_paint(&Paint::from_color(ColorF::new(r, g, b, alpha).to_u8()));
+            let mut draw_path = DrawPath::new(Outline::from_rect(frame * self.view_box), paint);
+            draw_path.set_clip_path(clip);
+            draw_path.set_blend_mode(self.blend_mode(mode));
+            self.push_draw_path(draw_path);
+        }
+        let mut gradient = match shading.kind {
+            ShadingKind::Axial { start, end } => Gradient::linear_from_points(start, end),
+            ShadingKind::Radial { start, r0, end, r1 } => Gradient::radial(LineSegment2F::new(start, end), Vector2F::new(r0, r1).0),
+            ShadingKind::Function { domain, matrix, size: (w, h), ref colors } => {
+                let pixels: Vec<ColorU> = colors.iter().map(|&(r, g, b)| ColorF::new(r, g, b, alpha).to_u8()).collect();
+                let image = Image::new(Vector2I::new(w as i32, h as i32), Arc::new(pixels));
+                let mut pattern = Pattern::from_image(image);
+                pattern.set_smoothing_enabled(true);
+                pattern.apply_transform(frame * transform * matrix
+                    * Transform2F::from_translation(domain.origin())
+                    * Transform2F::from_scale(Vector2F::new(domain.width() / w as f32, domain.height() / h as f32)));
+
+                let paint = self.scene.push_paint(&Paint::from_pattern(pattern));
+                let mut draw_path = DrawPath::new(outline, paint);
+                draw_path.set_clip_path(clip);
+                draw_path.set_blend_mode(self.blend_mode(mode));
+                self.push_draw_path(draw_path);
+                return;
+            }
+            ShadingKind::Mesh { ref triangles } => {
+                // no gouraud shading in pathfinder, so every triangle gets the average of its colors
+                for &[(a, (r0, g0, b0)), (b, (r1, g1, b1)), (c, (r2, g2, b2))] in triangles {
+                    let color = ColorF::new((r0 + r1 + r2) / 3., (g0 + g1 + g2) / 3., (b0 + b1 + b2) / 3., alpha);
+                    let paint = self.scene.push_paint(&Paint::from_color(color.to_u8()));
+                    let mut outline = Outline::new();
+                    outline.push_contour(triangle(a, b, c));
+                    if let Some(r) = shading.bbox {
+                        outline.clip_against_polygon(&[r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]);
+                    }
+                    let mut draw_path = DrawPath::new(self.device_outline(outline, transform), paint);
+                    draw_path.set_clip_path(clip);
+                    draw_path.set_blend_mode(self.blend_mode(mode));
+                    self.push_draw_path(draw_path);
+                }
+                return;
+            }
+        };
+        for &(offset, (r, g, b)) in &shading.stops {
+            gradient.add_color_stop(ColorF::new(r, g, b, alpha).to_u8(), offset);
+        }
+        gradient.apply_transform(frame * transform);
+
+        let paint = self.scene.push_paint(&Paint::from_gradient(gradient));
+        let mut draw_path = DrawPath::new(outline, paint);
+        draw_path.set_clip_path(clip);
+        draw_path.set_blend_mode(self.blend_mode(mode));
+        self.push_draw_path(draw_path);
+    }
+
+    fn begin_smask(&mut self, smask: &SoftMask) {
+        let transfer = smask.transfer.map(|f| (0 .. 256).map(|i| {
+            let mut out = [0.0];
+            match f.apply(&[i as f32 / 255.], &mut out) {
+                Ok(()) => out[0],
+                Err(_) => i as f32 / 255.
+            }
+        }).collect());
+        let mut mask = MaskBuilder {
+            kind: smask.kind,
+            transfer,
+            outline: Outline::new(),
+            open: false,
+        };
+        if let (SoftMaskKind::Luminosity, Some((r, g, b))) = (smask.kind, smask.backdrop) {
+            mask.open = mask.transfer(luminosity(r, g, b)) >= 0.5;
+        }
+        self.mask = Some(mask);
+    }
+    fn end_smask(&mut self, parent: Option<ClipPathId>) -> Option<ClipPathId> {
+        let mask = self.mask.take()?;
+        if mask.open {
+            return None;
+        }
+        Some(self.create_clip_path(mask.outline, FillRule::Winding, parent))
+    }
+}
+
+
+/// Build the pathfinder `Scene` of page `page` (0 based) with the options of `config`, for drawing it with a renderer
+/// of your own. `render_page_to_image` rasterizes the same scene.
+///
+/// Also returns the transform from the user space of the page (points, y up) to the scene,
+/// which is `config.transform * page_transform(page)`. Its view box is `config.transform * page_bounds(page)`,
+/// or `config.transform * region` with `RenderConfig::region`.
+pub fn build_scene<B, OC, SC, L>(file: &File<B, OC, SC, L>, page: u32, config: &RenderConfig, cache: &mut Cache) -> Result<(Scene, Transform2F), PdfError>
+where
+    B: pdf::backend::Backend,
+    OC: ObjectCache<Result<AnySync, Arc<PdfError>>>,
+    SC: ObjectCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log,
+{
+    let (scene, _, transform) = build_scenes(file, page, config, false, cache)?;
+    Ok((scene, transform))
 }
 
-fn blend_mode(mode: backend::BlendMode) -> pathfinder_content::effects::BlendMode {
-    match mode {
-        crate::BlendMode::Darken => pathfinder_content::effects::BlendMode::Multiply,
-        crate::BlendMode::Overlay => pathfinder_content::effects::BlendMode::Overlay,
+/// `build_scene`, and with `coverage` also the coverage scene (see `SceneBackend::set_coverage`)
+pub(crate) fn build_scenes<B, OC, SC, L>(file: &File<B, OC, SC, L>, page: u32, config: &RenderConfig, coverage: bool, cache: &mut Cache) -> Result<(Scene, Option<Scene>, Transform2F), PdfError>
+where
+    B: pdf::backend::Backend,
+    OC: ObjectCache<Result<AnySync, Arc<PdfError>>>,
+    SC: ObjectCache<Result<Arc<[u8]>, Arc<PdfError>>>,
+    L: Log,
+{
+    let page = file.get_page(page)?;
+    let resolver = file.resolver();
+    let mut backend = SceneBackend::new(cache);
+    backend.set_background(config.background);
+    backend.set_coverage(coverage);
+    if config.deterministic {
+        backend.set_snap_grid(Some(SNAP_GRID));
     }
-}
\ No newline at end of file
+    let transform = render_page_with_config(&mut backend, &resolver, &page, config)?;
+    let (scene, coverage) = backend.finish_with_coverage();
+    Ok((scene, coverage, transform))
+}
@@ -0,0 +1,541 @@
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::function::Function;
+use pdf::error::{PdfError, Result};
+use pathfinder_geometry::{
+    vector::Vector2F,
+    rect::RectF, transform2d::Transform2F,
+};
+use pathfinder_content::outline::{Outline, Contour};
+use crate::{BlendMode, colorspace::{ColorCache, convert_values}};
+
+/// number of color stops sampled from the shading function
+const SAMPLES: usize = 64;
+
+/// How finely shadings that pathfinder can't paint directly are approximated.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadingQuality {
+    /// samples per axis for function-based shadings
+    pub function_samples: usize,
+    /// cells per side that each patch of a mesh shading is divided into
+    pub patch_subdivisions: usize,
+}
+impl Default for ShadingQuality {
+    fn default() -> Self {
+        ShadingQuality {
+            function_samples: 64,
+            patch_subdivisions: 8,
+        }
+    }
+}
+
+type Rgb = (f32, f32, f32);
+
+#[derive(Debug, Clone)]
+pub enum ShadingKind {
+    /// type 2
+    Axial { start: Vector2F, end: Vector2F },
+    /// type 3, interpolating between the circles (start, r0) and (end, r1)
+    Radial { start: Vector2F, r0: f32, end: Vector2F, r1: f32 },
+    /// type 1, the function sampled on a grid of `size` cells covering `domain`.
+    /// `colors` is row-major, starting at the minimum of the domain.
+    Function { domain: RectF, matrix: Transform2F, size: (usize, usize), colors: Vec<(f32, f32, f32)> },
+    /// type 6 and 7, the patches divided into triangles with a color at each vertex
+    Mesh { triangles: Vec<[(Vector2F, (f32, f32, f32)); 3]> },
+}
+
+/// A shading with its function sampled into color stops.
+#[derive(Debug, Clone)]
+pub struct Shading {
+    pub kind: ShadingKind,
+    /// (position in 0 ..= 1, RGB color), ordered by position
+    pub stops: Vec<(f32, (f32, f32, f32))>,
+    /// whether the shading continues before the start and after the end
+    pub extend: (bool, bool),
+    /// in shading space
+    pub bbox: Option<RectF>,
+    /// painted outside of the shading when used in a pattern
+    pub background: Option<(f32, f32, f32)>,
+}
+
+impl Shading {
+    pub fn parse(p: &Primitive, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache, quality: ShadingQuality) -> Result<Shading> {
+        let p = p.clone().resolve(resolve)?;
+        // mesh shadings are streams
+        let (dict, data) = match p {
+            Primitive::Stream(ref s) => {
+                let data = t!(t!(Stream::<()>::from_primitive(p.clone(), resolve)).data(resolve));
+                (s.info.clone(), Some(data))
+            }
+            p => (p.into_dictionary()?, None)
+        };
+        let shading_type = match dict.get("ShadingType") {
+            Some(p) => p.as_integer()?,
+            None => return Err(PdfError::MissingEntry { typ: "Shading", field: "ShadingType".into() })
+        };
+        let color_space = match dict.get("ColorSpace") {
+            Some(p) => t!(ColorSpace::from_primitive(p.clone(), resolve)),
+            None => return Err(PdfError::MissingEntry { typ: "Shading", field: "ColorSpace".into() })
+        };
+        let bbox = match numbers(&dict, "BBox", resolve)? {
+            Some(b) if b.len() == 4 => Some(RectF::from_points(
+                Vector2F::new(b[0].min(b[2]), b[1].min(b[3])),
+                Vector2F::new(b[0].max(b[2]), b[1].max(b[3]))
+            )),
+            _ => None
+        };
+        // the color space is freed with the shading, so what the cache learned about it can't be kept
+        let shading = Shading::build(shading_type, &dict, data.as_deref(), &color_space, bbox, resources, resolve, mode, colors, quality);
+        colors.forget(&color_space);
+        shading
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn build(shading_type: i32, dict: &Dictionary, data: Option<&[u8]>, color_space: &ColorSpace, bbox: Option<RectF>, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache, quality: ShadingQuality) -> Result<Shading> {
+        let background = match numbers(dict, "Background", resolve)? {
+            Some(values) => Some(convert_values(color_space, &values, resources, resolve, mode, colors)?),
+            None => None
+        };
+
+        match shading_type {
+            1 => {
+                let functions = functions(&dict, resolve)?;
+                let domain = match numbers(&dict, "Domain", resolve)? {
+                    Some(d) if d.len() == 4 => d,
+                    _ => vec![0.0, 1.0, 0.0, 1.0]
+                };
+                let matrix = match numbers(&dict, "Matrix", resolve)?.as_deref() {
+                    Some(&[a, b, c, d, e, f]) => Transform2F::row_major(a, c, e, b, d, f),
+                    _ => Transform2F::default()
+                };
+                let n = quality.function_samples.max(1);
+                let mut grid = Vec::with_capacity(n * n);
+                for j in 0 .. n {
+                    let y = domain[2] + (j as f32 + 0.5) / n as f32 * (domain[3] - domain[2]);
+                    for i in 0 .. n {
+                        let x = domain[0] + (i as f32 + 0.5) / n as f32 * (domain[1] - domain[0]);
+                        let values = eval(&functions, &[x, y])?;
+                        grid.push(convert_values(&color_space, &values, resources, resolve, mode, colors)?);
+                    }
+                }
+                Ok(Shading {
+                    kind: ShadingKind::Function {
+                        domain: RectF::from_points(Vector2F::new(domain[0], domain[2]), Vector2F::new(domain[1], domain[3])),
+                        matrix,
+                        size: (n, n),
+                        colors: grid,
+                    },
+                    stops: vec![],
+                    extend: (false, false),
+                    bbox,
+                    background,
+                })
+            }
+            2 | 3 => {
+                let functions = functions(&dict, resolve)?;
+                let coords = numbers(&dict, "Coords", resolve)?.unwrap_or_default();
+                let kind = match (shading_type, &coords[..]) {
+                    (2, &[x0, y0, x1, y1]) => ShadingKind::Axial {
+                        start: Vector2F::new(x0, y0),
+                        end: Vector2F::new(x1, y1),
+                    },
+                    (3, &[x0, y0, r0, x1, y1, r1]) => ShadingKind::Radial {
+                        start: Vector2F::new(x0, y0),
+                        r0,
+                        end: Vector2F::new(x1, y1),
+                        r1,
+                    },
+                    _ => return Err(PdfError::Other { msg: format!("shading type {} with Coords {:?}", shading_type, coords) })
+                };
+                let domain = match numbers(&dict, "Domain", resolve)? {
+                    Some(d) if d.len() == 2 => (d[0], d[1]),
+                    _ => (0.0, 1.0)
+                };
+                let stops = sample(&functions, domain, &color_space, resources, resolve, mode, colors)?;
+                Ok(Shading {
+                    kind,
+                    stops,
+                    extend: extend(&dict, resolve)?,
+                    bbox,
+                    background,
+                })
+            }
+            6 | 7 => {
+                let data = data.ok_or(PdfError::Other { msg: format!("shading type {} is not a stream", shading_type) })?;
+                let functions = match dict.get("Function") {
+                    Some(_) => functions(&dict, resolve)?,
+                    None => vec![]
+                };
+                let mesh = Mesh {
+                    bits_per_coordinate: numbers(&dict, "BitsPerCoordinate", resolve)?.and_then(|n| n.first().cloned()),
+                    bits_per_component: numbers(&dict, "BitsPerComponent", resolve)?.and_then(|n| n.first().cloned()),
+                    bits_per_flag: numbers(&dict, "BitsPerFlag", resolve)?.and_then(|n| n.first().cloned()),
+                    decode: numbers(&dict, "Decode", resolve)?.unwrap_or_default(),
+                };
+                let patches = mesh.patches(&data, shading_type == 7, if functions.is_empty() { None } else { Some(1) })?;
+
+                let mut convert = |values: &[f32]| -> Result<Rgb> {
+                    if functions.is_empty() {
+                        convert_values(&color_space, values, resources, resolve, mode, colors)
+                    } else {
+                        let values = eval(&functions, values)?;
+                        convert_values(&color_space, &values, resources, resolve, mode, colors)
+                    }
+                };
+                let n = quality.patch_subdivisions.max(1);
+                let mut triangles = Vec::with_capacity(patches.len() * n * n * 2);
+                for patch in &patches {
+                    let corners = [convert(&patch.colors[0])?, convert(&patch.colors[1])?, convert(&patch.colors[2])?, convert(&patch.colors[3])?];
+                    patch.triangulate(&corners, n, &mut triangles);
+                }
+                Ok(Shading {
+                    kind: ShadingKind::Mesh { triangles },
+                    stops: vec![],
+                    extend: (false, false),
+                    bbox,
+                    background,
+                })
+            }
+            t => unimplemented!("shading type {}", t)
+        }
+    }
+
+    /// The area painted by `sh` within `view_box`, in device space.
+    pub fn region(&self, transform: Transform2F, view_box: RectF) -> Outline {
+        let inv = transform.inverse();
+        let corners = [view_box.origin(), view_box.upper_right(), view_box.lower_right(), view_box.lower_left()];
+
+        let mut outline = match self.kind {
+            ShadingKind::Function { domain, matrix, .. } => {
+                let mut outline = Outline::from_rect(domain);
+                outline.transform(&matrix);
+                outline
+            }
+            ShadingKind::Mesh { ref triangles } => {
+                let mut outline = Outline::new();
+                for t in triangles {
+                    outline.push_contour(triangle(t[0].0, t[1].0, t[2].0));
+                }
+                outline
+            }
+            ShadingKind::Axial { start, end } => {
+                let axis = end - start;
+                let len = axis.length();
+                if len == 0.0 {
+                    return Outline::new();
+                }
+                let dir = axis * (1.0 / len);
+                let normal = Vector2F::new(-dir.y(), dir.x());
+                // far enough to cover the view box
+                let r = corners.iter().map(|&c| (inv * c - start).length()).fold(0.0f32, f32::max) + len;
+
+                let s0 = if self.extend.0 { -r } else { 0.0 };
+                let s1 = if self.extend.1 { len + r } else { len };
+                let mut contour = Contour::new();
+                contour.push_endpoint(start + dir * s0 - normal * r);
+                contour.push_endpoint(start + dir * s1 - normal * r);
+                contour.push_endpoint(start + dir * s1 + normal * r);
+                contour.push_endpoint(start + dir * s0 + normal * r);
+                contour.close();
+                let mut outline = Outline::new();
+                outline.push_contour(contour);
+                outline
+            }
+            ShadingKind::Radial { start, r0, end, r1 } => {
+                let r = corners.iter().map(|&c| (inv * c - start).length()).fold(0.0f32, f32::max) + r0.max(r1);
+                let dr = r1 - r0;
+                let speed = dr.abs().max((end - start).length());
+                // parameter at which the extended circles cover the view box
+                let far = if speed > 0.0 { r / speed + 1.0 } else { 0.0 };
+
+                // extending towards a shrinking radius ends where the radius reaches zero
+                let t0 = match self.extend.0 {
+                    false => 0.0,
+                    true if dr > 0.0 => -r0 / dr,
+                    true => -far,
+                };
+                let t1 = match self.extend.1 {
+                    false => 1.0,
+                    true if dr < 0.0 => -r0 / dr,
+                    true => 1.0 + far,
+                };
+                let circle = |t: f32| (start + (end - start) * t, (r0 + t * dr).max(0.0));
+
+                // the circles in between sweep out the convex hull of the outer two
+                let mut outline = Outline::new();
+                outline.push_contour(hull(&[circle(t0), circle(t1)]));
+                outline
+            }
+        };
+        if let Some(r) = self.bbox {
+            outline.clip_against_polygon(&[r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]);
+        }
+        outline.transformed(&transform)
+    }
+}
+
+pub fn triangle(a: Vector2F, b: Vector2F, c: Vector2F) -> Contour {
+    let mut contour = Contour::new();
+    contour.push_endpoint(a);
+    contour.push_endpoint(b);
+    contour.push_endpoint(c);
+    contour.close();
+    contour
+}
+
+/// Reads the patch data of mesh shadings
+struct Mesh {
+    bits_per_coordinate: Option<f32>,
+    bits_per_component: Option<f32>,
+    bits_per_flag: Option<f32>,
+    decode: Vec<f32>,
+}
+
+/// A tensor product patch. `points[i][j]` are the control points, the colors are at
+/// `points[0][0]`, `points[0][3]`, `points[3][3]` and `points[3][0]`, in that order.
+struct Patch {
+    points: [[Vector2F; 4]; 4],
+    colors: [Vec<f32>; 4],
+}
+
+/// position of the 12 boundary points of a patch, in the order they appear in the data
+const BOUNDARY: [(usize, usize); 12] = [
+    (0, 0), (0, 1), (0, 2), (0, 3), (1, 3), (2, 3),
+    (3, 3), (3, 2), (3, 1), (3, 0), (2, 0), (1, 0),
+];
+/// interior points of tensor patches (type 7)
+const INTERIOR: [(usize, usize); 4] = [(1, 1), (1, 2), (2, 2), (2, 1)];
+
+impl Mesh {
+    /// `components` overrides the number of color components, for shadings with a function
+    fn patches(&self, data: &[u8], tensor: bool, components: Option<usize>) -> Result<Vec<Patch>> {
+        let bits = |b: Option<f32>, name: &str| match b {
+            Some(b) if b >= 1.0 && b <= 32.0 => Ok(b as u32),
+            _ => Err(PdfError::Other { msg: format!("invalid {} {:?}", name, b) })
+        };
+        let coord_bits = bits(self.bits_per_coordinate, "BitsPerCoordinate")?;
+        let comp_bits = bits(self.bits_per_component, "BitsPerComponent")?;
+        let flag_bits = bits(self.bits_per_flag, "BitsPerFlag")?;
+        if self.decode.len() < 6 || self.decode.len() % 2 != 0 {
+            return Err(PdfError::Other { msg: format!("mesh Decode {:?}", self.decode) });
+        }
+        let n = components.unwrap_or((self.decode.len() - 4) / 2);
+        let decode = |v: u32, bits: u32, i: usize| {
+            let (lo, hi) = (self.decode[2 * i], self.decode[2 * i + 1]);
+            lo + (v as f64 / ((1u64 << bits) - 1) as f64) as f32 * (hi - lo)
+        };
+
+        let mut reader = BitReader { data, pos: 0 };
+        let mut patches: Vec<Patch> = vec![];
+        while let Some(flag) = reader.read(flag_bits) {
+            let mut points = [[Vector2F::zero(); 4]; 4];
+            let mut colors: [Vec<f32>; 4] = Default::default();
+
+            // continue from an edge of the previous patch
+            let (first_point, first_color) = match (flag, patches.last()) {
+                (0, _) => (0, 0),
+                (f @ 1 ..= 3, Some(prev)) => {
+                    let f = f as usize;
+                    for k in 0 .. 4 {
+                        let (i, j) = BOUNDARY[(3 * f + k) % 12];
+                        let (pi, pj) = BOUNDARY[k];
+                        points[pi][pj] = prev.points[i][j];
+                    }
+                    colors[0] = prev.colors[f].clone();
+                    colors[1] = prev.colors[(f + 1) % 4].clone();
+                    (4, 2)
+                }
+                (f, _) => return Err(PdfError::Other { msg: format!("invalid patch flag {}", f) })
+            };
+
+            let mut read_point = || -> Option<Vector2F> {
+                let x = reader.read(coord_bits)?;
+                let y = reader.read(coord_bits)?;
+                Some(Vector2F::new(decode(x, coord_bits, 0), decode(y, coord_bits, 1)))
+            };
+            let mut complete = true;
+            for &(i, j) in &BOUNDARY[first_point ..] {
+                match read_point() {
+                    Some(p) => points[i][j] = p,
+                    None => complete = false
+                }
+            }
+            if tensor {
+                for &(i, j) in &INTERIOR {
+                    match read_point() {
+                        Some(p) => points[i][j] = p,
+                        None => complete = false
+                    }
+                }
+            }
+            for color in &mut colors[first_color ..] {
+                for c in 0 .. n {
+                    match reader.read(comp_bits) {
+                        Some(v) => color.push(decode(v, comp_bits, 2 + c)),
+                        None => complete = false
+                    }
+                }
+            }
+            if !complete {
+                warn!("truncated patch data");
+                break;
+            }
+            if !tensor {
+                coons_interior(&mut points);
+            }
+            patches.push(Patch { points, colors });
+            reader.align();
+        }
+        Ok(patches)
+    }
+}
+
+/// The implicit interior control points of a Coons patch
+fn coons_interior(p: &mut [[Vector2F; 4]; 4]) {
+    let f = 1.0 / 9.0;
+    p[1][1] = (p[0][0] * -4.0 + (p[0][1] + p[1][0]) * 6.0 - (p[0][3] + p[3][0]) * 2.0 + (p[3][1] + p[1][3]) * 3.0 - p[3][3]) * f;
+    p[1][2] = (p[0][3] * -4.0 + (p[0][2] + p[1][3]) * 6.0 - (p[0][0] + p[3][3]) * 2.0 + (p[3][2] + p[1][0]) * 3.0 - p[3][0]) * f;
+    p[2][1] = (p[3][0] * -4.0 + (p[3][1] + p[2][0]) * 6.0 - (p[3][3] + p[0][0]) * 2.0 + (p[0][1] + p[2][3]) * 3.0 - p[0][3]) * f;
+    p[2][2] = (p[3][3] * -4.0 + (p[3][2] + p[2][3]) * 6.0 - (p[3][0] + p[0][3]) * 2.0 + (p[0][2] + p[2][0]) * 3.0 - p[0][0]) * f;
+}
+
+impl Patch {
+    fn point(&self, u: f32, v: f32) -> Vector2F {
+        let bernstein = |t: f32| {
+            let s = 1.0 - t;
+            [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+        };
+        let (bu, bv) = (bernstein(u), bernstein(v));
+        let mut p = Vector2F::zero();
+        for i in 0 .. 4 {
+            for j in 0 .. 4 {
+                p = p + self.points[i][j] * (bu[i] * bv[j]);
+            }
+        }
+        p
+    }
+    /// Divide the patch into `n` x `n` cells of two triangles each
+    fn triangulate(&self, corners: &[Rgb; 4], n: usize, out: &mut Vec<[(Vector2F, Rgb); 3]>) {
+        let [c00, c03, c33, c30] = *corners;
+        let lerp = |a: Rgb, b: Rgb, t: f32| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, a.2 + (b.2 - a.2) * t);
+        let vertex = |i: usize, j: usize| {
+            let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+            let color = lerp(lerp(c00, c03, v), lerp(c30, c33, v), u);
+            (self.point(u, v), color)
+        };
+        for i in 0 .. n {
+            for j in 0 .. n {
+                let (a, b, c, d) = (vertex(i, j), vertex(i, j + 1), vertex(i + 1, j + 1), vertex(i + 1, j));
+                out.push([a, b, c]);
+                out.push([a, c, d]);
+            }
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        if self.pos + bits as usize > self.data.len() * 8 {
+            return None;
+        }
+        let mut v = 0u64;
+        for _ in 0 .. bits {
+            let bit = (self.data[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            v = v << 1 | bit as u64;
+            self.pos += 1;
+        }
+        Some(v as u32)
+    }
+    fn align(&mut self) {
+        self.pos = (self.pos + 7) / 8 * 8;
+    }
+}
+
+/// Convex hull of the given circles
+fn hull(circles: &[(Vector2F, f32)]) -> Contour {
+    const STEPS: usize = 64;
+    let mut points: Vec<Vector2F> = circles.iter().flat_map(|&(center, r)| {
+        (0 .. STEPS).map(move |i| {
+            let a = i as f32 * (2.0 * std::f32::consts::PI / STEPS as f32);
+            center + Vector2F::new(a.cos(), a.sin()) * r
+        })
+    }).collect();
+    points.sort_by(|a, b| a.x().total_cmp(&b.x()).then(a.y().total_cmp(&b.y())));
+
+    let cross = |o: Vector2F, a: Vector2F, b: Vector2F| (a - o).det(b - o);
+    let mut lower: Vec<Vector2F> = vec![];
+    for &p in &points {
+        while lower.len() >= 2 && cross(lower[lower.len() - 2], lower[lower.len() - 1], p) <= 0.0 {
+            lower.pop();
+        }
+        lower.push(p);
+    }
+    let mut upper: Vec<Vector2F> = vec![];
+    for &p in points.iter().rev() {
+        while upper.len() >= 2 && cross(upper[upper.len() - 2], upper[upper.len() - 1], p) <= 0.0 {
+            upper.pop();
+        }
+        upper.push(p);
+    }
+    lower.pop();
+    upper.pop();
+
+    let mut contour = Contour::new();
+    for p in lower.into_iter().chain(upper) {
+        contour.push_endpoint(p);
+    }
+    contour.close();
+    contour
+}
+
+fn numbers(dict: &Dictionary, key: &str, resolve: &impl Resolve) -> Result<Option<Vec<f32>>> {
+    match dict.get(key) {
+        Some(p) => Ok(Some(p.clone().resolve(resolve)?.into_array()?.iter().map(|n| n.as_number()).collect::<Result<_>>()?)),
+        None => Ok(None)
+    }
+}
+
+fn extend(dict: &Dictionary, resolve: &impl Resolve) -> Result<(bool, bool)> {
+    match dict.get("Extend") {
+        Some(p) => {
+            let a = p.clone().resolve(resolve)?.into_array()?;
+            match a[..] {
+                [ref e0, ref e1] => Ok((e0.as_bool()?, e1.as_bool()?)),
+                _ => Err(PdfError::Other { msg: format!("Extend has {} entries", a.len()) })
+            }
+        }
+        None => Ok((false, false))
+    }
+}
+
+/// /Function is either one function with one output per color component, or an array of functions with one output each.
+fn functions(dict: &Dictionary, resolve: &impl Resolve) -> Result<Vec<Function>> {
+    match dict.get("Function") {
+        Some(p) => match p.clone().resolve(resolve)? {
+            Primitive::Array(parts) => parts.into_iter().map(|f| Function::from_primitive(f, resolve)).collect(),
+            p => Ok(vec![t!(Function::from_primitive(p, resolve))]),
+        },
+        None => Err(PdfError::MissingEntry { typ: "Shading", field: "Function".into() })
+    }
+}
+
+fn eval(functions: &[Function], input: &[f32]) -> Result<Vec<f32>> {
+    let mut values = vec![];
+    for f in functions {
+        let mut out = vec![0.0; f.output_dim()];
+        f.apply(input, &mut out)?;
+        values.extend_from_slice(&out);
+    }
+    Ok(values)
+}
+
+fn sample(functions: &[Function], (t0, t1): (f32, f32), cs: &ColorSpace, resources: &Resources, resolve: &impl Resolve, mode: BlendMode, colors: &mut ColorCache) -> Result<Vec<(f32, (f32, f32, f32))>> {
+    (0 ..= SAMPLES).map(|i| {
+        let s = i as f32 / SAMPLES as f32;
+        let values = eval(functions, &[t0 + s * (t1 - t0)])?;
+        Ok((s, convert_values(cs, &values, resources, resolve, mode, colors)?))
+    }).collect()
+}
@@ -0,0 +1,169 @@
+//! The logical structure of tagged PDFs (/StructTreeRoot).
+use std::collections::{HashMap, HashSet};
+use pdf::object::*;
+use pdf::primitive::{Primitive, Dictionary};
+use pdf::error::Result;
+
+/// elements nested deeper than this are ignored
+const MAX_DEPTH: usize = 64;
+
+/// The structure tree of the document
+#[derive(Debug, Clone)]
+pub struct StructTree {
+    /// the top level elements, in logical order
+    pub elements: Vec<StructElement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructElement {
+    /// /S, mapped to a standard type by the /RoleMap, for example P, H1 or TD
+    pub kind: String,
+    /// /Alt, a description of figures and formulas
+    pub alt: Option<String>,
+    /// /ActualText, replaces the text of the content
+    pub actual_text: Option<String>,
+    pub kids: Vec<StructKid>,
+}
+
+#[derive(Debug, Clone)]
+pub enum StructKid {
+    Element(StructElement),
+    /// marked content with /MCID `mcid` in the content stream of `page`
+    Content { page: Option<PlainRef>, mcid: i32 },
+}
+
+impl StructTree {
+    /// Read /StructTreeRoot of the document catalog. `None` if the document is not tagged.
+    pub fn from_catalog(catalog: &Catalog, resolve: &impl Resolve) -> Result<Option<Self>> {
+        match catalog.other.get("StructTreeRoot") {
+            Some(p) => StructTree::parse(p, resolve).map(Some),
+            None => Ok(None)
+        }
+    }
+    pub fn parse(root: &Primitive, resolve: &impl Resolve) -> Result<Self> {
+        let dict = root.clone().resolve(resolve)?.into_dictionary()?;
+        let role_map: HashMap<String, String> = match dict.get("RoleMap") {
+            Some(p) => p.clone().resolve(resolve)?.into_dictionary()?.iter()
+                .filter_map(|(k, v)| match v {
+                    Primitive::Name(ref n) => Some((k.as_str().into(), n.as_str().into())),
+                    _ => None
+                }).collect(),
+            None => HashMap::new()
+        };
+        let mut parser = Parser { resolve, role_map, visited: HashSet::new() };
+        let mut elements = vec![];
+        if let Some(k) = dict.get("K") {
+            for kid in parser.kids(k, None, 0) {
+                match kid {
+                    StructKid::Element(e) => elements.push(e),
+                    StructKid::Content { .. } => debug!("marked content directly below the structure tree root"),
+                }
+            }
+        }
+        Ok(StructTree { elements })
+    }
+    /// Elements that directly contain marked content of `page`, in logical order,
+    /// with the MCIDs of their content on that page.
+    pub fn page_elements(&self, page: PlainRef) -> Vec<(&StructElement, Vec<i32>)> {
+        fn walk<'a>(e: &'a StructElement, page: PlainRef, out: &mut Vec<(&'a StructElement, Vec<i32>)>) {
+            let mut mcids = vec![];
+            for kid in &e.kids {
+                match *kid {
+                    StructKid::Content { page: Some(p), mcid } if p == page => mcids.push(mcid),
+                    StructKid::Content { .. } => {}
+                    StructKid::Element(ref child) => {
+                        // content before a child element keeps its place in the reading order
+                        if !mcids.is_empty() {
+                            out.push((e, std::mem::take(&mut mcids)));
+                        }
+                        walk(child, page, out);
+                    }
+                }
+            }
+            if !mcids.is_empty() {
+                out.push((e, mcids));
+            }
+        }
+        let mut out = vec![];
+        for e in &self.elements {
+            walk(e, page, &mut out);
+        }
+        out
+    }
+}
+
+struct Parser<'a, R> {
+    resolve: &'a R,
+    role_map: HashMap<String, String>,
+    /// element references already read, the tree must not contain cycles
+    visited: HashSet<PlainRef>,
+}
+impl<'a, R: Resolve> Parser<'a, R> {
+    /// the /K entry of an element on `page`
+    fn kids(&mut self, k: &Primitive, page: Option<PlainRef>, depth: usize) -> Vec<StructKid> {
+        match *k {
+            Primitive::Array(ref kids) => kids.iter().flat_map(|kid| self.kid(kid, page, depth)).collect(),
+            ref kid => self.kid(kid, page, depth).into_iter().collect()
+        }
+    }
+    fn kid(&mut self, kid: &Primitive, page: Option<PlainRef>, depth: usize) -> Option<StructKid> {
+        if let Primitive::Reference(r) = *kid {
+            if !self.visited.insert(r) {
+                warn!("structure element {:?} appears twice", r);
+                return None;
+            }
+        }
+        let dict = match kid.clone().resolve(self.resolve) {
+            Ok(Primitive::Integer(mcid)) => return Some(StructKid::Content { page, mcid }),
+            Ok(Primitive::Dictionary(dict)) => dict,
+            Ok(p) => {
+                warn!("invalid structure element {:?}", p);
+                return None;
+            }
+            Err(e) => {
+                warn!("can't read structure element: {:?}", e);
+                return None;
+            }
+        };
+        let page = match dict.get("Pg") {
+            Some(&Primitive::Reference(r)) => Some(r),
+            _ => page
+        };
+        match dict.get("Type") {
+            Some(Primitive::Name(ref t)) if t.as_str() == "MCR" => {
+                if dict.get("Stm").is_some() {
+                    debug!("marked content of a form XObject is not mapped");
+                    return None;
+                }
+                let mcid = dict.get("MCID")?.as_integer().ok()?;
+                return Some(StructKid::Content { page, mcid });
+            }
+            // annotations and XObjects
+            Some(Primitive::Name(ref t)) if t.as_str() == "OBJR" => return None,
+            _ => {}
+        }
+        if depth >= MAX_DEPTH {
+            warn!("structure tree nested more than {} levels deep", MAX_DEPTH);
+            return None;
+        }
+        Some(StructKid::Element(self.element(&dict, page, depth)))
+    }
+    fn element(&mut self, dict: &Dictionary, page: Option<PlainRef>, depth: usize) -> StructElement {
+        let kind = match dict.get("S") {
+            Some(Primitive::Name(ref s)) => s.as_str(),
+            _ => ""
+        };
+        let kind = self.role_map.get(kind).map(|s| s.as_str()).unwrap_or(kind).into();
+        let text = |key| match dict.get(key)?.clone().resolve(self.resolve).ok()? {
+            Primitive::String(s) => Some(s.to_string_lossy()),
+            _ => None
+        };
+        let alt = text("Alt");
+        let actual_text = text("ActualText");
+        let kids = match dict.get("K") {
+            Some(k) => self.kids(k, page, depth + 1),
+            None => vec![]
+        };
+        StructElement { kind, alt, actual_text, kids }
+    }
+}
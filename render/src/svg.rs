@@ -0,0 +1,413 @@
+//! A backend producing an SVG document.
+//!
+//! Text is drawn as outlines. Soft masks are not supported: the mask groups are left out, and the content they mask is drawn without them.
+use std::fmt::Write;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use pathfinder_color::{ColorF, ColorU};
+use pathfinder_content::{
+    fill::FillRule,
+    outline::{Outline, ContourIterFlags},
+    pattern::Image,
+    stroke::{LineCap, LineJoin},
+};
+use pathfinder_geometry::{
+    vector::{Vector2F, Vector2I},
+    rect::RectF, transform2d::Transform2F,
+};
+use pdf::object::{Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef, RenderingIntent};
+use pdf::font::Font as PdfFont;
+use pdf::error::PdfError;
+use base64::Engine;
+
+use crate::backend::{self, Blend, FillMode, SoftMask, Stroke};
+use crate::shading::triangle;
+use super::{FontEntry, TextSpan, DrawMode, Backend, Fill, Cache, Shading, ShadingKind, ShadingQuality, IccTransform, CmykConversion};
+
+#[derive(Copy, Clone, Debug)]
+pub struct SvgClipPathId(usize);
+
+pub struct SvgBackend<'a> {
+    cache: &'a mut Cache,
+    view_box: RectF,
+    /// everything after the opening <svg>
+    body: String,
+    clip_paths: usize,
+    gradients: usize,
+    blend: Blend,
+    /// of the images, see `Backend::set_rendering_intent`
+    intent: RenderingIntent,
+    shading_quality: ShadingQuality,
+    /// below the page content, see `set_background`
+    background: Option<ColorU>,
+    /// how many soft mask groups are being painted
+    mask: usize,
+}
+impl<'a> SvgBackend<'a> {
+    pub fn new(cache: &'a mut Cache) -> Self {
+        SvgBackend {
+            cache,
+            view_box: RectF::default(),
+            body: String::new(),
+            clip_paths: 0,
+            gradients: 0,
+            blend: Blend::Normal,
+            intent: RenderingIntent::RelativeColorimetric,
+            shading_quality: ShadingQuality::default(),
+            background: Some(ColorU::white()),
+            mask: 0,
+        }
+    }
+    /// The color of the page below its content, white by default. `None` is transparent, like `RenderConfig::background`.
+    pub fn set_background(&mut self, background: Option<ColorU>) {
+        self.background = background;
+    }
+    /// The complete SVG document
+    pub fn finish(self) -> String {
+        let r = self.view_box;
+        let mut out = String::new();
+        writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+            r.min_x(), r.min_y(), r.width(), r.height(), r.width(), r.height()).unwrap();
+        if let Some(bg) = self.background {
+            write!(out, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}""#, r.min_x(), r.min_y(), r.width(), r.height(), color(Fill::Solid(bg.r as f32 / 255., bg.g as f32 / 255., bg.b as f32 / 255.))).unwrap();
+            if bg.a < 255 {
+                write!(out, r#" fill-opacity="{}""#, bg.a as f32 / 255.).unwrap();
+            }
+            out.push_str("/>\n");
+        }
+        out.push_str(&self.body);
+        out.push_str("</svg>\n");
+        out
+    }
+
+    /// attributes shared by everything that is drawn
+    fn paint_attrs(&self, attr: &str, fill: &FillMode) -> String {
+        let mut s = String::new();
+        write!(s, r#" {}="{}""#, attr, color(fill.color)).unwrap();
+        if fill.alpha < 1.0 {
+            write!(s, r#" {}-opacity="{}""#, attr, fill.alpha).unwrap();
+        }
+        s
+    }
+    fn blend_style(&self) -> &'static str {
+        match self.blend {
+            Blend::Normal => "",
+            Blend::Multiply => r#" style="mix-blend-mode:multiply""#,
+            Blend::Screen => r#" style="mix-blend-mode:screen""#,
+            Blend::Overlay => r#" style="mix-blend-mode:overlay""#,
+            Blend::Darken => r#" style="mix-blend-mode:darken""#,
+            Blend::Lighten => r#" style="mix-blend-mode:lighten""#,
+            Blend::ColorDodge => r#" style="mix-blend-mode:color-dodge""#,
+            Blend::ColorBurn => r#" style="mix-blend-mode:color-burn""#,
+            Blend::HardLight => r#" style="mix-blend-mode:hard-light""#,
+            Blend::SoftLight => r#" style="mix-blend-mode:soft-light""#,
+            Blend::Difference => r#" style="mix-blend-mode:difference""#,
+            Blend::Exclusion => r#" style="mix-blend-mode:exclusion""#,
+            Blend::Hue => r#" style="mix-blend-mode:hue""#,
+            Blend::Saturation => r#" style="mix-blend-mode:saturation""#,
+            Blend::Color => r#" style="mix-blend-mode:color""#,
+            Blend::Luminosity => r#" style="mix-blend-mode:luminosity""#,
+        }
+    }
+    /// Clip paths are in device space, so the transformed element is wrapped in a group.
+    fn push(&mut self, element: &str, clip: Option<SvgClipPathId>) {
+        match clip {
+            Some(SvgClipPathId(id)) => writeln!(self.body, r#"<g clip-path="url(#c{})">{}</g>"#, id, element).unwrap(),
+            None => writeln!(self.body, "{}", element).unwrap(),
+        }
+    }
+    /// `image` in the unit square mapped by `transform`
+    fn push_image(&mut self, image: &Image, transform: Transform2F, alpha: f32, clip: Option<SvgClipPathId>) {
+        let data = match png_base64(image) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("can't encode image: {:?}", e);
+                return;
+            }
+        };
+        // the first row is at the top
+        let transform = transform * Transform2F::from_translation(Vector2F::new(0.0, 1.0)) * Transform2F::from_scale(Vector2F::new(1.0, -1.0));
+        let mut element = format!(r#"<image width="1" height="1" preserveAspectRatio="none" transform="{}""#, matrix(transform));
+        if alpha < 1.0 {
+            write!(element, r#" opacity="{}""#, alpha).unwrap();
+        }
+        write!(element, r#"{} xlink:href="data:image/png;base64,{}"/>"#, self.blend_style(), data).unwrap();
+        self.push(&element, clip);
+    }
+}
+
+impl<'a> Backend for SvgBackend<'a> {
+    type ClipPathId = SvgClipPathId;
+
+    fn create_clip_path(&mut self, path: Outline, fill_rule: FillRule, parent: Option<SvgClipPathId>) -> SvgClipPathId {
+        let id = self.clip_paths;
+        self.clip_paths += 1;
+        write!(self.body, r#"<clipPath id="c{}""#, id).unwrap();
+        if let Some(SvgClipPathId(parent)) = parent {
+            write!(self.body, r#" clip-path="url(#c{})""#, parent).unwrap();
+        }
+        writeln!(self.body, r#"><path d="{}" clip-rule="{}"/></clipPath>"#, path_data(&path), rule(fill_rule)).unwrap();
+        SvgClipPathId(id)
+    }
+    fn set_view_box(&mut self, r: RectF) {
+        self.view_box = r;
+    }
+    fn draw(&mut self, outline: &Outline, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<SvgClipPathId>) {
+        if self.mask > 0 {
+            return;
+        }
+        let mut element = format!(r#"<path transform="{}" d="{}""#, matrix(transform), path_data(outline));
+        match mode {
+            DrawMode::Fill { fill } | DrawMode::FillStroke { fill, .. } => {
+                element.push_str(&self.paint_attrs("fill", fill));
+                write!(element, r#" fill-rule="{}""#, rule(fill_rule)).unwrap();
+            }
+            DrawMode::Stroke { .. } => element.push_str(r#" fill="none""#),
+        }
+        match mode {
+            DrawMode::Stroke { stroke, stroke_mode } | DrawMode::FillStroke { stroke, stroke_mode, .. } => {
+                element.push_str(&self.paint_attrs("stroke", stroke));
+                element.push_str(&stroke_attrs(stroke_mode));
+            }
+            DrawMode::Fill { .. } => {}
+        }
+        element.push_str(self.blend_style());
+        element.push_str("/>");
+        self.push(&element, clip);
+    }
+    fn draw_image(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, alpha: f32, clip: Option<SvgClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if self.mask > 0 {
+            return Ok(());
+        }
+        let image = self.cache.get_image(xobject_ref, im, resources, resolve, mode, self.intent);
+        self.push_image(image.get()?, transform, alpha, clip);
+        Ok(())
+    }
+    fn draw_image_mask(&mut self, xobject_ref: Ref<XObject>, im: &ImageXObject, fill: &FillMode, _resources: &Resources, transform: Transform2F, clip: Option<SvgClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if self.mask > 0 {
+            return Ok(());
+        }
+        let color = match fill.color {
+            Fill::Solid(r, g, b) => ColorF::new(r, g, b, fill.alpha).to_u8(),
+            Fill::Pattern(_) => ColorU::black(),
+        };
+        let image = self.cache.get_stencil(xobject_ref, im, resolve, color);
+        self.push_image(image.get()?, transform, 1.0, clip);
+        Ok(())
+    }
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, resources: &Resources, transform: Transform2F, mode: backend::BlendMode, alpha: f32, clip: Option<SvgClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if self.mask > 0 {
+            return Ok(());
+        }
+        let image = self.cache.inline_image(im, resources, resolve, mode, self.intent)?;
+        self.push_image(&image, transform, alpha, clip);
+        Ok(())
+    }
+    fn draw_inline_image_mask(&mut self, im: &Arc<ImageXObject>, fill: &FillMode, _resources: &Resources, transform: Transform2F, clip: Option<SvgClipPathId>, resolve: &impl Resolve) -> Result<(), PdfError> {
+        if self.mask > 0 {
+            return Ok(());
+        }
+        let color = match fill.color {
+            Fill::Solid(r, g, b) => ColorF::new(r, g, b, fill.alpha).to_u8(),
+            Fill::Pattern(_) => ColorU::black(),
+        };
+        let image = self.cache.inline_stencil(im, resolve, color)?;
+        self.push_image(&image, transform, 1.0, clip);
+        Ok(())
+    }
+
+    fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
+        self.cache.get_font(font_ref, resolve)
+    }
+    fn add_text(&mut self, _span: TextSpan, _clip: Option<SvgClipPathId>) {}
+
+    fn icc_transform(&self) -> Option<Arc<dyn IccTransform>> {
+        self.cache.icc_transform()
+    }
+    fn cmyk_conversion(&self) -> CmykConversion {
+        self.cache.cmyk_conversion()
+    }
+    fn set_blend_mode(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+    fn set_rendering_intent(&mut self, intent: RenderingIntent) {
+        self.intent = intent;
+    }
+    fn shading_quality(&self) -> ShadingQuality {
+        self.shading_quality
+    }
+    fn draw_shading(&mut self, shading: &Shading, transform: Transform2F, _mode: backend::BlendMode, alpha: f32, clip: Option<SvgClipPathId>) {
+        if self.mask > 0 {
+            return;
+        }
+        let region = path_data(&shading.region(transform, self.view_box));
+        let opacity = match alpha {
+            a if a < 1.0 => format!(r#" fill-opacity="{}""#, a),
+            _ => String::new()
+        };
+        if let Some(bg) = shading.background {
+            let r = self.view_box;
+            let element = format!(r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"{}{}/>"#,
+                r.min_x(), r.min_y(), r.width(), r.height(), color(Fill::Solid(bg.0, bg.1, bg.2)), opacity, self.blend_style());
+            self.push(&element, clip);
+        }
+        let id = self.gradients;
+        let gradient = match shading.kind {
+            ShadingKind::Axial { start, end } => format!(
+                r#"<linearGradient id="g{}" gradientUnits="userSpaceOnUse" gradientTransform="{}" x1="{}" y1="{}" x2="{}" y2="{}">"#,
+                id, matrix(transform), start.x(), start.y(), end.x(), end.y()
+            ),
+            ShadingKind::Radial { start, r0, end, r1 } => format!(
+                r#"<radialGradient id="g{}" gradientUnits="userSpaceOnUse" gradientTransform="{}" fx="{}" fy="{}" fr="{}" cx="{}" cy="{}" r="{}">"#,
+                id, matrix(transform), start.x(), start.y(), r0, end.x(), end.y(), r1
+            ),
+            ShadingKind::Function { domain, matrix: m, size: (w, h), ref colors } => {
+                let pixels: Vec<ColorU> = colors.iter().map(|&(r, g, b)| ColorF::new(r, g, b, 1.0).to_u8()).collect();
+                let image = Image::new(Vector2I::new(w as i32, h as i32), Arc::new(pixels));
+                // the colors are not flipped, undo the flip of push_image
+                let tr = transform * m * Transform2F::from_translation(domain.origin())
+                    * Transform2F::from_scale(domain.size())
+                    * Transform2F::from_translation(Vector2F::new(0.0, 1.0)) * Transform2F::from_scale(Vector2F::new(1.0, -1.0));
+                self.push_image(&image, tr, alpha, clip);
+                return;
+            }
+            ShadingKind::Mesh { ref triangles } => {
+                // flat shaded, with the average color of each triangle
+                for &[(a, (r0, g0, b0)), (b, (r1, g1, b1)), (c, (r2, g2, b2))] in triangles {
+                    let fill = Fill::Solid((r0 + r1 + r2) / 3., (g0 + g1 + g2) / 3., (b0 + b1 + b2) / 3.);
+                    let mut outline = Outline::new();
+                    outline.push_contour(triangle(a, b, c));
+                    if let Some(r) = shading.bbox {
+                        outline.clip_against_polygon(&[r.origin(), r.upper_right(), r.lower_right(), r.lower_left()]);
+                    }
+                    let element = format!(r#"<path transform="{}" d="{}" fill="{}"{}{}/>"#,
+                        matrix(transform), path_data(&outline), color(fill), opacity, self.blend_style());
+                    self.push(&element, clip);
+                }
+                return;
+            }
+        };
+        self.gradients += 1;
+        let mut element = gradient;
+        for &(offset, (r, g, b)) in &shading.stops {
+            write!(element, r#"<stop offset="{}" stop-color="{}"/>"#, offset, color(Fill::Solid(r, g, b))).unwrap();
+        }
+        element.push_str(match shading.kind {
+            ShadingKind::Axial { .. } => "</linearGradient>",
+            _ => "</radialGradient>",
+        });
+        write!(element, r#"<path d="{}" fill="url(#g{})"{}{}/>"#, region, id, opacity, self.blend_style()).unwrap();
+        self.push(&element, clip);
+    }
+    fn begin_smask(&mut self, _mask: &SoftMask) {
+        self.mask += 1;
+    }
+    fn end_smask(&mut self, _parent: Option<SvgClipPathId>) -> Option<SvgClipPathId> {
+        // no clip, so the masked content is drawn unmasked
+        self.mask = self.mask.saturating_sub(1);
+        None
+    }
+}
+
+fn color(fill: Fill) -> String {
+    match fill {
+        Fill::Solid(r, g, b) => {
+            let c = ColorF::new(r, g, b, 1.0).to_u8();
+            format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+        }
+        Fill::Pattern(_) => "black".into()
+    }
+}
+
+fn rule(fill_rule: FillRule) -> &'static str {
+    match fill_rule {
+        FillRule::Winding => "nonzero",
+        FillRule::EvenOdd => "evenodd",
+    }
+}
+
+fn matrix(t: Transform2F) -> String {
+    let v = t.translation();
+    format!("matrix({} {} {} {} {} {})", t.m11(), t.m21(), t.m12(), t.m22(), v.x(), v.y())
+}
+
+fn stroke_attrs(stroke: &Stroke) -> String {
+    let style = stroke.style;
+    let mut s = format!(r#" stroke-width="{}""#, style.line_width);
+    match style.line_cap {
+        LineCap::Butt => {}
+        LineCap::Square => s.push_str(r#" stroke-linecap="square""#),
+        LineCap::Round => s.push_str(r#" stroke-linecap="round""#),
+    }
+    match style.line_join {
+        LineJoin::Miter(limit) => write!(s, r#" stroke-miterlimit="{}""#, limit).unwrap(),
+        LineJoin::Bevel => s.push_str(r#" stroke-linejoin="bevel""#),
+        LineJoin::Round => s.push_str(r#" stroke-linejoin="round""#),
+    }
+    if let Some((ref pattern, phase)) = stroke.dash_pattern {
+        let dashes: Vec<String> = pattern.iter().map(|d| d.to_string()).collect();
+        write!(s, r#" stroke-dasharray="{}" stroke-dashoffset="{}""#, dashes.join(" "), phase).unwrap();
+    }
+    s
+}
+
+fn path_data(outline: &Outline) -> String {
+    let mut d = String::new();
+    for contour in outline.contours() {
+        for (i, segment) in contour.iter(ContourIterFlags::IGNORE_CLOSE_SEGMENT).enumerate() {
+            let (from, to) = (segment.baseline.from(), segment.baseline.to());
+            if i == 0 {
+                write!(d, "M{} {}", from.x(), from.y()).unwrap();
+            }
+            if segment.is_cubic() {
+                let (c1, c2) = (segment.ctrl.from(), segment.ctrl.to());
+                write!(d, "C{} {} {} {} {} {}", c1.x(), c1.y(), c2.x(), c2.y(), to.x(), to.y()).unwrap();
+            } else if segment.is_quadratic() {
+                let c = segment.ctrl.from();
+                write!(d, "Q{} {} {} {}", c.x(), c.y(), to.x(), to.y()).unwrap();
+            } else {
+                write!(d, "L{} {}", to.x(), to.y()).unwrap();
+            }
+        }
+        if contour.is_closed() {
+            d.push('Z');
+        }
+    }
+    d
+}
+
+fn png_base64(image: &Image) -> Result<String, image::ImageError> {
+    let size = image.size();
+    let data: Vec<u8> = image.pixels().iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect();
+    let buffer = image::RgbaImage::from_raw(size.x() as u32, size.y() as u32, data).expect("pixel count");
+    let mut png = Cursor::new(Vec::new());
+    buffer.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(png.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_page;
+    use crate::test_util::{build_pdf, load};
+
+    #[test]
+    fn soft_masked_content_is_drawn_unmasked() {
+        let file = load(build_pdf("", "/MediaBox [0 0 10 10] /Resources << /ExtGState << /GS 5 0 R >> >>", b"/GS gs 0 0 1 rg 2 2 6 6 re f", &[
+            "<< /Type /ExtGState /SMask << /Type /Mask /S /Luminosity /G 6 0 R >> >>",
+            "<< /Type /XObject /Subtype /Form /BBox [0 0 10 10] /Group << /S /Transparency /CS /DeviceGray >> /Length 23 >>\nstream\n1 0 0 rg 0 0 10 10 re f\nendstream",
+        ]));
+        let page = file.get_page(0).unwrap();
+        let mut cache = Cache::new();
+        let mut backend = SvgBackend::new(&mut cache);
+        backend.set_background(None);
+        render_page(&mut backend, &file.resolver(), &page, Transform2F::default()).unwrap();
+        let svg = backend.finish();
+
+        assert!(svg.contains("#0000ff"), "{}", svg);
+        // neither the mask group nor a background
+        assert!(!svg.contains("#ff0000"), "{}", svg);
+        assert!(!svg.contains("<rect"), "{}", svg);
+    }
+}
@@ -0,0 +1,118 @@
+//! Small PDF files built in memory, for the tests.
+use pdf::file::{CachedFile, FileOptions};
+
+/// A file with a single page of `width` × `height` points that draws `content`.
+pub fn page_pdf(width: f32, height: f32, content: &[u8]) -> Vec<u8> {
+    build_pdf("", &format!("/MediaBox [0 0 {} {}]", width, height), content, &[])
+}
+
+/// A file with the catalog `1 0 R` (with the extra entries `catalog`), the page tree `2 0 R`,
+/// a single page `3 0 R` (with the extra entries `page`) and its content stream `4 0 R`.
+///
+/// `objects` are numbered from 5 on.
+pub fn build_pdf(catalog: &str, page: &str, content: &[u8], objects: &[&str]) -> Vec<u8> {
+    let mut bodies: Vec<Vec<u8>> = vec![
+        format!("<< /Type /Catalog /Pages 2 0 R {} >>", catalog).into_bytes(),
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+        format!("<< /Type /Page /Parent 2 0 R /Contents 4 0 R {} >>", page).into_bytes(),
+    ];
+    let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+    stream.extend_from_slice(content);
+    stream.extend_from_slice(b"\nendstream");
+    bodies.push(stream);
+    bodies.extend(objects.iter().map(|o| o.as_bytes().to_vec()));
+
+    let mut data = b"%PDF-1.7\n".to_vec();
+    let mut offsets = Vec::with_capacity(bodies.len());
+    for (i, body) in bodies.iter().enumerate() {
+        offsets.push(data.len());
+        data.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        data.extend_from_slice(body);
+        data.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = data.len();
+    data.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", bodies.len() + 1).as_bytes());
+    for offset in offsets {
+        data.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    data.extend_from_slice(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", bodies.len() + 1, xref).as_bytes());
+    data
+}
+
+pub fn load(data: Vec<u8>) -> CachedFile<Vec<u8>> {
+    FileOptions::cached().load(data).expect("test file doesn't load")
+}
+
+/// A TrueType font with 1000 units per em and a single glyph for 'A': the rectangle 100,0 – 500,700, advancing by 600.
+pub fn square_font() -> Vec<u8> {
+    fn be(out: &mut Vec<u8>, values: &[i32], size: usize) {
+        for &v in values {
+            out.extend_from_slice(&v.to_be_bytes()[4 - size ..]);
+        }
+    }
+    let mut glyf = vec![];
+    // contours, bbox, end point, no instructions, four on-curve points with 16 bit deltas
+    be(&mut glyf, &[1, 100, 0, 500, 700, 3, 0], 2);
+    glyf.extend_from_slice(&[1; 4]);
+    be(&mut glyf, &[100, 400, 0, -400, 0, 0, 700, 0], 2);
+
+    let mut head = vec![];
+    be(&mut head, &[0x10000, 0x10000, 0, 0x5F0F3CF5], 4);
+    be(&mut head, &[0, 1000], 2);
+    head.extend_from_slice(&[0; 16]);
+    be(&mut head, &[0, 0, 600, 700, 0, 8, 2, 0, 0], 2);
+
+    let mut hhea = vec![];
+    be(&mut hhea, &[0x10000], 4);
+    be(&mut hhea, &[800, -200, 0, 600, 0, 100, 500, 1, 0, 0, 0, 0, 0, 0, 0, 2], 2);
+
+    let mut maxp = vec![];
+    be(&mut maxp, &[0x10000], 4);
+    be(&mut maxp, &[2, 4, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0], 2);
+
+    let mut hmtx = vec![];
+    be(&mut hmtx, &[600, 0, 600, 100], 2);
+
+    // format 4 with the segments 'A' ..= 'A' (to glyph 1) and the final 0xFFFF
+    let mut cmap = vec![];
+    be(&mut cmap, &[0, 1, 3, 1], 2);
+    be(&mut cmap, &[12], 4);
+    be(&mut cmap, &[4, 32, 0, 4, 4, 1, 0, 0x41, 0xFFFF, 0, 0x41, 0xFFFF, 1 - 0x41, 1, 0, 0], 2);
+
+    let mut loca = vec![];
+    be(&mut loca, &[0, 0, glyf.len() as i32 / 2], 2);
+
+    let mut post = vec![];
+    be(&mut post, &[0x30000, 0, 0, 0, 0, 0, 0, 0], 4);
+
+    let tables: [(&[u8; 4], Vec<u8>); 8] = [
+        (b"cmap", cmap), (b"glyf", glyf), (b"head", head), (b"hhea", hhea),
+        (b"hmtx", hmtx), (b"loca", loca), (b"maxp", maxp), (b"post", post),
+    ];
+    let mut font = vec![];
+    be(&mut font, &[0x10000], 4);
+    be(&mut font, &[tables.len() as i32, 128, 3, 0], 2);
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, data) in tables.iter() {
+        let checksum = data.chunks(4).fold(0u32, |sum, c| {
+            let mut word = [0; 4];
+            word[.. c.len()].copy_from_slice(c);
+            sum.wrapping_add(u32::from_be_bytes(word))
+        });
+        font.extend_from_slice(&tag[..]);
+        font.extend_from_slice(&checksum.to_be_bytes());
+        be(&mut font, &[offset as i32, data.len() as i32], 4);
+        offset += (data.len() + 3) & !3;
+    }
+    for (_, data) in tables.iter() {
+        font.extend_from_slice(data);
+        font.resize((font.len() + 3) & !3, 0);
+    }
+    font
+}
+
+/// `data` as the body of an ASCIIHexDecode stream, so binary data fits into the objects of `build_pdf`
+pub fn hex_stream(data: &[u8]) -> String {
+    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("<< /Length {} /Filter /ASCIIHexDecode >>\nstream\n{}>\nendstream", hex.len() + 1, hex)
+}
@@ -0,0 +1,296 @@
+//! Text with its position on the page, for search and copy.
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+use pdf::object::{Page, PlainRef, Ref, XObject, ImageXObject, Resolve, Resources, MaybeRef};
+use pdf::font::Font as PdfFont;
+use pdf::error::PdfError;
+use pathfinder_geometry::{transform2d::Transform2F, vector::Vector2F, rect::RectF};
+use pathfinder_content::{fill::FillRule, outline::Outline};
+
+use crate::{render_page, Backend, BlendMode, DrawMode, FontEntry, StructTree, TextGlyph, TextSpan};
+use crate::tracer::{Tracer, TraceCache, DrawItem};
+
+#[derive(Debug, Clone)]
+pub struct TextItem {
+    pub text: String,
+    /// baseline start, baseline end, top end, top start, in device space
+    pub quad: [Vector2F; 4],
+    pub font_name: Option<String>,
+    /// the height of the em box in device space
+    pub font_size: f32,
+    /// width of a space in device space, from the font if it has one
+    pub space_width: f32,
+    pub chars: Vec<CharPosition>,
+    /// see `TextSpan::mcid`
+    pub mcid: Option<i32>,
+}
+
+/// Where a character of a `TextItem` was drawn
+#[derive(Debug, Clone)]
+pub struct CharPosition {
+    /// bytes of `TextItem::text`
+    pub range: Range<usize>,
+    /// the baseline of the glyph, in device space
+    pub start: Vector2F,
+    pub end: Vector2F,
+}
+
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub text: String,
+    /// in device space
+    pub rect: RectF,
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// in visual order along the baseline
+    pub words: Vec<Word>,
+    pub rect: RectF,
+    /// the baseline is not horizontal
+    pub rotated: bool,
+    /// mostly right-to-left script. The words are still in visual order.
+    pub rtl: bool,
+}
+impl Line {
+    pub fn text(&self) -> String {
+        self.words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// The text drawn by each text showing operator of the page, in content stream order.
+///
+/// `transform` is the same as for `render_page`. Coordinates ("device space") are those of the rendered page:
+/// turned upright by /Rotate, relative to the top left corner of the crop box, in millimeters with y pointing down,
+/// then transformed by `transform`. Use `page_transform(page).inverse()` as `transform` for the unrotated user space
+/// of the page instead, in points with y pointing up, as the content stream and annotations use it.
+pub fn extract_text(page: &Page, resolve: &impl Resolve, cache: &TraceCache, transform: Transform2F) -> Result<Vec<TextItem>, PdfError> {
+    let mut clip_paths = vec![];
+    let mut tracer = Tracer::new(cache, &mut clip_paths);
+    render_page(&mut tracer, resolve, page, transform)?;
+
+    Ok(tracer.finish().into_iter().filter_map(|item| match item {
+        DrawItem::Text(span, _) if !span.text.is_empty() => {
+            let font_size = (span.quad[3] - span.quad[0]).length();
+            let space = span.font.as_ref()
+                .filter(|f| !f.is_cid)
+                .and_then(|f| f.width(0x20))
+                .map(|w| w * 0.001)
+                .filter(|&w| w > 0.0)
+                .unwrap_or(0.25);
+            let (q0, q1) = (span.quad[0], span.quad[1]);
+            let at = |pos: f32| match span.width {
+                w if w != 0.0 => q0 + (q1 - q0) * (pos / w),
+                _ => q0
+            };
+            let chars = span.parts().map(|part| CharPosition {
+                range: part.offset .. part.offset + part.text.len(),
+                start: at(part.pos),
+                end: at(part.pos + part.width),
+            }).collect();
+            Some(TextItem {
+                font_name: span.font.as_ref().map(|f| f.name.clone()),
+                font_size,
+                space_width: space * font_size,
+                chars,
+                quad: span.quad,
+                mcid: span.mcid,
+                text: span.text,
+            })
+        }
+        _ => None
+    }).collect())
+}
+
+/// Text of a structure element, or a line of untagged text
+#[derive(Debug, Clone)]
+pub struct TaggedText {
+    /// the standard structure type, like P, H1 or TD. `None` for text that is not part of the structure tree.
+    pub kind: Option<String>,
+    /// the /ActualText of the element, or its text in lines
+    pub text: String,
+    /// what was drawn for the element, empty for untagged lines
+    pub items: Vec<TextItem>,
+}
+
+/// The text of the page grouped by the elements of the structure tree that contain it, in logical order.
+///
+/// `page_ref` is the reference of the page, which the structure tree uses to identify it.
+/// If the page has no tagged content, this is the text of `extract_lines`.
+/// Text that is not linked to an element follows at the end, in lines.
+pub fn extract_tagged_text(page: &Page, page_ref: PlainRef, tree: Option<&StructTree>, resolve: &impl Resolve, cache: &TraceCache, transform: Transform2F) -> Result<Vec<TaggedText>, PdfError> {
+    let items = extract_text(page, resolve, cache, transform)?;
+    let elements = tree.map(|t| t.page_elements(page_ref)).unwrap_or_default();
+
+    let mut by_mcid: HashMap<i32, Vec<TextItem>> = HashMap::new();
+    let mut untagged = vec![];
+    for item in items {
+        match item.mcid {
+            Some(mcid) if !elements.is_empty() => by_mcid.entry(mcid).or_default().push(item),
+            _ => untagged.push(item),
+        }
+    }
+    let lines_text = |items: &[TextItem]| group_lines(items).iter().map(Line::text).collect::<Vec<_>>().join("\n");
+
+    let mut out = vec![];
+    for (element, mcids) in elements {
+        let items: Vec<TextItem> = mcids.iter().flat_map(|mcid| by_mcid.remove(mcid).unwrap_or_default()).collect();
+        if items.is_empty() && element.actual_text.is_none() {
+            continue;
+        }
+        let text = element.actual_text.clone().unwrap_or_else(|| lines_text(&items));
+        out.push(TaggedText { kind: Some(element.kind.clone()), text, items });
+    }
+    // MCIDs without an element on this page
+    let mut rest: Vec<i32> = by_mcid.keys().cloned().collect();
+    rest.sort_unstable();
+    for mcid in rest {
+        untagged.extend(by_mcid.remove(&mcid).unwrap_or_default());
+    }
+    out.extend(group_lines(&untagged).into_iter().map(|line| TaggedText { kind: None, text: line.text(), items: vec![] }));
+    Ok(out)
+}
+
+/// The text of the page in lines and words. See `group_lines`.
+pub fn extract_lines(page: &Page, resolve: &impl Resolve, cache: &TraceCache, transform: Transform2F) -> Result<Vec<Line>, PdfError> {
+    Ok(group_lines(&extract_text(page, resolve, cache, transform)?))
+}
+
+/// Call `f` for every glyph the page shows, including invisible text, without drawing anything.
+///
+/// `transform` is the same as for `render_page`.
+pub fn for_each_glyph(page: &Page, resolve: &impl Resolve, cache: &TraceCache, transform: Transform2F, f: impl FnMut(&TextGlyph)) -> Result<(), PdfError> {
+    let mut backend = GlyphBackend { cache, f };
+    render_page(&mut backend, resolve, page, transform)?;
+    Ok(())
+}
+
+/// Ignores everything but the glyphs
+struct GlyphBackend<'a, F> {
+    cache: &'a TraceCache,
+    f: F,
+}
+impl<'a, F: FnMut(&TextGlyph)> Backend for GlyphBackend<'a, F> {
+    type ClipPathId = ();
+
+    fn create_clip_path(&mut self, _path: Outline, _fill_rule: FillRule, _parent: Option<()>) {}
+    fn draw(&mut self, _outline: &Outline, _mode: &DrawMode, _fill_rule: FillRule, _transform: Transform2F, _clip: Option<()>) {}
+    fn set_view_box(&mut self, _r: RectF) {}
+    fn draw_image(&mut self, _xref: Ref<XObject>, _im: &ImageXObject, _resources: &Resources, _transform: Transform2F, _mode: BlendMode, _alpha: f32, _clip: Option<()>, _resolve: &impl Resolve) -> Result<(), PdfError> { Ok(()) }
+    fn draw_inline_image(&mut self, _im: &Arc<ImageXObject>, _resources: &Resources, _transform: Transform2F, _mode: BlendMode, _alpha: f32, _clip: Option<()>, _resolve: &impl Resolve) -> Result<(), PdfError> { Ok(()) }
+    fn draw_glyph(&mut self, _glyph: &font::Glyph, _code: u16, _mode: &DrawMode, _transform: Transform2F, _clip: Option<()>) {}
+    fn text_glyph(&mut self, glyph: &TextGlyph) {
+        (self.f)(glyph);
+    }
+    fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
+        self.cache.get_font(font_ref, resolve)
+    }
+    fn add_text(&mut self, _span: TextSpan, _clip: Option<()>) {}
+}
+
+struct Glyph<'a> {
+    text: &'a str,
+    /// along the baseline direction
+    u0: f32,
+    u1: f32,
+    /// across it
+    v: f32,
+    size: f32,
+    space: f32,
+    bounds: RectF,
+}
+
+/// Group characters into lines by their baseline, and lines into words by the gaps between characters.
+///
+/// Lines with the same direction are ordered top to bottom, horizontal ones first.
+pub fn group_lines(items: &[TextItem]) -> Vec<Line> {
+    // by the angle of the baseline in degrees
+    let mut directions: BTreeMap<i32, Vec<Glyph>> = BTreeMap::new();
+    for item in items {
+        let up = item.quad[3] - item.quad[0];
+        if up.length() == 0.0 {
+            continue;
+        }
+        let up_n = up.normalize();
+        let dir = Vector2F::new(-up_n.y(), up_n.x());
+        let across = Vector2F::new(-dir.y(), dir.x());
+        let angle = (dir.y().atan2(dir.x()).to_degrees().round() as i32).rem_euclid(360);
+
+        let glyphs = directions.entry(angle).or_default();
+        for c in &item.chars {
+            let corners = [c.start, c.end, c.end + up, c.start + up];
+            let min = corners.iter().fold(corners[0], |a, &b| a.min(b));
+            let max = corners.iter().fold(corners[0], |a, &b| a.max(b));
+            let (u0, u1) = (c.start.dot(dir), c.end.dot(dir));
+            glyphs.push(Glyph {
+                text: &item.text[c.range.clone()],
+                u0: u0.min(u1),
+                u1: u0.max(u1),
+                v: c.start.dot(across),
+                size: item.font_size,
+                space: item.space_width,
+                bounds: RectF::from_points(min, max),
+            });
+        }
+    }
+
+    let mut lines = vec![];
+    for (angle, mut glyphs) in directions {
+        glyphs.sort_by(|a, b| a.v.total_cmp(&b.v));
+
+        // cluster baselines that are less than half a line apart
+        let mut clusters: Vec<Vec<Glyph>> = vec![];
+        for g in glyphs {
+            match clusters.last_mut() {
+                Some(line) if (g.v - line[0].v).abs() < 0.5 * g.size.min(line[0].size) => line.push(g),
+                _ => clusters.push(vec![g]),
+            }
+        }
+
+        for mut line in clusters {
+            line.sort_by(|a, b| a.u0.total_cmp(&b.u0));
+            let mut words: Vec<Word> = vec![];
+            let mut prev: Option<&Glyph> = None;
+            for g in &line {
+                if g.text.chars().all(char::is_whitespace) {
+                    prev = None;
+                    continue;
+                }
+                let gap = prev.map(|p| g.u0 - p.u1);
+                match (gap, words.last_mut()) {
+                    (Some(gap), Some(word)) if gap < 0.5 * g.space => {
+                        word.text.push_str(g.text);
+                        word.rect = word.rect.union_rect(g.bounds);
+                    }
+                    _ => words.push(Word { text: g.text.into(), rect: g.bounds }),
+                }
+                prev = Some(g);
+            }
+            if words.is_empty() {
+                continue;
+            }
+
+            let (mut rtl_chars, mut ltr_chars) = (0, 0);
+            for c in words.iter().flat_map(|w| w.text.chars()).filter(|c| c.is_alphabetic()) {
+                match is_rtl(c) {
+                    true => rtl_chars += 1,
+                    false => ltr_chars += 1,
+                }
+            }
+            let rect = words.iter().skip(1).fold(words[0].rect, |r, w| r.union_rect(w.rect));
+            lines.push(Line {
+                words,
+                rect,
+                rotated: angle != 0,
+                rtl: rtl_chars > ltr_chars,
+            });
+        }
+    }
+    lines
+}
+
+/// Hebrew, Arabic, Syriac, Thaana, NKo and their presentation forms
+fn is_rtl(c: char) -> bool {
+    matches!(c as u32, 0x0590 ..= 0x08FF | 0xFB1D ..= 0xFDFF | 0xFE70 ..= 0xFEFF)
+}
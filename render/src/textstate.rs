@@ -2,8 +2,9 @@ use pathfinder_geometry::{
     vector::Vector2F,
     transform2d::Transform2F,
 };
-use font::GlyphId;
-use crate::{BlendMode, backend::{FillMode, Stroke}};
+use pathfinder_content::outline::Outline;
+use font::{Glyph, GlyphId};
+use crate::{BlendMode, RenderConfig, backend::{FillMode, Stroke}};
 
 use super::{
     BBox,
@@ -12,13 +13,16 @@ use super::{
     DrawMode,
     Backend,
     TextChar,
+    TextGlyph,
+    hinting::hint_outline,
 };
-use std::convert::TryInto;
 use pdf::content::TextMode;
 use std::sync::Arc;
-use itertools::Either;
 use istring::SmallString;
 
+/// stroke width of faux bold, in em
+const FAUX_BOLD_WIDTH: f32 = 0.03;
+
 #[derive(Clone)]
 pub struct TextState {
     pub text_matrix: Transform2F, // tracks current glyph
@@ -31,7 +35,7 @@ pub struct TextState {
     pub font_size: f32, // Text font size
     pub mode: TextMode, // Text rendering mode
     pub rise: f32, // Text rise
-    pub knockout: f32, //Text knockout
+    pub knockout: bool, // Text knockout, /TK of the ExtGState
 }
 impl TextState {
     pub fn new() -> TextState {
@@ -46,7 +50,7 @@ impl TextState {
             font_size: 0.,
             mode: TextMode::Fill,
             rise: 0.,
-            knockout: 0.
+            knockout: true
         }
     }
     pub fn reset_matrix(&mut self) {
@@ -66,7 +70,13 @@ impl TextState {
         self.text_matrix = m;
         self.line_matrix = m;
     }
-    pub fn draw_text<B: Backend>(&mut self, backend: &mut B, gs: &GraphicsState<B>, data: &[u8], span: &mut Span, fill_mode: BlendMode, stroke_mode: BlendMode) {
+    /// With `RenderConfig::hinting`, glyph outlines are fitted to the device pixel grid.
+    /// Hairlines of stroked text are widened like those of paths.
+    ///
+    /// `hidden` text (in hidden optional content) is laid out and clips, but nothing is drawn or reported to the backend.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_text<B: Backend>(&mut self, backend: &mut B, gs: &GraphicsState<B>, data: &[u8], span: &mut Span, fill_mode: BlendMode, stroke_mode: BlendMode, config: Option<&RenderConfig>, hidden: bool) {
+        let hinting = config.map_or(false, |c| c.hinting);
         let e = match self.font_entry {
             Some(ref e) => e,
             None => {
@@ -75,19 +85,18 @@ impl TextState {
             }
         };
 
-        let codepoints = if e.is_cid {
-            Either::Left(data.chunks_exact(2).map(|s| u16::from_be_bytes(s.try_into().unwrap())))
-        } else {
-            Either::Right(data.iter().map(|&b| b as u16))
-        };
-
-        let glyphs = codepoints.map(|cid|
-            (cid, e.cmap.get(&cid).map(|&(gid, ref uni)| (gid, uni.clone())))
+        let glyphs = e.codes_and_spaces(data).map(|(cid, is_space)|
+            (cid, is_space, e.cmap.get(&cid).map(|&(gid, ref uni)| (gid, uni.clone())))
         );
 
         let fill = FillMode { color: gs.fill_color, alpha: gs.fill_color_alpha, mode: fill_mode };
         let stroke = FillMode { color: gs.stroke_color, alpha: gs.stroke_color_alpha, mode: stroke_mode };
-        let stroke_mode = gs.stroke();
+        // glyphs are stroked in glyph space
+        let glyph_transform = gs.transform * self.text_matrix * Transform2F::row_major(
+            self.horiz_scale * self.font_size, 0., 0.,
+            0., self.font_size, self.rise
+        ) * e.font_matrix;
+        let stroke_mode = gs.stroke().widened(glyph_transform, config);
 
         let draw_mode = match self.mode {
             TextMode::Fill => Some(DrawMode::Fill { fill }),
@@ -98,54 +107,99 @@ impl TextState {
             TextMode::StrokeAndClip => Some(DrawMode::Stroke { stroke, stroke_mode }),
         };
         let e = self.font_entry.as_ref().expect("no font");
+        // faux bold: also stroke filled glyphs, with a width in glyph space
+        let draw_mode = match draw_mode {
+            Some(DrawMode::Fill { fill }) if e.faux_bold => {
+                let mut stroke_mode = gs.stroke();
+                stroke_mode.dash_pattern = None;
+                stroke_mode.style.line_width = FAUX_BOLD_WIDTH / e.font_matrix.m11().abs().max(1e-6);
+                Some(DrawMode::FillStroke { stroke: fill.clone(), fill, stroke_mode })
+            }
+            m => m
+        };
+        let (fill_marks, stroke_marks) = gs.marks();
+        let draw_mode = draw_mode.and_then(|m| m.painted(fill_marks, stroke_marks)).filter(|_| !hidden);
+        if e.font.is_none() {
+            return;
+        }
 
+        // negative font sizes and Tz mirror the glyphs, like a negative text matrix does. The glyphs are filled
+        // with the nonzero rule, which doesn't depend on the direction of the contours.
         let tr = Transform2F::row_major(
             self.horiz_scale * self.font_size, 0., 0.,
             0., self.font_size, self.rise
-        ) * e.font.font_matrix();
+        ) * e.font_matrix;
         
-        for (cid, t) in glyphs {
-            let (gid, unicode, is_space) = match t {
-                Some((gid, unicode)) => {
-                    let is_space = !e.is_cid && unicode.as_deref() == Some(" ");
-                    (gid, unicode, is_space)
-                }
-                None => (GlyphId(0), None, cid == 0x20)
+        // word spacing only applies to the single byte code 32, whatever glyph it maps to
+        for (cid, is_space, t) in glyphs {
+            let (gid, unicode) = match t {
+                Some((gid, unicode)) => (gid, unicode),
+                None => (GlyphId(0), None)
             };
             //debug!("cid {} -> gid {:?} {:?}", cid, gid, unicode);
             
-            let glyph = e.font.glyph(gid);
-            let width: f32 = e.widths.as_ref().map(|w| w.get(cid as usize) * 0.001 * self.horiz_scale * self.font_size)
+            let glyph = e.glyph(gid);
+            let width: f32 = e.width(cid).map(|w| w * 0.001 * self.horiz_scale * self.font_size)
                 .or_else(|| glyph.as_ref().map(|g| tr.m11() * g.metrics.advance))
                 .unwrap_or(0.0);
             
-            if is_space {
-                let advance = (self.char_space + self.word_space) * self.horiz_scale + width;
-                self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
-
-                let offset = span.text.len();
-                span.text.push(' ');
-                span.chars.push(TextChar {
-                    offset,
-                    pos: span.width,
-                    width
-                });
-                span.width += advance;
-                continue;
-            }
+            // vertical mode: (displacement of the glyph origin, advance along y)
+            let vertical = e.vertical.as_ref().map(|vm| {
+                let w0 = e.width(cid).unwrap_or(1000.);
+                let (w1y, v) = vm.get(cid, w0);
+                let mut ty = w1y * 0.001 * self.font_size + self.char_space;
+                if is_space {
+                    ty += self.word_space;
+                }
+                (v * (-0.001 * self.font_size), ty)
+            });
+            let step = |advance: f32| match vertical {
+                Some((_, ty)) => (Vector2F::new(0., ty), ty.abs()),
+                None => (Vector2F::new(advance, 0.), advance)
+            };
+            
             if let Some(glyph) = glyph {
-                let transform = gs.transform * self.text_matrix * tr;
+                let origin = vertical.map(|(v, _)| v).unwrap_or_default();
+                let transform = gs.transform * self.text_matrix * Transform2F::from_translation(origin) * tr;
                 if glyph.path.len() != 0 {
-                    span.bbox.add(gs.transform * transform * glyph.path.bounds());
+                    span.bbox.add(transform * glyph.path.bounds());
+                    if self.mode.is_clip() {
+                        let clip = span.clip.get_or_insert_with(Outline::new);
+                        for contour in glyph.path.clone().transformed(&transform).into_contours() {
+                            clip.push_contour(contour);
+                        }
+                    }
                     if let Some(ref draw_mode) = draw_mode {
-                        backend.draw_glyph(&glyph, draw_mode, transform, gs.clip_path_id);
+                        match hinting.then(|| hint_outline(&glyph.path, transform)).flatten() {
+                            Some(path) => backend.draw_glyph(&Glyph { path, .. (*glyph).clone() }, cid, draw_mode, transform, gs.clip_path_id),
+                            None => backend.draw_glyph(&glyph, cid, draw_mode, transform, gs.clip_path_id),
+                        }
                     }
                 }
             } else {
                 debug!("no glyph for gid {:?}", gid);
             }
-            let advance = self.char_space * self.horiz_scale + width;
-            self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
+            // Tc and Tw are in unscaled text space units, so Tz applies to them as well
+            let spacing = match is_space {
+                true => self.char_space + self.word_space,
+                false => self.char_space
+            };
+            let (step, advance) = step(spacing * self.horiz_scale + width);
+            let unicode = match unicode {
+                None if is_space => Some(" ".into()),
+                u => u
+            };
+
+            let origin = vertical.map(|(v, _)| v).unwrap_or_default();
+            let quad = [
+                Vector2F::new(0., self.rise), Vector2F::new(width, self.rise),
+                Vector2F::new(width, self.rise + self.font_size), Vector2F::new(0., self.rise + self.font_size)
+            ].map(|p| gs.transform * self.text_matrix * (origin + p));
+            if !hidden {
+                backend.text_glyph(&TextGlyph { code: cid, unicode: unicode.as_deref(), quad, advance });
+            }
+
+            self.text_matrix = self.text_matrix * Transform2F::from_translation(step);
             
             let offset = span.text.len();
             if let Some(s) = unicode {
@@ -159,8 +213,18 @@ impl TextState {
             span.width += advance;
         }
     }
+    /// A number in a TJ array, in thousandths of text space units.
+    /// It is subtracted from the position, so positive values move the next glyph left (or down in vertical mode).
+    pub fn adjust(&mut self, amount: f32) -> f32 {
+        self.advance(-0.001 * amount)
+    }
     pub fn advance(&mut self, delta: f32) -> f32 {
         //debug!("advance by {}", delta);
+        if self.font_entry.as_ref().map_or(false, |e| e.is_vertical()) {
+            let advance = delta * self.font_size;
+            self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(0., advance));
+            return -advance;
+        }
         let advance = delta * self.font_size * self.horiz_scale;
         self.text_matrix = self.text_matrix * Transform2F::from_translation(Vector2F::new(advance, 0.));
         advance
@@ -173,4 +237,67 @@ pub struct Span {
     pub chars: Vec<TextChar>,
     pub width: f32,
     pub bbox: BBox,
-}
\ No newline at end of file
+    /// glyph outlines in device space, for the clipping text modes
+    pub clip: Option<Outline>,
+}
+
+pub trait TextModeExt {
+    /// adds the glyphs to the clip path at ET
+    fn is_clip(&self) -> bool;
+}
+impl TextModeExt for TextMode {
+    fn is_clip(&self) -> bool {
+        matches!(self, TextMode::FillAndClip | TextMode::StrokeAndClip)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use pathfinder_geometry::rect::RectF;
+    use crate::render_page;
+    use crate::tracer::{Tracer, TraceCache, DrawItem};
+    use crate::test_util::{build_pdf, square_font, hex_stream, load};
+    use super::*;
+
+    fn assert_rect(rect: RectF, min: (f32, f32), max: (f32, f32)) {
+        let close = |a: Vector2F, (x, y): (f32, f32)| (a.x() - x).abs() < 1e-3 && (a.y() - y).abs() < 1e-3;
+        assert!(close(rect.origin(), min) && close(rect.lower_right(), max), "{:?} != {:?} {:?}", rect, min, max);
+    }
+
+    #[test]
+    fn mirrored_text() {
+        // the negative size mirrors the glyphs in x and y, the text matrix mirrors them back in x
+        let font = hex_stream(&square_font());
+        let file = load(build_pdf("", "/MediaBox [0 0 100 100] /Resources << /Font << /F 5 0 R >> >>",
+            b"BT /F -10 Tf -1 0 0 1 50 20 Tm (AA) Tj ET", &[
+            "<< /Type /Font /Subtype /TrueType /BaseFont /Square /FirstChar 65 /LastChar 65 /Widths [600] \
+                /Encoding /WinAnsiEncoding /FontDescriptor 6 0 R >>",
+            "<< /Type /FontDescriptor /FontName /Square /Flags 32 /FontBBox [0 0 600 700] /ItalicAngle 0 \
+                /Ascent 700 /Descent 0 /CapHeight 700 /StemV 80 /FontFile2 7 0 R >>",
+            &font,
+        ]));
+        let page = file.get_page(0).unwrap();
+        let cache = TraceCache::new();
+        let mut clip_paths = vec![];
+        let mut tracer = Tracer::new(&cache, &mut clip_paths);
+        let root = render_page(&mut tracer, &file.resolver(), &page, Transform2F::default()).unwrap();
+        let to_user = root.inverse();
+
+        let items = tracer.finish();
+        let glyphs: Vec<RectF> = items.iter().filter_map(|item| match item {
+            DrawItem::Glyph(g) => Some(to_user * g.bounds),
+            _ => None
+        }).collect();
+        // the glyph 0.1 – 0.5 em by 0 – 0.7 em at -10 points, mirrored in x around 50, above the baseline at 20 - 7.
+        // the advance of -6 points runs right in user space.
+        std::assert_eq!(glyphs.len(), 2);
+        assert_rect(glyphs[0], (51., 13.), (55., 20.));
+        assert_rect(glyphs[1], (57., 13.), (61., 20.));
+
+        let span = items.iter().find_map(|item| match item {
+            DrawItem::Text(span, _) => Some(span),
+            _ => None
+        }).unwrap();
+        std::assert_eq!(span.text, "AA");
+        assert_rect(to_user * span.bbox.unwrap(), (51., 13.), (61., 20.));
+    }
+}
@@ -17,8 +17,7 @@ use pdf::font::Font as PdfFont;
 use pdf::error::PdfError;
 use std::sync::Arc;
 use std::path::PathBuf;
-use crate::font::{load_font, StandardCache};
-use globalcache::sync::SyncCache;
+use crate::font::{FontCache, FontDatabase, StandardCache};
 use crate::backend::Stroke;
 
 pub struct ClipPath {
@@ -30,51 +29,58 @@ pub struct ClipPath {
 #[derive(Copy, Clone, Debug)]
 pub struct ClipPathId(pub usize);
 
+/// The area `id` clips to in device space, the bounds of its path intersected with those of its parents.
+/// `None` if nothing is visible, or `id` isn't one of `clip_paths`, like the ids of clips ignored by the tracer.
+pub fn clip_bounds(clip_paths: &[ClipPath], id: ClipPathId) -> Option<RectF> {
+    let clip = clip_paths.get(id.0)?;
+    let mut bounds = clip.path.bounds();
+    let mut parent = clip.parent;
+    while let Some(p) = parent {
+        let clip = clip_paths.get(p.0)?;
+        bounds = bounds.intersection(clip.path.bounds())?;
+        parent = clip.parent;
+    }
+    Some(bounds)
+}
+
 pub struct Tracer<'a> {
     pub items: Vec<DrawItem>,
     clip_paths: &'a mut Vec<ClipPath>,
     pub view_box: RectF,
     cache: &'a TraceCache,
     op_nr: usize,
+    record_clips: bool,
+    ignore_clips: bool,
 }
 pub struct TraceCache {
-    fonts: Arc<SyncCache<u64, Option<Arc<FontEntry>>>>,
-    std: StandardCache,
-}
-fn font_key(font_ref: &MaybeRef<PdfFont>) -> u64 {
-    match font_ref {
-        MaybeRef::Direct(ref shared) => shared.as_ref() as *const PdfFont as _,
-        MaybeRef::Indirect(re) => re.get_ref().get_inner().id as _
-    }
+    fonts: FontCache,
 }
 impl TraceCache {
     pub fn new() -> Self {
         let standard_fonts = PathBuf::from(std::env::var_os("STANDARD_FONTS").expect("STANDARD_FONTS is not set. Please check https://github.com/pdf-rs/pdf_render/#fonts for instructions."));
 
         TraceCache {
-            fonts: SyncCache::new(),
-            std: StandardCache::new(standard_fonts),
+            fonts: FontCache::new(StandardCache::new(standard_fonts)),
         }
     }
-    pub fn get_font(&self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
-        let mut error = None;
-        let val = self.fonts.get(font_key(font_ref), |_| 
-            match load_font(font_ref, resolve, &self.std) {
-                Ok(Some(f)) => Some(Arc::new(f)),
-                Ok(None) => None,
-                Err(e) => {
-                    error = Some(e);
-                    None
-                }
-            }
-        );
-        match error {
-            None => Ok(val),
-            Some(e) => Err(e)
+    /// Load standard and substitute fonts from `db`, which can be shared with other caches.
+    pub fn with_font_database(db: Arc<FontDatabase>) -> Self {
+        TraceCache {
+            fonts: FontCache::new(StandardCache::with_database(db)),
         }
     }
+    pub fn get_font(&self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
+        self.fonts.get(font_ref, resolve)
+    }
+    pub fn clear_fonts(&self) {
+        self.fonts.clear();
+    }
     pub fn require_unique_unicode(&mut self, require_unique_unicode: bool) {
-        self.std.require_unique_unicode(require_unique_unicode);
+        self.fonts.require_unique_unicode(require_unique_unicode);
+    }
+    /// Embolden and slant substitutes for missing bold and italic fonts.
+    pub fn synthesize_styles(&mut self, s: bool) {
+        self.fonts.synthesize_styles(s);
     }
 }
 impl<'a> Tracer<'a> {
@@ -85,8 +91,21 @@ impl<'a> Tracer<'a> {
             cache,
             op_nr: 0,
             clip_paths,
+            record_clips: false,
+            ignore_clips: false,
         }
     }
+    /// Add a `DrawItem::Clip` to the items where each clip path is set.
+    pub fn record_clips(&mut self, record: bool) {
+        self.record_clips = record;
+    }
+    /// Don't record clip paths, all items have no clip. For extracting content that is clipped away, too.
+    pub fn ignore_clips(&mut self, ignore: bool) {
+        self.ignore_clips = ignore;
+    }
+    fn clip(&self, clip: Option<ClipPathId>) -> Option<ClipPathId> {
+        clip.filter(|_| !self.ignore_clips)
+    }
     pub fn finish(self) -> Vec<DrawItem> {
         self.items
     }
@@ -98,15 +117,26 @@ impl<'a> Backend for Tracer<'a> {
     type ClipPathId = ClipPathId;
 
     fn create_clip_path(&mut self, path: Outline, fill_rule: FillRule, parent: Option<ClipPathId>) -> ClipPathId {
+        if self.ignore_clips {
+            // never handed out with an item
+            return ClipPathId(usize::MAX);
+        }
         let id = ClipPathId(self.clip_paths.len());
         self.clip_paths.push(ClipPath {
             path,
             fill_rule,
             parent,
         });
+        if self.record_clips {
+            self.items.push(DrawItem::Clip(ClipItem {
+                id,
+                bounds: clip_bounds(self.clip_paths, id),
+                op_nr: self.op_nr,
+            }));
+        }
         id
     }
-    fn draw(&mut self, outline: &Outline, mode: &DrawMode, _fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
+    fn draw(&mut self, outline: &Outline, mode: &DrawMode, fill_rule: FillRule, transform: Transform2F, clip: Option<ClipPathId>) {
         let stroke = match mode {
             DrawMode::FillStroke { stroke, stroke_mode, .. } | DrawMode::Stroke { stroke, stroke_mode } => Some((stroke.clone(), stroke_mode.clone())),
             DrawMode::Fill { .. } => None,
@@ -118,37 +148,60 @@ impl<'a> Backend for Tracer<'a> {
                 _ => None
             },
             stroke,
+            fill_rule,
+            bounds: outline.clone().transformed(&transform).bounds(),
             transform,
-            clip,
+            clip: self.clip(clip),
             op_nr: self.op_nr,
         }));
     }
     fn set_view_box(&mut self, r: RectF) {
         self.view_box = r;
     }
-    fn draw_image(&mut self, xref: Ref<XObject>, _im: &ImageXObject, _resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) {
+    fn draw_image(&mut self, xref: Ref<XObject>, _im: &ImageXObject, _resources: &Resources, transform: Transform2F, mode: BlendMode, alpha: f32, clip: Option<ClipPathId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
+        let clip = self.clip(clip);
         let rect = transform * RectF::new(
             Vector2F::new(0.0, 0.0), Vector2F::new(1.0, 1.0)
         );
         self.items.push(DrawItem::Image(ImageObject {
-            rect, id: xref, transform, op_nr: self.op_nr, mode, clip
+            rect, id: xref, transform, op_nr: self.op_nr, mode, alpha, clip
         }));
+        Ok(())
     }
-    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, _resources: &Resources, transform: Transform2F, mode: BlendMode, clip: Option<ClipPathId>, _resolve: &impl Resolve) {
+    fn draw_inline_image(&mut self, im: &Arc<ImageXObject>, _resources: &Resources, transform: Transform2F, mode: BlendMode, alpha: f32, clip: Option<ClipPathId>, _resolve: &impl Resolve) -> Result<(), PdfError> {
+        let clip = self.clip(clip);
         let rect = transform * RectF::new(
             Vector2F::new(0.0, 0.0), Vector2F::new(1.0, 1.0)
         );
 
         self.items.push(DrawItem::InlineImage(InlineImageObject {
-            rect, im: im.clone(), transform, op_nr: self.op_nr, mode, clip
+            rect, im: im.clone(), transform, op_nr: self.op_nr, mode, alpha, clip
+        }));
+        Ok(())
+    }
+    fn draw_glyph(&mut self, glyph: &Glyph, code: u16, mode: &DrawMode, transform: Transform2F, clip: Option<ClipPathId>) {
+        let (fill, stroke) = match mode {
+            DrawMode::Fill { fill } => (Some(fill.clone()), None),
+            DrawMode::FillStroke { fill, stroke, stroke_mode } => (Some(fill.clone()), Some((stroke.clone(), stroke_mode.clone()))),
+            DrawMode::Stroke { stroke, stroke_mode } => (None, Some((stroke.clone(), stroke_mode.clone()))),
+        };
+        self.items.push(DrawItem::Glyph(GlyphObject {
+            code,
+            outline: glyph.path.clone(),
+            position: transform.translation(),
+            bounds: glyph.path.clone().transformed(&transform).bounds(),
+            fill,
+            stroke,
+            transform,
+            op_nr: self.op_nr,
+            clip: self.clip(clip),
         }));
     }
-    fn draw_glyph(&mut self, _glyph: &Glyph, _mode: &DrawMode, _transform: Transform2F, clip: Option<ClipPathId>) {}
     fn get_font(&mut self, font_ref: &MaybeRef<PdfFont>, resolve: &impl Resolve) -> Result<Option<Arc<FontEntry>>, PdfError> {
         self.cache.get_font(font_ref, resolve)
     }
     fn add_text(&mut self, span: TextSpan, clip: Option<Self::ClipPathId>) {
-        self.items.push(DrawItem::Text(span, clip));
+        self.items.push(DrawItem::Text(span, self.clip(clip)));
     }
     fn bug_op(&mut self, op_nr: usize) {
         self.op_nr = op_nr;
@@ -162,6 +215,7 @@ pub struct ImageObject {
     pub transform: Transform2F,
     pub op_nr: usize,
     pub mode: BlendMode,
+    pub alpha: f32,
     pub clip: Option<ClipPathId>,
 }
 #[derive(Debug)]
@@ -171,6 +225,7 @@ pub struct InlineImageObject {
     pub transform: Transform2F,
     pub op_nr: usize,
     pub mode: BlendMode,
+    pub alpha: f32,
     pub clip: Option<ClipPathId>,
 }
 
@@ -180,6 +235,18 @@ pub enum DrawItem {
     Image(ImageObject),
     InlineImage(InlineImageObject),
     Text(TextSpan, Option<ClipPathId>),
+    Glyph(GlyphObject),
+    /// only with `Tracer::record_clips`
+    Clip(ClipItem),
+}
+
+/// A clip path was set. Items drawn after it with the same `id` are clipped to it.
+#[derive(Debug)]
+pub struct ClipItem {
+    pub id: ClipPathId,
+    /// `clip_bounds` of the clip path, `None` if it hides everything
+    pub bounds: Option<RectF>,
+    pub op_nr: usize,
 }
 
 #[derive(Debug)]
@@ -187,6 +254,25 @@ pub struct VectorPath {
     pub outline: Outline,
     pub fill: Option<FillMode>,
     pub stroke: Option<(FillMode, Stroke)>,
+    pub fill_rule: FillRule,
+    /// of the transformed outline, ignoring the stroke width
+    pub bounds: RectF,
+    pub transform: Transform2F,
+    pub op_nr: usize,
+    pub clip: Option<ClipPathId>,
+}
+
+#[derive(Debug)]
+pub struct GlyphObject {
+    /// character code in the font
+    pub code: u16,
+    /// in glyph space
+    pub outline: Outline,
+    /// the glyph origin on the page
+    pub position: Vector2F,
+    pub bounds: RectF,
+    pub fill: Option<FillMode>,
+    pub stroke: Option<(FillMode, Stroke)>,
     pub transform: Transform2F,
     pub op_nr: usize,
     pub clip: Option<ClipPathId>,
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use pdf::object::*;
+use pdf::primitive::Dictionary;
+use pdf::content::{Op, parse_ops};
+use pdf::error::{PdfError, Result};
+use pathfinder_geometry::transform2d::Transform2F;
+
+/// A Type3 font. Each glyph is a content stream drawn in glyph space.
+pub struct Type3Font {
+    pub font_matrix: Transform2F,
+    pub glyphs: HashMap<u16, Type3Glyph>,
+    pub resources: Option<Resources>,
+}
+
+pub struct Type3Glyph {
+    pub ops: Vec<Op>,
+    /// horizontal displacement in glyph space
+    pub width: f32,
+    /// declared with `d1`: the glyph only describes a shape and is painted in the current color
+    pub shape_only: bool,
+}
+
+impl Type3Font {
+    pub fn parse(dict: &Dictionary, differences: &HashMap<u32, String>, resolve: &impl Resolve) -> Result<Type3Font> {
+        let font_matrix = match dict.get("FontMatrix") {
+            Some(p) => {
+                let m = p.clone().resolve(resolve)?.into_array()?;
+                if m.len() != 6 {
+                    return Err(PdfError::Other { msg: format!("FontMatrix has {} entries", m.len()) });
+                }
+                let n = |i: usize| m[i].as_number();
+                Transform2F::row_major(n(0)?, n(2)?, n(4)?, n(1)?, n(3)?, n(5)?)
+            }
+            None => Transform2F::from_scale(0.001),
+        };
+
+        let resources = match dict.get("Resources") {
+            Some(p) => Some(t!(Resources::from_primitive(p.clone(), resolve))),
+            None => None
+        };
+
+        let first_char = match dict.get("FirstChar") {
+            Some(p) => p.as_integer()? as u32,
+            None => 0
+        };
+        let widths: Vec<f32> = match dict.get("Widths") {
+            Some(p) => p.clone().resolve(resolve)?.into_array()?.iter().map(|w| w.as_number()).collect::<Result<_>>()?,
+            None => vec![]
+        };
+
+        let char_procs = match dict.get("CharProcs") {
+            Some(p) => p.clone().resolve(resolve)?.into_dictionary()?,
+            None => return Err(PdfError::MissingEntry { typ: "Type3 Font", field: "CharProcs".into() })
+        };
+
+        let mut glyphs = HashMap::new();
+        for (&code, name) in differences.iter() {
+            let proc = match char_procs.get(name.as_str()) {
+                Some(p) => p,
+                None => {
+                    debug!("no CharProc for {} ({})", name, code);
+                    continue;
+                }
+            };
+            let stream = t!(Stream::<()>::from_primitive(proc.clone(), resolve));
+            let data = t!(stream.data(resolve));
+            let (wx, shape_only, start) = match glyph_metrics(&data) {
+                Some(m) => m,
+                None => {
+                    warn!("glyph {} does not start with d0 or d1", name);
+                    (0.0, false, 0)
+                }
+            };
+            let ops = t!(parse_ops(&data[start..], resolve));
+
+            let width = code.checked_sub(first_char)
+                .and_then(|i| widths.get(i as usize))
+                .cloned()
+                .unwrap_or(wx);
+
+            glyphs.insert(code as u16, Type3Glyph { ops, width, shape_only });
+        }
+
+        Ok(Type3Font {
+            font_matrix,
+            glyphs,
+            resources,
+        })
+    }
+}
+
+/// Read the leading `wx wy d0` or `wx wy llx lly urx ury d1` operator of a glyph description.
+///
+/// Returns the horizontal displacement, whether `d1` was used and the offset of the following operators.
+fn glyph_metrics(data: &[u8]) -> Option<(f32, bool, usize)> {
+    let mut args: Vec<f32> = Vec::with_capacity(6);
+    let mut pos = 0;
+    while pos < data.len() {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        match &data[start..pos] {
+            b"" => break,
+            b"d0" => return args.first().map(|&wx| (wx, false, pos)),
+            b"d1" => return args.first().map(|&wx| (wx, true, pos)),
+            token => args.push(std::str::from_utf8(token).ok()?.parse().ok()?),
+        }
+    }
+    None
+}